@@ -2,11 +2,30 @@
 
 pub mod prelude;
 
+pub mod audit_events;
 pub mod games;
+pub mod impersonations;
 pub mod mod_authors;
 pub mod mod_media;
 pub mod mod_releases;
+pub mod mod_subscriptions;
+pub mod mod_updates;
 pub mod mods;
+pub mod oauth_authorization_codes;
+pub mod oauth_clients;
+pub mod oauth_device_codes;
+pub mod oauth_refresh_tokens;
 pub mod passkeys;
+pub mod personal_access_tokens;
+pub mod recovery_codes;
+pub mod role_scopes;
+pub mod roles;
 pub mod sea_orm_active_enums;
+pub mod session_refresh_tokens;
+pub mod sessions;
+pub mod user_follows;
+pub mod user_roles;
+pub mod user_scopes;
+pub mod username_history;
 pub mod users;
+pub mod webauthn_challenges;
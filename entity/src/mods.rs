@@ -15,6 +15,7 @@ pub struct Model {
     pub game_id: Uuid,
     pub published_at: Option<TimeDateTimeWithTimeZone>,
     pub thumbnail_url: Option<String>,
+    pub archived_at: Option<TimeDateTimeWithTimeZone>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -33,6 +34,10 @@ pub enum Relation {
     ModMedia,
     #[sea_orm(has_many = "super::mod_releases::Entity")]
     ModReleases,
+    #[sea_orm(has_many = "super::mod_subscriptions::Entity")]
+    ModSubscriptions,
+    #[sea_orm(has_many = "super::mod_updates::Entity")]
+    ModUpdates,
 }
 
 impl Related<super::games::Entity> for Entity {
@@ -59,4 +64,16 @@ impl Related<super::mod_releases::Entity> for Entity {
     }
 }
 
+impl Related<super::mod_subscriptions::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::ModSubscriptions.def()
+    }
+}
+
+impl Related<super::mod_updates::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::ModUpdates.def()
+    }
+}
+
 impl ActiveModelBehavior for ActiveModel {}
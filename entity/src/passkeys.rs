@@ -17,6 +17,9 @@ pub struct Model {
     pub data: Json,
     pub created_at: TimeDateTimeWithTimeZone,
     pub last_used_at: Option<TimeDateTimeWithTimeZone>,
+    pub name: Option<String>,
+    pub flagged_at: Option<TimeDateTimeWithTimeZone>,
+    pub flag_reason: Option<String>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -1,9 +1,28 @@
 //! `SeaORM` Entity, @generated by sea-orm-codegen 1.1.12
 
+pub use super::audit_events::Entity as AuditEvents;
 pub use super::games::Entity as Games;
+pub use super::impersonations::Entity as Impersonations;
 pub use super::mod_authors::Entity as ModAuthors;
 pub use super::mod_media::Entity as ModMedia;
 pub use super::mod_releases::Entity as ModReleases;
+pub use super::mod_subscriptions::Entity as ModSubscriptions;
+pub use super::mod_updates::Entity as ModUpdates;
 pub use super::mods::Entity as Mods;
+pub use super::oauth_authorization_codes::Entity as OauthAuthorizationCodes;
+pub use super::oauth_clients::Entity as OauthClients;
+pub use super::oauth_device_codes::Entity as OauthDeviceCodes;
+pub use super::oauth_refresh_tokens::Entity as OauthRefreshTokens;
 pub use super::passkeys::Entity as Passkeys;
+pub use super::personal_access_tokens::Entity as PersonalAccessTokens;
+pub use super::recovery_codes::Entity as RecoveryCodes;
+pub use super::role_scopes::Entity as RoleScopes;
+pub use super::roles::Entity as Roles;
+pub use super::session_refresh_tokens::Entity as SessionRefreshTokens;
+pub use super::sessions::Entity as Sessions;
+pub use super::user_follows::Entity as UserFollows;
+pub use super::user_roles::Entity as UserRoles;
+pub use super::user_scopes::Entity as UserScopes;
+pub use super::username_history::Entity as UsernameHistory;
 pub use super::users::Entity as Users;
+pub use super::webauthn_challenges::Entity as WebauthnChallenges;
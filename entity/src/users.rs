@@ -13,14 +13,43 @@ pub struct Model {
     pub username: String,
     #[sea_orm(unique)]
     pub username_normalized: String,
+    pub display_name: Option<String>,
+    #[sea_orm(column_type = "Text", nullable)]
+    pub bio: Option<String>,
+    #[sea_orm(column_type = "JsonBinary")]
+    pub links: Json,
+    pub avatar_id: Option<Uuid>,
+    pub feed_read_at: Option<TimeDateTimeWithTimeZone>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
 pub enum Relation {
     #[sea_orm(has_many = "super::mod_authors::Entity")]
     ModAuthors,
+    #[sea_orm(has_many = "super::mod_subscriptions::Entity")]
+    ModSubscriptions,
+    #[sea_orm(has_many = "super::oauth_authorization_codes::Entity")]
+    OauthAuthorizationCodes,
+    #[sea_orm(has_many = "super::oauth_clients::Entity")]
+    OauthClients,
+    #[sea_orm(has_many = "super::oauth_device_codes::Entity")]
+    OauthDeviceCodes,
+    #[sea_orm(has_many = "super::oauth_refresh_tokens::Entity")]
+    OauthRefreshTokens,
     #[sea_orm(has_many = "super::passkeys::Entity")]
     Passkeys,
+    #[sea_orm(has_many = "super::personal_access_tokens::Entity")]
+    PersonalAccessTokens,
+    #[sea_orm(has_many = "super::recovery_codes::Entity")]
+    RecoveryCodes,
+    #[sea_orm(has_many = "super::sessions::Entity")]
+    Sessions,
+    #[sea_orm(has_many = "super::user_roles::Entity")]
+    UserRoles,
+    #[sea_orm(has_many = "super::user_scopes::Entity")]
+    UserScopes,
+    #[sea_orm(has_many = "super::username_history::Entity")]
+    UsernameHistory,
 }
 
 impl Related<super::mod_authors::Entity> for Entity {
@@ -29,10 +58,85 @@ impl Related<super::mod_authors::Entity> for Entity {
     }
 }
 
+impl Related<super::mod_subscriptions::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::ModSubscriptions.def()
+    }
+}
+
+impl Related<super::oauth_authorization_codes::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::OauthAuthorizationCodes.def()
+    }
+}
+
+impl Related<super::oauth_clients::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::OauthClients.def()
+    }
+}
+
+impl Related<super::oauth_device_codes::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::OauthDeviceCodes.def()
+    }
+}
+
+impl Related<super::oauth_refresh_tokens::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::OauthRefreshTokens.def()
+    }
+}
+
 impl Related<super::passkeys::Entity> for Entity {
     fn to() -> RelationDef {
         Relation::Passkeys.def()
     }
 }
 
+impl Related<super::personal_access_tokens::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::PersonalAccessTokens.def()
+    }
+}
+
+impl Related<super::recovery_codes::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::RecoveryCodes.def()
+    }
+}
+
+impl Related<super::sessions::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Sessions.def()
+    }
+}
+
+impl Related<super::user_roles::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::UserRoles.def()
+    }
+}
+
+impl Related<super::user_scopes::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::UserScopes.def()
+    }
+}
+
+impl Related<super::username_history::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::UsernameHistory.def()
+    }
+}
+
+impl Related<super::roles::Entity> for Entity {
+    fn to() -> RelationDef {
+        super::user_roles::Relation::Roles.def()
+    }
+    fn via() -> Option<RelationDef> {
+        Some(super::user_roles::Relation::Users.def().rev())
+    }
+}
+
 impl ActiveModelBehavior for ActiveModel {}
@@ -5,6 +5,25 @@ mod m20250624_165444_passkeys;
 mod m20250705_185912_mods;
 mod m20250706_115515_games;
 mod m20250706_123338_mod_media;
+mod m20261018_090000_webauthn_challenges;
+mod m20261018_100000_sessions;
+mod m20261018_110000_passkey_names;
+mod m20261018_120000_recovery_codes;
+mod m20261018_130000_roles;
+mod m20261018_140000_personal_access_tokens;
+mod m20261018_150000_developer_role;
+mod m20261018_160000_oauth;
+mod m20261018_170000_username_history;
+mod m20261018_180000_passkey_flags;
+mod m20261018_190000_session_refresh_tokens;
+mod m20261018_200000_audit_log;
+mod m20261018_210000_archived_mods;
+mod m20261018_220000_impersonations;
+mod m20261018_230000_display_names;
+mod m20261019_000000_profiles;
+mod m20261019_010000_avatars;
+mod m20261019_020000_follows;
+mod m20261019_030000_oauth_refresh_used;
 
 pub struct Migrator;
 
@@ -17,6 +36,25 @@ impl MigratorTrait for Migrator {
             Box::new(m20250705_185912_mods::Migration),
             Box::new(m20250706_115515_games::Migration),
             Box::new(m20250706_123338_mod_media::Migration),
+            Box::new(m20261018_090000_webauthn_challenges::Migration),
+            Box::new(m20261018_100000_sessions::Migration),
+            Box::new(m20261018_110000_passkey_names::Migration),
+            Box::new(m20261018_120000_recovery_codes::Migration),
+            Box::new(m20261018_130000_roles::Migration),
+            Box::new(m20261018_140000_personal_access_tokens::Migration),
+            Box::new(m20261018_150000_developer_role::Migration),
+            Box::new(m20261018_160000_oauth::Migration),
+            Box::new(m20261018_170000_username_history::Migration),
+            Box::new(m20261018_180000_passkey_flags::Migration),
+            Box::new(m20261018_190000_session_refresh_tokens::Migration),
+            Box::new(m20261018_200000_audit_log::Migration),
+            Box::new(m20261018_210000_archived_mods::Migration),
+            Box::new(m20261018_220000_impersonations::Migration),
+            Box::new(m20261018_230000_display_names::Migration),
+            Box::new(m20261019_000000_profiles::Migration),
+            Box::new(m20261019_010000_avatars::Migration),
+            Box::new(m20261019_020000_follows::Migration),
+            Box::new(m20261019_030000_oauth_refresh_used::Migration),
         ]
     }
 }
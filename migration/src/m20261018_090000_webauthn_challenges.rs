@@ -0,0 +1,59 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(WebauthnChallenges::Table)
+                    .if_not_exists()
+                    .col(pk_uuid(WebauthnChallenges::Id))
+                    .col(string(WebauthnChallenges::Ceremony))
+                    .col(string(WebauthnChallenges::Client))
+                    .col(json_binary(WebauthnChallenges::State)) // Opaque webauthn_rs ceremony state
+                    .col(timestamp_with_time_zone(WebauthnChallenges::CreatedAt).default(Expr::current_timestamp()))
+                    .col(timestamp_with_time_zone(WebauthnChallenges::ExpiresAt))
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_webauthn_challenges_client")
+                    .table(WebauthnChallenges::Table)
+                    .col(WebauthnChallenges::Client)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_webauthn_challenges_expires_at")
+                    .table(WebauthnChallenges::Table)
+                    .col(WebauthnChallenges::ExpiresAt)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(WebauthnChallenges::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum WebauthnChallenges {
+    Table,
+    Id,
+    Ceremony,
+    Client,
+    State,
+    CreatedAt,
+    ExpiresAt,
+}
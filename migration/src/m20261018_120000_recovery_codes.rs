@@ -0,0 +1,108 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(RecoveryCodes::Table)
+                    .if_not_exists()
+                    .col(pk_uuid(RecoveryCodes::Id))
+                    .col(uuid(RecoveryCodes::UserId))
+                    .col(binary(RecoveryCodes::CodeHash)) // SHA-256 of the normalized code
+                    .col(timestamp_with_time_zone(RecoveryCodes::CreatedAt).default(Expr::current_timestamp()))
+                    .col(timestamp_with_time_zone_null(RecoveryCodes::UsedAt))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(RecoveryCodes::Table, RecoveryCodes::UserId)
+                            .to(Users::Table, Users::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_recovery_codes_user_id_code_hash")
+                    .table(RecoveryCodes::Table)
+                    .col(RecoveryCodes::UserId)
+                    .col(RecoveryCodes::CodeHash)
+                    .unique()
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_table(
+                Table::create()
+                    .table(SecurityEvents::Table)
+                    .if_not_exists()
+                    .col(pk_uuid(SecurityEvents::Id))
+                    .col(uuid(SecurityEvents::UserId))
+                    .col(string(SecurityEvents::Event))
+                    .col(string_null(SecurityEvents::Ip))
+                    .col(string_null(SecurityEvents::UserAgent))
+                    .col(json_binary_null(SecurityEvents::Details))
+                    .col(timestamp_with_time_zone(SecurityEvents::CreatedAt).default(Expr::current_timestamp()))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(SecurityEvents::Table, SecurityEvents::UserId)
+                            .to(Users::Table, Users::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_security_events_user_id_created_at")
+                    .table(SecurityEvents::Table)
+                    .col(SecurityEvents::UserId)
+                    .col(SecurityEvents::CreatedAt)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(SecurityEvents::Table).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(RecoveryCodes::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum RecoveryCodes {
+    Table,
+    Id,
+    UserId,
+    CodeHash,
+    CreatedAt,
+    UsedAt,
+}
+
+#[derive(DeriveIden)]
+enum SecurityEvents {
+    Table,
+    Id,
+    UserId,
+    Event,
+    Ip,
+    UserAgent,
+    Details,
+    CreatedAt,
+}
+
+#[derive(DeriveIden)]
+enum Users {
+    Table,
+    Id,
+}
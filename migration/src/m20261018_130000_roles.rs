@@ -0,0 +1,167 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Roles::Table)
+                    .if_not_exists()
+                    .col(string(Roles::Id).primary_key())
+                    .col(string(Roles::Name))
+                    .col(string(Roles::Description))
+                    .col(boolean(Roles::IsDefault).default(false)) // Every user has default roles implicitly
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_table(
+                Table::create()
+                    .table(RoleScopes::Table)
+                    .if_not_exists()
+                    .col(string(RoleScopes::RoleId))
+                    .col(string(RoleScopes::Scope))
+                    .primary_key(Index::create().col(RoleScopes::RoleId).col(RoleScopes::Scope))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(RoleScopes::Table, RoleScopes::RoleId)
+                            .to(Roles::Table, Roles::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_table(
+                Table::create()
+                    .table(UserRoles::Table)
+                    .if_not_exists()
+                    .col(uuid(UserRoles::UserId))
+                    .col(string(UserRoles::RoleId))
+                    .col(timestamp_with_time_zone(UserRoles::CreatedAt).default(Expr::current_timestamp()))
+                    .primary_key(Index::create().col(UserRoles::UserId).col(UserRoles::RoleId))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(UserRoles::Table, UserRoles::UserId)
+                            .to(Users::Table, Users::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(UserRoles::Table, UserRoles::RoleId)
+                            .to(Roles::Table, Roles::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_table(
+                Table::create()
+                    .table(UserScopes::Table)
+                    .if_not_exists()
+                    .col(uuid(UserScopes::UserId))
+                    .col(string(UserScopes::Scope))
+                    .col(boolean(UserScopes::Granted)) // false revokes the scope even if a role grants it
+                    .col(string_null(UserScopes::Reason))
+                    .col(timestamp_with_time_zone(UserScopes::CreatedAt).default(Expr::current_timestamp()))
+                    .primary_key(Index::create().col(UserScopes::UserId).col(UserScopes::Scope))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(UserScopes::Table, UserScopes::UserId)
+                            .to(Users::Table, Users::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Sessions::Table)
+                    .add_column(boolean(Sessions::RefreshScopes).default(false))
+                    .to_owned(),
+            )
+            .await?;
+
+        manager.get_connection().execute_unprepared(
+            "INSERT INTO roles (id, name, description, is_default) VALUES
+                ('member', 'Member', 'Can create and publish their own mods', true),
+                ('admin', 'Administrator', 'Can manage every mod and user', false);
+            INSERT INTO role_scopes (role_id, scope) VALUES
+                ('member', 'create_mod'),
+                ('member', 'publish_mod'),
+                ('admin', 'admin_author_all_mods'),
+                ('admin', 'manage_users');"
+        ).await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(Table::alter().table(Sessions::Table).drop_column(Sessions::RefreshScopes).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(UserScopes::Table).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(UserRoles::Table).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(RoleScopes::Table).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(Roles::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Roles {
+    Table,
+    Id,
+    Name,
+    Description,
+    IsDefault,
+}
+
+#[derive(DeriveIden)]
+enum RoleScopes {
+    Table,
+    RoleId,
+    Scope,
+}
+
+#[derive(DeriveIden)]
+enum UserRoles {
+    Table,
+    UserId,
+    RoleId,
+    CreatedAt,
+}
+
+#[derive(DeriveIden)]
+enum UserScopes {
+    Table,
+    UserId,
+    Scope,
+    Granted,
+    Reason,
+    CreatedAt,
+}
+
+#[derive(DeriveIden)]
+enum Sessions {
+    Table,
+    RefreshScopes,
+}
+
+#[derive(DeriveIden)]
+enum Users {
+    Table,
+    Id,
+}
@@ -0,0 +1,68 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(PersonalAccessTokens::Table)
+                    .if_not_exists()
+                    .col(pk_uuid(PersonalAccessTokens::Id))
+                    .col(uuid(PersonalAccessTokens::UserId))
+                    .col(string(PersonalAccessTokens::Name))
+                    .col(binary_uniq(PersonalAccessTokens::TokenHash)) // SHA-256 of the token
+                    .col(json_binary(PersonalAccessTokens::Scopes)) // Array of scope names
+                    .col(timestamp_with_time_zone(PersonalAccessTokens::CreatedAt).default(Expr::current_timestamp()))
+                    .col(timestamp_with_time_zone(PersonalAccessTokens::ExpiresAt))
+                    .col(timestamp_with_time_zone_null(PersonalAccessTokens::LastUsedAt))
+                    .col(timestamp_with_time_zone_null(PersonalAccessTokens::RevokedAt))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(PersonalAccessTokens::Table, PersonalAccessTokens::UserId)
+                            .to(Users::Table, Users::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_personal_access_tokens_user_id")
+                    .table(PersonalAccessTokens::Table)
+                    .col(PersonalAccessTokens::UserId)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(PersonalAccessTokens::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum PersonalAccessTokens {
+    Table,
+    Id,
+    UserId,
+    Name,
+    TokenHash,
+    Scopes,
+    CreatedAt,
+    ExpiresAt,
+    LastUsedAt,
+    RevokedAt,
+}
+
+#[derive(DeriveIden)]
+enum Users {
+    Table,
+    Id,
+}
@@ -0,0 +1,25 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.get_connection().execute_unprepared(
+            "INSERT INTO roles (id, name, description, is_default) VALUES
+                ('developer', 'Developer', 'Can use the shared build cache', false);
+            INSERT INTO role_scopes (role_id, scope) VALUES
+                ('developer', 'sccache_read'),
+                ('developer', 'sccache_write');"
+        ).await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.get_connection().execute_unprepared(
+            "DELETE FROM roles WHERE id = 'developer';"
+        ).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,201 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(OauthClients::Table)
+                    .if_not_exists()
+                    .col(pk_uuid(OauthClients::Id)) // `client_id`
+                    .col(uuid(OauthClients::OwnerId))
+                    .col(string(OauthClients::Name))
+                    .col(json_binary(OauthClients::RedirectUris)) // Array of exact redirect URIs
+                    .col(binary_null(OauthClients::SecretHash)) // Null for public clients, which can't keep a secret
+                    .col(timestamp_with_time_zone(OauthClients::CreatedAt).default(Expr::current_timestamp()))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(OauthClients::Table, OauthClients::OwnerId)
+                            .to(Users::Table, Users::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_table(
+                Table::create()
+                    .table(OauthAuthorizationCodes::Table)
+                    .if_not_exists()
+                    .col(pk_uuid(OauthAuthorizationCodes::Id))
+                    .col(binary_uniq(OauthAuthorizationCodes::CodeHash))
+                    .col(uuid(OauthAuthorizationCodes::ClientId))
+                    .col(uuid(OauthAuthorizationCodes::UserId))
+                    .col(string(OauthAuthorizationCodes::RedirectUri))
+                    .col(json_binary(OauthAuthorizationCodes::Scopes))
+                    .col(string(OauthAuthorizationCodes::CodeChallenge)) // PKCE, always S256
+                    .col(timestamp_with_time_zone(OauthAuthorizationCodes::CreatedAt).default(Expr::current_timestamp()))
+                    .col(timestamp_with_time_zone(OauthAuthorizationCodes::ExpiresAt))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(OauthAuthorizationCodes::Table, OauthAuthorizationCodes::ClientId)
+                            .to(OauthClients::Table, OauthClients::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(OauthAuthorizationCodes::Table, OauthAuthorizationCodes::UserId)
+                            .to(Users::Table, Users::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_table(
+                Table::create()
+                    .table(OauthDeviceCodes::Table)
+                    .if_not_exists()
+                    .col(pk_uuid(OauthDeviceCodes::Id))
+                    .col(binary_uniq(OauthDeviceCodes::DeviceCodeHash))
+                    .col(string_uniq(OauthDeviceCodes::UserCode))
+                    .col(uuid(OauthDeviceCodes::ClientId))
+                    .col(uuid_null(OauthDeviceCodes::UserId)) // Set once someone approves or denies
+                    .col(json_binary(OauthDeviceCodes::Scopes))
+                    .col(string(OauthDeviceCodes::Status)) // pending, approved or denied
+                    .col(timestamp_with_time_zone(OauthDeviceCodes::CreatedAt).default(Expr::current_timestamp()))
+                    .col(timestamp_with_time_zone(OauthDeviceCodes::ExpiresAt))
+                    .col(timestamp_with_time_zone_null(OauthDeviceCodes::LastPolledAt))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(OauthDeviceCodes::Table, OauthDeviceCodes::ClientId)
+                            .to(OauthClients::Table, OauthClients::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(OauthDeviceCodes::Table, OauthDeviceCodes::UserId)
+                            .to(Users::Table, Users::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_table(
+                Table::create()
+                    .table(OauthRefreshTokens::Table)
+                    .if_not_exists()
+                    .col(pk_uuid(OauthRefreshTokens::Id))
+                    .col(binary_uniq(OauthRefreshTokens::TokenHash))
+                    .col(uuid(OauthRefreshTokens::ClientId))
+                    .col(uuid(OauthRefreshTokens::UserId))
+                    .col(json_binary(OauthRefreshTokens::Scopes))
+                    .col(timestamp_with_time_zone(OauthRefreshTokens::CreatedAt).default(Expr::current_timestamp()))
+                    .col(timestamp_with_time_zone(OauthRefreshTokens::ExpiresAt))
+                    .col(timestamp_with_time_zone_null(OauthRefreshTokens::RevokedAt))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(OauthRefreshTokens::Table, OauthRefreshTokens::ClientId)
+                            .to(OauthClients::Table, OauthClients::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(OauthRefreshTokens::Table, OauthRefreshTokens::UserId)
+                            .to(Users::Table, Users::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_oauth_refresh_tokens_user_id_client_id")
+                    .table(OauthRefreshTokens::Table)
+                    .col(OauthRefreshTokens::UserId)
+                    .col(OauthRefreshTokens::ClientId)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(OauthRefreshTokens::Table).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(OauthDeviceCodes::Table).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(OauthAuthorizationCodes::Table).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(OauthClients::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum OauthClients {
+    Table,
+    Id,
+    OwnerId,
+    Name,
+    RedirectUris,
+    SecretHash,
+    CreatedAt,
+}
+
+#[derive(DeriveIden)]
+enum OauthAuthorizationCodes {
+    Table,
+    Id,
+    CodeHash,
+    ClientId,
+    UserId,
+    RedirectUri,
+    Scopes,
+    CodeChallenge,
+    CreatedAt,
+    ExpiresAt,
+}
+
+#[derive(DeriveIden)]
+enum OauthDeviceCodes {
+    Table,
+    Id,
+    DeviceCodeHash,
+    UserCode,
+    ClientId,
+    UserId,
+    Scopes,
+    Status,
+    CreatedAt,
+    ExpiresAt,
+    LastPolledAt,
+}
+
+#[derive(DeriveIden)]
+enum OauthRefreshTokens {
+    Table,
+    Id,
+    TokenHash,
+    ClientId,
+    UserId,
+    Scopes,
+    CreatedAt,
+    ExpiresAt,
+    RevokedAt,
+}
+
+#[derive(DeriveIden)]
+enum Users {
+    Table,
+    Id,
+}
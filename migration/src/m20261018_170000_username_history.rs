@@ -0,0 +1,72 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(UsernameHistory::Table)
+                    .if_not_exists()
+                    .col(pk_uuid(UsernameHistory::Id))
+                    .col(uuid(UsernameHistory::UserId))
+                    .col(string(UsernameHistory::Username)) // The name the user had before this change
+                    .col(string(UsernameHistory::UsernameNormalized))
+                    .col(timestamp_with_time_zone(UsernameHistory::ChangedAt).default(Expr::current_timestamp()))
+                    .col(timestamp_with_time_zone(UsernameHistory::ReservedUntil)) // Only the user can take the name back until then
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(UsernameHistory::Table, UsernameHistory::UserId)
+                            .to(Users::Table, Users::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_username_history_username_normalized")
+                    .table(UsernameHistory::Table)
+                    .col(UsernameHistory::UsernameNormalized)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_username_history_user_id_changed_at")
+                    .table(UsernameHistory::Table)
+                    .col(UsernameHistory::UserId)
+                    .col(UsernameHistory::ChangedAt)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(UsernameHistory::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum UsernameHistory {
+    Table,
+    Id,
+    UserId,
+    Username,
+    UsernameNormalized,
+    ChangedAt,
+    ReservedUntil,
+}
+
+#[derive(DeriveIden)]
+enum Users {
+    Table,
+    Id,
+}
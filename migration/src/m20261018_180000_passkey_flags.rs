@@ -0,0 +1,38 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Passkeys::Table)
+                    .add_column(timestamp_with_time_zone_null(Passkeys::FlaggedAt)) // Set when the passkey may have been cloned
+                    .add_column(string_null(Passkeys::FlagReason))
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Passkeys::Table)
+                    .drop_column(Passkeys::FlaggedAt)
+                    .drop_column(Passkeys::FlagReason)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Passkeys {
+    Table,
+    FlaggedAt,
+    FlagReason,
+}
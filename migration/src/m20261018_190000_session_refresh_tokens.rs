@@ -0,0 +1,60 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(SessionRefreshTokens::Table)
+                    .if_not_exists()
+                    .col(pk_uuid(SessionRefreshTokens::Id))
+                    .col(uuid(SessionRefreshTokens::SessionId)) // Every token a session has had is one family
+                    .col(binary_uniq(SessionRefreshTokens::TokenHash))
+                    .col(timestamp_with_time_zone(SessionRefreshTokens::CreatedAt).default(Expr::current_timestamp()))
+                    .col(timestamp_with_time_zone_null(SessionRefreshTokens::UsedAt))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(SessionRefreshTokens::Table, SessionRefreshTokens::SessionId)
+                            .to(Sessions::Table, Sessions::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_session_refresh_tokens_session_id")
+                    .table(SessionRefreshTokens::Table)
+                    .col(SessionRefreshTokens::SessionId)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(SessionRefreshTokens::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum SessionRefreshTokens {
+    Table,
+    Id,
+    SessionId,
+    TokenHash,
+    CreatedAt,
+    UsedAt,
+}
+
+#[derive(DeriveIden)]
+enum Sessions {
+    Table,
+    Id,
+}
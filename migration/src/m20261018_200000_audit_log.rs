@@ -0,0 +1,146 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // The audit log grows out of the per-account security history
+        manager
+            .rename_table(Table::rename().table(SecurityEvents::Table, AuditEvents::Table).to_owned())
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(AuditEvents::Table)
+                    // Entries outlive the users they mention
+                    .drop_foreign_key(Alias::new("security_events_user_id_fkey"))
+                    .modify_column(uuid_null(AuditEvents::UserId)) // The account the event is about, if any
+                    .add_column(uuid_null(AuditEvents::ActorId)) // Null for things the server did on its own
+                    .add_column(string_null(AuditEvents::TargetType))
+                    .add_column(string_null(AuditEvents::TargetId))
+                    .to_owned(),
+            )
+            .await?;
+
+        let db = manager.get_connection();
+        db.execute_unprepared(
+            "ALTER INDEX idx_security_events_user_id_created_at RENAME TO idx_audit_events_user_id_created_at;
+            ALTER TABLE audit_events RENAME CONSTRAINT security_events_pkey TO audit_events_pkey;
+            UPDATE audit_events SET actor_id = CASE
+                WHEN details ? 'by' THEN (details->>'by')::uuid
+                WHEN event IN ('oauth_refresh_token_reused', 'session_refresh_token_reused') THEN NULL
+                ELSE user_id
+            END;"
+        ).await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_audit_events_actor_id_created_at")
+                    .table(AuditEvents::Table)
+                    .col(AuditEvents::ActorId)
+                    .col(AuditEvents::CreatedAt)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_audit_events_target")
+                    .table(AuditEvents::Table)
+                    .col(AuditEvents::TargetType)
+                    .col(AuditEvents::TargetId)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_audit_events_created_at")
+                    .table(AuditEvents::Table)
+                    .col(AuditEvents::CreatedAt)
+                    .to_owned(),
+            )
+            .await?;
+
+        // Append-only, even for a compromised application
+        db.execute_unprepared(
+            "CREATE FUNCTION audit_events_append_only() RETURNS trigger AS $$
+            BEGIN
+                RAISE EXCEPTION 'audit_events is append-only';
+            END;
+            $$ LANGUAGE plpgsql;
+            CREATE TRIGGER audit_events_append_only
+                BEFORE UPDATE OR DELETE ON audit_events
+                FOR EACH ROW EXECUTE FUNCTION audit_events_append_only();
+            CREATE TRIGGER audit_events_no_truncate
+                BEFORE TRUNCATE ON audit_events
+                FOR EACH STATEMENT EXECUTE FUNCTION audit_events_append_only();
+            INSERT INTO role_scopes (role_id, scope) VALUES ('admin', 'view_audit_log');"
+        ).await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+        db.execute_unprepared(
+            "DELETE FROM role_scopes WHERE scope = 'view_audit_log';
+            DELETE FROM user_scopes WHERE scope = 'view_audit_log';
+            DROP TRIGGER audit_events_no_truncate ON audit_events;
+            DROP TRIGGER audit_events_append_only ON audit_events;
+            DROP FUNCTION audit_events_append_only();
+            DELETE FROM audit_events WHERE user_id IS NULL OR user_id NOT IN (SELECT id FROM users);
+            DROP INDEX idx_audit_events_created_at;
+            DROP INDEX idx_audit_events_target;
+            DROP INDEX idx_audit_events_actor_id_created_at;
+            ALTER INDEX idx_audit_events_user_id_created_at RENAME TO idx_security_events_user_id_created_at;
+            ALTER TABLE audit_events RENAME CONSTRAINT audit_events_pkey TO security_events_pkey;"
+        ).await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(AuditEvents::Table)
+                    .drop_column(AuditEvents::ActorId)
+                    .drop_column(AuditEvents::TargetType)
+                    .drop_column(AuditEvents::TargetId)
+                    .modify_column(uuid(AuditEvents::UserId))
+                    .add_foreign_key(
+                        TableForeignKey::new()
+                            .name("security_events_user_id_fkey")
+                            .from_tbl(AuditEvents::Table)
+                            .from_col(AuditEvents::UserId)
+                            .to_tbl(Users::Table)
+                            .to_col(Users::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .rename_table(Table::rename().table(AuditEvents::Table, SecurityEvents::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum SecurityEvents {
+    Table,
+}
+
+#[derive(DeriveIden)]
+enum AuditEvents {
+    Table,
+    UserId,
+    ActorId,
+    TargetType,
+    TargetId,
+    CreatedAt,
+}
+
+#[derive(DeriveIden)]
+enum Users {
+    Table,
+    Id,
+}
@@ -0,0 +1,95 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Impersonations::Table)
+                    .if_not_exists()
+                    .col(pk_uuid(Impersonations::Id))
+                    .col(uuid(Impersonations::ActorId)) // The admin viewing as someone else
+                    .col(uuid(Impersonations::UserId))
+                    .col(uuid(Impersonations::SessionId)) // The admin's own session
+                    .col(string(Impersonations::Reason))
+                    .col(boolean(Impersonations::ReadOnly).default(true))
+                    .col(timestamp_with_time_zone(Impersonations::StartedAt).default(Expr::current_timestamp()))
+                    .col(timestamp_with_time_zone(Impersonations::ExpiresAt))
+                    .col(timestamp_with_time_zone_null(Impersonations::EndedAt))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(Impersonations::Table, Impersonations::ActorId)
+                            .to(Users::Table, Users::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(Impersonations::Table, Impersonations::UserId)
+                            .to(Users::Table, Users::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(Impersonations::Table, Impersonations::SessionId)
+                            .to(Sessions::Table, Sessions::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_impersonations_session_id")
+                    .table(Impersonations::Table)
+                    .col(Impersonations::SessionId)
+                    .to_owned(),
+            )
+            .await?;
+
+        let db = manager.get_connection();
+        db.execute_unprepared("INSERT INTO role_scopes (role_id, scope) VALUES ('admin', 'impersonate_users')").await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+        db.execute_unprepared(
+            "DELETE FROM role_scopes WHERE scope = 'impersonate_users';
+            DELETE FROM user_scopes WHERE scope = 'impersonate_users';"
+        ).await?;
+        manager
+            .drop_table(Table::drop().table(Impersonations::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Impersonations {
+    Table,
+    Id,
+    ActorId,
+    UserId,
+    SessionId,
+    Reason,
+    ReadOnly,
+    StartedAt,
+    ExpiresAt,
+    EndedAt,
+}
+
+#[derive(DeriveIden)]
+enum Users {
+    Table,
+    Id,
+}
+
+#[derive(DeriveIden)]
+enum Sessions {
+    Table,
+    Id,
+}
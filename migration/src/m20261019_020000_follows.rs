@@ -0,0 +1,147 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(UserFollows::Table)
+                    .if_not_exists()
+                    .col(uuid(UserFollows::FollowerId))
+                    .col(uuid(UserFollows::FollowedId))
+                    .col(timestamp_with_time_zone(UserFollows::CreatedAt).default(Expr::current_timestamp()))
+                    .primary_key(Index::create().col(UserFollows::FollowerId).col(UserFollows::FollowedId))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(UserFollows::Table, UserFollows::FollowerId)
+                            .to(Users::Table, Users::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(UserFollows::Table, UserFollows::FollowedId)
+                            .to(Users::Table, Users::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_user_follows_followed_id")
+                    .table(UserFollows::Table)
+                    .col(UserFollows::FollowedId)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_table(
+                Table::create()
+                    .table(ModSubscriptions::Table)
+                    .if_not_exists()
+                    .col(uuid(ModSubscriptions::UserId))
+                    .col(uuid(ModSubscriptions::ModId))
+                    .col(timestamp_with_time_zone(ModSubscriptions::CreatedAt).default(Expr::current_timestamp()))
+                    .primary_key(Index::create().col(ModSubscriptions::UserId).col(ModSubscriptions::ModId))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(ModSubscriptions::Table, ModSubscriptions::UserId)
+                            .to(Users::Table, Users::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(ModSubscriptions::Table, ModSubscriptions::ModId)
+                            .to(Mods::Table, Mods::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_table(
+                Table::create()
+                    .table(ModUpdates::Table)
+                    .if_not_exists()
+                    // Edits that the authors told followers about
+                    .col(uuid(ModUpdates::Id).primary_key())
+                    .col(uuid(ModUpdates::ModId))
+                    .col(text(ModUpdates::Summary))
+                    .col(timestamp_with_time_zone(ModUpdates::CreatedAt).default(Expr::current_timestamp()))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(ModUpdates::Table, ModUpdates::ModId)
+                            .to(Mods::Table, Mods::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Users::Table)
+                    // Feed items from before then are read
+                    .add_column(timestamp_with_time_zone_null(Users::FeedReadAt))
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Users::Table)
+                    .drop_column(Users::FeedReadAt)
+                    .to_owned(),
+            )
+            .await?;
+        manager.drop_table(Table::drop().table(ModUpdates::Table).to_owned()).await?;
+        manager.drop_table(Table::drop().table(ModSubscriptions::Table).to_owned()).await?;
+        manager.drop_table(Table::drop().table(UserFollows::Table).to_owned()).await
+    }
+}
+
+#[derive(DeriveIden)]
+enum UserFollows {
+    Table,
+    FollowerId,
+    FollowedId,
+    CreatedAt,
+}
+
+#[derive(DeriveIden)]
+enum ModSubscriptions {
+    Table,
+    UserId,
+    ModId,
+    CreatedAt,
+}
+
+#[derive(DeriveIden)]
+enum ModUpdates {
+    Table,
+    Id,
+    ModId,
+    Summary,
+    CreatedAt,
+}
+
+#[derive(DeriveIden)]
+enum Users {
+    Table,
+    Id,
+    FeedReadAt,
+}
+
+#[derive(DeriveIden)]
+enum Mods {
+    Table,
+    Id,
+}
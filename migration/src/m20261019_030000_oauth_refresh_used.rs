@@ -0,0 +1,37 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(OauthRefreshTokens::Table)
+                    // When the token was exchanged for a new one. Only reusing a token after that suggests it was stolen;
+                    // `revoked_at` is for the user or a detected reuse cutting off the app.
+                    .add_column(timestamp_with_time_zone_null(OauthRefreshTokens::UsedAt))
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(OauthRefreshTokens::Table)
+                    .drop_column(OauthRefreshTokens::UsedAt)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum OauthRefreshTokens {
+    Table,
+    UsedAt,
+}
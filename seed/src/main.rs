@@ -78,7 +78,6 @@ async fn main() {
             description: Set(m.description.clone()),
             download_url: Set(m.downloadUrl.clone()),
             created_at: Set(parse_date(&m.releaseDate)),
-            ..Default::default()
         };
 
         release_active.insert(&db).await.expect("release insert to succeed");
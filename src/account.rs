@@ -0,0 +1,53 @@
+use crate::prelude::*;
+
+mod activity;
+mod apps;
+mod data;
+mod profile;
+mod security;
+mod sessions;
+mod tokens;
+
+pub use activity::{ActivityPage, AuditEntryList, Pagination};
+pub use apps::AppsPage;
+pub use data::DataPage;
+pub use profile::ProfilePage;
+pub use security::{ErrorMessage, SecurityPage, TrustedPasskeyBanner};
+pub use sessions::SessionsPage;
+pub use tokens::TokensPage;
+
+/// Layout shared by the account settings pages, with a tab for each page.
+#[component]
+fn AccountShell(children: Children) -> impl IntoView {
+    view! {
+        <Shell>
+            <div class="w-full max-w-screen-md mx-auto my-8">
+                <crate::create::SessionRequiredBanner />
+                <h1 class="text-2xl font-bold mb-4">"Your account"</h1>
+                <ul class="flex gap-2 mb-8 border-b border-stone-600">
+                    <AccountTab href="/account/profile">"Profile"</AccountTab>
+                    <AccountTab href="/account/security">"Security"</AccountTab>
+                    <AccountTab href="/account/sessions">"Sessions"</AccountTab>
+                    <AccountTab href="/account/activity">"Activity"</AccountTab>
+                    <AccountTab href="/account/tokens">"Access tokens"</AccountTab>
+                    <AccountTab href="/account/apps">"Apps"</AccountTab>
+                    <AccountTab href="/account/data">"Your data"</AccountTab>
+                </ul>
+                {children()}
+            </div>
+        </Shell>
+    }
+}
+
+#[component]
+fn AccountTab(href: &'static str, children: Children) -> impl IntoView {
+    let location = leptos_router::hooks::use_location();
+    let is_current = move || location.pathname.get() == href;
+    view! {
+        <li>
+            <a href=href class="block px-3 py-2 -mb-px border-b-2 border-transparent text-stone-400 hover:text-stone-200" class=(["!border-yellow-500", "!text-stone-200"], is_current)>
+                {children()}
+            </a>
+        </li>
+    }
+}
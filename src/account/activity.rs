@@ -0,0 +1,124 @@
+use crate::prelude::*;
+
+use time::OffsetDateTime;
+
+use super::ErrorMessage;
+use crate::auth::audit::{AuditEntry, next_page};
+use crate::pagination::Cursor;
+
+/// What has happened to the signed-in user's account, newest first.
+#[server]
+async fn list_activity(before: Option<Cursor>) -> Result<Vec<AuditEntry>, ServerFnError> {
+    use entity::audit_events::Column;
+
+    let Some(user_id) = session().await.account_uuid() else {
+        return Err(ServerFnError::ServerError("Must be signed in".to_string()));
+    };
+
+    let entries = crate::auth::audit::entries(&db(), sea_orm::Condition::all().add(Column::UserId.eq(user_id)), before)
+        .await?
+        .into_iter()
+        .map(|mut entry| {
+            // Don't reveal who the admins are or where they work from
+            if !entry.by_user {
+                entry.actor = entry.actor.map(|_| "an administrator".to_string());
+                entry.ip = None;
+                entry.device = None;
+            }
+            entry
+        })
+        .collect();
+    Ok(entries)
+}
+
+#[component]
+pub fn ActivityPage() -> impl IntoView {
+    let before = RwSignal::new(None::<Cursor>);
+    let entries = Resource::new(move || before.get(), list_activity);
+
+    view! {
+        <super::AccountShell>
+            <p class="text-stone-400 mb-4">
+                "Sign-ins and changes to your account's security. If you don't recognise something, sign out everywhere and check your passkeys."
+            </p>
+            <Transition fallback=|| {}>
+                {move || entries.get().map(|result| match result {
+                    Ok(entries) => view! {
+                        <AuditEntryList entries=entries.clone() admin=false />
+                        <Pagination oldest=next_page(&entries) before=before />
+                    }.into_any(),
+                    Err(error) => view! { <ErrorMessage message=Some(error.to_string()) /> }.into_any(),
+                })}
+            </Transition>
+        </super::AccountShell>
+    }
+}
+
+/// Entries from the audit log. Admins see who did what to whom; users only see what happened to their own account.
+#[component]
+pub fn AuditEntryList(entries: Vec<AuditEntry>, admin: bool) -> impl IntoView {
+    if entries.is_empty() {
+        return view! { <p class="text-stone-400">"Nothing to show."</p> }.into_any();
+    }
+
+    view! {
+        <ul class="flex flex-col gap-2 mb-4">
+            {entries.into_iter().map(|entry| {
+                let by = match (&entry.actor, entry.by_user) {
+                    (_, true) if !admin => None,
+                    (Some(actor), _) => Some(format!("By {actor}")),
+                    (None, _) => Some("By Star Haven".to_string()),
+                };
+                let meta = [Some(format_timestamp(entry.created_at)), entry.device.clone(), entry.ip.clone()]
+                    .into_iter()
+                    .flatten()
+                    .collect::<Vec<_>>()
+                    .join(" · ");
+                view! {
+                    <li class="bg-stone-800 p-4 rounded">
+                        <p class="text-stone-200 font-semibold">
+                            {entry.description()}
+                            {by.map(|by| view! { <span class="ml-2 text-xs font-normal text-stone-400">{by}</span> })}
+                        </p>
+                        <p class="text-stone-400 text-xs">{meta}</p>
+                        {admin.then(|| view! {
+                            <p class="text-stone-400 text-xs font-mono mt-1">
+                                {entry.event_name.clone()}
+                                {entry.user.clone().map(|user| format!(" · account {user}"))}
+                                {entry.target.clone().map(|target| format!(" · {target}"))}
+                            </p>
+                            {entry.details.clone().map(|details| view! {
+                                <p class="text-stone-500 text-xs font-mono break-all">{details}</p>
+                            })}
+                        })}
+                    </li>
+                }
+            }).collect_view()}
+        </ul>
+    }.into_any()
+}
+
+/// Buttons to page through a list that's newest first. `oldest` is the last item shown, if there may be older ones.
+#[component]
+pub fn Pagination(oldest: Option<Cursor>, before: RwSignal<Option<Cursor>>) -> impl IntoView {
+    view! {
+        <div class="flex gap-4">
+            <Show when=move || before.get().is_some()>
+                <button class="text-stone-400 hover:text-stone-200 font-semibold" on:click=move |_| before.set(None)>
+                    "Newest"
+                </button>
+            </Show>
+            {oldest.map(|oldest| view! {
+                <button class="text-stone-400 hover:text-stone-200 font-semibold" on:click=move |_| before.set(Some(oldest))>
+                    "Older"
+                </button>
+            })}
+        </div>
+    }
+}
+
+/// Timestamps are shown in UTC, down to the second, so that they can be matched up with server logs.
+fn format_timestamp(timestamp: OffsetDateTime) -> String {
+    let format = time::macros::format_description!("[year]-[month]-[day] [hour]:[minute]:[second] UTC");
+    timestamp.to_offset(time::UtcOffset::UTC).format(&format).unwrap_or_default()
+}
@@ -0,0 +1,394 @@
+use crate::prelude::*;
+
+use phosphor_leptos::{Icon, IconWeight, PLUGS_CONNECTED, PLUS};
+use time::OffsetDateTime;
+
+use super::ErrorMessage;
+use crate::auth::Scope;
+use crate::browse::LocaleDate;
+
+/// An app the user has let act on their behalf.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuthorizedApp {
+    client_id: Uuid,
+    name: String,
+    scopes: Vec<Scope>,
+    /// When the app last refreshed its tokens, which it does whenever it's used
+    last_used_at: OffsetDateTime,
+}
+
+/// An OAuth client the user registered.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RegisteredApp {
+    client_id: Uuid,
+    name: String,
+    redirect_uris: Vec<String>,
+    /// Whether the app has a client secret, i.e. it runs on a server rather than on users' devices
+    confidential: bool,
+    created_at: OffsetDateTime,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AppList {
+    authorized: Vec<AuthorizedApp>,
+    registered: Vec<RegisteredApp>,
+}
+
+/// Credentials for a newly registered app. The secret is only stored as a hash, so this is the only time it is shown.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NewApp {
+    client_id: Uuid,
+    client_secret: Option<String>,
+}
+
+const MAX_APP_NAME_LENGTH: usize = 64;
+
+#[server]
+async fn list_apps() -> Result<AppList, ServerFnError> {
+    use std::collections::BTreeMap;
+    use entity::{oauth_clients, oauth_refresh_tokens};
+    use crate::auth::access_token::parse_scopes;
+
+    let Some(user_id) = session().await.account_uuid() else {
+        return Err(ServerFnError::ServerError("Must be signed in".to_string()));
+    };
+
+    // An app is authorized for as long as it holds a live refresh token
+    let mut authorized = BTreeMap::<Uuid, AuthorizedApp>::new();
+    let tokens = OauthRefreshTokens::find()
+        .filter(oauth_refresh_tokens::Column::UserId.eq(user_id))
+        .filter(oauth_refresh_tokens::Column::RevokedAt.is_null())
+        .filter(oauth_refresh_tokens::Column::UsedAt.is_null())
+        .filter(oauth_refresh_tokens::Column::ExpiresAt.gt(OffsetDateTime::now_utc()))
+        .find_also_related(OauthClients)
+        .all(&db())
+        .await?;
+    for (token, client) in tokens {
+        let Some(client) = client else { continue };
+        let app = authorized.entry(client.id).or_insert_with(|| AuthorizedApp {
+            client_id: client.id,
+            name: client.name,
+            scopes: Vec::new(),
+            last_used_at: token.created_at,
+        });
+        app.scopes.extend(parse_scopes(&token.scopes));
+        app.scopes.sort();
+        app.scopes.dedup();
+        app.last_used_at = app.last_used_at.max(token.created_at);
+    }
+
+    let registered = OauthClients::find()
+        .filter(oauth_clients::Column::OwnerId.eq(user_id))
+        .order_by_desc(oauth_clients::Column::CreatedAt)
+        .all(&db())
+        .await?
+        .into_iter()
+        .map(|client| RegisteredApp {
+            client_id: client.id,
+            name: client.name,
+            redirect_uris: serde_json::from_value(client.redirect_uris).unwrap_or_default(),
+            confidential: client.secret_hash.is_some(),
+            created_at: client.created_at,
+        })
+        .collect();
+
+    Ok(AppList { authorized: authorized.into_values().collect(), registered })
+}
+
+/// Registers an OAuth client owned by the signed-in user. `redirect_uris` has one URI per line.
+#[server]
+async fn register_app(name: String, redirect_uris: String, confidential: bool) -> Result<NewApp, ServerFnError> {
+    use sea_orm::Set;
+    use crate::auth::audit::{AuditEvent, Entry};
+    use crate::oauth::grant;
+
+    const MAX_REDIRECT_URIS: usize = 10;
+
+    let Some(user_id) = session().await.account_uuid() else {
+        return Err(ServerFnError::ServerError("Must be signed in".to_string()));
+    };
+
+    let name = name.trim().to_string();
+    if name.is_empty() {
+        return Err(ServerFnError::ServerError("Give the app a name. Users see it when they're asked to allow access.".to_string()));
+    }
+    if name.chars().count() > MAX_APP_NAME_LENGTH {
+        return Err(ServerFnError::ServerError(format!("Names can be at most {MAX_APP_NAME_LENGTH} characters")));
+    }
+    let redirect_uris = redirect_uris
+        .lines()
+        .map(str::trim)
+        .filter(|uri| !uri.is_empty())
+        .map(|uri| grant::validate_redirect_uri(uri).map(|url| url.to_string()))
+        .collect::<Result<Vec<_>, _>>();
+    let redirect_uris = match redirect_uris {
+        Ok(redirect_uris) => redirect_uris,
+        Err(message) => return Err(ServerFnError::ServerError(message)),
+    };
+    if redirect_uris.is_empty() {
+        return Err(ServerFnError::ServerError("Add at least one redirect URI".to_string()));
+    }
+    if redirect_uris.len() > MAX_REDIRECT_URIS {
+        return Err(ServerFnError::ServerError(format!("Apps can have at most {MAX_REDIRECT_URIS} redirect URIs")));
+    }
+
+    let (client_secret, secret_hash) = if confidential {
+        let (secret, hash) = crate::auth::secret::generate(grant::CLIENT_SECRET_PREFIX);
+        (Some(secret), Some(hash))
+    } else {
+        (None, None)
+    };
+    let client = entity::oauth_clients::ActiveModel {
+        id: Set(Uuid::new_v4()),
+        owner_id: Set(user_id),
+        name: Set(name),
+        redirect_uris: Set(serde_json::to_value(&redirect_uris)?),
+        secret_hash: Set(secret_hash),
+        created_at: Set(OffsetDateTime::now_utc()),
+    }.insert(&db()).await?;
+
+    Entry::new(AuditEvent::OauthClientRegistered, Some(user_id))
+        .user(user_id)
+        .details(serde_json::json!({
+            "client_id": client.id,
+            "name": client.name,
+        }))
+        .record(&db())
+        .await?;
+    Ok(NewApp { client_id: client.id, client_secret })
+}
+
+/// Deletes an app the user registered, which also signs it out of every account that authorized it.
+#[server]
+async fn delete_app(client_id: Uuid) -> Result<(), ServerFnError> {
+    use entity::oauth_clients::Column;
+    use crate::auth::audit::{AuditEvent, Entry};
+
+    let Some(user_id) = session().await.account_uuid() else {
+        return Err(ServerFnError::ServerError("Must be signed in".to_string()));
+    };
+
+    let result = OauthClients::delete_many()
+        .filter(Column::Id.eq(client_id))
+        .filter(Column::OwnerId.eq(user_id))
+        .exec(&db())
+        .await?;
+    if result.rows_affected == 0 {
+        return Err(ServerFnError::ServerError("App not found".to_string()));
+    }
+
+    Entry::new(AuditEvent::OauthClientDeleted, Some(user_id))
+        .user(user_id)
+        .details(serde_json::json!({ "client_id": client_id }))
+        .record(&db())
+        .await?;
+    Ok(())
+}
+
+/// Stops an app from acting on the user's behalf. Access tokens it already has keep working until they expire, which
+/// takes at most a few minutes.
+#[server]
+async fn revoke_app(client_id: Uuid) -> Result<(), ServerFnError> {
+    use crate::auth::audit::{AuditEvent, Entry};
+
+    let Some(user_id) = session().await.account_uuid() else {
+        return Err(ServerFnError::ServerError("Must be signed in".to_string()));
+    };
+
+    if crate::oauth::grant::revoke_grant(&db(), user_id, client_id).await? == 0 {
+        return Err(ServerFnError::ServerError("App not found".to_string()));
+    }
+
+    Entry::new(AuditEvent::OauthAppRevoked, Some(user_id))
+        .user(user_id)
+        .details(serde_json::json!({ "client_id": client_id }))
+        .record(&db())
+        .await?;
+    Ok(())
+}
+
+#[component]
+pub fn AppsPage() -> impl IntoView {
+    let apps = Resource::new(|| (), |_| list_apps());
+
+    let name = RwSignal::new(String::new());
+    let redirect_uris = RwSignal::new(String::new());
+    let confidential = RwSignal::new(true);
+    let new_app = RwSignal::new(None::<NewApp>);
+
+    let register = Action::new(move |_: &()| async move {
+        let result = register_app(name.get_untracked(), redirect_uris.get_untracked(), confidential.get_untracked()).await;
+        if let Ok(app) = &result {
+            new_app.set(Some(app.clone()));
+            name.set(String::new());
+            redirect_uris.set(String::new());
+            apps.refetch();
+        }
+        result.map(|_| ())
+    });
+
+    let delete = Action::new(move |client_id: &Uuid| {
+        let client_id = *client_id;
+        async move {
+            let result = delete_app(client_id).await;
+            apps.refetch();
+            result
+        }
+    });
+
+    let revoke = Action::new(move |client_id: &Uuid| {
+        let client_id = *client_id;
+        async move {
+            let result = revoke_app(client_id).await;
+            apps.refetch();
+            result
+        }
+    });
+
+    let error = Signal::derive(move || {
+        register.value().get().and_then(Result::err)
+            .or_else(|| delete.value().get().and_then(Result::err))
+            .or_else(|| revoke.value().get().and_then(Result::err))
+            .map(|error| match error {
+                ServerFnError::ServerError(message) => message,
+                _ => "Something went wrong, please try again".to_string(),
+            })
+    });
+
+    view! {
+        <super::AccountShell>
+            <Transition fallback=|| {}>
+                {move || apps.get().map(|result| match result {
+                    Ok(AppList { authorized, registered }) => view! {
+                        <h2 class="text-lg font-semibold mb-2">"Authorized apps"</h2>
+                        <p class="text-stone-400 mb-4">"Apps you've allowed to use your account."</p>
+                        <ul class="flex flex-col gap-2 mb-8">
+                            {authorized.is_empty().then(|| view! { <li class="text-stone-400">"You haven't authorized any apps."</li> })}
+                            {authorized.into_iter().map(|app| view! {
+                                <li class="bg-stone-800 p-4 rounded flex items-center gap-4">
+                                    <Icon icon=PLUGS_CONNECTED weight=IconWeight::Regular size="24px" />
+                                    <div class="grow">
+                                        <p class="text-stone-200 font-semibold">{app.name}</p>
+                                        <p class="text-stone-400 text-xs font-mono">
+                                            {app.scopes.iter().map(|scope| scope.as_str()).collect::<Vec<_>>().join(", ")}
+                                        </p>
+                                        <p class="text-stone-400 text-xs">
+                                            "Last used " <LocaleDate date=Signal::derive(move || app.last_used_at) />
+                                        </p>
+                                    </div>
+                                    <button
+                                        class="text-stone-400 hover:text-stone-200 font-semibold"
+                                        on:click=move |_| {
+                                            if window().confirm_with_message("Remove this app's access to your account?").unwrap_or(false) {
+                                                revoke.dispatch(app.client_id);
+                                            }
+                                        }
+                                    >
+                                        "Revoke"
+                                    </button>
+                                </li>
+                            }).collect_view()}
+                        </ul>
+
+                        <h2 class="text-lg font-semibold mb-2">"Your apps"</h2>
+                        <p class="text-stone-400 mb-4">
+                            "Register an app to let other Star Haven users sign in to it with OAuth and give it access to their account."
+                        </p>
+
+                        <Show when=move || new_app.read().is_some()>
+                            <div class="bg-green-900 border border-green-700 p-4 rounded mb-4 flex flex-col gap-2">
+                                <p class="font-semibold">"App registered."</p>
+                                <label class="block">
+                                    <span class="text-sm">"Client ID"</span>
+                                    <input
+                                        type="text"
+                                        readonly
+                                        prop:value=move || new_app.get().map(|app| app.client_id.to_string()).unwrap_or_default()
+                                        onfocus="this.select()"
+                                        class="w-full font-mono bg-stone-900 text-stone-200 p-2 rounded"
+                                    />
+                                </label>
+                                <Show when=move || new_app.read().as_ref().is_some_and(|app| app.client_secret.is_some())>
+                                    <label class="block">
+                                        <span class="text-sm">"Client secret. Copy it now. You won't be able to see it again."</span>
+                                        <input
+                                            type="text"
+                                            readonly
+                                            prop:value=move || new_app.get().and_then(|app| app.client_secret).unwrap_or_default()
+                                            onfocus="this.select()"
+                                            class="w-full font-mono bg-stone-900 text-stone-200 p-2 rounded"
+                                        />
+                                    </label>
+                                </Show>
+                            </div>
+                        </Show>
+
+                        <ul class="flex flex-col gap-2 mb-8">
+                            {registered.into_iter().map(|app| view! {
+                                <li class="bg-stone-800 p-4 rounded flex items-center gap-4">
+                                    <div class="grow">
+                                        <p class="text-stone-200 font-semibold">{app.name}</p>
+                                        <p class="text-stone-400 text-xs font-mono">{app.client_id.to_string()}</p>
+                                        <p class="text-stone-400 text-xs font-mono">{app.redirect_uris.join(" ")}</p>
+                                        <p class="text-stone-400 text-xs">
+                                            {if app.confidential { "Confidential" } else { "Public" }}
+                                            " · Registered " <LocaleDate date=Signal::derive(move || app.created_at) />
+                                        </p>
+                                    </div>
+                                    <button
+                                        class="text-stone-400 hover:text-stone-200 font-semibold"
+                                        on:click=move |_| {
+                                            if window().confirm_with_message("Delete this app? Everyone signed in to it will be signed out.").unwrap_or(false) {
+                                                delete.dispatch(app.client_id);
+                                            }
+                                        }
+                                    >
+                                        "Delete"
+                                    </button>
+                                </li>
+                            }).collect_view()}
+                        </ul>
+
+                        <h2 class="text-lg font-semibold mb-2">"Register an app"</h2>
+                        <div class="bg-stone-800 p-4 rounded flex flex-col gap-4">
+                            <label class="block">
+                                <span class="font-semibold">"Name"</span>
+                                <input
+                                    type="text"
+                                    placeholder="e.g. Mod Manager"
+                                    maxlength=MAX_APP_NAME_LENGTH
+                                    bind:value=name
+                                    class="block w-full mt-1 p-2 border-2 border-stone-500 text-stone-200 bg-stone-700 rounded-sm"
+                                />
+                            </label>
+                            <label class="block">
+                                <span class="font-semibold">"Redirect URIs"</span>
+                                <span class="block text-stone-400 text-sm">"One per line. Use HTTPS, or http://127.0.0.1 for apps that run on the user's computer."</span>
+                                <textarea
+                                    rows=3
+                                    placeholder="https://example.com/oauth/callback"
+                                    bind:value=redirect_uris
+                                    class="block w-full mt-1 p-2 border-2 border-stone-500 text-stone-200 bg-stone-700 rounded-sm font-mono"
+                                />
+                            </label>
+                            <label class="flex items-center gap-2">
+                                <input type="checkbox" bind:checked=confidential />
+                                "The app runs on a server and can keep a client secret"
+                            </label>
+                            <button
+                                class="bg-yellow-600 text-white font-semibold select-none shadow-sm py-2 px-3 rounded inline-flex items-center justify-center gap-2 self-start disabled:opacity-50"
+                                disabled=move || name.read().trim().is_empty() || redirect_uris.read().trim().is_empty()
+                                on:click=move |_| { register.dispatch(()); }
+                            >
+                                <Icon icon=PLUS weight=IconWeight::Bold />
+                                "Register app"
+                            </button>
+                        </div>
+                    }.into_any(),
+                    Err(error) => view! { <p>"Error loading apps: " {error.to_string()}</p> }.into_any(),
+                })}
+            </Transition>
+            <ErrorMessage message=error />
+        </super::AccountShell>
+    }
+}
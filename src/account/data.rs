@@ -0,0 +1,428 @@
+use crate::prelude::*;
+
+use phosphor_leptos::{Icon, IconWeight, DOWNLOAD_SIMPLE, TRASH};
+use server_fn::{ServerFn, codec::{ByteStream, GetUrl, Streaming}};
+
+use super::ErrorMessage;
+
+/// A zip of everything the signed-in user has given us: their profile, avatar, passkeys, and the mods they author, with their
+/// releases and media.
+#[server(prefix = "/account", endpoint = "export", input = GetUrl, output = Streaming)]
+async fn export_account_data() -> Result<ByteStream, ServerFnError> {
+    use crate::auth::audit::{AuditEvent, Entry};
+
+    let session = session().await;
+    let (Some(user_id), Some(user)) = (session.account_uuid(), session.user().await?) else {
+        let response = expect_context::<leptos_axum::ResponseOptions>();
+        response.set_status(http::StatusCode::UNAUTHORIZED);
+        return Err(ServerFnError::ServerError("Must be signed in".to_string()));
+    };
+
+    let filename = format!("star-haven-{}.zip", user.username);
+    let files = export::collect(&db(), user).await?;
+    Entry::new(AuditEvent::DataExported, Some(user_id)).user(user_id).record(&db()).await?;
+
+    let response = expect_context::<leptos_axum::ResponseOptions>();
+    response.insert_header(http::header::CONTENT_TYPE, http::HeaderValue::from_static("application/zip"));
+    response.insert_header(
+        http::header::CONTENT_DISPOSITION,
+        http::HeaderValue::from_str(&format!("attachment; filename=\"{filename}\""))?,
+    );
+    response.insert_header(http::header::CACHE_CONTROL, http::HeaderValue::from_static("no-store"));
+    Ok(ByteStream::new(export::zip(files)))
+}
+
+#[cfg(feature = "ssr")]
+mod export {
+    use std::io::{self, BufWriter, Write};
+    use std::path::PathBuf;
+
+    use base64::Engine;
+    use entity::sea_orm_active_enums::ModMediaType;
+    use futures::Stream;
+    use serde_json::json;
+    use time::{OffsetDateTime, format_description::well_known::Rfc3339};
+    use tokio::sync::mpsc;
+    use zip::{CompressionMethod, ZipWriter, write::SimpleFileOptions};
+
+    use crate::prelude::*;
+
+    /// How much of the archive is buffered before it's sent to the client
+    const CHUNK_SIZE: usize = 64 * 1024;
+
+    /// A file to put in the export.
+    pub struct File {
+        name: String,
+        contents: Contents,
+    }
+
+    enum Contents {
+        Json(serde_json::Value),
+        /// Read from disk while the archive is written. These are images, so they are stored without compressing them
+        /// again.
+        Upload(PathBuf),
+    }
+
+    /// Gathers what goes in the export for `user`. Uploaded files are only read once the archive is written, by [`zip`].
+    pub async fn collect(conn: &impl ConnectionTrait, user: User) -> Result<Vec<File>, ServerFnError> {
+        let previous_usernames = UsernameHistory::find()
+            .filter(entity::username_history::Column::UserId.eq(user.id))
+            .order_by_asc(entity::username_history::Column::ChangedAt)
+            .all(conn)
+            .await?;
+        let following = UserFollows::find()
+            .filter(entity::user_follows::Column::FollowerId.eq(user.id))
+            .all(conn)
+            .await?;
+        let following = Users::find()
+            .filter(entity::users::Column::Id.is_in(following.into_iter().map(|follow| follow.followed_id)))
+            .all(conn)
+            .await?;
+        let subscriptions = ModSubscriptions::find()
+            .filter(entity::mod_subscriptions::Column::UserId.eq(user.id))
+            .find_also_related(Mods)
+            .all(conn)
+            .await?;
+        let roles = UserRoles::find()
+            .filter(entity::user_roles::Column::UserId.eq(user.id))
+            .all(conn)
+            .await?;
+        let profile = json!({
+            "id": user.id,
+            "username": user.username,
+            "display_name": user.display_name,
+            "bio": user.bio,
+            "links": user.links,
+            "created_at": timestamp(user.created_at.assume_utc()),
+            "previous_usernames": previous_usernames.into_iter().map(|previous| json!({
+                "username": previous.username,
+                "changed_at": timestamp(previous.changed_at),
+            })).collect::<Vec<_>>(),
+            "roles": roles.into_iter().map(|role| role.role_id).collect::<Vec<_>>(),
+            "following": following.into_iter().map(|followed| followed.username).collect::<Vec<_>>(),
+            "subscriptions": subscriptions.into_iter().filter_map(|(_, subscribed)| subscribed).map(|subscribed| subscribed.slug).collect::<Vec<_>>(),
+        });
+
+        // Metadata only: the public keys are of no use outside Star Haven
+        let passkeys = Passkeys::find()
+            .filter(entity::passkeys::Column::UserId.eq(user.id))
+            .order_by_asc(entity::passkeys::Column::CreatedAt)
+            .all(conn)
+            .await?
+            .into_iter()
+            .map(|passkey| json!({
+                "id": base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(&passkey.id),
+                "name": passkey.name,
+                "created_at": timestamp(passkey.created_at),
+                "last_used_at": passkey.last_used_at.map(timestamp),
+                "flagged_at": passkey.flagged_at.map(timestamp),
+                "flag_reason": passkey.flag_reason,
+            }))
+            .collect::<Vec<_>>();
+
+        let mods = Mods::find()
+            .join(JoinType::InnerJoin, entity::mods::Relation::ModAuthors.def())
+            .filter(entity::mod_authors::Column::UserId.eq(user.id))
+            .all(conn)
+            .await?;
+
+        let mut files = Vec::new();
+        if let Some(avatar_id) = user.avatar_id {
+            use crate::profile::avatar::{SIZES, paths};
+            files.extend(upload("avatar.webp".to_string(), paths(avatar_id, SIZES[SIZES.len() - 1]).0).await);
+        }
+        files.push(File { name: "profile.json".to_string(), contents: Contents::Json(profile) });
+        files.push(File { name: "passkeys.json".to_string(), contents: Contents::Json(passkeys.into()) });
+
+        for mod_data in mods {
+            let game = Games::find_by_id(mod_data.game_id).one(conn).await?;
+            let releases = ModReleases::find()
+                .filter(entity::mod_releases::Column::ModId.eq(mod_data.id))
+                .order_by_asc(entity::mod_releases::Column::CreatedAt)
+                .all(conn)
+                .await?;
+            let media = entity::mod_media::Entity::find()
+                .filter(entity::mod_media::Column::ModId.eq(mod_data.id))
+                .order_by_asc(entity::mod_media::Column::Position)
+                .all(conn)
+                .await?;
+
+            // Slugs are chosen by users, so they don't make safe paths
+            let dir = format!("mods/{}", mod_data.id);
+            let mut media_json = Vec::new();
+            for item in &media {
+                let file = match item.media_type {
+                    ModMediaType::Image => {
+                        upload(format!("{dir}/media/{}.webp", item.id), crate::browse::paths_for_image(item.id).0).await
+                    }
+                    ModMediaType::Youtube => None,
+                };
+                let name = file.as_ref().map(|file| file.name.clone());
+                files.extend(file);
+                media_json.push(json!({
+                    "id": item.id,
+                    "type": item.media_type,
+                    "url": item.url,
+                    "position": item.position,
+                    "file": name,
+                }));
+            }
+
+            let mod_json = json!({
+                "id": mod_data.id,
+                "slug": mod_data.slug,
+                "name": mod_data.name,
+                "description": mod_data.description,
+                "game": game.map(|game| game.name),
+                "published_at": mod_data.published_at.map(timestamp),
+                "releases": releases.into_iter().map(|release| json!({
+                    "id": release.id,
+                    "version": release.version,
+                    "description": release.description,
+                    "download_url": release.download_url,
+                    "created_at": timestamp(release.created_at),
+                })).collect::<Vec<_>>(),
+                "media": media_json,
+            });
+            files.push(File { name: format!("{dir}/mod.json"), contents: Contents::Json(mod_json) });
+        }
+
+        Ok(files)
+    }
+
+    /// An uploaded file to include as `name`, if it's still on disk.
+    async fn upload(name: String, path: PathBuf) -> Option<File> {
+        match tokio::fs::try_exists(&path).await {
+            Ok(true) => Some(File { name, contents: Contents::Upload(path) }),
+            Ok(false) => {
+                log::error!("{} is missing from the export", path.display());
+                None
+            }
+            Err(error) => {
+                log::error!("failed to check for {} for export: {error}", path.display());
+                None
+            }
+        }
+    }
+
+    /// Streams a zip archive of `files`. It's written on a blocking thread, which reads the uploads from disk, and sent on
+    /// in chunks as it goes. The writer stops if the client goes away.
+    pub fn zip(files: Vec<File>) -> impl Stream<Item = Result<Vec<u8>, ServerFnError>> + Send + 'static {
+        let (sender, receiver) = mpsc::channel(4);
+        tokio::task::spawn_blocking(move || {
+            if let Err(error) = write(files, BufWriter::with_capacity(CHUNK_SIZE, Chunks(sender.clone()))) {
+                log::error!("failed to write account export: {error}");
+                let _ = sender.blocking_send(Err(ServerFnError::new("Failed to write the export")));
+            }
+        });
+        futures::stream::unfold(receiver, |mut receiver| async move {
+            receiver.recv().await.map(|chunk| (chunk, receiver))
+        })
+    }
+
+    fn write(files: Vec<File>, writer: impl Write) -> zip::result::ZipResult<()> {
+        let mut zip = ZipWriter::new_stream(writer);
+        let now = OffsetDateTime::now_utc();
+        let modified = zip::DateTime::from_date_and_time(
+            now.year() as u16, now.month().into(), now.day(), now.hour(), now.minute(), now.second(),
+        ).unwrap_or_default();
+        let options = SimpleFileOptions::default()
+            .compression_method(CompressionMethod::Deflated)
+            .last_modified_time(modified);
+
+        for file in files {
+            match file.contents {
+                Contents::Json(value) => {
+                    zip.start_file(file.name, options)?;
+                    zip.write_all(&serde_json::to_vec_pretty(&value).map_err(io::Error::from)?)?;
+                }
+                Contents::Upload(path) => match std::fs::read(&path) {
+                    Ok(contents) => {
+                        zip.start_file(file.name, options.compression_method(CompressionMethod::Stored))?;
+                        zip.write_all(&contents)?;
+                    }
+                    Err(error) => log::error!("failed to read {} for export: {error}", path.display()),
+                },
+            }
+        }
+        zip.finish()?.flush()?;
+        Ok(())
+    }
+
+    /// Sends what's written to it down a channel, failing once the receiving end is dropped.
+    struct Chunks(mpsc::Sender<Result<Vec<u8>, ServerFnError>>);
+
+    impl Write for Chunks {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.blocking_send(Ok(buf.to_vec())).map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn timestamp(timestamp: OffsetDateTime) -> String {
+        timestamp.format(&Rfc3339).unwrap_or_default()
+    }
+}
+
+/// Deletes the signed-in user's account and passkeys. Mods they author with others stay with the other authors, and
+/// mods they author alone are archived rather than deleted, so that admins can restore them.
+#[server]
+async fn delete_account(username: String) -> Result<(), ServerFnError> {
+    use crate::auth::cookie::set_cookie;
+    use crate::auth::refresh::{ACCESS_COOKIE, REFRESH_COOKIE};
+
+    let session = session().await;
+    let (Some(user_id), Some(user)) = (session.account_uuid(), session.user().await?) else {
+        return Err(ServerFnError::ServerError("Must be signed in".to_string()));
+    };
+    if username.trim() != user.username {
+        return Err(ServerFnError::ServerError("That isn't your username".to_string()));
+    }
+
+    let avatar_id = user.avatar_id;
+    let (archived, transferred) = db().transaction::<_, _, DbErr>(|txn| {
+        Box::pin(async move { delete_user(txn, user).await })
+    }).await.map_err(|error| -> ServerFnError { match error {
+        sea_orm::TransactionError::Connection(error) => error.into(),
+        sea_orm::TransactionError::Transaction(error) => error.into(),
+    }})?;
+
+    if let Some(avatar_id) = avatar_id {
+        crate::profile::avatar::remove_files(avatar_id);
+    }
+
+    log::info!("user {user_id} deleted their account, archiving {archived} mods and leaving {transferred} to co-authors");
+    set_cookie(ACCESS_COOKIE, "", 0);
+    set_cookie(REFRESH_COOKIE, "", 0);
+    Ok(())
+}
+
+/// Deletes `user`, returning how many of their mods were archived and how many were left to their co-authors. Everything
+/// else of theirs goes with them through foreign keys, apart from the audit log.
+#[cfg(feature = "ssr")]
+async fn delete_user(txn: &impl ConnectionTrait, user: User) -> Result<(usize, usize), DbErr> {
+    use entity::mod_authors::Column;
+    use sea_orm::PaginatorTrait;
+    use time::OffsetDateTime;
+    use crate::auth::audit::{AuditEvent, Entry, Target};
+
+    let now = OffsetDateTime::now_utc();
+    let mod_ids: Vec<Uuid> = ModAuthors::find()
+        .filter(Column::UserId.eq(user.id))
+        .all(txn)
+        .await?
+        .into_iter()
+        .map(|author| author.mod_id)
+        .collect();
+
+    let mut archived = Vec::new();
+    let mut transferred = Vec::new();
+    for mod_id in mod_ids {
+        let co_authors = ModAuthors::find()
+            .filter(Column::ModId.eq(mod_id))
+            .filter(Column::UserId.ne(user.id))
+            .count(txn)
+            .await?;
+        if co_authors > 0 {
+            transferred.push(mod_id);
+        } else {
+            Mods::update_many()
+                .col_expr(entity::mods::Column::ArchivedAt, Expr::value(now))
+                .filter(entity::mods::Column::Id.eq(mod_id))
+                .exec(txn)
+                .await?;
+            Entry::new(AuditEvent::ModArchived, Some(user.id)).target(Target::Mod(mod_id)).record(txn).await?;
+            archived.push(mod_id);
+        }
+    }
+    ModAuthors::delete_many().filter(Column::UserId.eq(user.id)).exec(txn).await?;
+
+    Entry::new(AuditEvent::AccountDeleted, Some(user.id))
+        .user(user.id)
+        .details(serde_json::json!({
+            "username": user.username,
+            "archived_mods": archived,
+            "transferred_mods": transferred,
+        }))
+        .record(txn)
+        .await?;
+    Users::delete_by_id(user.id).exec(txn).await?;
+    Ok((archived.len(), transferred.len()))
+}
+
+#[component]
+pub fn DataPage() -> impl IntoView {
+    let confirmation = RwSignal::new(String::new());
+    let delete = Action::new(move |username: &String| {
+        let username = username.clone();
+        async move {
+            let result = delete_account(username).await;
+            if result.is_ok() {
+                window().location().set_href("/").expect("failed to redirect to home page");
+            }
+            result
+        }
+    });
+    let error = Signal::derive(move || {
+        delete.value().get().and_then(Result::err).map(|error| match error {
+            ServerFnError::ServerError(message) => message,
+            _ => "Something went wrong, please try again".to_string(),
+        })
+    });
+
+    view! {
+        <super::AccountShell>
+            <h2 class="text-lg font-semibold mb-2">"Download your data"</h2>
+            <p class="text-stone-400 mb-4">
+                "Get a zip of your profile, your passkeys, and the mods you author, with their releases and media."
+            </p>
+            <a
+                href=ExportAccountData::PATH
+                download
+                class="bg-yellow-600 text-white font-semibold select-none shadow-sm py-2 px-3 rounded inline-flex items-center justify-center gap-2"
+            >
+                <Icon icon=DOWNLOAD_SIMPLE weight=IconWeight::Bold />
+                "Download"
+            </a>
+
+            <h2 class="text-lg font-semibold mt-8 mb-2">"Delete your account"</h2>
+            <div class="bg-stone-800 p-4 rounded flex flex-col gap-4">
+                <p class="text-stone-400">
+                    "This deletes your account, passkeys, sessions, access tokens and the apps you registered, and can't be undone. "
+                    "Mods you author with others stay with them. Mods only you author are archived and hidden. "
+                    "A record that you deleted your account is kept in the audit log."
+                </p>
+                <form
+                    class="flex gap-2"
+                    on:submit=move |event| {
+                        event.prevent_default();
+                        if window().confirm_with_message("Delete your account? This can't be undone.").unwrap_or(false) {
+                            delete.dispatch(confirmation.get_untracked());
+                        }
+                    }
+                >
+                    <input
+                        type="text"
+                        autocomplete="off"
+                        placeholder="Type your username to confirm"
+                        bind:value=confirmation
+                        class="grow p-2 border-2 border-stone-500 text-stone-200 bg-stone-700 rounded-sm"
+                    />
+                    <button
+                        type="submit"
+                        class="bg-red-700 text-white font-semibold select-none shadow-sm py-2 px-3 rounded inline-flex items-center justify-center gap-2 disabled:opacity-50"
+                        disabled=move || confirmation.read().trim().is_empty() || delete.pending().get()
+                    >
+                        <Icon icon=TRASH weight=IconWeight::Bold />
+                        "Delete account"
+                    </button>
+                </form>
+            </div>
+            <ErrorMessage message=error />
+        </super::AccountShell>
+    }
+}
@@ -0,0 +1,492 @@
+use crate::prelude::*;
+
+use leptos::web_sys::{FormData, HtmlFormElement};
+use phosphor_leptos::{Icon, IconWeight, PENCIL_SIMPLE, UPLOAD_SIMPLE};
+use server_fn::codec::{MultipartData, MultipartFormData};
+use time::OffsetDateTime;
+
+use super::ErrorMessage;
+use crate::browse::LocaleDate;
+use crate::profile::avatar::{Avatar, AvatarRef};
+
+/// A name the user had before.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PreviousUsername {
+    username: String,
+    changed_at: OffsetDateTime,
+    /// Nobody else can take the name until then
+    reserved_until: OffsetDateTime,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UsernameSettings {
+    username: String,
+    display_name: Option<String>,
+    avatar: AvatarRef,
+    bio: Option<String>,
+    links: Vec<String>,
+    /// When the user can next change their username, if they changed it recently
+    next_change_at: Option<OffsetDateTime>,
+    previous: Vec<PreviousUsername>,
+    cooldown_days: i64,
+    reservation_days: i64,
+}
+
+#[server]
+async fn get_username_settings() -> Result<UsernameSettings, ServerFnError> {
+    use crate::auth::username::{self, CHANGE_COOLDOWN, RESERVATION_PERIOD};
+
+    let session = session().await;
+    let (Some(user_id), Some(user)) = (session.account_uuid(), session.user().await?) else {
+        return Err(ServerFnError::ServerError("Must be signed in".to_string()));
+    };
+
+    let previous = UsernameHistory::find()
+        .filter(entity::username_history::Column::UserId.eq(user_id))
+        .order_by_desc(entity::username_history::Column::ChangedAt)
+        .all(&db())
+        .await?
+        .into_iter()
+        .map(|change| PreviousUsername {
+            username: change.username,
+            changed_at: change.changed_at,
+            reserved_until: change.reserved_until,
+        })
+        .collect();
+
+    Ok(UsernameSettings {
+        avatar: AvatarRef::from(&user),
+        username: user.username,
+        display_name: user.display_name,
+        bio: user.bio,
+        links: serde_json::from_value(user.links).unwrap_or_default(),
+        next_change_at: username::next_change_at(&db(), user_id).await?,
+        previous,
+        cooldown_days: CHANGE_COOLDOWN.whole_days(),
+        reservation_days: RESERVATION_PERIOD.whole_days(),
+    })
+}
+
+#[server]
+async fn change_username(username: String) -> Result<(), ServerFnError> {
+    use crate::auth::username::{self, RenameError};
+
+    let session = session().await;
+    let (Some(_), Some(user)) = (session.account_uuid(), session.user().await?) else {
+        return Err(ServerFnError::ServerError("Must be signed in".to_string()));
+    };
+
+    let old_username = user.username.clone();
+    let user = db().transaction::<_, User, RenameError>(|txn| {
+        Box::pin(async move { username::rename(txn, user, &username).await })
+    }).await.map_err(|error| -> ServerFnError { match error {
+        sea_orm::TransactionError::Connection(error) => error.into(),
+        sea_orm::TransactionError::Transaction(RenameError::Db(error)) => error.into(),
+        sea_orm::TransactionError::Transaction(error) => ServerFnError::ServerError(error.to_string()),
+    }})?;
+
+    log::info!("user {} renamed from {old_username} to {}", user.id, user.username);
+    Ok(())
+}
+
+#[server]
+async fn set_display_name(display_name: String) -> Result<(), ServerFnError> {
+    use crate::auth::display_name::{self, SetDisplayNameError};
+
+    let session = session().await;
+    let (Some(user_id), Some(user)) = (session.account_uuid(), session.user().await?) else {
+        return Err(ServerFnError::ServerError("Must be signed in".to_string()));
+    };
+
+    display_name::set(&db(), user, &display_name, user_id, None).await.map_err(|error| -> ServerFnError { match error {
+        SetDisplayNameError::Invalid(error) => ServerFnError::ServerError(error.to_string()),
+        SetDisplayNameError::Db(error) => error.into(),
+    }})?;
+    Ok(())
+}
+
+#[server(input = MultipartFormData)]
+async fn upload_avatar(multipart: MultipartData) -> Result<(), ServerFnError> {
+    use crate::profile::avatar;
+
+    let session = session().await;
+    let (Some(user_id), Some(user)) = (session.account_uuid(), session.user().await?) else {
+        return Err(ServerFnError::ServerError("Must be signed in".to_string()));
+    };
+
+    let mut multipart = multipart.into_inner().unwrap();
+    let mut image = None;
+    while let Some(field) = multipart.next_field().await? {
+        if field.name() == Some("avatar") {
+            image = Some(field.bytes().await?);
+        }
+    }
+    let Some(image) = image else {
+        let response = expect_context::<leptos_axum::ResponseOptions>();
+        response.set_status(http::status::StatusCode::BAD_REQUEST);
+        return Err(ServerFnError::ServerError("Missing avatar".to_string()));
+    };
+    let image = crate::browse::decode_image(image)
+        .map_err(|_| -> ServerFnError { ServerFnError::ServerError("Couldn't read this image".to_string()) })?;
+
+    avatar::set(&db(), user, Some(&image), user_id, None).await?;
+    Ok(())
+}
+
+#[server]
+async fn remove_avatar() -> Result<(), ServerFnError> {
+    let session = session().await;
+    let (Some(user_id), Some(user)) = (session.account_uuid(), session.user().await?) else {
+        return Err(ServerFnError::ServerError("Must be signed in".to_string()));
+    };
+
+    crate::profile::avatar::set(&db(), user, None, user_id, None).await?;
+    Ok(())
+}
+
+/// Lets the user upload an avatar, or go back to their identicon.
+#[component]
+fn AvatarForm(avatar: AvatarRef) -> impl IntoView {
+    let file_input = NodeRef::<leptos::html::Input>::new();
+
+    let upload = Action::new_local(move |data: &FormData| {
+        let data = data.clone();
+        async move {
+            let result = upload_avatar(data.into()).await;
+            if result.is_ok() {
+                // The navigation bar shows the avatar too
+                window().location().reload().expect("failed to reload after changing avatar");
+            }
+            result
+        }
+    });
+    let remove = Action::new(move |_: &()| async move {
+        let result = remove_avatar().await;
+        if result.is_ok() {
+            window().location().reload().expect("failed to reload after changing avatar");
+        }
+        result
+    });
+    let pending = Signal::derive(move || upload.pending().get() || remove.pending().get());
+
+    let error = Signal::derive(move || {
+        upload.value().get().and_then(Result::err).or_else(|| remove.value().get().and_then(Result::err)).map(|error| match error {
+            ServerFnError::ServerError(message) => message,
+            _ => "Something went wrong, please try again".to_string(),
+        })
+    });
+
+    view! {
+        <h2 class="text-lg font-semibold mb-2">"Avatar"</h2>
+        <p class="text-stone-400 mb-4">
+            "Cropped to a square from the middle. Without one, you're shown a pattern made from your account."
+        </p>
+        <ErrorMessage message=error />
+        <form class="bg-stone-800 p-4 rounded flex items-center gap-4 mb-8" enctype="multipart/form-data" on:submit=move |ev| ev.prevent_default()>
+            <Avatar avatar=avatar size=64 />
+            <input
+                class="hidden"
+                type="file"
+                name="avatar"
+                accept=".avif,.bmp,.dds,.exr,.ff,.gif,.hdr,.ico,.jpeg,.jpg,.png,.pnm,.qoi,.tga,.tiff,.tif,.webp"
+                node_ref=file_input
+                on:change=move |_| {
+                    use leptos::wasm_bindgen::JsCast;
+
+                    let file_input = file_input.get().unwrap();
+                    let form = file_input.form().unwrap().unchecked_into::<HtmlFormElement>();
+                    let form_data = FormData::new_with_form(&form).unwrap();
+                    upload.dispatch_local(form_data);
+                }
+            />
+            <button
+                type="button"
+                class="bg-yellow-600 text-white font-semibold select-none shadow-sm py-2 px-3 rounded inline-flex items-center justify-center gap-2 disabled:opacity-50"
+                disabled=pending
+                on:click=move |_| file_input.get().unwrap().click()
+            >
+                <Icon icon=UPLOAD_SIMPLE weight=IconWeight::Bold />
+                "Upload"
+            </button>
+            {avatar.avatar_id.is_some().then(|| view! {
+                <button
+                    type="button"
+                    class="bg-stone-600 text-white font-semibold select-none shadow-sm py-2 px-3 rounded"
+                    disabled=pending
+                    on:click=move |_| { remove.dispatch(()); }
+                >
+                    "Remove"
+                </button>
+            })}
+        </form>
+    }
+}
+
+#[server]
+async fn set_about(bio: String, links: String) -> Result<(), ServerFnError> {
+    use sea_orm::Set;
+    use crate::profile::{check_validity, normalize_bio, parse_links};
+
+    let session = session().await;
+    let (Some(_), Some(user)) = (session.account_uuid(), session.user().await?) else {
+        return Err(ServerFnError::ServerError("Must be signed in".to_string()));
+    };
+
+    let bio = normalize_bio(&bio);
+    let links = parse_links(&links);
+    check_validity(&bio, &links).map_err(|error| -> ServerFnError { ServerFnError::ServerError(error.to_string()) })?;
+
+    let mut record: entity::users::ActiveModel = user.into();
+    record.bio = Set(Some(bio).filter(|bio| !bio.is_empty()));
+    record.links = Set(serde_json::to_value(links)?);
+    record.update(&db()).await?;
+    Ok(())
+}
+
+/// Lets the user write a bio and list links for their public profile.
+#[component]
+fn AboutForm(bio: Option<String>, links: Vec<String>, username: String) -> impl IntoView {
+    use crate::profile::{MAX_BIO_LENGTH, MAX_LINKS, check_validity, normalize_bio, parse_links};
+
+    let new_bio = RwSignal::new(bio.clone().unwrap_or_default());
+    let new_links = RwSignal::new(links.join("\n"));
+    let validation_error = Signal::derive(move || {
+        check_validity(&normalize_bio(&new_bio.read()), &parse_links(&new_links.read())).err().map(|error| error.to_string())
+    });
+
+    let save = Action::new(move |(bio, links): &(String, String)| set_about(bio.clone(), links.clone()));
+
+    let error = Signal::derive(move || {
+        validation_error.get().or_else(|| {
+            save.value().get().and_then(Result::err).map(|error| match error {
+                ServerFnError::ServerError(message) => message,
+                _ => "Something went wrong, please try again".to_string(),
+            })
+        })
+    });
+
+    view! {
+        <h2 class="text-lg font-semibold mb-2">"About you"</h2>
+        <p class="text-stone-400 mb-4">
+            "Shown on " <a href=format!("/user/{username}") class="text-yellow-400 underline">"your public profile"</a>
+            {format!(", along with when you joined and your published mods. Up to {MAX_BIO_LENGTH} characters, and {MAX_LINKS} links.")}
+        </p>
+        <ErrorMessage message=error />
+        <form
+            class="bg-stone-800 p-4 rounded flex flex-col gap-2 mb-8"
+            on:submit=move |event| {
+                event.prevent_default();
+                save.dispatch((new_bio.get_untracked(), new_links.get_untracked()));
+            }
+        >
+            <textarea
+                placeholder="Bio"
+                rows=4
+                prop:value=move || new_bio.get()
+                on:input:target=move |ev| new_bio.set(ev.target().value())
+                class="p-2 border-2 border-stone-500 text-stone-200 bg-stone-700 rounded-sm"
+            />
+            <textarea
+                placeholder="Links, one per line"
+                rows=3
+                prop:value=move || new_links.get()
+                on:input:target=move |ev| new_links.set(ev.target().value())
+                class="p-2 border-2 border-stone-500 text-stone-200 bg-stone-700 rounded-sm"
+            />
+            <button
+                type="submit"
+                class="self-end bg-yellow-600 text-white font-semibold select-none shadow-sm py-2 px-3 rounded inline-flex items-center justify-center gap-2 disabled:opacity-50"
+                disabled=move || validation_error.read().is_some() || save.pending().get()
+            >
+                <Icon icon=PENCIL_SIMPLE weight=IconWeight::Bold />
+                {move || if matches!(save.value().get(), Some(Ok(()))) { "Saved" } else { "Save" }}
+            </button>
+        </form>
+    }
+}
+
+/// Lets the user pick a display name, or go back to being shown by their username.
+#[component]
+fn DisplayNameForm(display_name: Option<String>, username: String) -> impl IntoView {
+    use crate::auth::display_name;
+
+    let has_display_name = display_name.is_some();
+    let new_display_name = RwSignal::new(display_name.clone().unwrap_or_default());
+    let validation_error = Signal::derive(move || {
+        display_name::check_validity(&display_name::normalize(&new_display_name.read())).err().map(|error| error.to_string())
+    });
+    let unchanged = move || display_name::normalize(&new_display_name.read()) == display_name.clone().unwrap_or_default();
+
+    let save = Action::new(move |display_name: &String| {
+        let display_name = display_name.clone();
+        async move {
+            let result = set_display_name(display_name.clone()).await;
+            if result.is_ok() {
+                new_display_name.set(display_name::normalize(&display_name));
+                // The navigation bar shows the display name too
+                window().location().reload().expect("failed to reload after changing display name");
+            }
+            result
+        }
+    });
+
+    let error = Signal::derive(move || {
+        validation_error.get().or_else(|| {
+            save.value().get().and_then(Result::err).map(|error| match error {
+                ServerFnError::ServerError(message) => message,
+                _ => "Something went wrong, please try again".to_string(),
+            })
+        })
+    });
+
+    view! {
+        <h2 class="text-lg font-semibold mb-2">"Display name"</h2>
+        <p class="text-stone-400 mb-4">
+            {format!("The name other people see, in any language. It doesn't have to be unique, but it can't look like someone else's username. Without one, you're shown as {username}.")}
+        </p>
+        <ErrorMessage message=error />
+        <form
+            class="bg-stone-800 p-4 rounded flex gap-2 mb-8"
+            on:submit=move |event| {
+                event.prevent_default();
+                save.dispatch(new_display_name.get_untracked());
+            }
+        >
+            <input
+                type="text"
+                autocomplete="nickname"
+                placeholder="Display name"
+                bind:value=new_display_name
+                class="grow p-2 border-2 border-stone-500 text-stone-200 bg-stone-700 rounded-sm"
+            />
+            <button
+                type="submit"
+                class="bg-yellow-600 text-white font-semibold select-none shadow-sm py-2 px-3 rounded inline-flex items-center justify-center gap-2 disabled:opacity-50"
+                disabled=move || unchanged() || validation_error.read().is_some() || save.pending().get()
+            >
+                <Icon icon=PENCIL_SIMPLE weight=IconWeight::Bold />
+                "Save"
+            </button>
+            {has_display_name.then(|| view! {
+                <button
+                    type="button"
+                    class="bg-stone-600 text-white font-semibold select-none shadow-sm py-2 px-3 rounded"
+                    disabled=move || save.pending().get()
+                    on:click=move |_| { save.dispatch(String::new()); }
+                >
+                    "Clear"
+                </button>
+            })}
+        </form>
+    }
+}
+
+#[component]
+pub fn ProfilePage() -> impl IntoView {
+    let settings = Resource::new(|| (), |_| get_username_settings());
+
+    let new_username = RwSignal::new(String::new());
+    let validation_error = Signal::derive(move || {
+        let username = new_username.read();
+        if username.is_empty() {
+            None
+        } else {
+            crate::auth::check_username_validity(&username).err().map(|error| error.to_string())
+        }
+    });
+
+    let rename = Action::new(move |username: &String| {
+        let username = username.clone();
+        async move {
+            let result = change_username(username).await;
+            if result.is_ok() {
+                new_username.set(String::new());
+                settings.refetch();
+            }
+            result
+        }
+    });
+
+    let error = Signal::derive(move || {
+        validation_error.get().or_else(|| {
+            rename.value().get().and_then(Result::err).map(|error| match error {
+                ServerFnError::ServerError(message) => message,
+                _ => "Something went wrong, please try again".to_string(),
+            })
+        })
+    });
+
+    view! {
+        <super::AccountShell>
+            <Transition fallback=|| {}>
+                {move || settings.get().map(|result| match result {
+                    Ok(UsernameSettings { username, display_name, avatar, bio, links, next_change_at, previous, cooldown_days, reservation_days }) => view! {
+                        <AvatarForm avatar />
+                        <DisplayNameForm display_name username=username.clone() />
+                        <AboutForm bio links username=username.clone() />
+                        <h2 class="text-lg font-semibold mb-2">"Username"</h2>
+                        <p class="text-stone-400 mb-4">
+                            {format!("You can change your username once every {cooldown_days} days. Links to your old username will keep working, and nobody else can take it for {reservation_days} days.")}
+                        </p>
+                        <div class="bg-stone-800 p-4 rounded flex flex-col gap-4">
+                            <p>"Your username is " <span class="font-semibold text-stone-200">{username}</span></p>
+                            {match next_change_at {
+                                Some(date) => view! {
+                                    <p class="text-stone-400">
+                                        "You can change it again on " <LocaleDate date=Signal::derive(move || date) /> "."
+                                    </p>
+                                }.into_any(),
+                                None => view! {
+                                    <form
+                                        class="flex gap-2"
+                                        on:submit=move |event| {
+                                            event.prevent_default();
+                                            rename.dispatch(new_username.get_untracked());
+                                        }
+                                    >
+                                        <input
+                                            type="text"
+                                            autocomplete="username"
+                                            maxlength=20
+                                            placeholder="New username"
+                                            bind:value=new_username
+                                            class="grow p-2 border-2 border-stone-500 text-stone-200 bg-stone-700 rounded-sm"
+                                        />
+                                        <button
+                                            type="submit"
+                                            class="bg-yellow-600 text-white font-semibold select-none shadow-sm py-2 px-3 rounded inline-flex items-center justify-center gap-2 disabled:opacity-50"
+                                            disabled=move || new_username.read().is_empty() || validation_error.read().is_some()
+                                        >
+                                            <Icon icon=PENCIL_SIMPLE weight=IconWeight::Bold />
+                                            "Change"
+                                        </button>
+                                    </form>
+                                    <p class="text-stone-400 text-sm">
+                                        "Your passkeys may keep showing your old username. That's fine: they still work."
+                                    </p>
+                                }.into_any(),
+                            }}
+                        </div>
+
+                        {(!previous.is_empty()).then(|| view! {
+                            <h2 class="text-lg font-semibold mt-8 mb-2">"Previous usernames"</h2>
+                            <ul class="flex flex-col gap-2">
+                                {previous.into_iter().map(|previous| view! {
+                                    <li class="bg-stone-800 p-4 rounded">
+                                        <p class="text-stone-200 font-semibold">{previous.username}</p>
+                                        <p class="text-stone-400 text-xs">
+                                            "Changed " <LocaleDate date=Signal::derive(move || previous.changed_at) />
+                                            " · Reserved for you until " <LocaleDate date=Signal::derive(move || previous.reserved_until) />
+                                        </p>
+                                    </li>
+                                }).collect_view()}
+                            </ul>
+                        })}
+                    }.into_any(),
+                    Err(error) => view! { <p>"Error loading profile: " {error.to_string()}</p> }.into_any(),
+                })}
+            </Transition>
+            <ErrorMessage message=error />
+        </super::AccountShell>
+    }
+}
@@ -0,0 +1,454 @@
+use crate::prelude::*;
+
+use phosphor_leptos::{Icon, IconWeight, KEY, PLUS, TRASH, WARNING};
+use time::OffsetDateTime;
+
+use crate::auth::recovery::{RECOVERY_CODE_COUNT, RecoveryCodes, recovery_code_count, regenerate_recovery_codes};
+use crate::browse::LocaleDate;
+
+/// A passkey, as shown to the user that owns it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PasskeyInfo {
+    /// Base64url-encoded credential ID
+    id: String,
+    name: Option<String>,
+    created_at: OffsetDateTime,
+    last_used_at: Option<OffsetDateTime>,
+    /// The authenticator model, if the passkey was attested
+    authenticator: Option<String>,
+    /// Set if the passkey may have been cloned
+    flag: Option<PasskeyFlag>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PasskeyFlag {
+    flagged_at: OffsetDateTime,
+    reason: String,
+    /// Whether the passkey can't be used to sign in until the flag is dismissed
+    blocked: bool,
+}
+
+impl PasskeyFlag {
+    fn description(&self) -> &'static str {
+        match self.reason.as_str() {
+            "counter_regressed" => "It was used with an out-of-date signature counter, which happens when a passkey is copied to another device.",
+            "backup_state_changed" => "Whether it's backed up to the cloud changed unexpectedly.",
+            _ => "It behaved unexpectedly when you signed in.",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PasskeyList {
+    username: String,
+    passkeys: Vec<PasskeyInfo>,
+    /// Whether the user's access requires a passkey from a trusted authenticator and none of theirs is
+    needs_trusted_passkey: bool,
+}
+
+const MAX_PASSKEY_NAME_LENGTH: usize = 64;
+
+#[cfg(feature = "ssr")]
+fn decode_passkey_id(id: &str) -> Result<Vec<u8>, ServerFnError> {
+    use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+    URL_SAFE_NO_PAD.decode(id).map_err(|_| ServerFnError::ServerError("Invalid passkey ID".to_string()))
+}
+
+#[server]
+async fn list_passkeys() -> Result<PasskeyList, ServerFnError> {
+    use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+    use crate::auth::attestation;
+
+    let session = session().await;
+    let Some(user) = session.user().await?.filter(|_| session.account_uuid().is_some()) else {
+        return Err(ServerFnError::ServerError("Must be signed in".to_string()));
+    };
+
+    let passkeys = Passkeys::find()
+        .filter(entity::passkeys::Column::UserId.eq(user.id))
+        .order_by_asc(entity::passkeys::Column::CreatedAt)
+        .all(&db())
+        .await?
+        .into_iter()
+        .map(|passkey| PasskeyInfo {
+            id: URL_SAFE_NO_PAD.encode(&passkey.id),
+            name: passkey.name,
+            created_at: passkey.created_at,
+            last_used_at: passkey.last_used_at,
+            authenticator: attestation::recorded_authenticator(&passkey.data).map(|authenticator| authenticator.model),
+            flag: passkey.flagged_at.zip(passkey.flag_reason).map(|(flagged_at, reason)| PasskeyFlag {
+                flagged_at,
+                blocked: crate::auth::cloning::reason_policy(&reason) == crate::config::CredentialPolicy::Block,
+                reason,
+            }),
+        })
+        .collect();
+    Ok(PasskeyList { needs_trusted_passkey: attestation::needs_passkey(&db(), user.id).await?, username: user.username, passkeys })
+}
+
+/// Whether the signed-in user's access requires a passkey from a trusted authenticator and none of theirs is.
+#[server]
+async fn needs_trusted_passkey() -> Result<bool, ServerFnError> {
+    let session = session().await;
+    let Some(user_id) = session.account_uuid() else {
+        return Ok(false);
+    };
+    Ok(crate::auth::attestation::needs_passkey(&db(), user_id).await?)
+}
+
+#[server]
+async fn rename_passkey(id: String, name: String) -> Result<(), ServerFnError> {
+    use sea_orm::Set;
+    use crate::auth::audit::{AuditEvent, Entry, Target};
+
+    let Some(user_id) = session().await.account_uuid() else {
+        return Err(ServerFnError::ServerError("Must be signed in".to_string()));
+    };
+
+    let name = name.trim();
+    if name.chars().count() > MAX_PASSKEY_NAME_LENGTH {
+        return Err(ServerFnError::ServerError(format!("Names can be at most {MAX_PASSKEY_NAME_LENGTH} characters")));
+    }
+
+    let Some(passkey) = Passkeys::find_by_id(decode_passkey_id(&id)?)
+        .filter(entity::passkeys::Column::UserId.eq(user_id))
+        .one(&db())
+        .await?
+    else {
+        return Err(ServerFnError::ServerError("Passkey not found".to_string()));
+    };
+
+    let details = serde_json::json!({ "from": passkey.name, "to": name });
+    let target = Target::Passkey(passkey.id.clone());
+    let mut passkey: entity::passkeys::ActiveModel = passkey.into();
+    passkey.name = Set((!name.is_empty()).then(|| name.to_string()));
+    passkey.update(&db()).await?;
+    Entry::new(AuditEvent::PasskeyRenamed, Some(user_id)).user(user_id).target(target).details(details).record(&db()).await?;
+    Ok(())
+}
+
+/// Clears the warning on a passkey that may have been cloned, once the user has checked that it's theirs.
+#[server]
+async fn dismiss_passkey_flag(id: String) -> Result<(), ServerFnError> {
+    use sea_orm::Set;
+    use crate::auth::audit::{AuditEvent, Entry, Target};
+
+    let Some(user_id) = session().await.account_uuid() else {
+        return Err(ServerFnError::ServerError("Must be signed in".to_string()));
+    };
+
+    let Some(passkey) = Passkeys::find_by_id(decode_passkey_id(&id)?)
+        .filter(entity::passkeys::Column::UserId.eq(user_id))
+        .one(&db())
+        .await?
+    else {
+        return Err(ServerFnError::ServerError("Passkey not found".to_string()));
+    };
+
+    let details = serde_json::json!({ "name": passkey.name, "reason": passkey.flag_reason });
+    let target = Target::Passkey(passkey.id.clone());
+    let mut passkey: entity::passkeys::ActiveModel = passkey.into();
+    passkey.flagged_at = Set(None);
+    passkey.flag_reason = Set(None);
+    passkey.update(&db()).await?;
+    Entry::new(AuditEvent::PasskeyFlagDismissed, Some(user_id)).user(user_id).target(target).details(details).record(&db()).await?;
+    Ok(())
+}
+
+#[server]
+async fn delete_passkey(id: String) -> Result<(), ServerFnError> {
+    use crate::auth::audit::{AuditEvent, Entry, Target};
+
+    let Some(user_id) = session().await.account_uuid() else {
+        return Err(ServerFnError::ServerError("Must be signed in".to_string()));
+    };
+    let id = decode_passkey_id(&id)?;
+
+    db().transaction::<_, (), ServerFnError>(|txn| {
+        Box::pin(async move {
+            // Lock the user's passkeys so that concurrent deletions can't remove the last two at once
+            let passkeys = Passkeys::find()
+                .filter(entity::passkeys::Column::UserId.eq(user_id))
+                .lock_exclusive()
+                .all(txn)
+                .await?;
+            let Some(passkey) = passkeys.iter().find(|passkey| passkey.id == id) else {
+                return Err(ServerFnError::ServerError("Passkey not found".to_string()));
+            };
+            if passkeys.len() <= 1 {
+                return Err(ServerFnError::ServerError("You can't delete your only passkey, or you would be locked out of your account".to_string()));
+            }
+
+            let details = serde_json::json!({ "name": passkey.name });
+            Passkeys::delete_by_id(id.clone()).exec(txn).await?;
+            Entry::new(AuditEvent::PasskeyRemoved, Some(user_id))
+                .user(user_id)
+                .target(Target::Passkey(id))
+                .details(details)
+                .record(txn)
+                .await?;
+            Ok(())
+        })
+    }).await.map_err(|error| match error {
+        sea_orm::TransactionError::Connection(error) => error.into(),
+        sea_orm::TransactionError::Transaction(error) => error,
+    })
+}
+
+#[component]
+pub fn SecurityPage() -> impl IntoView {
+    let passkeys = Resource::new(|| (), |_| list_passkeys());
+
+    let add_passkey: Action<_, Result<()>> = Action::new_local(move |username: &String| {
+        let username = username.to_owned();
+        async move {
+            #[cfg(feature = "hydrate")]
+            crate::auth::passkey::register(username).await?;
+            #[cfg(not(feature = "hydrate"))]
+            let _ = username;
+
+            passkeys.refetch();
+            Ok(())
+        }
+    });
+
+    view! {
+        <super::AccountShell>
+            <h2 class="text-lg font-semibold mb-2">"Passkeys"</h2>
+            <p class="text-stone-400 mb-4">
+                "Passkeys let you sign in with your fingerprint, face, screen lock or security key. Add one for each device you use."
+            </p>
+            <Transition fallback=|| {}>
+                {move || passkeys.get().map(|result| match result {
+                    Ok(PasskeyList { username, passkeys: list, needs_trusted_passkey }) => {
+                        let is_only_passkey = list.len() <= 1;
+                        view! {
+                            {needs_trusted_passkey.then(|| view! {
+                                <div class="mb-4 p-3 rounded border border-yellow-500 text-yellow-100 flex items-center gap-2">
+                                    <Icon icon=WARNING weight=IconWeight::Fill />
+                                    "Your account's access requires a passkey on a security key this site trusts, and none of yours is. Add one below; your other passkeys keep working."
+                                </div>
+                            })}
+                            <ul class="flex flex-col gap-2 mb-4">
+                                <For
+                                    each=move || list.clone()
+                                    key=|passkey| passkey.id.clone()
+                                    let(passkey)
+                                >
+                                    <PasskeyItem passkey=passkey can_delete=!is_only_passkey on_change=move || passkeys.refetch() />
+                                </For>
+                            </ul>
+                            <button
+                                class="bg-yellow-600 text-white font-semibold select-none shadow-sm py-2 px-3 rounded inline-flex items-center justify-center gap-2"
+                                on:click=move |_| { add_passkey.dispatch_local(username.clone()); }
+                            >
+                                <Icon icon=PLUS weight=IconWeight::Bold />
+                                "Add a passkey"
+                            </button>
+                            <ErrorMessage message=Signal::derive(move || add_passkey.value().read().as_ref().and_then(|result| result.as_ref().err()).map(|error| error.to_string())) />
+                        }.into_any()
+                    }
+                    Err(error) => view! { <p>"Error loading passkeys: " {error.to_string()}</p> }.into_any(),
+                })}
+            </Transition>
+            <RecoveryCodeSection />
+        </super::AccountShell>
+    }
+}
+
+#[component]
+fn RecoveryCodeSection() -> impl IntoView {
+    let count = Resource::new(|| (), |_| recovery_code_count());
+    let new_codes = RwSignal::new(None::<Vec<String>>);
+
+    let regenerate = Action::new(move |_: &()| async move {
+        let result = regenerate_recovery_codes().await;
+        if let Ok(codes) = &result {
+            new_codes.set(Some(codes.clone()));
+        }
+        result.map(|_| ())
+    });
+
+    view! {
+        <h2 class="text-lg font-semibold mt-8 mb-2">"Recovery codes"</h2>
+        <Show
+            when=move || new_codes.read().is_none()
+            fallback=move || view! {
+                <div class="bg-stone-800 p-4 rounded">
+                    <RecoveryCodes
+                        codes=new_codes.get().unwrap_or_default()
+                        on_done=move || { new_codes.set(None); count.refetch(); }
+                    />
+                </div>
+            }
+        >
+            <p class="text-stone-400 mb-4">
+                "If you lose all of your passkeys, you can use a recovery code to register a new one. "
+                <Transition fallback=|| {}>
+                    {move || count.get().and_then(Result::ok).map(|count| match count {
+                        0 => "You have no unused recovery codes.".to_string(),
+                        1 => "You have 1 unused recovery code.".to_string(),
+                        count => format!("You have {count} of {RECOVERY_CODE_COUNT} unused recovery codes."),
+                    })}
+                </Transition>
+            </p>
+            <button
+                class="bg-stone-700 text-white font-semibold select-none shadow-sm py-2 px-3 rounded inline-flex items-center justify-center gap-2"
+                on:click=move |_| {
+                    if window().confirm_with_message("Generate new recovery codes? Your old codes will stop working.").unwrap_or(false) {
+                        regenerate.dispatch(());
+                    }
+                }
+            >
+                "Generate new codes"
+            </button>
+            <ErrorMessage message=Signal::derive(move || regenerate.value().get().and_then(Result::err).map(|error| error.to_string())) />
+        </Show>
+    }
+}
+
+#[component]
+fn PasskeyItem(passkey: PasskeyInfo, can_delete: bool, on_change: impl Fn() + Copy + Send + Sync + 'static) -> impl IntoView {
+    let name = RwSignal::new(passkey.name.clone().unwrap_or_default());
+
+    let id = passkey.id.clone();
+    let rename = Action::new(move |name: &String| {
+        let (id, name) = (id.clone(), name.clone());
+        async move { rename_passkey(id, name).await }
+    });
+
+    let id = passkey.id.clone();
+    let delete = Action::new(move |_: &()| {
+        let id = id.clone();
+        async move {
+            let result = delete_passkey(id).await;
+            if result.is_ok() {
+                on_change();
+            }
+            result
+        }
+    });
+
+    let id = passkey.id.clone();
+    let dismiss = Action::new(move |_: &()| {
+        let id = id.clone();
+        async move {
+            let result = dismiss_passkey_flag(id).await;
+            if result.is_ok() {
+                on_change();
+            }
+            result
+        }
+    });
+
+    let error = Signal::derive(move || {
+        rename.value().get().and_then(Result::err)
+            .or_else(|| delete.value().get().and_then(Result::err))
+            .or_else(|| dismiss.value().get().and_then(Result::err))
+            .map(|error| match error {
+                ServerFnError::ServerError(message) => message,
+                _ => "Something went wrong, please try again".to_string(),
+            })
+    });
+
+    view! {
+        <li class="bg-stone-800 p-4 rounded">
+            <div class="flex items-center gap-4">
+                <Icon icon=KEY weight=IconWeight::Regular size="24px" />
+                <div class="grow">
+                    <input
+                        type="text"
+                        aria-label="Passkey name"
+                        placeholder="Unnamed passkey"
+                        maxlength=MAX_PASSKEY_NAME_LENGTH
+                        bind:value=name
+                        on:change=move |_| { rename.dispatch(name.get()); }
+                        class="text-stone-200 font-semibold bg-transparent w-full placeholder-stone-500"
+                    />
+                    <p class="text-stone-400 text-xs">
+                        "Added " <LocaleDate date=Signal::derive(move || passkey.created_at) />
+                        " · "
+                        {match passkey.last_used_at {
+                            Some(date) => view! { "Last used " <LocaleDate date=Signal::derive(move || date) /> }.into_any(),
+                            None => view! { "Never used" }.into_any(),
+                        }}
+                        {passkey.authenticator.map(|model| format!(" · {model}"))}
+                    </p>
+                </div>
+                <button
+                    title=if can_delete { "Delete passkey" } else { "You can't delete your only passkey" }
+                    disabled=!can_delete
+                    class="text-stone-400 hover:text-stone-200 disabled:opacity-40 disabled:hover:text-stone-400"
+                    on:click=move |_| {
+                        if window().confirm_with_message("Delete this passkey? You won't be able to sign in with it any more.").unwrap_or(false) {
+                            delete.dispatch(());
+                        }
+                    }
+                >
+                    <Icon icon=TRASH weight=IconWeight::Regular size="21px" />
+                </button>
+            </div>
+            {passkey.flag.map(|flag| view! {
+                <div class="mt-3 p-3 rounded border border-red-400 text-red-200 text-sm flex flex-col gap-2">
+                    <p class="flex items-center gap-2 font-semibold">
+                        <Icon icon=WARNING weight=IconWeight::Fill />
+                        "This passkey may have been copied"
+                    </p>
+                    <p>
+                        {flag.description()} " Noticed " <LocaleDate date=Signal::derive(move || flag.flagged_at) /> ". "
+                        {if flag.blocked { "It can't be used to sign in until you dismiss this warning. " } else { "" }}
+                        "If you don't know why, delete it and check your recent sign-ins."
+                    </p>
+                    <div>
+                        <button
+                            class="bg-stone-700 text-white font-semibold select-none shadow-sm py-1 px-3 rounded"
+                            on:click=move |_| {
+                                if window().confirm_with_message("Dismiss this warning? Only do this if you're sure nobody else has a copy of the passkey.").unwrap_or(false) {
+                                    dismiss.dispatch(());
+                                }
+                            }
+                        >
+                            "It's mine, dismiss"
+                        </button>
+                    </div>
+                </div>
+            })}
+            <ErrorMessage message=error />
+        </li>
+    }
+}
+
+/// Asks users whose access requires a passkey from a trusted authenticator to add one, on every page until they do.
+/// The security page asks in its own way.
+#[component]
+pub fn TrustedPasskeyBanner() -> impl IntoView {
+    let needed = Resource::new(|| (), |_| needs_trusted_passkey());
+    let location = leptos_router::hooks::use_location();
+    let on_security_page = move || location.pathname.get() == "/account/security";
+
+    view! {
+        <Transition fallback=|| {}>
+            {move || (needed.get().and_then(Result::ok).unwrap_or_default() && !on_security_page()).then(|| view! {
+                <section role="status" class="flex items-center gap-4 p-4 pl-16 bg-stone-800 text-yellow-100 border-b border-yellow-500">
+                    <Icon icon=KEY weight=IconWeight::Bold size="20px" />
+                    <p class="grow">"Your account's access requires a passkey on a security key this site trusts."</p>
+                    <a href="/account/security" class="bg-yellow-600 text-white font-semibold select-none shadow-sm py-2 px-3 rounded">
+                        "Add one"
+                    </a>
+                </section>
+            })}
+        </Transition>
+    }
+}
+
+#[component]
+pub fn ErrorMessage(#[prop(into)] message: Signal<Option<String>>) -> impl IntoView {
+    view! {
+        <Show when=move || message.get().is_some()>
+            <p class="text-red-300 mt-2 flex items-center gap-2" aria-live="polite">
+                <Icon icon=WARNING weight=IconWeight::Fill />
+                {move || message.get()}
+            </p>
+        </Show>
+    }
+}
@@ -0,0 +1,161 @@
+use crate::prelude::*;
+
+use phosphor_leptos::{Icon, IconWeight, DESKTOP, SIGN_OUT};
+use time::OffsetDateTime;
+
+use crate::browse::LocaleDate;
+
+/// A signed-in device, as shown to the user that owns it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ActiveSession {
+    id: Uuid,
+    device: String,
+    ip: Option<String>,
+    created_at: OffsetDateTime,
+    last_seen_at: OffsetDateTime,
+    is_current: bool,
+}
+
+#[server]
+async fn list_sessions() -> Result<Vec<ActiveSession>, ServerFnError> {
+    use entity::sessions::Column;
+
+    let session = session().await;
+    let Some(user_id) = session.account_uuid() else {
+        return Err(ServerFnError::ServerError("Must be signed in".to_string()));
+    };
+
+    let sessions = Sessions::find()
+        .filter(Column::UserId.eq(user_id))
+        .filter(Column::RevokedAt.is_null())
+        .filter(Column::ExpiresAt.gt(OffsetDateTime::now_utc()))
+        .order_by_desc(Column::LastSeenAt)
+        .all(&db())
+        .await?
+        .into_iter()
+        .map(|record| ActiveSession {
+            id: record.id,
+            device: crate::request::describe_user_agent(record.user_agent.as_deref().unwrap_or_default()),
+            ip: record.ip,
+            created_at: record.created_at,
+            last_seen_at: record.last_seen_at,
+            is_current: session.id() == Some(record.id),
+        })
+        .collect();
+    Ok(sessions)
+}
+
+#[server]
+async fn revoke_session(id: Uuid) -> Result<(), ServerFnError> {
+    use entity::sessions::Column;
+    use crate::auth::audit::{AuditEvent, Entry, Target};
+    use crate::auth::session::revoke_sessions;
+
+    let mut session = session().await;
+    let Some(user_id) = session.account_uuid() else {
+        return Err(ServerFnError::ServerError("Must be signed in".to_string()));
+    };
+
+    if session.id() == Some(id) {
+        session.logout().await?;
+    } else if revoke_sessions(Column::Id.eq(id).and(Column::UserId.eq(user_id))).await? > 0 {
+        Entry::new(AuditEvent::SessionRevoked, Some(user_id)).user(user_id).target(Target::Session(id)).record(&db()).await?;
+    }
+    Ok(())
+}
+
+/// Signs the user out on every device, including this one.
+#[server]
+async fn revoke_all_sessions() -> Result<(), ServerFnError> {
+    use crate::auth::audit::{AuditEvent, Entry};
+    use crate::auth::session::revoke_sessions;
+
+    let mut session = session().await;
+    let Some(user_id) = session.account_uuid() else {
+        return Err(ServerFnError::ServerError("Must be signed in".to_string()));
+    };
+
+    let count = revoke_sessions(entity::sessions::Column::UserId.eq(user_id)).await?;
+    log::info!("user {user_id} revoked all {count} of their sessions");
+    Entry::new(AuditEvent::AllSessionsRevoked, Some(user_id))
+        .user(user_id)
+        .details(serde_json::json!({ "count": count }))
+        .record(&db())
+        .await?;
+    session.logout().await?;
+    Ok(())
+}
+
+#[component]
+pub fn SessionsPage() -> impl IntoView {
+    let sessions = Resource::new(|| (), |_| list_sessions());
+
+    let revoke = Action::new(move |id: &Uuid| {
+        let id = *id;
+        let is_current = sessions
+            .get()
+            .and_then(Result::ok)
+            .is_some_and(|sessions| sessions.iter().any(|session| session.id == id && session.is_current));
+        async move {
+            match revoke_session(id).await {
+                Ok(()) if is_current => window().location().set_href("/auth").expect("failed to redirect to sign in page"),
+                Ok(()) => sessions.refetch(),
+                Err(error) => log::error!("failed to revoke session: {error:?}"),
+            }
+        }
+    });
+
+    let revoke_all = Action::new(move |_: &()| async move {
+        match revoke_all_sessions().await {
+            Ok(()) => window().location().set_href("/auth").expect("failed to redirect to sign in page"),
+            Err(error) => log::error!("failed to revoke sessions: {error:?}"),
+        }
+    });
+
+    view! {
+        <super::AccountShell>
+            <p class="text-stone-400 mb-4">
+                "These devices are signed in to your account. If you don't recognise one, revoke it."
+            </p>
+            <Transition fallback=|| {}>
+                <ul class="flex flex-col gap-2 mb-8">
+                    <For
+                        each=move || sessions.get().and_then(Result::ok).unwrap_or_default()
+                        key=|session| session.id
+                        let(session)
+                    >
+                        <li class="bg-stone-800 p-4 rounded flex items-center gap-4">
+                            <Icon icon=DESKTOP weight=IconWeight::Regular size="24px" />
+                            <div class="grow">
+                                <p class="text-stone-200 font-semibold">
+                                    {session.device}
+                                    <Show when=move || session.is_current>
+                                        <span class="ml-2 text-xs text-green-400">"This device"</span>
+                                    </Show>
+                                </p>
+                                <p class="text-stone-400 text-xs">
+                                    {session.ip.map(|ip| format!("{ip} · "))}
+                                    "Signed in " <LocaleDate date=Signal::derive(move || session.created_at) />
+                                    " · Last active " <LocaleDate date=Signal::derive(move || session.last_seen_at) />
+                                </p>
+                            </div>
+                            <button
+                                class="text-stone-400 hover:text-stone-200 font-semibold"
+                                on:click=move |_| { revoke.dispatch(session.id); }
+                            >
+                                "Revoke"
+                            </button>
+                        </li>
+                    </For>
+                </ul>
+            </Transition>
+            <button
+                class="bg-red-700 text-white font-semibold select-none shadow-sm py-2 px-3 rounded inline-flex items-center justify-center gap-2"
+                on:click=move |_| { revoke_all.dispatch(()); }
+            >
+                <Icon icon=SIGN_OUT weight=IconWeight::Bold />
+                "Sign out everywhere"
+            </button>
+        </super::AccountShell>
+    }
+}
@@ -0,0 +1,310 @@
+use crate::prelude::*;
+
+use phosphor_leptos::{Icon, IconWeight, KEY, PLUS};
+use time::OffsetDateTime;
+
+use super::ErrorMessage;
+use crate::auth::Scope;
+use crate::browse::LocaleDate;
+
+/// A personal access token, as shown to the user that owns it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AccessTokenInfo {
+    id: Uuid,
+    name: String,
+    scopes: Vec<Scope>,
+    created_at: OffsetDateTime,
+    expires_at: OffsetDateTime,
+    last_used_at: Option<OffsetDateTime>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AccessTokenList {
+    tokens: Vec<AccessTokenInfo>,
+    /// Scopes the user has, and so can give to a token
+    available_scopes: Vec<Scope>,
+}
+
+const MAX_TOKEN_NAME_LENGTH: usize = 64;
+
+/// Lifetimes a token can be created with, in days.
+const EXPIRY_DAYS: [i64; 4] = [7, 30, 90, 365];
+
+#[server]
+async fn list_access_tokens() -> Result<AccessTokenList, ServerFnError> {
+    use entity::personal_access_tokens::Column;
+    use crate::auth::access_token::parse_scopes;
+
+    let Some(user_id) = session().await.account_uuid() else {
+        return Err(ServerFnError::ServerError("Must be signed in".to_string()));
+    };
+
+    let tokens = PersonalAccessTokens::find()
+        .filter(Column::UserId.eq(user_id))
+        .filter(Column::RevokedAt.is_null())
+        .filter(Column::ExpiresAt.gt(OffsetDateTime::now_utc()))
+        .order_by_desc(Column::CreatedAt)
+        .all(&db())
+        .await?
+        .into_iter()
+        .map(|token| AccessTokenInfo {
+            id: token.id,
+            name: token.name,
+            scopes: parse_scopes(&token.scopes).into_iter().collect(),
+            created_at: token.created_at,
+            expires_at: token.expires_at,
+            last_used_at: token.last_used_at,
+        })
+        .collect();
+    let available_scopes = crate::auth::roles::scopes_for(&db(), user_id)
+        .await?
+        .into_iter()
+        .filter(|scope| scope.is_delegable())
+        .collect();
+    Ok(AccessTokenList { tokens, available_scopes })
+}
+
+/// Creates a token, returning it. Only its hash is stored, so this is the only time it can be shown.
+#[server]
+async fn create_access_token(name: String, scopes: Vec<Scope>, expires_in_days: i64) -> Result<String, ServerFnError> {
+    use std::collections::BTreeSet;
+    use sea_orm::Set;
+    use crate::auth::audit::{AuditEvent, Entry};
+
+    let Some(user_id) = session().await.account_uuid() else {
+        return Err(ServerFnError::ServerError("Must be signed in".to_string()));
+    };
+
+    let name = name.trim().to_string();
+    if name.is_empty() {
+        return Err(ServerFnError::ServerError("Give the token a name so you can tell it apart later".to_string()));
+    }
+    if name.chars().count() > MAX_TOKEN_NAME_LENGTH {
+        return Err(ServerFnError::ServerError(format!("Names can be at most {MAX_TOKEN_NAME_LENGTH} characters")));
+    }
+    if !EXPIRY_DAYS.contains(&expires_in_days) {
+        return Err(ServerFnError::ServerError("Invalid expiry".to_string()));
+    }
+
+    let scopes: BTreeSet<Scope> = scopes.into_iter().collect();
+    if scopes.is_empty() {
+        return Err(ServerFnError::ServerError("Choose at least one scope".to_string()));
+    }
+    if let Some(scope) = scopes.iter().find(|scope| !scope.is_delegable()) {
+        return Err(ServerFnError::ServerError(format!("Tokens can't be given the `{}` scope", scope.as_str())));
+    }
+    if !scopes.is_subset(&crate::auth::roles::scopes_for(&db(), user_id).await?) {
+        return Err(ServerFnError::ServerError("You can't give a token scopes that you don't have".to_string()));
+    }
+
+    let (token, token_hash) = crate::auth::access_token::generate();
+    let now = OffsetDateTime::now_utc();
+    let record = entity::personal_access_tokens::ActiveModel {
+        id: Set(Uuid::new_v4()),
+        user_id: Set(user_id),
+        name: Set(name),
+        token_hash: Set(token_hash),
+        scopes: Set(serde_json::to_value(&scopes)?),
+        created_at: Set(now),
+        expires_at: Set(now + time::Duration::days(expires_in_days)),
+        last_used_at: Set(None),
+        revoked_at: Set(None),
+    }.insert(&db()).await?;
+
+    Entry::new(AuditEvent::AccessTokenCreated, Some(user_id))
+        .user(user_id)
+        .details(serde_json::json!({
+            "id": record.id,
+            "name": record.name,
+            "scopes": scopes,
+        }))
+        .record(&db())
+        .await?;
+    Ok(token)
+}
+
+#[server]
+async fn revoke_access_token(id: Uuid) -> Result<(), ServerFnError> {
+    use entity::personal_access_tokens::Column;
+    use crate::auth::audit::{AuditEvent, Entry};
+
+    let Some(user_id) = session().await.account_uuid() else {
+        return Err(ServerFnError::ServerError("Must be signed in".to_string()));
+    };
+
+    let result = PersonalAccessTokens::update_many()
+        .col_expr(Column::RevokedAt, Expr::value(OffsetDateTime::now_utc()))
+        .filter(Column::Id.eq(id))
+        .filter(Column::UserId.eq(user_id))
+        .filter(Column::RevokedAt.is_null())
+        .exec(&db())
+        .await?;
+    if result.rows_affected == 0 {
+        return Err(ServerFnError::ServerError("Token not found".to_string()));
+    }
+
+    Entry::new(AuditEvent::AccessTokenRevoked, Some(user_id))
+        .user(user_id)
+        .details(serde_json::json!({ "id": id }))
+        .record(&db())
+        .await?;
+    Ok(())
+}
+
+#[component]
+pub fn TokensPage() -> impl IntoView {
+    let tokens = Resource::new(|| (), |_| list_access_tokens());
+
+    let name = RwSignal::new(String::new());
+    let scopes = RwSignal::new(Vec::<Scope>::new());
+    let expires_in_days = RwSignal::new(30);
+    let new_token = RwSignal::new(None::<String>);
+
+    let create = Action::new(move |_: &()| async move {
+        let result = create_access_token(name.get_untracked(), scopes.get_untracked(), expires_in_days.get_untracked()).await;
+        if let Ok(token) = &result {
+            new_token.set(Some(token.clone()));
+            name.set(String::new());
+            scopes.set(Vec::new());
+            tokens.refetch();
+        }
+        result.map(|_| ())
+    });
+
+    let revoke = Action::new(move |id: &Uuid| {
+        let id = *id;
+        async move {
+            let result = revoke_access_token(id).await;
+            tokens.refetch();
+            result
+        }
+    });
+
+    let error = Signal::derive(move || {
+        create.value().get().and_then(Result::err)
+            .or_else(|| revoke.value().get().and_then(Result::err))
+            .map(|error| match error {
+                ServerFnError::ServerError(message) => message,
+                _ => "Something went wrong, please try again".to_string(),
+            })
+    });
+
+    view! {
+        <super::AccountShell>
+            <p class="text-stone-400 mb-4">
+                "Personal access tokens let scripts and CI pipelines act as you, with only the scopes you choose. Send one in an "
+                <code>"Authorization: Bearer"</code>
+                " header. Treat them like passwords."
+            </p>
+
+            <Show when=move || new_token.read().is_some()>
+                <div class="bg-green-900 border border-green-700 p-4 rounded mb-4">
+                    <p class="font-semibold mb-2">"Copy your new token now. You won't be able to see it again."</p>
+                    <input
+                        type="text"
+                        readonly
+                        prop:value=move || new_token.get().unwrap_or_default()
+                        onfocus="this.select()"
+                        class="w-full font-mono bg-stone-900 text-stone-200 p-2 rounded"
+                    />
+                </div>
+            </Show>
+
+            <Transition fallback=|| {}>
+                {move || tokens.get().map(|result| match result {
+                    Ok(AccessTokenList { tokens: list, available_scopes }) => view! {
+                        <ul class="flex flex-col gap-2 mb-8">
+                            {list.into_iter().map(|token| view! {
+                                <li class="bg-stone-800 p-4 rounded flex items-center gap-4">
+                                    <Icon icon=KEY weight=IconWeight::Regular size="24px" />
+                                    <div class="grow">
+                                        <p class="text-stone-200 font-semibold">{token.name}</p>
+                                        <p class="text-stone-400 text-xs font-mono">
+                                            {token.scopes.iter().map(|scope| scope.as_str()).collect::<Vec<_>>().join(", ")}
+                                        </p>
+                                        <p class="text-stone-400 text-xs">
+                                            "Created " <LocaleDate date=Signal::derive(move || token.created_at) />
+                                            " · Expires " <LocaleDate date=Signal::derive(move || token.expires_at) />
+                                            " · "
+                                            {match token.last_used_at {
+                                                Some(date) => view! { "Last used " <LocaleDate date=Signal::derive(move || date) /> }.into_any(),
+                                                None => view! { "Never used" }.into_any(),
+                                            }}
+                                        </p>
+                                    </div>
+                                    <button
+                                        class="text-stone-400 hover:text-stone-200 font-semibold"
+                                        on:click=move |_| {
+                                            if window().confirm_with_message("Revoke this token? Anything using it will stop working.").unwrap_or(false) {
+                                                revoke.dispatch(token.id);
+                                            }
+                                        }
+                                    >
+                                        "Revoke"
+                                    </button>
+                                </li>
+                            }).collect_view()}
+                        </ul>
+
+                        <h2 class="text-lg font-semibold mb-2">"New token"</h2>
+                        <div class="bg-stone-800 p-4 rounded flex flex-col gap-4">
+                            <label class="block">
+                                <span class="font-semibold">"Name"</span>
+                                <input
+                                    type="text"
+                                    placeholder="e.g. GitHub Actions"
+                                    maxlength=MAX_TOKEN_NAME_LENGTH
+                                    bind:value=name
+                                    class="block w-full mt-1 p-2 border-2 border-stone-500 text-stone-200 bg-stone-700 rounded-sm"
+                                />
+                            </label>
+                            <fieldset>
+                                <legend class="font-semibold">"Scopes"</legend>
+                                {available_scopes.into_iter().map(|scope| view! {
+                                    <label class="flex items-center gap-2 mt-1">
+                                        <input
+                                            type="checkbox"
+                                            prop:checked=move || scopes.read().contains(&scope)
+                                            on:change=move |event| {
+                                                let checked = event_target_checked(&event);
+                                                scopes.update(|scopes| {
+                                                    scopes.retain(|s| *s != scope);
+                                                    if checked {
+                                                        scopes.push(scope);
+                                                    }
+                                                });
+                                            }
+                                        />
+                                        {scope.description()}
+                                        <span class="text-stone-400 text-xs font-mono">{scope.as_str()}</span>
+                                    </label>
+                                }).collect_view()}
+                            </fieldset>
+                            <label class="block">
+                                <span class="font-semibold">"Expires after"</span>
+                                <select
+                                    class="block mt-1 bg-stone-700 text-stone-200 p-2 rounded"
+                                    on:change=move |event| expires_in_days.set(event_target_value(&event).parse().unwrap_or(30))
+                                >
+                                    {EXPIRY_DAYS.into_iter().map(|days| view! {
+                                        <option value=days selected=days == 30>{format!("{days} days")}</option>
+                                    }).collect_view()}
+                                </select>
+                            </label>
+                            <button
+                                class="bg-yellow-600 text-white font-semibold select-none shadow-sm py-2 px-3 rounded inline-flex items-center justify-center gap-2 self-start disabled:opacity-50"
+                                disabled=move || name.read().trim().is_empty() || scopes.read().is_empty()
+                                on:click=move |_| { create.dispatch(()); }
+                            >
+                                <Icon icon=PLUS weight=IconWeight::Bold />
+                                "Create token"
+                            </button>
+                        </div>
+                    }.into_any(),
+                    Err(error) => view! { <p>"Error loading tokens: " {error.to_string()}</p> }.into_any(),
+                })}
+            </Transition>
+            <ErrorMessage message=error />
+        </super::AccountShell>
+    }
+}
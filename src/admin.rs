@@ -0,0 +1,496 @@
+use crate::prelude::*;
+
+use leptos::Params;
+use leptos_router::{NavigateOptions, hooks::{use_navigate, use_params}, params::Params};
+use phosphor_leptos::{Icon, IconWeight, CHECK, MAGNIFYING_GLASS, X};
+
+use crate::account::ErrorMessage;
+use crate::auth::Scope;
+use crate::profile::avatar::{Avatar, AvatarRef};
+
+mod audit;
+mod impersonation;
+
+pub use audit::AdminAuditPage;
+pub use impersonation::ImpersonationBanner;
+
+/// A role, and whether a particular user has it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RoleInfo {
+    id: String,
+    name: String,
+    description: String,
+    scopes: Vec<Scope>,
+    is_default: bool,
+    assigned: bool,
+}
+
+/// A scope granted or revoked directly, overriding the user's roles.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScopeOverride {
+    scope: Scope,
+    granted: bool,
+    reason: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UserAccess {
+    username: String,
+    display_name: Option<String>,
+    avatar: AvatarRef,
+    roles: Vec<RoleInfo>,
+    overrides: Vec<ScopeOverride>,
+    /// The scopes the user ends up with
+    scopes: Vec<Scope>,
+}
+
+/// Returns the signed-in user's ID if they may manage other users.
+#[cfg(feature = "ssr")]
+async fn require_manage_users() -> Result<Uuid, ServerFnError> {
+    let session = session().await;
+    match session.account_uuid() {
+        Some(user_id) if session.has_scope(Scope::ManageUsers) => Ok(user_id),
+        Some(_) => Err(ServerFnError::ServerError("You don't have permission to manage users".to_string())),
+        None => Err(ServerFnError::ServerError("Must be signed in".to_string())),
+    }
+}
+
+/// Finds a user by their current or a previous username.
+#[cfg(feature = "ssr")]
+async fn find_user(username: &str) -> Result<User, ServerFnError> {
+    crate::auth::username::resolve(&db(), username)
+        .await?
+        .ok_or_else(|| ServerFnError::ServerError("User not found".to_string()))
+}
+
+/// Fails if `actor` would no longer be able to manage users, so that admins can't lock themselves out.
+#[cfg(feature = "ssr")]
+async fn check_not_locked_out(txn: &sea_orm::DatabaseTransaction, actor: Uuid) -> Result<(), ServerFnError> {
+    if !crate::auth::roles::scopes_for(txn, actor).await?.contains(&Scope::ManageUsers) {
+        return Err(ServerFnError::ServerError("You can't take away your own permission to manage users".to_string()));
+    }
+    Ok(())
+}
+
+#[server]
+async fn get_user_access(username: String) -> Result<UserAccess, ServerFnError> {
+    use std::collections::HashSet;
+
+    require_manage_users().await?;
+    let user = find_user(&username).await?;
+
+    let assigned: HashSet<String> = UserRoles::find()
+        .filter(entity::user_roles::Column::UserId.eq(user.id))
+        .all(&db())
+        .await?
+        .into_iter()
+        .map(|user_role| user_role.role_id)
+        .collect();
+    let role_scopes = RoleScopes::find().all(&db()).await?;
+    let roles = Roles::find()
+        .order_by_desc(entity::roles::Column::IsDefault)
+        .order_by_asc(entity::roles::Column::Name)
+        .all(&db())
+        .await?
+        .into_iter()
+        .map(|role| RoleInfo {
+            scopes: role_scopes
+                .iter()
+                .filter(|role_scope| role_scope.role_id == role.id)
+                .filter_map(|role_scope| role_scope.scope.parse().ok())
+                .collect(),
+            assigned: assigned.contains(&role.id),
+            id: role.id,
+            name: role.name,
+            description: role.description,
+            is_default: role.is_default,
+        })
+        .collect();
+
+    let overrides = UserScopes::find()
+        .filter(entity::user_scopes::Column::UserId.eq(user.id))
+        .all(&db())
+        .await?
+        .into_iter()
+        .filter_map(|user_scope| Some(ScopeOverride {
+            scope: user_scope.scope.parse().ok()?,
+            granted: user_scope.granted,
+            reason: user_scope.reason,
+        }))
+        .collect();
+
+    let scopes = crate::auth::roles::scopes_for(&db(), user.id).await?.into_iter().collect();
+    Ok(UserAccess { avatar: AvatarRef::from(&user), username: user.username, display_name: user.display_name, roles, overrides, scopes })
+}
+
+#[server]
+async fn set_user_role(username: String, role_id: String, assigned: bool) -> Result<(), ServerFnError> {
+    let actor = require_manage_users().await?;
+    let user = find_user(&username).await?;
+    if Roles::find_by_id(role_id.clone()).one(&db()).await?.is_none() {
+        return Err(ServerFnError::ServerError("Role not found".to_string()));
+    }
+
+    let role = role_id.clone();
+    db().transaction::<_, (), ServerFnError>(|txn| {
+        Box::pin(async move {
+            crate::auth::roles::set_role(txn, user.id, &role, assigned, Some(actor)).await?;
+            check_not_locked_out(txn, actor).await
+        })
+    }).await.map_err(|error| match error {
+        sea_orm::TransactionError::Connection(error) => error.into(),
+        sea_orm::TransactionError::Transaction(error) => error,
+    })?;
+
+    log::info!("user {actor} {} role {role_id} for {username}", if assigned { "assigned" } else { "removed" });
+    Ok(())
+}
+
+/// `granted` is `Some(true)` to grant the scope, `Some(false)` to revoke it, or `None` to let the user's roles decide.
+#[server]
+async fn set_user_scope(username: String, scope: Scope, granted: Option<bool>, reason: String) -> Result<(), ServerFnError> {
+    let actor = require_manage_users().await?;
+    let user = find_user(&username).await?;
+    if scope == Scope::Unknown {
+        return Err(ServerFnError::ServerError("Unknown scope".to_string()));
+    }
+    let reason = Some(reason.trim().to_string()).filter(|reason| !reason.is_empty());
+
+    db().transaction::<_, (), ServerFnError>(|txn| {
+        Box::pin(async move {
+            crate::auth::roles::set_scope(txn, user.id, scope, granted, reason, Some(actor)).await?;
+            check_not_locked_out(txn, actor).await
+        })
+    }).await.map_err(|error| match error {
+        sea_orm::TransactionError::Connection(error) => error.into(),
+        sea_orm::TransactionError::Transaction(error) => error,
+    })?;
+
+    log::info!("user {actor} set {} to {granted:?} for {username}", scope.as_str());
+    Ok(())
+}
+
+/// Takes away a user's display name, e.g. because it's offensive, so they're shown by their username.
+#[server]
+async fn reset_display_name(username: String, reason: String) -> Result<(), ServerFnError> {
+    use crate::auth::display_name::{self, SetDisplayNameError};
+
+    let actor = require_manage_users().await?;
+    let user = find_user(&username).await?;
+    let reason = reason.trim();
+    if reason.is_empty() {
+        return Err(ServerFnError::ServerError("Give a reason for the audit log".to_string()));
+    }
+
+    display_name::set(&db(), user, "", actor, Some(reason.to_string())).await.map_err(|error| -> ServerFnError { match error {
+        SetDisplayNameError::Invalid(error) => ServerFnError::ServerError(error.to_string()),
+        SetDisplayNameError::Db(error) => error.into(),
+    }})?;
+    log::info!("user {actor} reset the display name of {username}");
+    Ok(())
+}
+
+/// Takes away a user's avatar, e.g. because it's offensive, so they're shown their identicon.
+#[server]
+async fn remove_user_avatar(username: String, reason: String) -> Result<(), ServerFnError> {
+    let actor = require_manage_users().await?;
+    let user = find_user(&username).await?;
+    let reason = reason.trim();
+    if reason.is_empty() {
+        return Err(ServerFnError::ServerError("Give a reason for the audit log".to_string()));
+    }
+
+    crate::profile::avatar::set(&db(), user, None, actor, Some(reason.to_string())).await?;
+    log::info!("user {actor} removed the avatar of {username}");
+    Ok(())
+}
+
+#[derive(Params, PartialEq)]
+struct AdminUsersParams {
+    username: Option<String>,
+}
+
+#[component]
+pub fn AdminUsersPage() -> impl IntoView {
+    let params = use_params::<AdminUsersParams>();
+    let username = Signal::derive(move || params.read().as_ref().ok().and_then(|params| params.username.clone()));
+    let search = RwSignal::new(username.get_untracked().unwrap_or_default());
+    let navigate = use_navigate();
+
+    view! {
+        <Shell>
+            <div class="w-full max-w-screen-md mx-auto my-8">
+                <crate::create::SessionRequiredBanner />
+                <h1 class="text-2xl font-bold mb-4">"Manage users"</h1>
+                <form
+                    class="flex gap-2 mb-8"
+                    on:submit=move |event| {
+                        event.prevent_default();
+                        navigate(&format!("/admin/users/{}", search.get()), Default::default());
+                    }
+                >
+                    <input
+                        type="text"
+                        placeholder="Username"
+                        bind:value=search
+                        class="grow p-2 border-2 border-stone-500 text-stone-200 bg-stone-700 rounded-sm"
+                    />
+                    <button type="submit" class="bg-yellow-600 text-white font-semibold select-none shadow-sm py-2 px-3 rounded inline-flex items-center justify-center gap-2">
+                        <Icon icon=MAGNIFYING_GLASS weight=IconWeight::Bold />
+                        "Look up"
+                    </button>
+                </form>
+                {move || username.get().map(|username| view! { <UserAccessPanel username=username /> })}
+            </div>
+        </Shell>
+    }
+}
+
+#[component]
+fn UserAccessPanel(username: String) -> impl IntoView {
+    let access = Resource::new(
+        {
+            let username = username.clone();
+            move || username.clone()
+        },
+        get_user_access,
+    );
+
+    // Previous usernames still find the user, so show the page under their current one
+    Effect::new({
+        let username = username.clone();
+        let navigate = use_navigate();
+        move || {
+            if let Some(Ok(access)) = access.get() {
+                if access.username != username {
+                    navigate(&format!("/admin/users/{}", access.username), NavigateOptions { replace: true, ..Default::default() });
+                }
+            }
+        }
+    });
+
+    let set_role = Action::new({
+        let username = username.clone();
+        move |(role_id, assigned): &(String, bool)| {
+            let (username, role_id, assigned) = (username.clone(), role_id.clone(), *assigned);
+            async move {
+                let result = set_user_role(username, role_id, assigned).await;
+                access.refetch();
+                result
+            }
+        }
+    });
+
+    let set_scope = Action::new({
+        let username = username.clone();
+        move |(scope, granted, reason): &(Scope, Option<bool>, String)| {
+            let (username, scope, granted, reason) = (username.clone(), *scope, *granted, reason.clone());
+            async move {
+                let result = set_user_scope(username, scope, granted, reason).await;
+                access.refetch();
+                result
+            }
+        }
+    });
+
+    let avatar_reason = RwSignal::new(String::new());
+    let remove_avatar = Action::new({
+        let username = username.clone();
+        move |reason: &String| {
+            let (username, reason) = (username.clone(), reason.clone());
+            async move {
+                let result = remove_user_avatar(username, reason).await;
+                if result.is_ok() {
+                    avatar_reason.set(String::new());
+                }
+                access.refetch();
+                result
+            }
+        }
+    });
+
+    let display_name_reason = RwSignal::new(String::new());
+    let reset = Action::new(move |reason: &String| {
+        let (username, reason) = (username.clone(), reason.clone());
+        async move {
+            let result = reset_display_name(username, reason).await;
+            if result.is_ok() {
+                display_name_reason.set(String::new());
+            }
+            access.refetch();
+            result
+        }
+    });
+
+    let error = Signal::derive(move || {
+        set_role.value().get().and_then(Result::err)
+            .or_else(|| set_scope.value().get().and_then(Result::err))
+            .or_else(|| reset.value().get().and_then(Result::err))
+            .or_else(|| remove_avatar.value().get().and_then(Result::err))
+            .map(|error| error.to_string())
+    });
+
+    view! {
+        <Transition fallback=|| {}>
+            {move || access.get().map(|result| match result {
+                Ok(UserAccess { username, display_name, avatar, roles, overrides, scopes }) => view! {
+                    <h2 class="text-xl font-semibold mb-4">
+                        {username.clone()}
+                        <a href=format!("/admin/audit?user={username}") class="ml-4 text-sm font-normal text-stone-400 hover:text-stone-200 underline">
+                            "Audit log"
+                        </a>
+                    </h2>
+                    <ErrorMessage message=error />
+
+                    {display_name.map(|display_name| view! {
+                        <h3 class="text-lg font-semibold mt-4 mb-2">"Display name"</h3>
+                        <form
+                            class="bg-stone-800 p-4 rounded flex flex-wrap items-center gap-4"
+                            on:submit=move |event| {
+                                event.prevent_default();
+                                reset.dispatch(display_name_reason.get_untracked());
+                            }
+                        >
+                            <span class="text-stone-200 font-semibold">{display_name}</span>
+                            <input
+                                type="text"
+                                placeholder="Reason"
+                                required
+                                bind:value=display_name_reason
+                                class="grow p-2 border-2 border-stone-500 text-stone-200 bg-stone-700 rounded-sm"
+                            />
+                            <button
+                                type="submit"
+                                disabled=move || reset.pending().get()
+                                class="bg-stone-600 text-white font-semibold select-none shadow-sm py-2 px-3 rounded"
+                            >
+                                "Reset"
+                            </button>
+                        </form>
+                    })}
+
+                    {avatar.avatar_id.is_some().then(|| view! {
+                        <h3 class="text-lg font-semibold mt-4 mb-2">"Avatar"</h3>
+                        <form
+                            class="bg-stone-800 p-4 rounded flex flex-wrap items-center gap-4"
+                            on:submit=move |event| {
+                                event.prevent_default();
+                                remove_avatar.dispatch(avatar_reason.get_untracked());
+                            }
+                        >
+                            <Avatar avatar=avatar size=48 />
+                            <input
+                                type="text"
+                                placeholder="Reason"
+                                required
+                                bind:value=avatar_reason
+                                class="grow p-2 border-2 border-stone-500 text-stone-200 bg-stone-700 rounded-sm"
+                            />
+                            <button
+                                type="submit"
+                                disabled=move || remove_avatar.pending().get()
+                                class="bg-stone-600 text-white font-semibold select-none shadow-sm py-2 px-3 rounded"
+                            >
+                                "Remove"
+                            </button>
+                        </form>
+                    })}
+
+                    <h3 class="text-lg font-semibold mt-4 mb-2">"Roles"</h3>
+                    <ul class="flex flex-col gap-2 mb-8">
+                        {roles.into_iter().map(|role| {
+                            let id = role.id.clone();
+                            view! {
+                                <li class="bg-stone-800 p-4 rounded">
+                                    <label class="flex items-center gap-4">
+                                        <input
+                                            type="checkbox"
+                                            prop:checked=role.assigned || role.is_default
+                                            disabled=role.is_default
+                                            on:change=move |event| { set_role.dispatch((id.clone(), event_target_checked(&event))); }
+                                        />
+                                        <div>
+                                            <p class="text-stone-200 font-semibold">
+                                                {role.name}
+                                                {role.is_default.then_some(view! { <span class="ml-2 text-xs text-stone-400">"Everyone"</span> })}
+                                            </p>
+                                            <p class="text-stone-400 text-xs">
+                                                {role.description} " · "
+                                                {role.scopes.iter().map(|scope| scope.as_str()).collect::<Vec<_>>().join(", ")}
+                                            </p>
+                                        </div>
+                                    </label>
+                                </li>
+                            }
+                        }).collect_view()}
+                    </ul>
+
+                    <h3 class="text-lg font-semibold mb-2">"Scopes"</h3>
+                    <p class="text-stone-400 mb-4">
+                        "Grant a scope to give it to this user alone, or revoke it to take it away even if one of their roles grants it."
+                    </p>
+                    <ul class="flex flex-col gap-2">
+                        {Scope::ALL.into_iter().map(|scope| {
+                            let existing = overrides.iter().find(|o| o.scope == scope).cloned();
+                            view! {
+                                <ScopeRow
+                                    scope=scope
+                                    has_scope=scopes.contains(&scope)
+                                    existing=existing
+                                    on_change=move |granted, reason| { set_scope.dispatch((scope, granted, reason)); }
+                                />
+                            }
+                        }).collect_view()}
+                    </ul>
+
+                    <impersonation::ImpersonateForm username=username />
+                }.into_any(),
+                Err(error) => view! { <ErrorMessage message=Some(error.to_string()) /> }.into_any(),
+            })}
+        </Transition>
+    }
+}
+
+#[component]
+fn ScopeRow(
+    scope: Scope,
+    has_scope: bool,
+    existing: Option<ScopeOverride>,
+    on_change: impl Fn(Option<bool>, String) + Copy + Send + Sync + 'static,
+) -> impl IntoView {
+    let granted = existing.as_ref().map(|o| o.granted);
+    let reason = RwSignal::new(existing.and_then(|o| o.reason).unwrap_or_default());
+
+    view! {
+        <li class="bg-stone-800 p-4 rounded flex items-center gap-4">
+            <span title=if has_scope { "Has this scope" } else { "Doesn't have this scope" }>
+                <Icon icon=if has_scope { CHECK } else { X } weight=IconWeight::Bold size="20px" />
+            </span>
+            <div class="grow">
+                <p class="text-stone-200 font-semibold">{scope.description()}</p>
+                <p class="text-stone-400 text-xs font-mono">{scope.as_str()}</p>
+                <input
+                    type="text"
+                    placeholder="Reason (optional)"
+                    bind:value=reason
+                    class="mt-1 w-full bg-transparent text-stone-300 text-sm placeholder-stone-500"
+                />
+            </div>
+            <select
+                class="bg-stone-700 text-stone-200 p-2 rounded"
+                on:change=move |event| {
+                    let granted = match event_target_value(&event).as_str() {
+                        "grant" => Some(true),
+                        "revoke" => Some(false),
+                        _ => None,
+                    };
+                    on_change(granted, reason.get());
+                }
+            >
+                <option value="inherit" selected=granted.is_none()>"From roles"</option>
+                <option value="grant" selected=granted == Some(true)>"Granted"</option>
+                <option value="revoke" selected=granted == Some(false)>"Revoked"</option>
+            </select>
+        </li>
+    }
+}
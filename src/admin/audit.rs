@@ -0,0 +1,123 @@
+use crate::prelude::*;
+
+use leptos_router::hooks::{use_navigate, use_query_map};
+use phosphor_leptos::{Icon, IconWeight, MAGNIFYING_GLASS};
+use crate::account::{AuditEntryList, ErrorMessage, Pagination};
+use crate::auth::audit::{AuditEntry, AuditEvent, next_page};
+use crate::pagination::Cursor;
+
+/// Entries in the audit log involving `username`, either as who did it or as whose account it was about, that match
+/// `event` and `target` (`type:id`, e.g. `mod:<uuid>`) if given.
+#[server]
+async fn search_audit_log(
+    username: String,
+    event: Option<AuditEvent>,
+    target: String,
+    before: Option<Cursor>,
+) -> Result<Vec<AuditEntry>, ServerFnError> {
+    use entity::audit_events::Column;
+    use sea_orm::Condition;
+    use crate::auth::Scope;
+
+    let session = session().await;
+    match session.account_uuid() {
+        Some(_) if session.has_scope(Scope::ViewAuditLog) => {}
+        Some(_) => return Err(ServerFnError::ServerError("You don't have permission to view the audit log".to_string())),
+        None => return Err(ServerFnError::ServerError("Must be signed in".to_string())),
+    }
+
+    let mut condition = Condition::all();
+    let username = username.trim();
+    if !username.is_empty() {
+        let Some(user) = crate::auth::username::resolve(&db(), username).await? else {
+            return Err(ServerFnError::ServerError("User not found".to_string()));
+        };
+        condition = condition.add(Condition::any().add(Column::ActorId.eq(user.id)).add(Column::UserId.eq(user.id)));
+    }
+    if let Some(event) = event {
+        condition = condition.add(Column::Event.eq(event.as_str()));
+    }
+    let target = target.trim();
+    if !target.is_empty() {
+        let Some((kind, id)) = target.split_once(':') else {
+            return Err(ServerFnError::ServerError("Targets look like type:id, e.g. mod:<id>".to_string()));
+        };
+        condition = condition.add(Column::TargetType.eq(kind)).add(Column::TargetId.eq(id));
+    }
+
+    Ok(crate::auth::audit::entries(&db(), condition, before).await?)
+}
+
+#[component]
+pub fn AdminAuditPage() -> impl IntoView {
+    let query = use_query_map();
+    let username = Memo::new(move |_| query.read().get("user").unwrap_or_default());
+    let event = Memo::new(move |_| query.read().get_str("event").and_then(|event| event.parse::<AuditEvent>().ok()));
+    let target = Memo::new(move |_| query.read().get("target").unwrap_or_default());
+
+    let username_input = RwSignal::new(username.get_untracked());
+    let event_input = RwSignal::new(event.get_untracked().map(|event| event.as_str().to_string()).unwrap_or_default());
+    let target_input = RwSignal::new(target.get_untracked());
+    let navigate = use_navigate();
+
+    let before = RwSignal::new(None::<Cursor>);
+    let entries = Resource::new(
+        move || (username.get(), event.get(), target.get(), before.get()),
+        |(username, event, target, before)| search_audit_log(username, event, target, before),
+    );
+
+    view! {
+        <Shell>
+            <div class="w-full max-w-screen-md mx-auto my-8">
+                <crate::create::SessionRequiredBanner />
+                <h1 class="text-2xl font-bold mb-4">"Audit log"</h1>
+                <form
+                    class="flex flex-wrap gap-2 mb-8"
+                    on:submit=move |ev| {
+                        ev.prevent_default();
+                        let params = [("user", username_input.get()), ("event", event_input.get()), ("target", target_input.get())]
+                            .into_iter()
+                            .filter(|(_, value)| !value.trim().is_empty())
+                            .map(|(key, value)| format!("{key}={}", leptos_router::location::Url::escape(value.trim())))
+                            .collect::<Vec<_>>()
+                            .join("&");
+                        before.set(None);
+                        navigate(&format!("/admin/audit?{params}"), Default::default());
+                    }
+                >
+                    <input
+                        type="text"
+                        placeholder="Username"
+                        bind:value=username_input
+                        class="grow p-2 border-2 border-stone-500 text-stone-200 bg-stone-700 rounded-sm"
+                    />
+                    <select bind:value=event_input class="p-2 border-2 border-stone-500 text-stone-200 bg-stone-700 rounded-sm">
+                        <option value="">"Any event"</option>
+                        {AuditEvent::ALL.into_iter().map(|event| view! {
+                            <option value=event.as_str()>{event.as_str()}</option>
+                        }).collect_view()}
+                    </select>
+                    <input
+                        type="text"
+                        placeholder="Target, e.g. mod:<id>"
+                        bind:value=target_input
+                        class="grow p-2 border-2 border-stone-500 text-stone-200 bg-stone-700 rounded-sm"
+                    />
+                    <button type="submit" class="bg-yellow-600 text-white font-semibold select-none shadow-sm py-2 px-3 rounded inline-flex items-center justify-center gap-2">
+                        <Icon icon=MAGNIFYING_GLASS weight=IconWeight::Bold />
+                        "Search"
+                    </button>
+                </form>
+                <Transition fallback=|| {}>
+                    {move || entries.get().map(|result| match result {
+                        Ok(entries) => view! {
+                            <AuditEntryList entries=entries.clone() admin=true />
+                            <Pagination oldest=next_page(&entries) before=before />
+                        }.into_any(),
+                        Err(error) => view! { <ErrorMessage message=Some(error.to_string()) /> }.into_any(),
+                    })}
+                </Transition>
+            </div>
+        </Shell>
+    }
+}
@@ -0,0 +1,224 @@
+use crate::prelude::*;
+
+use phosphor_leptos::{Icon, IconWeight, EYE};
+use time::OffsetDateTime;
+
+use crate::account::ErrorMessage;
+
+/// What the banner shows while an admin is viewing the site as someone else.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImpersonationInfo {
+    username: String,
+    read_only: bool,
+    expires_at: OffsetDateTime,
+}
+
+#[server]
+async fn current_impersonation() -> Result<Option<ImpersonationInfo>, ServerFnError> {
+    let session = session().await;
+    let Some(actor) = session.impersonator() else {
+        return Ok(None);
+    };
+    let Some(impersonation) = Impersonations::find_by_id(actor.impersonation_id).one(&db()).await? else {
+        return Ok(None);
+    };
+    let Some(user) = session.user().await? else {
+        return Ok(None);
+    };
+    Ok(Some(ImpersonationInfo {
+        username: user.username,
+        read_only: impersonation.read_only,
+        expires_at: impersonation.expires_at,
+    }))
+}
+
+/// Switches the signed-in admin's session to viewing the site as `username`, for helping them with a problem. Unless
+/// `allow_changes` is set, they can only look.
+#[server]
+async fn start_impersonation(username: String, reason: String, allow_changes: bool) -> Result<(), ServerFnError> {
+    use sea_orm::Set;
+    use crate::auth::{
+        Scope,
+        audit::{AuditEvent, Entry, Target},
+        cookie::set_cookie,
+        impersonation::IMPERSONATION_LENGTH,
+        refresh::{ACCESS_COOKIE, ACCESS_TOKEN_LIFETIME_SECONDS, session_claims},
+        roles,
+    };
+
+    let session = session().await;
+    let (Some(actor_id), Some(session_id)) = (session.account_uuid(), session.id()) else {
+        return Err(ServerFnError::ServerError("Must be signed in".to_string()));
+    };
+    if session.impersonator().is_some() {
+        return Err(ServerFnError::ServerError("Stop viewing the site as the current user first".to_string()));
+    }
+    if !session.has_scope(Scope::ImpersonateUsers) {
+        return Err(ServerFnError::ServerError("You don't have permission to view the site as other users".to_string()));
+    }
+    let reason = reason.trim();
+    if reason.is_empty() {
+        return Err(ServerFnError::ServerError("Give a reason for the audit log".to_string()));
+    }
+    let user = super::find_user(&username).await?;
+    if user.id == actor_id {
+        return Err(ServerFnError::ServerError("That's you".to_string()));
+    }
+    // Viewing the site as someone must not give an admin access they don't already have
+    let actor_scopes = roles::scopes_for(&db(), actor_id).await?;
+    if !roles::scopes_for(&db(), user.id).await?.is_subset(&actor_scopes) {
+        return Err(ServerFnError::ServerError("That user has access you don't have".to_string()));
+    }
+    let Some(record) = Sessions::find_by_id(session_id).one(&db()).await? else {
+        return Err(ServerFnError::ServerError("Must be signed in".to_string()));
+    };
+
+    let now = OffsetDateTime::now_utc();
+    let impersonation = entity::impersonations::ActiveModel {
+        id: Set(Uuid::new_v4()),
+        actor_id: Set(actor_id),
+        user_id: Set(user.id),
+        session_id: Set(session_id),
+        reason: Set(reason.to_string()),
+        read_only: Set(!allow_changes),
+        started_at: Set(now),
+        expires_at: Set(now + IMPERSONATION_LENGTH),
+        ended_at: Set(None),
+    }.insert(&db()).await?;
+    Entry::new(AuditEvent::ImpersonationStarted, Some(actor_id))
+        .user(user.id)
+        .target(Target::Impersonation(impersonation.id))
+        .details(serde_json::json!({ "reason": reason, "read_only": impersonation.read_only }))
+        .record(&db())
+        .await?;
+
+    let claims = session_claims(&db(), &record).await?;
+    set_cookie(ACCESS_COOKIE, &claims.encode()?, ACCESS_TOKEN_LIFETIME_SECONDS);
+    log::info!("user {actor_id} started viewing the site as {} ({})", user.username, impersonation.id);
+    Ok(())
+}
+
+/// Switches the session back to the admin, returning the username they were viewing the site as.
+#[server]
+async fn stop_impersonation() -> Result<String, ServerFnError> {
+    use entity::impersonations::Column;
+    use crate::auth::{
+        audit::{AuditEvent, Entry, Target},
+        cookie::set_cookie,
+        refresh::{ACCESS_COOKIE, ACCESS_TOKEN_LIFETIME_SECONDS, session_claims},
+    };
+
+    let session = session().await;
+    let (Some(actor), Some(user_id), Some(session_id)) = (session.impersonator(), session.uuid(), session.id()) else {
+        return Err(ServerFnError::ServerError("You aren't viewing the site as anyone".to_string()));
+    };
+    let Some(record) = Sessions::find_by_id(session_id).one(&db()).await? else {
+        return Err(ServerFnError::ServerError("Must be signed in".to_string()));
+    };
+
+    Impersonations::update_many()
+        .col_expr(Column::EndedAt, Expr::value(OffsetDateTime::now_utc()))
+        .filter(Column::Id.eq(actor.impersonation_id))
+        .filter(Column::EndedAt.is_null())
+        .exec(&db())
+        .await?;
+    Entry::new(AuditEvent::ImpersonationEnded, Some(actor.sub))
+        .user(user_id)
+        .target(Target::Impersonation(actor.impersonation_id))
+        .record(&db())
+        .await?;
+
+    let claims = session_claims(&db(), &record).await?;
+    set_cookie(ACCESS_COOKIE, &claims.encode()?, ACCESS_TOKEN_LIFETIME_SECONDS);
+    log::info!("user {} stopped impersonation {}", actor.sub, actor.impersonation_id);
+    Ok(session.user().await?.map(|user| user.username).unwrap_or_default())
+}
+
+/// Reminds an admin who is viewing the site as someone else that they are, and lets them stop.
+#[component]
+pub fn ImpersonationBanner() -> impl IntoView {
+    let impersonation = Resource::new(|| (), |_| current_impersonation());
+    let stop = Action::new(|_: &()| async move {
+        match stop_impersonation().await {
+            Ok(username) => window().location().set_href(&format!("/admin/users/{username}")).expect("failed to redirect after impersonating"),
+            Err(error) => log::error!("failed to stop impersonating: {error}"),
+        }
+    });
+
+    view! {
+        <Transition fallback=|| {}>
+            {move || impersonation.get().and_then(Result::ok).flatten().map(|ImpersonationInfo { username, read_only, expires_at }| {
+                let format = time::macros::format_description!("[hour]:[minute] UTC");
+                let until = expires_at.to_offset(time::UtcOffset::UTC).format(&format).unwrap_or_default();
+                view! {
+                    <section role="status" class="sticky top-0 z-10 flex items-center gap-4 p-4 pl-16 bg-yellow-600 text-white">
+                        <Icon icon=EYE weight=IconWeight::Bold size="20px" />
+                        <p class="grow">
+                            "You're viewing the site as " <strong>{username}</strong> " until " {until} ". "
+                            {if read_only { "You can't change anything." } else { "You can edit their mods." }}
+                        </p>
+                        <button
+                            class="bg-stone-800 font-semibold select-none shadow-sm py-2 px-3 rounded"
+                            on:click=move |_| { stop.dispatch(()); }
+                        >
+                            "Stop"
+                        </button>
+                    </section>
+                }
+            })}
+        </Transition>
+    }
+}
+
+/// Starts viewing the site as `username`, on the admin users page.
+#[component]
+pub fn ImpersonateForm(username: String) -> impl IntoView {
+    let reason = RwSignal::new(String::new());
+    let allow_changes = RwSignal::new(false);
+    let start = Action::new(move |(username, reason, allow_changes): &(String, String, bool)| {
+        let (username, reason, allow_changes) = (username.clone(), reason.clone(), *allow_changes);
+        async move {
+            let result = start_impersonation(username, reason, allow_changes).await;
+            if result.is_ok() {
+                window().location().set_href("/").expect("failed to redirect after impersonating");
+            }
+            result
+        }
+    });
+    let error = Signal::derive(move || start.value().get().and_then(Result::err).map(|error| error.to_string()));
+
+    view! {
+        <h3 class="text-lg font-semibold mt-8 mb-2">"View as this user"</h3>
+        <p class="text-stone-400 mb-4">
+            "See the site the way they do for the next 30 minutes, to help them with a problem. They can see that you did in their account activity."
+        </p>
+        <ErrorMessage message=error />
+        <form
+            class="flex flex-wrap items-center gap-4"
+            on:submit=move |event| {
+                event.prevent_default();
+                start.dispatch((username.clone(), reason.get_untracked(), allow_changes.get_untracked()));
+            }
+        >
+            <input
+                type="text"
+                placeholder="Reason"
+                required
+                bind:value=reason
+                class="grow p-2 border-2 border-stone-500 text-stone-200 bg-stone-700 rounded-sm"
+            />
+            <label class="flex items-center gap-2">
+                <input type="checkbox" bind:checked=allow_changes />
+                "Allow editing their mods"
+            </label>
+            <button
+                type="submit"
+                disabled=move || start.pending().get()
+                class="bg-yellow-600 text-white font-semibold select-none shadow-sm py-2 px-3 rounded inline-flex items-center justify-center gap-2"
+            >
+                <Icon icon=EYE weight=IconWeight::Bold />
+                "Start"
+            </button>
+        </form>
+    }
+}
@@ -42,10 +42,24 @@ pub fn App() -> impl IntoView {
                 <Route path=path!("/browse") view=crate::browse::ShopPage />
                 <Route path=path!("/create") view=crate::create::DashboardPage />
                 <Route path=path!("/create/new") view=crate::create::NewModPage  />
+                <Route path=path!("/feed") view=crate::feed::FeedPage />
                 <Route path=path!("/community") view=HomePage />
                 <Route path=path!("/about") view=HomePage />
                 <Route path=path!("/auth") view=crate::auth::AuthPage />
+                <Route path=path!("/account/profile") view=crate::account::ProfilePage />
+                <Route path=path!("/account/security") view=crate::account::SecurityPage />
+                <Route path=path!("/account/sessions") view=crate::account::SessionsPage />
+                <Route path=path!("/account/activity") view=crate::account::ActivityPage />
+                <Route path=path!("/account/tokens") view=crate::account::TokensPage />
+                <Route path=path!("/account/apps") view=crate::account::AppsPage />
+                <Route path=path!("/account/data") view=crate::account::DataPage />
+                <Route path=path!("/oauth/authorize") view=crate::oauth::AuthorizePage />
+                <Route path=path!("/oauth/device") view=crate::oauth::DevicePage />
+                <Route path=path!("/admin/users") view=crate::admin::AdminUsersPage />
+                <Route path=path!("/admin/users/:username") view=crate::admin::AdminUsersPage />
+                <Route path=path!("/admin/audit") view=crate::admin::AdminAuditPage />
                 <Route path=path!("/mod/:slug") view=crate::browse::ModPage/>
+                <Route path=path!("/user/:username") view=crate::profile::UserProfilePage />
             </Routes>
         </Router>
     }
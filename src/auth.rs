@@ -3,42 +3,83 @@ use crate::prelude::*;
 use webauthn_rs_proto::{PublicKeyCredential, RequestChallengeResponse, RegisterPublicKeyCredential, CreationChallengeResponse};
 use phosphor_leptos::{Icon, IconWeight, WARNING};
 
+use recovery::{RecoveryCodes, RecoveryForm};
+
 #[cfg(feature = "ssr")]
-mod token;
+pub mod token;
+
+mod scope;
+
+pub mod return_to;
 
 #[cfg(feature = "ssr")]
-mod cookie;
+pub(crate) mod cookie;
 
 #[cfg(feature = "ssr")]
 pub mod session;
 
 #[cfg(feature = "ssr")]
-pub use token::Scope;
+pub mod refresh;
+
+#[cfg(feature = "ssr")]
+pub mod challenge;
+
+#[cfg(feature = "ssr")]
+pub mod keys;
+
+pub mod audit;
+
+pub mod recovery;
+
+#[cfg(feature = "ssr")]
+pub mod roles;
+
+#[cfg(feature = "ssr")]
+pub mod secret;
+
+#[cfg(feature = "ssr")]
+pub mod access_token;
+
+#[cfg(feature = "ssr")]
+pub mod sccache;
+
+#[cfg(feature = "ssr")]
+pub mod username;
+
+pub mod display_name;
+
+#[cfg(feature = "ssr")]
+pub mod cloning;
+
+#[cfg(feature = "ssr")]
+pub mod throttle;
+
+#[cfg(feature = "ssr")]
+pub mod impersonation;
+
+#[cfg(feature = "ssr")]
+pub mod attestation;
+
+pub use scope::Scope;
 
 #[server]
 pub async fn logout() -> Result<(), ServerFnError> {
-    session().logout();
+    session().await.logout().await?;
     Ok(())
 }
 
 #[server]
 pub async fn is_logged_in() -> Result<bool, ServerFnError> {
-    Ok(session().is_logged_in())
+    Ok(session().await.is_logged_in())
 }
 
-#[server]
-async fn user_exists(username: String) -> Result<bool, ServerFnError> {
-    Ok(Users::find()
-        .filter(entity::users::Column::Username.eq(username))
-        .one(&db())
-        .await
-        .map_err(|e| ServerFnError::new(e.to_string()))?
-        .is_some())
-}
+/// Shown as is when a passkey endpoint is throttling the client.
+pub const TOO_MANY_ATTEMPTS: &str = "Too many attempts. Please wait a while and try again.";
 
 #[component]
 pub fn AuthPage() -> impl IntoView {
     let is_logged_in = OnceResource::new_blocking(is_logged_in());
+    let recovering = RwSignal::new(false);
 
     view! {
         <Shell>
@@ -46,7 +87,11 @@ pub fn AuthPage() -> impl IntoView {
                 <div class="max-w-sm w-full p-8 lg:p-16 bg-stone-100 crumpled-paper shadow-md">
                     <h1 class="text-4xl font-black text-stone-600 mb-5">"Welcome!"</h1>
                     <p class="text-stone-400 text-sm mb-7">
-                        "Enter your username to sign in or sign up."
+                        {move || if recovering.get() {
+                            "Enter your username and one of your recovery codes to register a new passkey."
+                        } else {
+                            "Enter your username to sign in or create an account."
+                        }}
                     </p>
 
                     <Suspense fallback=|| {}>
@@ -67,7 +112,12 @@ pub fn AuthPage() -> impl IntoView {
                                         </button>
                                     }
                                 }>
-                                    <LoginForm />
+                                    <Show when=move || recovering.get() fallback=|| view! { <LoginForm /> }>
+                                        <RecoveryForm />
+                                    </Show>
+                                    <button class="text-stone-400 hover:text-stone-500 text-xs underline mt-4" on:click=move |_| recovering.update(|recovering| *recovering = !*recovering)>
+                                        {move || if recovering.get() { "Back to sign in" } else { "Lost your passkeys? Use a recovery code" }}
+                                    </button>
                                 </Show>
                             }
                         })}
@@ -78,21 +128,31 @@ pub fn AuthPage() -> impl IntoView {
     }
 }
 
+/// Which button the user pressed. Signing in never reveals whether the username exists, so the user has to say.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Intent {
+    SignIn,
+    CreateAccount,
+}
+
 #[component]
 fn LoginForm() -> impl IntoView {
     let username = RwSignal::new("".to_string());
     let username_error = RwSignal::new(None::<UsernameValidationError>);
+    let recovery_codes = RwSignal::new(None::<Vec<String>>);
+    // Errors from signing in through autofill, which doesn't go through `login`
+    let autofill_error = RwSignal::new(None::<String>);
+    let return_path = return_to::use_return_path();
 
-    let login: Action<_, Result<()>> = Action::new_local(|username: &String| {
-        let username = username.to_owned();
+    let login: Action<_, Result<()>> = Action::new_local(move |(username, intent): &(String, Intent)| {
+        let (username, intent) = (username.to_owned(), *intent);
         async move {
             #[cfg(feature = "hydrate")]
-            match user_exists(username.clone()).await {
-                Ok(true) => {
-                    // Login
+            match intent {
+                Intent::SignIn => {
                     let (challenge, id) = passkey::start_login(username).await.map_err(|error| {
                         log::error!("failed to get login challenge: {error:?}");
-                        anyhow::anyhow!("Failed to get login challenge")
+                        passkey::user_error(error, "Failed to get login challenge")
                     })?;
                     let c_options: web_sys::CredentialRequestOptions = challenge.into();
                     let promise = window()
@@ -101,49 +161,29 @@ fn LoginForm() -> impl IntoView {
                         .get_with_options(&c_options)
                         .expect_throw("unable to create promise");
                     let credential = web_sys::PublicKeyCredential::from(JsFuture::from(promise).await.map_err(|error| {
-                        // User probably cancelled the prompt
+                        // User probably cancelled the prompt, or has no passkey for this username
                         log::error!("failed to get credential: {error:?}");
                         anyhow::anyhow!("Failed to get passkey")
                     })?);
-                    passkey::finish_login(id, PublicKeyCredential::from(credential)).await.map_err(|error| {
+                    let flagged = passkey::finish_login(id, PublicKeyCredential::from(credential)).await.map_err(|error| {
                         log::error!("failed to finish passkey login: {error:?}");
-                        anyhow::anyhow!("Bad credentials")
+                        passkey::user_error(error, "Bad credentials")
                     })?;
 
-                    window().location().set_href("/").expect("failed to redirect to home page");
+                    // Show the user the warning on their possibly cloned passkey
+                    let destination = if flagged { "/account/security".to_string() } else { return_path.get_untracked() };
+                    window().location().set_href(&destination).expect("failed to redirect after login");
                 }
-                Ok(false) => {
-                    // Register
-                    let ccr = passkey::start_register(username.clone()).await.map_err(|error| {
-                        log::error!("failed to start passkey registration: {error:?}");
-                        anyhow::anyhow!("Username already taken") // likely problem
-                    })?;
-                    let c_options: web_sys::CredentialCreationOptions = ccr.into();
-                    let promise = window()
-                        .navigator()
-                        .credentials()
-                        .create_with_options(&c_options)
-                        .expect_throw("unable to create promise");
-                    let credential = web_sys::PublicKeyCredential::from(JsFuture::from(promise).await.map_err(|error| {
-                        // User probably cancelled the prompt
-                        log::error!("failed to create credential: {error:?}");
-                        anyhow::anyhow!("Failed to create passkey")
-                    })?);
-                    passkey::finish_register(username, RegisterPublicKeyCredential::from(credential)).await.map_err(|error| {
-                        log::error!("failed to finish passkey registration: {error:?}");
-                        anyhow::anyhow!("Failed to register passkey and/or create user")
-                    })?;
-
-                    window().location().set_href("/").expect("failed to redirect to home page");
-                }
-                Err(error) => {
-                    log::error!("failed to check if user exists: {error:?}");
-                    return Err(anyhow::anyhow!("Failed to check if user exists"));
+                Intent::CreateAccount => {
+                    match passkey::register(username).await? {
+                        Some(codes) => recovery_codes.set(Some(codes)),
+                        None => window().location().set_href(&return_path.get_untracked()).expect("failed to redirect after registering"),
+                    }
                 }
             }
             #[cfg(not(feature = "hydrate"))]
             {
-                let _ = username;
+                let _ = (username, intent, recovery_codes);
             }
             Ok(())
         }
@@ -164,7 +204,13 @@ fn LoginForm() -> impl IntoView {
                 return;
             }
 
-            let (challenge, id) = passkey::start_discoverable_login().await.expect_throw("failed to get login challenge");
+            let (challenge, id) = match passkey::start_discoverable_login().await {
+                Ok(challenge) => challenge,
+                Err(error) => {
+                    log::error!("failed to get login challenge: {error:?}");
+                    return;
+                }
+            };
 
             let c_options: web_sys::CredentialRequestOptions = challenge.into();
             c_options.set_signal(&signal);
@@ -181,8 +227,16 @@ fn LoginForm() -> impl IntoView {
                 }
                 return;
             };
-            passkey::finish_discoverable_login(id, web_sys::PublicKeyCredential::from(credential).into()).await.expect_throw("failed to finish passkey login");
-            window().location().set_href("/").expect("failed to redirect to home page");
+            let flagged = match passkey::finish_discoverable_login(id, web_sys::PublicKeyCredential::from(credential).into()).await {
+                Ok(flagged) => flagged,
+                Err(error) => {
+                    log::error!("failed to finish passkey login: {error:?}");
+                    autofill_error.set(Some(passkey::user_error(error, "Bad credentials").to_string()));
+                    return;
+                }
+            };
+            let destination = if flagged { "/account/security".to_string() } else { return_path.get_untracked() };
+            window().location().set_href(&destination).expect("failed to redirect after login");
         });
 
         struct AbortOnDrop(web_sys::AbortController);
@@ -194,47 +248,64 @@ fn LoginForm() -> impl IntoView {
         AbortOnDrop(controller)
     });
 
+    // Newly created users see their recovery codes before continuing
+    let show_recovery_codes = move || view! {
+        <RecoveryCodes
+            codes=recovery_codes.get().unwrap_or_default()
+            on_done=move || window().location().set_href(&return_path.get_untracked()).expect("failed to redirect after registering")
+        />
+    };
+
     view! {
-        <noscript>"Please enable JavaScript to authenticate."</noscript>
+        <Show when=move || recovery_codes.read().is_none() fallback=show_recovery_codes>
+            <noscript>"Please enable JavaScript to authenticate."</noscript>
+
+            <div>
+                <input type="text" autocomplete="username webauthn" autofocus maxlength=20 placeholder="Username" bind:value=username on:change={move |_| {
+                    if username.get().is_empty() {
+                        username_error.set(None);
+                    }
+                    username_error.set(check_username_validity(&username.get()).err());
+                }} class="border border-stone-200 py-1.5 px-4 rounded-md w-full bg-white text-stone-800 placeholder-stone-400" />
+
+                <div class="mt-1 text-xs text-red-500 min-h-5 flex items-center gap-1" aria-live="polite">
+                    <Show when={move || username_error.get().is_some()}>
+                        <Icon icon=WARNING weight=IconWeight::Fill />
+                    </Show>
+                    {move || username_error.get().map(|e| e.to_string())}
+                </div>
+            </div>
 
-        <div>
-            <input type="text" autocomplete="username webauthn" autofocus maxlength=20 placeholder="Username" bind:value=username on:change={move |_| {
-                if username.get().is_empty() {
-                    username_error.set(None);
-                }
-                username_error.set(check_username_validity(&username.get()).err());
-            }} class="border border-stone-200 py-1.5 px-4 rounded-md w-full bg-white text-stone-800 placeholder-stone-400" />
+            <div class="flex flex-col gap-2 mt-3">
+                <button
+                    class="bg-yellow-500 hover:bg-yellow-600 text-white font-semibold py-1.5 px-4 rounded w-full flex items-center justify-center gap-2"
+                    on:click={move |_| { login.dispatch((username.get(), Intent::SignIn)); }}
+                    disabled={move || username.get().is_empty() || username_error.get().is_some()}
+                >
+                    <img src="/FIDO_Passkey_mark_A_white.svg" class="w-6 h-6" />
+                    "Sign in with passkey"
+                </button>
+                <button
+                    class="border border-yellow-500 text-yellow-600 hover:bg-yellow-50 font-semibold py-1.5 px-4 rounded w-full"
+                    on:click={move |_| { login.dispatch((username.get(), Intent::CreateAccount)); }}
+                    disabled={move || username.get().is_empty() || username_error.get().is_some()}
+                >
+                    "Create account"
+                </button>
+            </div>
 
             <div class="mt-1 text-xs text-red-500 min-h-5 flex items-center gap-1" aria-live="polite">
-                <Show when={move || username_error.get().is_some()}>
+                <Show when={move || login.value().read().as_ref().is_some_and(|r| r.is_err()) || autofill_error.read().is_some()}>
                     <Icon icon=WARNING weight=IconWeight::Fill />
                 </Show>
-                {move || username_error.get().map(|e| e.to_string())}
+                {move || login.value().read().as_ref().and_then(|r| r.as_ref().err().map(|e| e.to_string())).or_else(|| autofill_error.get())}
             </div>
-        </div>
-
-        <div class="flex items-center justify-between mt-3">
-            <button
-                class="bg-yellow-500 hover:bg-yellow-600 text-white font-semibold py-1.5 px-4 rounded w-full flex items-center justify-center gap-2"
-                on:click={move |_| { login.dispatch(username.get()); }}
-                disabled={move || username.get().is_empty() || username_error.get().is_some()}
-            >
-                <img src="/FIDO_Passkey_mark_A_white.svg" class="w-6 h-6" />
-                "Continue with passkey"
-            </button>
-        </div>
-
-        <div class="mt-1 text-xs text-red-500 min-h-5 flex items-center gap-1" aria-live="polite">
-            <Show when={move || login.value().read().as_ref().is_some_and(|r| r.is_err())}>
-                <Icon icon=WARNING weight=IconWeight::Fill />
-            </Show>
-            {move || login.value().read().as_ref().map(|r| r.as_ref().err().map(|e| e.to_string()))}
-        </div>
+        </Show>
     }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
-enum UsernameValidationError {
+pub enum UsernameValidationError {
     #[error("Too short, must be at least 4 characters")]
     TooShort,
     #[error("Too long, must be at most 20 characters")]
@@ -247,16 +318,18 @@ enum UsernameValidationError {
     Banned,
 }
 
-fn normalize_username(username: &str) -> String {
+pub(crate) fn normalize_username(username: &str) -> String {
     username.trim().to_lowercase().replace('-', "_").replace('1', "l")
 }
 
-fn check_username_validity(username: &str) -> Result<(), UsernameValidationError> {
-    let banlist = [
-        "admin", "administrator", "root", "superuser", "test", "guest", "anon", "anonymous",
-        "support", "info", "contact", "webmaster", "sysadmin", "system", "service", "starhaven", "star_haven",
-    ];
-    if banlist.contains(&normalize_username(username).as_str()) {
+/// Names nobody may use, normalized, because they could pass for the site or its staff
+pub(crate) const RESERVED_NAMES: [&str; 17] = [
+    "admin", "administrator", "root", "superuser", "test", "guest", "anon", "anonymous",
+    "support", "info", "contact", "webmaster", "sysadmin", "system", "service", "starhaven", "star_haven",
+];
+
+pub(crate) fn check_username_validity(username: &str) -> Result<(), UsernameValidationError> {
+    if RESERVED_NAMES.contains(&normalize_username(username).as_str()) {
         return Err(UsernameValidationError::Banned);
     }
     if username.len() < 4 {
@@ -280,94 +353,226 @@ pub mod passkey {
 
     use cfg_if::cfg_if;
 
+    /// Why signing in failed when the passkey may have been cloned and the policy blocks it.
+    pub const PASSKEY_BLOCKED: &str = "This passkey may have been copied, so it can't be used to sign in. Use another passkey, or a recovery code to add a new one.";
+
+    /// Why registering a passkey failed when the attestation policy applies to the user and the passkey isn't from a
+    /// trusted authenticator.
+    pub const ATTESTATION_REQUIRED: &str = "Your account needs a passkey on one of the security keys this site trusts, and this isn't one of them.";
+
     cfg_if! {
         if #[cfg(feature = "ssr")] {
-            use std::collections::HashMap;
-            use std::sync::LazyLock;
-            use tokio::sync::RwLock;
-
             use webauthn_rs::prelude::*;
 
-            fn webauthn() -> Webauthn {
-                // TODO: prod
-                let rp_id = "localhost";
-                let rp_origin = Url::parse("http://localhost").expect("valid rp_origin");
-                WebauthnBuilder::new(rp_id, &rp_origin).expect("compatible rp_id and rp_origin")
-                    .rp_name("Star Haven")
-                    .allow_any_port(true)
-                    .build()
-                    .expect("valid configuration")
+            use std::sync::Arc;
+
+            /// Builds the relying party from configuration. Called once at startup.
+            pub fn build_webauthn(config: &crate::config::WebauthnConfig) -> WebauthnResult<Webauthn> {
+                let (first, rest) = config.origins.split_first().ok_or(WebauthnError::Configuration)?;
+                let mut builder = WebauthnBuilder::new(&config.rp_id, first)?
+                    .rp_name(&config.rp_name)
+                    .allow_subdomains(config.allow_subdomains)
+                    .allow_any_port(config.allow_any_port);
+                for origin in rest {
+                    builder = builder.append_allowed_origin(origin);
+                }
+                builder.build()
+            }
+
+            pub(crate) fn webauthn() -> Arc<Webauthn> {
+                expect_context()
+            }
+
+            use crate::auth::challenge::{Ceremony, challenges};
+
+            /// State held between start_register and finish_register calls.
+            #[derive(Serialize, Deserialize)]
+            struct RegistrationState {
+                username: String,
+                /// The user's uuid, or the one they will have if this registration creates them
+                user_id: Uuid,
+                registration: crate::auth::attestation::Registration,
+            }
+
+            /// State held between start_login and finish_login calls.
+            #[derive(Serialize, Deserialize)]
+            struct LoginState {
+                username: String,
+                /// `None` if the user doesn't exist or has no passkeys, in which case the client got a decoy challenge
+                authentication: Option<PasskeyAuthentication>,
             }
 
-            /// Map of usernames to future user uuids / passkey registration challenges. Temporary state held between start_register and finish_register calls.
-            static REGISTER_CHALLENGES: LazyLock<RwLock<HashMap<String, (Uuid, PasskeyRegistration)>>> = LazyLock::new(|| RwLock::new(HashMap::new()));
+            /// Why a sign-in failed, whatever the reason, so that failures don't reveal whether the username exists.
+            const SIGN_IN_FAILED: &str = "Sign-in failed.";
+
+            /// Why creating an account failed when the username was taken. Starting registration doesn't say, for the
+            /// same reason as [`SIGN_IN_FAILED`].
+            const REGISTRATION_FAILED: &str = "Registration failed.";
+
+            /// Common passkey credential ID lengths, in bytes: platform authenticators use 16 to 32, security keys that
+            /// wrap the key in the ID use 64 or more.
+            const DECOY_ID_LENGTHS: [usize; 6] = [16, 20, 32, 48, 64, 80];
+
+            /// A challenge that looks like one for a user with passkeys, for usernames that don't exist. How many
+            /// credential IDs it has and how long each is are derived from the username, so asking twice gives the same
+            /// ones. Real challenges don't list transports, so neither does this.
+            fn decoy_challenge(username: &str) -> WebauthnResult<RequestChallengeResponse> {
+                use webauthn_rs_proto::AllowCredentials;
+
+                let keyring = crate::auth::keys::keyring();
+                let username = normalize_username(username);
+                let shape = keyring.derive("decoy shape", username.as_bytes());
+                // Most people have one passkey, some a backup or two
+                let count = match shape[0] % 8 {
+                    0..=4 => 1,
+                    5 | 6 => 2,
+                    _ => 3,
+                };
 
-            /// Map of random id to passkey authentication state
-            static LOGIN_CHALLENGES: LazyLock<RwLock<HashMap<Uuid, PasskeyAuthentication>>> = LazyLock::new(|| RwLock::new(HashMap::new()));
+                let (mut challenge, _) = webauthn().start_discoverable_authentication()?;
+                challenge.mediation = None;
+                challenge.public_key.extensions = None;
+                challenge.public_key.allow_credentials = (0..count)
+                    .map(|index| {
+                        let length = DECOY_ID_LENGTHS[usize::from(shape[1 + index]) % DECOY_ID_LENGTHS.len()];
+                        let mut id = Vec::with_capacity(length);
+                        for block in 0u8.. {
+                            if id.len() >= length {
+                                break;
+                            }
+                            let input = [username.as_bytes(), &[0, index as u8, block]].concat();
+                            id.extend(keyring.derive("decoy credential", &input));
+                        }
+                        id.truncate(length);
+                        AllowCredentials { type_: "public-key".to_string(), id: id.into(), transports: None }
+                    })
+                    .collect();
+                Ok(challenge)
+            }
 
-            static DISCOVERABLE_LOGIN_CHALLENGES: LazyLock<RwLock<HashMap<Uuid, DiscoverableAuthentication>>> = LazyLock::new(|| RwLock::new(HashMap::new()));
+            /// Identifies the client for the purpose of limiting outstanding challenges. Requests whose IP address isn't
+            /// known each get a random key rather than sharing one, so that they can't push out each other's challenges.
+            pub(crate) fn challenge_client() -> String {
+                crate::request::client_ip().map(|ip| ip.to_string()).unwrap_or_else(|| Uuid::new_v4().to_string())
+            }
         }
     }
 
+    /// Turns an error from a passkey endpoint into one to show the user: `fallback`, unless the server's message is one
+    /// meant for them.
+    #[cfg(feature = "hydrate")]
+    pub fn user_error(error: ServerFnError, fallback: &str) -> anyhow::Error {
+        match error {
+            ServerFnError::ServerError(message) if [TOO_MANY_ATTEMPTS, PASSKEY_BLOCKED, ATTESTATION_REQUIRED].contains(&message.as_str()) => anyhow::anyhow!(message),
+            _ => anyhow::anyhow!(fallback.to_string()),
+        }
+    }
+
+    /// Asks the browser's authenticator to create a passkey in response to `ccr`.
+    #[cfg(feature = "hydrate")]
+    pub async fn create_credential(ccr: CreationChallengeResponse) -> Result<RegisterPublicKeyCredential> {
+        let c_options: web_sys::CredentialCreationOptions = ccr.into();
+        let promise = window()
+            .navigator()
+            .credentials()
+            .create_with_options(&c_options)
+            .expect_throw("unable to create promise");
+        let credential = web_sys::PublicKeyCredential::from(JsFuture::from(promise).await.map_err(|error| {
+            // User probably cancelled the prompt
+            log::error!("failed to create credential: {error:?}");
+            anyhow::anyhow!("Failed to create passkey")
+        })?);
+        Ok(RegisterPublicKeyCredential::from(credential))
+    }
+
+    /// Creates a passkey with the browser's authenticator and registers it to `username`. If nobody is signed in, this
+    /// creates the user and signs them in, returning their recovery codes; otherwise `username` must be the signed-in
+    /// user.
+    #[cfg(feature = "hydrate")]
+    pub async fn register(username: String) -> Result<Option<Vec<String>>> {
+        let (ccr, id) = start_register(username).await.map_err(|error| {
+            log::error!("failed to start passkey registration: {error:?}");
+            user_error(error, "Invalid username") // likely problem
+        })?;
+        let credential = create_credential(ccr).await?;
+        finish_register(id, credential).await.map_err(|error| {
+            log::error!("failed to finish passkey registration: {error:?}");
+            user_error(error, "Failed to register passkey and/or create user. If you already have an account, log in instead.")
+        })
+    }
+
     #[server]
-    pub async fn start_register(username: String) -> Result<CreationChallengeResponse, ServerFnError> {
-        let existing_credentials = match session().user().await? {
+    pub async fn start_register(username: String) -> Result<(CreationChallengeResponse, Uuid), ServerFnError> {
+        let subjects = super::throttle::check("start_register", Some(&username))?;
+        super::throttle::record(&subjects, start_register_inner(username).await)
+    }
+
+    #[cfg(feature = "ssr")]
+    async fn start_register_inner(username: String) -> Result<(CreationChallengeResponse, Uuid), ServerFnError> {
+        let session = session().await;
+        if session.access_token_id().is_some() {
+            return Err(ServerFnError::new("Passkeys can't be registered with an access token."));
+        }
+
+        let (user_id, existing_credentials) = match session.user().await? {
             // Registering a new passkey for an existing user
             Some(user) => {
                 if user.username != username {
                     return Err(ServerFnError::new("Username does not match the logged-in user."));
                 }
 
-                Some(Passkeys::find()
+                (user.id, Some(Passkeys::find()
                     .filter(entity::passkeys::Column::UserId.eq(user.id))
                     .all(&db())
                     .await?
                     .into_iter()
                     .map(|passkey| passkey.id.into())
-                    .collect())
+                    .collect()))
             }
 
-            // Registering a new passkey for a new user
+            // Registering a new passkey for a new user. Whether the username is taken is only checked when finishing,
+            // so that this looks the same either way, like the decoy challenges from `start_login`.
             None => {
                 check_username_validity(&username)?;
-                if Users::find()
-                    .filter(entity::users::Column::UsernameNormalized.eq(normalize_username(&username)))
-                    .one(&db())
-                    .await?
-                    .is_some()
-                {
-                    return Err(ServerFnError::new("Username already in use, please log in instead."));
-                }
-                None
+                (Uuid::new_v4(), None)
             }
         };
 
-        let user_id = Uuid::new_v4();
-        let (ccr, skr) = webauthn().start_passkey_registration(
-            user_id,
-            &username,
-            &username,
-            existing_credentials,
-        )?;
-        if REGISTER_CHALLENGES.write().await.insert(username.clone(), (user_id, skr)).is_some() {
-            log::warn!("overwriting existing passkey registration challenge for username: {username}");
-        }
-        Ok(ccr)
+        // Discoverable passkeys tell us whose they are with this ID, so it must be the user's
+        let (ccr, registration) = super::attestation::start_registration(&db(), user_id, &username, existing_credentials).await?;
+        let state = RegistrationState { username, user_id, registration };
+        let id = challenges().insert(Ceremony::Register, &challenge_client(), &state).await?;
+        Ok((ccr, id))
     }
 
+    /// Returns the new user's recovery codes if this created a user.
     #[server]
-    pub async fn finish_register(username: String, reg: RegisterPublicKeyCredential) -> Result<(), ServerFnError> {
-        if let Some(user) = session().user().await? {
+    pub async fn finish_register(id: Uuid, reg: RegisterPublicKeyCredential) -> Result<Option<Vec<String>>, ServerFnError> {
+        let subjects = super::throttle::check("finish_register", None)?;
+        super::throttle::record(&subjects, finish_register_inner(id, reg).await)
+    }
+
+    #[cfg(feature = "ssr")]
+    async fn finish_register_inner(id: Uuid, reg: RegisterPublicKeyCredential) -> Result<Option<Vec<String>>, ServerFnError> {
+        use crate::auth::audit::{AuditEvent, Entry, Target};
+
+        let Some(RegistrationState { username, user_id, registration }) = challenges().take(Ceremony::Register, id).await? else {
+            return Err(ServerFnError::new("No registration challenge found."));
+        };
+
+        let mut session = session().await;
+        if session.access_token_id().is_some() {
+            return Err(ServerFnError::new("Passkeys can't be registered with an access token."));
+        }
+        let session_user = session.user().await?;
+        if let Some(user) = &session_user {
             if user.username != username {
                 return Err(ServerFnError::new("Username does not match the logged-in user."));
             }
         }
 
-        let Some((user_id, skr)) = REGISTER_CHALLENGES.write().await.remove(&username) else {
-            return Err(ServerFnError::new("No registration challenge found."));
-        };
-        let passkey = webauthn().finish_passkey_registration(&reg, &skr)?;
-        let id = passkey.cred_id().to_vec();
+        let passkey = super::attestation::finish_registration(&reg, &registration)?;
+        let id = passkey.id.clone();
 
         // Passkeys must not be registered to this user or another user
         if Passkeys::find_by_id(id.clone())
@@ -377,14 +582,20 @@ pub mod passkey {
         {
             return Err(ServerFnError::new("This passkey is already registered."));
         }
-        
-        db().transaction::<_, (), anyhow::Error>(|txn| {
+        if session_user.is_none() && !super::username::is_available(&db(), &username, None).await? {
+            log::info!("passkey registration attempted for taken username {username}");
+            return Err(ServerFnError::new(REGISTRATION_FAILED));
+        }
+
+
+        let is_new_user = session_user.is_none();
+        let (user, recovery_codes) = db().transaction::<_, (User, Option<Vec<String>>), anyhow::Error>(|txn| {
             Box::pin(async move {
                 use sea_orm::Set;
 
                 // Use the session user or create a new user if not logged in
-                let user = if let Some(user) = session().user().await? {
-                    user
+                let (user, recovery_codes) = if let Some(user) = session_user {
+                    (user, None)
                 } else {
                     let user = entity::users::ActiveModel {
                         id: Set(user_id),
@@ -392,106 +603,198 @@ pub mod passkey {
                         username_normalized: Set(normalize_username(&username)),
                         ..Default::default()
                     }.insert(txn).await?;
-                    session().login(&user)?;
-                    user
+                    let recovery_codes = super::recovery::replace_recovery_codes(txn, user.id).await?;
+                    (user, Some(recovery_codes))
                 };
 
                 entity::passkeys::ActiveModel {
-                    id: Set(id),
+                    id: Set(id.clone()),
                     user_id: Set(user.id),
-                    data: Set(serde_json::to_value(&passkey)?),
+                    data: Set(passkey.data),
+                    name: Set(crate::request::user_agent().map(|user_agent| crate::request::describe_user_agent(&user_agent))),
                     ..Default::default()
                 }.insert(txn).await?;
 
-                Ok(())
+                let event = if recovery_codes.is_some() { AuditEvent::AccountCreated } else { AuditEvent::PasskeyAdded };
+                let mut entry = Entry::new(event, Some(user.id)).user(user.id).target(Target::Passkey(id));
+                if let Some(authenticator) = passkey.authenticator {
+                    entry = entry.details(serde_json::json!({ "authenticator": authenticator.model }));
+                }
+                entry.record(txn).await?;
+
+                Ok((user, recovery_codes))
             })
         }).await?;
 
-        Ok(())
+        if is_new_user {
+            session.login(&user).await?;
+        }
+
+        Ok(recovery_codes)
     }
 
+    /// Always returns a challenge, so that it doesn't reveal whether the username exists. Unknown usernames get a decoy
+    /// that asks for a passkey nobody has.
     #[server]
     pub async fn start_login(username: String) -> Result<(RequestChallengeResponse, Uuid), ServerFnError> {
+        let subjects = super::throttle::check("start_login", Some(&username))?;
+        super::throttle::record_failure(&subjects, start_login_inner(username).await)
+    }
+
+    #[cfg(feature = "ssr")]
+    async fn start_login_inner(username: String) -> Result<(RequestChallengeResponse, Uuid), ServerFnError> {
+
         let passkeys = Passkeys::find()
             .filter(entity::passkeys::Column::UserId.in_subquery(
                 sea_orm::sea_query::Query::select()
                     .column(entity::users::Column::Id)
                     .from(entity::users::Entity)
-                    .and_where(entity::users::Column::Username.eq(&username))
+                    .and_where(entity::users::Column::UsernameNormalized.eq(normalize_username(&username)))
                     .to_owned()
             ))
             .all(&db())
             .await?
             .into_iter()
-            .map(|passkey| serde_json::from_value::<Passkey>(passkey.data).expect("valid passkey data"))
-            .collect::<Vec<Passkey>>();
-    
-        if passkeys.is_empty() {
-            return Err(ServerFnError::new("No passkeys found for this user."));
-        }
-
-        let (challenge, auth) = webauthn().start_passkey_authentication(&passkeys)?;
-        let id = Uuid::new_v4();
-        LOGIN_CHALLENGES.write().await.insert(id, auth);
+            .map(|passkey| super::cloning::for_authentication(passkey.data))
+            .collect::<Result<Vec<Passkey>, _>>()?;
+
+        let (challenge, authentication) = if passkeys.is_empty() {
+            (decoy_challenge(&username)?, None)
+        } else {
+            let (mut challenge, authentication) = webauthn().start_passkey_authentication(&passkeys)?;
+            // Transports are left out so that decoys don't have to guess them
+            for credential in &mut challenge.public_key.allow_credentials {
+                credential.transports = None;
+            }
+            (challenge, Some(authentication))
+        };
+        let state = LoginState { username, authentication };
+        let id = challenges().insert(Ceremony::Login, &challenge_client(), &state).await?;
         Ok((challenge, id))
     }
 
+    /// Signs in with a verified passkey, unless it may have been cloned and the policy says to block it. Returns whether
+    /// the passkey was flagged, so that the user can be shown it.
     #[cfg(feature = "ssr")]
-    async fn authenticate(authentication: AuthenticationResult) -> Result<(), ServerFnError> {
+    async fn authenticate(authentication: AuthenticationResult) -> Result<bool, ServerFnError> {
+        use sea_orm::Set;
+        use crate::auth::audit::{AuditEvent, Entry, Target};
+        use crate::config::CredentialPolicy;
+
         let passkey_id = authentication.cred_id().to_vec();
         let passkey_db = Passkeys::find_by_id(passkey_id.clone())
             .one(&db())
             .await?
             .ok_or_else(|| ServerFnError::new("Passkey not found."))?;
-        let mut passkey_data = serde_json::from_value::<Passkey>(passkey_db.data.clone()).expect("valid passkey data");
+        let mut passkey_data = serde_json::from_value::<Passkey>(passkey_db.data.clone())?;
         let user =  Users::find_by_id(passkey_db.user_id)
             .one(&db())
             .await?
             .ok_or_else(|| ServerFnError::new("User not found."))?;
 
-        assert!(passkey_data.update_credential(&authentication).is_some());
+        // Blocked passkeys stay blocked until their owner dismisses the warning
+        if passkey_db.flag_reason.as_deref().is_some_and(|reason| super::cloning::reason_policy(reason) == CredentialPolicy::Block) {
+            return Err(ServerFnError::new(PASSKEY_BLOCKED));
+        }
+
+        let anomalies = super::cloning::check(&passkey_db.data, &authentication);
+        if passkey_data.update_credential(&authentication).is_none() {
+            return Err(ServerFnError::new("Passkey doesn't match the sign-in."));
+        }
 
+        for anomaly in &anomalies {
+            log::warn!("passkey of user {} may have been cloned ({:?} policy): {anomaly:?}", user.id, anomaly.policy());
+        }
+        // Flag the passkey for the most serious one
+        let flag = anomalies.into_iter()
+            .filter(|anomaly| anomaly.policy() != CredentialPolicy::Allow)
+            .max_by_key(|anomaly| anomaly.policy() == CredentialPolicy::Block);
+
+        let name = passkey_db.name.clone();
+        // Keep what's stored next to the credential, such as the authenticator model
+        let mut data = passkey_db.data.clone();
+        data["cred"] = serde_json::to_value(&passkey_data)?["cred"].take();
         let mut passkey_db: entity::passkeys::ActiveModel = passkey_db.into();
-        passkey_db.data = sea_orm::Set(serde_json::to_value(&passkey_data)?);
-        passkey_db.last_used_at = sea_orm::Set(Some(time::OffsetDateTime::now_utc()));
+        passkey_db.data = Set(data);
+        passkey_db.last_used_at = Set(Some(time::OffsetDateTime::now_utc()));
+        if let Some(anomaly) = flag {
+            passkey_db.flagged_at = Set(Some(time::OffsetDateTime::now_utc()));
+            passkey_db.flag_reason = Set(Some(anomaly.reason().to_string()));
+        }
         passkey_db.update(&db()).await?;
 
-        session().login(&user)?;
-        Ok(())
+        if let Some(anomaly) = flag {
+            let mut details = anomaly.details();
+            details["name"] = name.into();
+            details["blocked"] = (anomaly.policy() == CredentialPolicy::Block).into();
+            Entry::new(AuditEvent::PasskeyFlagged, None)
+                .user(user.id)
+                .target(Target::Passkey(passkey_id))
+                .details(details)
+                .record(&db())
+                .await?;
+            if anomaly.policy() == CredentialPolicy::Block {
+                return Err(ServerFnError::new(PASSKEY_BLOCKED));
+            }
+        }
+
+        session().await.login(&user).await?;
+        Ok(flag.is_some())
     }
 
+    /// Returns whether the passkey was flagged as possibly cloned.
     #[server]
-    pub async fn finish_login(id: Uuid, credential: PublicKeyCredential) -> Result<(), ServerFnError> {
-        let Some(auth) = LOGIN_CHALLENGES.write().await.remove(&id) else {
+    pub async fn finish_login(id: Uuid, credential: PublicKeyCredential) -> Result<bool, ServerFnError> {
+        let Some(LoginState { username, authentication }) = challenges().take(Ceremony::Login, id).await? else {
             return Err(ServerFnError::new("Invalid challenge ID."));
         };
+        let subjects = super::throttle::check("finish_login", Some(&username))?;
 
-        authenticate(webauthn().finish_passkey_authentication(&credential, &auth)?).await
+        let result = match authentication.map(|authentication| webauthn().finish_passkey_authentication(&credential, &authentication)) {
+            Some(Ok(authentication)) => authenticate(authentication).await,
+            Some(Err(error)) => {
+                log::info!("passkey sign-in failed for {username}: {error}");
+                Err(ServerFnError::new(SIGN_IN_FAILED))
+            }
+            None => {
+                log::info!("passkey sign-in attempted for unknown user {username}");
+                Err(ServerFnError::new(SIGN_IN_FAILED))
+            }
+        };
+        super::throttle::record(&subjects, result)
     }
 
     #[server]
     pub async fn start_discoverable_login() -> Result<(RequestChallengeResponse, Uuid), ServerFnError> {
-        let (challenge, auth) = webauthn().start_discoverable_authentication()?;
-        let id = Uuid::new_v4();
-        DISCOVERABLE_LOGIN_CHALLENGES.write().await.insert(id, auth);
-        Ok((challenge, id))
+        let subjects = super::throttle::check("start_discoverable_login", None)?;
+        let result = async {
+            let (challenge, auth) = webauthn().start_discoverable_authentication()?;
+            let id = challenges().insert(Ceremony::DiscoverableLogin, &challenge_client(), &auth).await?;
+            Ok((challenge, id))
+        }.await;
+        super::throttle::record_failure(&subjects, result)
     }
 
+    /// Returns whether the passkey was flagged as possibly cloned.
     #[server]
-    pub async fn finish_discoverable_login(id: Uuid, credential: PublicKeyCredential) -> Result<(), ServerFnError> {
-        let Some(auth) = DISCOVERABLE_LOGIN_CHALLENGES.write().await.remove(&id) else {
+    pub async fn finish_discoverable_login(id: Uuid, credential: PublicKeyCredential) -> Result<bool, ServerFnError> {
+        let Some(auth) = challenges().take::<DiscoverableAuthentication>(Ceremony::DiscoverableLogin, id).await? else {
             return Err(ServerFnError::new("Invalid challenge ID."));
         };
-
-        let (user_id, _) = webauthn().identify_discoverable_authentication(&credential)?;
-        let passkeys = Passkeys::find()
-            .filter(entity::passkeys::Column::UserId.eq(user_id))
-            .all(&db())
-            .await?
-            .into_iter()
-            .map(|passkey| serde_json::from_value::<Passkey>(passkey.data).expect("valid passkey data").into())
-            .collect::<Vec<DiscoverableKey>>();
-
-        authenticate(webauthn().finish_discoverable_authentication(&credential, auth, &passkeys)?).await
+        let subjects = super::throttle::check("finish_discoverable_login", None)?;
+
+        let result = async {
+            let (user_id, _) = webauthn().identify_discoverable_authentication(&credential)?;
+            let passkeys = Passkeys::find()
+                .filter(entity::passkeys::Column::UserId.eq(user_id))
+                .all(&db())
+                .await?
+                .into_iter()
+                .map(|passkey| super::cloning::for_authentication(passkey.data).map(DiscoverableKey::from))
+                .collect::<Result<Vec<DiscoverableKey>, _>>()?;
+
+            authenticate(webauthn().finish_discoverable_authentication(&credential, auth, &passkeys)?).await
+        }.await;
+        super::throttle::record(&subjects, result)
     }
 }
@@ -0,0 +1,62 @@
+//! Personal access tokens, which let scripts act as a user with some of their scopes.
+
+use std::collections::BTreeSet;
+
+use sea_orm::Set;
+use time::OffsetDateTime;
+
+use crate::auth::{Scope, roles, secret, token::{Claims, Service}};
+use crate::prelude::*;
+
+/// Marks a string as one of our tokens, so that it is recognisable if leaked (e.g. by secret scanners).
+pub const TOKEN_PREFIX: &str = "shpat_";
+
+/// How stale `personal_access_tokens.last_used_at` may get before a request updates it
+const LAST_USED_GRANULARITY: time::Duration = time::Duration::minutes(5);
+
+/// Generates a new token, returning it and its hash.
+pub fn generate() -> (String, Vec<u8>) {
+    secret::generate(TOKEN_PREFIX)
+}
+
+/// Parses the scopes column, skipping any that no longer exist.
+pub fn parse_scopes(scopes: &serde_json::Value) -> BTreeSet<Scope> {
+    serde_json::from_value::<Vec<Scope>>(scopes.clone())
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|scope| *scope != Scope::Unknown)
+        .collect()
+}
+
+/// Looks up an access token, returning claims for its user with the scopes that both the token and the user still
+/// have. The claims' `jti` is the token's ID.
+pub async fn authenticate(token: &str) -> Result<Option<Claims>, DbErr> {
+    if !token.starts_with(TOKEN_PREFIX) {
+        return Ok(None);
+    }
+
+    let Some(record) = PersonalAccessTokens::find()
+        .filter(entity::personal_access_tokens::Column::TokenHash.eq(secret::hash(token)))
+        .one(&db())
+        .await?
+    else {
+        return Ok(None);
+    };
+    let now = OffsetDateTime::now_utc();
+    if record.revoked_at.is_some() || record.expires_at <= now {
+        return Ok(None);
+    }
+
+    let user_scopes = roles::scopes_for(&db(), record.user_id).await?;
+    let scopes = parse_scopes(&record.scopes).intersection(&user_scopes).copied().collect::<Vec<_>>();
+    let remaining = (record.expires_at - now).whole_seconds() as u64;
+    let mut claims = Claims::new(record.user_id, scopes, [Service::StarHavenPlatform], remaining);
+    claims.jti = record.id;
+
+    if record.last_used_at.is_none_or(|last_used_at| now - last_used_at > LAST_USED_GRANULARITY) {
+        let mut record: entity::personal_access_tokens::ActiveModel = record.into();
+        record.last_used_at = Set(Some(now));
+        record.update(&db()).await?;
+    }
+    Ok(Some(claims))
+}
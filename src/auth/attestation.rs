@@ -0,0 +1,129 @@
+//! Holding privileged accounts to passkeys from trusted authenticators. The operator lists the authenticator models
+//! they trust, and users with any of the scopes in the [`AttestationPolicy`](crate::config::AttestationPolicy) can only
+//! register passkeys whose attestation proves they're one of those models. Passkeys registered before the policy
+//! applied to the user keep working, but until they have a compliant one they're asked to add it.
+//!
+//! The model of each attested passkey is recorded in `passkeys.data` under `authenticator`, next to the credential.
+
+use webauthn_rs::prelude::{
+    AttestationMetadata, AttestedPasskey, AttestedPasskeyRegistration, CreationChallengeResponse, CredentialID,
+    PasskeyRegistration, RegisterPublicKeyCredential,
+};
+
+use crate::prelude::*;
+
+use super::passkey::{ATTESTATION_REQUIRED, webauthn};
+
+/// An authenticator model, as recorded with a passkey.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Authenticator {
+    pub aaguid: Uuid,
+    pub model: String,
+}
+
+/// A passkey registration in progress.
+#[derive(Serialize, Deserialize)]
+pub enum Registration {
+    Passkey(PasskeyRegistration),
+    /// For a user that the policy applies to
+    Attested(AttestedPasskeyRegistration),
+}
+
+/// A passkey that has just been registered.
+pub struct NewPasskey {
+    pub id: Vec<u8>,
+    /// What to store in `passkeys.data`
+    pub data: serde_json::Value,
+    /// Set if the passkey was attested
+    pub authenticator: Option<Authenticator>,
+}
+
+/// Whether `user_id` has a scope that the policy applies to. Users that don't exist yet get the default role's scopes.
+pub async fn applies_to(conn: &impl ConnectionTrait, user_id: Uuid) -> Result<bool, DbErr> {
+    let config = config();
+    if config.passkey_attestation.scopes.is_empty() {
+        return Ok(false);
+    }
+    let scopes = super::roles::scopes_for(conn, user_id).await?;
+    Ok(config.passkey_attestation.scopes.iter().any(|scope| scopes.contains(scope)))
+}
+
+/// Whether a stored passkey's attestation shows it's a trusted model. This is checked against the current list, so
+/// taking a model off it makes its passkeys non-compliant.
+pub fn complies(data: &serde_json::Value) -> bool {
+    let Ok(passkey) = serde_json::from_value::<AttestedPasskey>(data.clone()) else {
+        return false;
+    };
+    let Some(aaguid) = aaguid(&passkey) else {
+        return false;
+    };
+    passkey.verify_attestation(&config().passkey_attestation.authenticators).is_ok_and(|ca| ca.aaguids().contains_key(&aaguid))
+}
+
+/// Whether `user_id` needs a compliant passkey and has none.
+pub async fn needs_passkey(conn: &impl ConnectionTrait, user_id: Uuid) -> Result<bool, DbErr> {
+    if !applies_to(conn, user_id).await? {
+        return Ok(false);
+    }
+    let passkeys = Passkeys::find().filter(entity::passkeys::Column::UserId.eq(user_id)).all(conn).await?;
+    Ok(!passkeys.iter().any(|passkey| complies(&passkey.data)))
+}
+
+/// The model recorded with a stored passkey, if it was attested.
+pub fn recorded_authenticator(data: &serde_json::Value) -> Option<Authenticator> {
+    serde_json::from_value(data.get("authenticator")?.clone()).ok()
+}
+
+fn aaguid(passkey: &AttestedPasskey) -> Option<Uuid> {
+    match passkey.attestation().metadata {
+        AttestationMetadata::Packed { aaguid } | AttestationMetadata::Tpm { aaguid, .. } => Some(aaguid),
+        _ => None,
+    }
+}
+
+/// Starts registering a passkey to `user_id`, with attestation if the policy applies to them.
+pub async fn start_registration(
+    conn: &impl ConnectionTrait,
+    user_id: Uuid,
+    username: &str,
+    existing_credentials: Option<Vec<CredentialID>>,
+) -> Result<(CreationChallengeResponse, Registration), ServerFnError> {
+    if applies_to(conn, user_id).await? {
+        let (ccr, registration) = webauthn().start_attested_passkey_registration(
+            user_id,
+            username,
+            username,
+            existing_credentials,
+            config().passkey_attestation.authenticators.clone(),
+            None,
+        )?;
+        Ok((ccr, Registration::Attested(registration)))
+    } else {
+        let (ccr, registration) = webauthn().start_passkey_registration(user_id, username, username, existing_credentials)?;
+        Ok((ccr, Registration::Passkey(registration)))
+    }
+}
+
+/// Verifies the authenticator's response to [`start_registration`].
+pub fn finish_registration(reg: &RegisterPublicKeyCredential, registration: &Registration) -> Result<NewPasskey, ServerFnError> {
+    match registration {
+        Registration::Passkey(registration) => {
+            let passkey = webauthn().finish_passkey_registration(reg, registration)?;
+            Ok(NewPasskey { id: passkey.cred_id().to_vec(), data: serde_json::to_value(&passkey)?, authenticator: None })
+        }
+        Registration::Attested(registration) => {
+            let passkey = webauthn().finish_attested_passkey_registration(reg, registration).map_err(|error| {
+                log::info!("passkey registration failed the attestation policy: {error}");
+                ServerFnError::new(ATTESTATION_REQUIRED)
+            })?;
+            let config = config();
+            let authenticator = aaguid(&passkey).and_then(|aaguid| {
+                let ca = passkey.verify_attestation(&config.passkey_attestation.authenticators).ok()?;
+                Some(Authenticator { aaguid, model: ca.aaguids().get(&aaguid)?.description_en().to_string() })
+            });
+            let mut data = serde_json::to_value(&passkey)?;
+            data["authenticator"] = serde_json::to_value(&authenticator)?;
+            Ok(NewPasskey { id: passkey.cred_id().to_vec(), data, authenticator })
+        }
+    }
+}
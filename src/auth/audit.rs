@@ -0,0 +1,371 @@
+//! An append-only log of security-sensitive and moderation actions: who did what, to whose account, and to what. Users
+//! see the entries about their own account at `/account/activity`, and users with [`Scope::ViewAuditLog`] can search
+//! every entry at `/admin/audit`.
+//!
+//! [`Scope::ViewAuditLog`]: crate::auth::Scope::ViewAuditLog
+
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::pagination::Cursor;
+
+#[cfg(feature = "ssr")]
+use sea_orm::{ConnectionTrait, Set};
+
+#[cfg(feature = "ssr")]
+use crate::prelude::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditEvent {
+    AccountCreated,
+    AccountDeleted,
+    DataExported,
+    SignedIn,
+    SignedOut,
+    SessionRevoked,
+    AllSessionsRevoked,
+    SessionRefreshTokenReused,
+    PasskeyAdded,
+    PasskeyRenamed,
+    PasskeyRemoved,
+    PasskeyFlagged,
+    PasskeyFlagDismissed,
+    RecoveryCodesGenerated,
+    RecoveryCodeUsed,
+    UsernameChanged,
+    DisplayNameChanged,
+    AvatarChanged,
+    RoleAssigned,
+    RoleRemoved,
+    ScopeGranted,
+    ScopeRevoked,
+    ScopeReset,
+    AccessTokenCreated,
+    AccessTokenRevoked,
+    OauthClientRegistered,
+    OauthClientDeleted,
+    OauthAppAuthorized,
+    OauthAppRevoked,
+    OauthRefreshTokenReused,
+    ModCreated,
+    ModEdited,
+    ModMediaUploaded,
+    ModMediaDeleted,
+    ModArchived,
+    ImpersonationStarted,
+    ImpersonationEnded,
+}
+
+impl AuditEvent {
+    pub const ALL: [AuditEvent; 37] = [
+        AuditEvent::AccountCreated,
+        AuditEvent::AccountDeleted,
+        AuditEvent::DataExported,
+        AuditEvent::SignedIn,
+        AuditEvent::SignedOut,
+        AuditEvent::SessionRevoked,
+        AuditEvent::AllSessionsRevoked,
+        AuditEvent::SessionRefreshTokenReused,
+        AuditEvent::PasskeyAdded,
+        AuditEvent::PasskeyRenamed,
+        AuditEvent::PasskeyRemoved,
+        AuditEvent::PasskeyFlagged,
+        AuditEvent::PasskeyFlagDismissed,
+        AuditEvent::RecoveryCodesGenerated,
+        AuditEvent::RecoveryCodeUsed,
+        AuditEvent::UsernameChanged,
+        AuditEvent::DisplayNameChanged,
+        AuditEvent::AvatarChanged,
+        AuditEvent::RoleAssigned,
+        AuditEvent::RoleRemoved,
+        AuditEvent::ScopeGranted,
+        AuditEvent::ScopeRevoked,
+        AuditEvent::ScopeReset,
+        AuditEvent::AccessTokenCreated,
+        AuditEvent::AccessTokenRevoked,
+        AuditEvent::OauthClientRegistered,
+        AuditEvent::OauthClientDeleted,
+        AuditEvent::OauthAppAuthorized,
+        AuditEvent::OauthAppRevoked,
+        AuditEvent::OauthRefreshTokenReused,
+        AuditEvent::ModCreated,
+        AuditEvent::ModEdited,
+        AuditEvent::ModMediaUploaded,
+        AuditEvent::ModMediaDeleted,
+        AuditEvent::ModArchived,
+        AuditEvent::ImpersonationStarted,
+        AuditEvent::ImpersonationEnded,
+    ];
+
+    /// The name stored in `audit_events.event`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AuditEvent::AccountCreated => "account_created",
+            AuditEvent::AccountDeleted => "account_deleted",
+            AuditEvent::DataExported => "data_exported",
+            AuditEvent::SignedIn => "signed_in",
+            AuditEvent::SignedOut => "signed_out",
+            AuditEvent::SessionRevoked => "session_revoked",
+            AuditEvent::AllSessionsRevoked => "all_sessions_revoked",
+            AuditEvent::SessionRefreshTokenReused => "session_refresh_token_reused",
+            AuditEvent::PasskeyAdded => "passkey_added",
+            AuditEvent::PasskeyRenamed => "passkey_renamed",
+            AuditEvent::PasskeyRemoved => "passkey_removed",
+            AuditEvent::PasskeyFlagged => "passkey_flagged",
+            AuditEvent::PasskeyFlagDismissed => "passkey_flag_dismissed",
+            AuditEvent::RecoveryCodesGenerated => "recovery_codes_generated",
+            AuditEvent::RecoveryCodeUsed => "recovery_code_used",
+            AuditEvent::UsernameChanged => "username_changed",
+            AuditEvent::DisplayNameChanged => "display_name_changed",
+            AuditEvent::AvatarChanged => "avatar_changed",
+            AuditEvent::RoleAssigned => "role_assigned",
+            AuditEvent::RoleRemoved => "role_removed",
+            AuditEvent::ScopeGranted => "scope_granted",
+            AuditEvent::ScopeRevoked => "scope_revoked",
+            AuditEvent::ScopeReset => "scope_reset",
+            AuditEvent::AccessTokenCreated => "access_token_created",
+            AuditEvent::AccessTokenRevoked => "access_token_revoked",
+            AuditEvent::OauthClientRegistered => "oauth_client_registered",
+            AuditEvent::OauthClientDeleted => "oauth_client_deleted",
+            AuditEvent::OauthAppAuthorized => "oauth_app_authorized",
+            AuditEvent::OauthAppRevoked => "oauth_app_revoked",
+            AuditEvent::OauthRefreshTokenReused => "oauth_refresh_token_reused",
+            AuditEvent::ModCreated => "mod_created",
+            AuditEvent::ModEdited => "mod_edited",
+            AuditEvent::ModMediaUploaded => "mod_media_uploaded",
+            AuditEvent::ModMediaDeleted => "mod_media_deleted",
+            AuditEvent::ModArchived => "mod_archived",
+            AuditEvent::ImpersonationStarted => "impersonation_started",
+            AuditEvent::ImpersonationEnded => "impersonation_ended",
+        }
+    }
+
+    /// What happened, as shown to users.
+    pub fn description(self) -> &'static str {
+        match self {
+            AuditEvent::AccountCreated => "Created the account",
+            AuditEvent::AccountDeleted => "Deleted the account",
+            AuditEvent::DataExported => "Downloaded a copy of the account's data",
+            AuditEvent::SignedIn => "Signed in",
+            AuditEvent::SignedOut => "Signed out",
+            AuditEvent::SessionRevoked => "Signed out a device",
+            AuditEvent::AllSessionsRevoked => "Signed out everywhere",
+            AuditEvent::SessionRefreshTokenReused => "Signed out a device whose session may have been stolen",
+            AuditEvent::PasskeyAdded => "Added a passkey",
+            AuditEvent::PasskeyRenamed => "Renamed a passkey",
+            AuditEvent::PasskeyRemoved => "Removed a passkey",
+            AuditEvent::PasskeyFlagged => "Flagged a passkey that may have been copied",
+            AuditEvent::PasskeyFlagDismissed => "Dismissed a warning about a passkey",
+            AuditEvent::RecoveryCodesGenerated => "Generated new recovery codes",
+            AuditEvent::RecoveryCodeUsed => "Used a recovery code",
+            AuditEvent::UsernameChanged => "Changed username",
+            AuditEvent::DisplayNameChanged => "Changed display name",
+            AuditEvent::AvatarChanged => "Changed avatar",
+            AuditEvent::RoleAssigned => "Assigned a role",
+            AuditEvent::RoleRemoved => "Removed a role",
+            AuditEvent::ScopeGranted => "Granted a permission",
+            AuditEvent::ScopeRevoked => "Revoked a permission",
+            AuditEvent::ScopeReset => "Reset a permission to come from roles",
+            AuditEvent::AccessTokenCreated => "Created an access token",
+            AuditEvent::AccessTokenRevoked => "Revoked an access token",
+            AuditEvent::OauthClientRegistered => "Registered an app",
+            AuditEvent::OauthClientDeleted => "Deleted an app",
+            AuditEvent::OauthAppAuthorized => "Gave an app access",
+            AuditEvent::OauthAppRevoked => "Revoked an app's access",
+            AuditEvent::OauthRefreshTokenReused => "Revoked an app's access after its refresh token was reused",
+            AuditEvent::ModCreated => "Created a mod",
+            AuditEvent::ModEdited => "Edited a mod",
+            AuditEvent::ModMediaUploaded => "Uploaded media to a mod",
+            AuditEvent::ModMediaDeleted => "Deleted media from a mod",
+            AuditEvent::ModArchived => "Archived a mod whose last author left",
+            AuditEvent::ImpersonationStarted => "Started viewing the site as this account",
+            AuditEvent::ImpersonationEnded => "Stopped viewing the site as this account",
+        }
+    }
+}
+
+impl FromStr for AuditEvent {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        AuditEvent::ALL.into_iter().find(|event| event.as_str() == s).ok_or(())
+    }
+}
+
+/// An entry in the audit log, as shown to users.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub id: Uuid,
+    /// `None` for events recorded by a newer version of the server
+    pub event: Option<AuditEvent>,
+    pub event_name: String,
+    /// The current username of whoever did it, if anyone. Deleted users are shown as such.
+    pub actor: Option<String>,
+    /// Whether whoever did it is also the user the event is about
+    pub by_user: bool,
+    /// The current username of the user the event is about, if any. Deleted users are shown as such.
+    pub user: Option<String>,
+    /// What the event was done to, as `type:id`
+    pub target: Option<String>,
+    pub ip: Option<String>,
+    pub device: Option<String>,
+    pub details: Option<String>,
+    pub created_at: OffsetDateTime,
+}
+
+impl AuditEntry {
+    pub fn description(&self) -> String {
+        self.event.map_or_else(|| self.event_name.clone(), |event| event.description().to_string())
+    }
+}
+
+/// How many entries are shown at a time.
+pub const PAGE_SIZE: u64 = 50;
+
+/// The last of a page of entries, if the page is full so there may be older ones.
+pub fn next_page(entries: &[AuditEntry]) -> Option<Cursor> {
+    entries
+        .last()
+        .map(|entry| Cursor { created_at: entry.created_at, id: entry.id })
+        .filter(|_| entries.len() as u64 >= PAGE_SIZE)
+}
+
+/// What an event was done to, besides the account it's about.
+#[cfg(feature = "ssr")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    Mod(Uuid),
+    ModMedia(Uuid),
+    /// The passkey's credential ID
+    Passkey(Vec<u8>),
+    Session(Uuid),
+    Impersonation(Uuid),
+}
+
+#[cfg(feature = "ssr")]
+impl Target {
+    fn parts(&self) -> (&'static str, String) {
+        match self {
+            Target::Mod(id) => ("mod", id.to_string()),
+            Target::ModMedia(id) => ("mod_media", id.to_string()),
+            // The same encoding as the IDs on the security page
+            Target::Passkey(id) => ("passkey", base64::Engine::encode(&base64::engine::general_purpose::URL_SAFE_NO_PAD, id)),
+            Target::Session(id) => ("session", id.to_string()),
+            Target::Impersonation(id) => ("impersonation", id.to_string()),
+        }
+    }
+}
+
+/// An entry to add to the audit log.
+#[cfg(feature = "ssr")]
+#[must_use = "entries must be recorded"]
+pub struct Entry {
+    event: AuditEvent,
+    actor_id: Option<Uuid>,
+    user_id: Option<Uuid>,
+    target: Option<Target>,
+    details: Option<serde_json::Value>,
+}
+
+#[cfg(feature = "ssr")]
+impl Entry {
+    /// `event`, done by `actor_id`, or by the server itself if `None`.
+    pub fn new(event: AuditEvent, actor_id: Option<Uuid>) -> Self {
+        Entry { event, actor_id, user_id: None, target: None, details: None }
+    }
+
+    /// Makes the entry about `user_id`'s account, so that they can see it.
+    pub fn user(mut self, user_id: Uuid) -> Self {
+        self.user_id = Some(user_id);
+        self
+    }
+
+    pub fn target(mut self, target: Target) -> Self {
+        self.target = Some(target);
+        self
+    }
+
+    pub fn details(mut self, details: serde_json::Value) -> Self {
+        self.details = Some(details);
+        self
+    }
+
+    /// Adds the entry to the log, along with the IP address and user agent of the current request. Anything done while
+    /// an admin is viewing the site as someone else is put down to the admin.
+    pub async fn record(mut self, conn: &impl ConnectionTrait) -> Result<(), DbErr> {
+        if let Some(actor) = crate::request::impersonator().filter(|_| self.actor_id.is_some()) {
+            self.actor_id = Some(actor.sub);
+            let mut details = self.details.take().unwrap_or_else(|| serde_json::json!({}));
+            details["impersonation_id"] = actor.impersonation_id.to_string().into();
+            self.details = Some(details);
+        }
+        let (target_type, target_id) = self.target.as_ref().map(Target::parts).unzip();
+        entity::audit_events::ActiveModel {
+            id: Set(Uuid::new_v4()),
+            user_id: Set(self.user_id),
+            event: Set(self.event.as_str().to_string()),
+            ip: Set(crate::request::client_ip().map(|ip| ip.to_string())),
+            user_agent: Set(crate::request::user_agent()),
+            details: Set(self.details),
+            created_at: Set(OffsetDateTime::now_utc()),
+            actor_id: Set(self.actor_id),
+            target_type: Set(target_type.map(str::to_owned)),
+            target_id: Set(target_id),
+        }.insert(conn).await?;
+        Ok(())
+    }
+}
+
+/// A page of the entries matching `condition`, newest first, starting before `before`.
+#[cfg(feature = "ssr")]
+pub async fn entries(
+    conn: &impl ConnectionTrait,
+    condition: sea_orm::Condition,
+    before: Option<Cursor>,
+) -> Result<Vec<AuditEntry>, DbErr> {
+    use std::collections::HashMap;
+    use entity::audit_events::Column;
+
+    let mut query = AuditEvents::find().filter(condition);
+    if let Some(before) = before {
+        query = query.filter(before.is_before_expr(Column::CreatedAt, Column::Id));
+    }
+    let records = query
+        .order_by_desc(Column::CreatedAt)
+        .order_by_desc(Column::Id)
+        .limit(PAGE_SIZE)
+        .all(conn)
+        .await?;
+
+    let user_ids: Vec<Uuid> = records.iter().flat_map(|record| [record.actor_id, record.user_id]).flatten().collect();
+    let usernames: HashMap<Uuid, String> = Users::find()
+        .filter(entity::users::Column::Id.is_in(user_ids))
+        .all(conn)
+        .await?
+        .into_iter()
+        .map(|user| (user.id, user.username))
+        .collect();
+
+    Ok(records.into_iter().map(|record| AuditEntry {
+        id: record.id,
+        event: record.event.parse().ok(),
+        actor: record.actor_id.map(|id| username(&usernames, id)),
+        by_user: record.actor_id.is_some() && record.actor_id == record.user_id,
+        user: record.user_id.map(|id| username(&usernames, id)),
+        target: record.target_type.zip(record.target_id).map(|(kind, id)| format!("{kind}:{id}")),
+        ip: record.ip,
+        device: record.user_agent.as_deref().map(crate::request::describe_user_agent),
+        details: record.details.map(|details| details.to_string()),
+        event_name: record.event,
+        created_at: record.created_at,
+    }).collect())
+}
+
+#[cfg(feature = "ssr")]
+fn username(usernames: &std::collections::HashMap<Uuid, String>, id: Uuid) -> String {
+    usernames.get(&id).cloned().unwrap_or_else(|| "a deleted user".to_string())
+}
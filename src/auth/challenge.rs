@@ -0,0 +1,260 @@
+//! Temporary state held between the start and finish of a WebAuthn ceremony.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use sea_orm::{DatabaseConnection, Set};
+use serde::{Serialize, de::DeserializeOwned};
+use time::OffsetDateTime;
+use tokio::sync::RwLock;
+
+use crate::prelude::*;
+use crate::config::{Config, ChallengeStoreKind};
+
+/// Which ceremony a challenge belongs to. A challenge can only be finished by the same kind of ceremony that started it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ceremony {
+    Register,
+    Login,
+    DiscoverableLogin,
+    Recover,
+}
+
+impl Ceremony {
+    fn as_str(self) -> &'static str {
+        match self {
+            Ceremony::Register => "register",
+            Ceremony::Login => "login",
+            Ceremony::DiscoverableLogin => "discoverable_login",
+            Ceremony::Recover => "recover",
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ChallengeError {
+    #[error("database error: {0}")]
+    Db(#[from] sea_orm::DbErr),
+    #[error("bad challenge state: {0}")]
+    Serde(#[from] serde_json::Error),
+}
+
+/// Expiring store for WebAuthn ceremony state, keyed by a random challenge ID handed to the client.
+pub struct ChallengeStore {
+    backend: Backend,
+    ttl: Duration,
+    max_per_client: usize,
+}
+
+enum Backend {
+    Memory(RwLock<HashMap<Uuid, MemoryEntry>>),
+    Postgres(DatabaseConnection),
+}
+
+struct MemoryEntry {
+    ceremony: Ceremony,
+    client: String,
+    state: serde_json::Value,
+    created_at: OffsetDateTime,
+    expires_at: OffsetDateTime,
+}
+
+impl ChallengeStore {
+    pub fn from_config(config: &Config, db: DatabaseConnection) -> Self {
+        let backend = match config.challenge_store {
+            ChallengeStoreKind::Memory => Backend::Memory(RwLock::new(HashMap::new())),
+            ChallengeStoreKind::Postgres => Backend::Postgres(db),
+        };
+        ChallengeStore {
+            backend,
+            ttl: config.challenge_ttl,
+            max_per_client: config.challenges_per_client.max(1),
+        }
+    }
+
+    /// Stores ceremony state for `client`, returning the ID the client must present to finish the ceremony.
+    /// If the client already has too many outstanding challenges, the oldest are discarded.
+    pub async fn insert<T: Serialize>(&self, ceremony: Ceremony, client: &str, state: &T) -> Result<Uuid, ChallengeError> {
+        let id = Uuid::new_v4();
+        let state = serde_json::to_value(state)?;
+        let now = OffsetDateTime::now_utc();
+        let expires_at = now + self.ttl;
+
+        match &self.backend {
+            Backend::Memory(map) => {
+                let mut map = map.write().await;
+                let mut existing = map
+                    .iter()
+                    .filter(|(_, entry)| entry.client == client)
+                    .map(|(id, entry)| (entry.created_at, *id))
+                    .collect::<Vec<_>>();
+                if existing.len() >= self.max_per_client {
+                    existing.sort();
+                    for (_, id) in &existing[..=existing.len() - self.max_per_client] {
+                        map.remove(id);
+                    }
+                }
+                map.insert(id, MemoryEntry {
+                    ceremony,
+                    client: client.to_owned(),
+                    state,
+                    created_at: now,
+                    expires_at,
+                });
+            }
+            Backend::Postgres(db) => {
+                use entity::webauthn_challenges::Column;
+
+                // Keep the newest `max_per_client - 1` to make room for this one
+                let stale = WebauthnChallenges::find()
+                    .select_only()
+                    .column(Column::Id)
+                    .filter(Column::Client.eq(client))
+                    .order_by_desc(Column::CreatedAt)
+                    .offset(self.max_per_client as u64 - 1)
+                    .into_tuple::<Uuid>()
+                    .all(db)
+                    .await?;
+                if !stale.is_empty() {
+                    WebauthnChallenges::delete_many()
+                        .filter(Column::Id.is_in(stale))
+                        .exec(db)
+                        .await?;
+                }
+
+                entity::webauthn_challenges::ActiveModel {
+                    id: Set(id),
+                    ceremony: Set(ceremony.as_str().to_owned()),
+                    client: Set(client.to_owned()),
+                    state: Set(state),
+                    created_at: Set(now),
+                    expires_at: Set(expires_at),
+                }.insert(db).await?;
+            }
+        }
+
+        Ok(id)
+    }
+
+    /// Removes and returns the state for a challenge. Returns `None` if the challenge does not exist,
+    /// has expired, or was started by a different ceremony. A challenge can only be taken once.
+    pub async fn take<T: DeserializeOwned>(&self, ceremony: Ceremony, id: Uuid) -> Result<Option<T>, ChallengeError> {
+        let now = OffsetDateTime::now_utc();
+
+        let state = match &self.backend {
+            Backend::Memory(map) => {
+                let mut map = map.write().await;
+                match map.get(&id) {
+                    Some(entry) if entry.ceremony == ceremony => {
+                        map.remove(&id).filter(|entry| entry.expires_at > now).map(|entry| entry.state)
+                    }
+                    _ => None,
+                }
+            }
+            Backend::Postgres(db) => {
+                use entity::webauthn_challenges::Column;
+
+                WebauthnChallenges::delete_many()
+                    .filter(Column::Id.eq(id))
+                    .filter(Column::Ceremony.eq(ceremony.as_str()))
+                    .exec_with_returning(db)
+                    .await?
+                    .into_iter()
+                    .next()
+                    .filter(|challenge| challenge.expires_at > now)
+                    .map(|challenge| challenge.state)
+            }
+        };
+
+        Ok(state.map(serde_json::from_value).transpose()?)
+    }
+
+    /// Deletes all expired challenges, returning how many were removed.
+    pub async fn sweep(&self) -> Result<u64, ChallengeError> {
+        let now = OffsetDateTime::now_utc();
+
+        match &self.backend {
+            Backend::Memory(map) => {
+                let mut map = map.write().await;
+                let before = map.len();
+                map.retain(|_, entry| entry.expires_at > now);
+                Ok((before - map.len()) as u64)
+            }
+            Backend::Postgres(db) => {
+                let result = WebauthnChallenges::delete_many()
+                    .filter(entity::webauthn_challenges::Column::ExpiresAt.lte(now))
+                    .exec(db)
+                    .await?;
+                Ok(result.rows_affected)
+            }
+        }
+    }
+
+    /// Periodically sweeps expired challenges in the background.
+    pub fn spawn_sweeper(self: Arc<Self>) -> tokio::task::JoinHandle<()> {
+        // Expired challenges are already rejected by `take`; sweeping only reclaims space
+        let period = self.ttl.max(Duration::from_secs(60));
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+                match self.sweep().await {
+                    Ok(0) => {}
+                    Ok(count) => log::debug!("swept {count} expired webauthn challenges"),
+                    Err(error) => log::error!("failed to sweep webauthn challenges: {error}"),
+                }
+            }
+        })
+    }
+}
+
+pub fn challenges() -> Arc<ChallengeStore> {
+    expect_context()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn memory_store(ttl: Duration, max_per_client: usize) -> ChallengeStore {
+        ChallengeStore { backend: Backend::Memory(RwLock::new(HashMap::new())), ttl, max_per_client }
+    }
+
+    #[tokio::test]
+    async fn challenges_can_only_be_taken_once() {
+        let store = memory_store(Duration::from_secs(60), 3);
+        let id = store.insert(Ceremony::Login, "192.0.2.1", &"state").await.unwrap();
+
+        assert_eq!(store.take::<String>(Ceremony::Register, id).await.unwrap(), None, "other ceremonies can't take it");
+        assert_eq!(store.take::<String>(Ceremony::Login, id).await.unwrap(), Some("state".to_string()));
+        assert_eq!(store.take::<String>(Ceremony::Login, id).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn challenges_expire() {
+        let store = memory_store(Duration::ZERO, 3);
+        let id = store.insert(Ceremony::Login, "192.0.2.1", &"state").await.unwrap();
+        assert_eq!(store.take::<String>(Ceremony::Login, id).await.unwrap(), None);
+
+        store.insert(Ceremony::Login, "192.0.2.1", &"state").await.unwrap();
+        assert_eq!(store.sweep().await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn oldest_challenges_are_evicted_at_the_cap() {
+        let store = memory_store(Duration::from_secs(60), 2);
+        let mut ids = Vec::new();
+        for index in 0..3 {
+            ids.push(store.insert(Ceremony::Login, "192.0.2.1", &index).await.unwrap());
+            // Keep creation times distinct, as they decide which is oldest
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        let other = store.insert(Ceremony::Login, "192.0.2.2", &3).await.unwrap();
+
+        assert_eq!(store.take::<i32>(Ceremony::Login, ids[0]).await.unwrap(), None);
+        assert_eq!(store.take::<i32>(Ceremony::Login, ids[1]).await.unwrap(), Some(1));
+        assert_eq!(store.take::<i32>(Ceremony::Login, ids[2]).await.unwrap(), Some(2));
+        assert_eq!(store.take::<i32>(Ceremony::Login, other).await.unwrap(), Some(3), "other clients are unaffected");
+    }
+}
@@ -0,0 +1,135 @@
+//! Spotting passkeys that may have been copied to another authenticator. The WebAuthn spec leaves it to each site to
+//! decide what a signature counter going backwards means, so the operator picks a [`CredentialPolicy`].
+
+use webauthn_rs::prelude::{AuthenticationResult, Passkey};
+
+use crate::config::CredentialPolicy;
+use crate::prelude::*;
+
+/// Something about a sign-in that suggests the passkey may have been cloned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Anomaly {
+    /// The signature counter didn't go up since the last sign-in
+    CounterRegressed { stored: u32, observed: u32 },
+    /// The passkey was backed up (synced) and now isn't, or the other way round along with a counter regression
+    BackupStateChanged { stored: bool, observed: bool },
+}
+
+impl Anomaly {
+    /// Stored in `passkeys.flag_reason`.
+    pub fn reason(self) -> &'static str {
+        match self {
+            Anomaly::CounterRegressed { .. } => "counter_regressed",
+            Anomaly::BackupStateChanged { .. } => "backup_state_changed",
+        }
+    }
+
+    pub fn policy(self) -> CredentialPolicy {
+        reason_policy(self.reason())
+    }
+
+    pub fn details(self) -> serde_json::Value {
+        match self {
+            Anomaly::CounterRegressed { stored, observed } => serde_json::json!({
+                "reason": self.reason(),
+                "stored_counter": stored,
+                "observed_counter": observed,
+            }),
+            Anomaly::BackupStateChanged { stored, observed } => serde_json::json!({
+                "reason": self.reason(),
+                "stored_backup_state": stored,
+                "observed_backup_state": observed,
+            }),
+        }
+    }
+}
+
+/// The policy for a passkey flagged with `reason`.
+pub fn reason_policy(reason: &str) -> CredentialPolicy {
+    match reason {
+        "counter_regressed" => config().passkey_counter_policy,
+        "backup_state_changed" => config().passkey_backup_state_policy,
+        _ => CredentialPolicy::Warn,
+    }
+}
+
+/// Deserialises a stored passkey for an authentication ceremony, with its signature counter reset. webauthn-rs fails
+/// any ceremony whose counter goes backwards before we get to see it, so [`check`] compares the counters instead, once
+/// the signature has been verified.
+pub fn for_authentication(mut data: serde_json::Value) -> Result<Passkey, serde_json::Error> {
+    data["cred"]["counter"] = 0.into();
+    serde_json::from_value(data)
+}
+
+/// Compares a verified sign-in with the passkey's stored `data` from before it.
+pub fn check(data: &serde_json::Value, authentication: &AuthenticationResult) -> Vec<Anomaly> {
+    let stored = Observation {
+        counter: data["cred"]["counter"].as_u64().unwrap_or_default() as u32,
+        backup_state: data["cred"]["backup_state"].as_bool().unwrap_or_default(),
+    };
+    let observed = Observation { counter: authentication.counter(), backup_state: authentication.backup_state() };
+    compare(stored, observed)
+}
+
+/// What an authenticator reported about a passkey in one sign-in.
+#[derive(Debug, Clone, Copy)]
+struct Observation {
+    counter: u32,
+    backup_state: bool,
+}
+
+fn compare(stored: Observation, observed: Observation) -> Vec<Anomaly> {
+    let mut anomalies = Vec::new();
+
+    // Authenticators that don't keep a counter always send 0
+    if (stored.counter > 0 || observed.counter > 0) && observed.counter <= stored.counter {
+        anomalies.push(Anomaly::CounterRegressed { stored: stored.counter, observed: observed.counter });
+    }
+
+    // A passkey that was synced and no longer is may be a copy on an authenticator that doesn't sync. Going the other
+    // way is normal, e.g. when the user turns on a password manager's sync, so it only counts alongside a bad counter.
+    let backup_state_changed = match (stored.backup_state, observed.backup_state) {
+        (true, false) => true,
+        (false, true) => !anomalies.is_empty(),
+        _ => false,
+    };
+    if backup_state_changed {
+        anomalies.push(Anomaly::BackupStateChanged { stored: stored.backup_state, observed: observed.backup_state });
+    }
+
+    anomalies
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn observation(counter: u32, backup_state: bool) -> Observation {
+        Observation { counter, backup_state }
+    }
+
+    #[test]
+    fn losing_backup_state_is_flagged() {
+        assert_eq!(compare(observation(0, true), observation(0, false)), [
+            Anomaly::BackupStateChanged { stored: true, observed: false },
+        ]);
+    }
+
+    #[test]
+    fn gaining_backup_state_is_only_flagged_with_a_bad_counter() {
+        assert_eq!(compare(observation(0, false), observation(0, true)), []);
+        assert_eq!(compare(observation(4, false), observation(5, true)), []);
+        assert_eq!(compare(observation(5, false), observation(3, true)), [
+            Anomaly::CounterRegressed { stored: 5, observed: 3 },
+            Anomaly::BackupStateChanged { stored: false, observed: true },
+        ]);
+    }
+
+    #[test]
+    fn counters_must_go_up() {
+        assert_eq!(compare(observation(5, false), observation(6, false)), []);
+        assert_eq!(compare(observation(5, false), observation(5, false)), [
+            Anomaly::CounterRegressed { stored: 5, observed: 5 },
+        ]);
+    }
+}
@@ -12,7 +12,7 @@ pub fn get_cookie(key: &str) -> Option<String> {
     get_cookie_value(cookie, key)
 }
 
-fn get_cookie_value(cookies: &str, key: &str) -> Option<String> {
+pub(crate) fn get_cookie_value(cookies: &str, key: &str) -> Option<String> {
     cookies.split(';').find_map(|cookie| {
         let cookie_arr = cookie.split_once('=').unwrap_or_default();
         if cookie_arr.0.trim().eq(key) && !cookie_arr.1.trim().is_empty() {
@@ -28,7 +28,11 @@ pub fn set_cookie(key: &str, value: &str, age: u64) {
         log::warn!("set_cookie got no ResponseOptions");
         return;
     };
+    response.append_header(SET_COOKIE, cookie_header(key, value, age));
+}
 
+/// The `Set-Cookie` header value for a session cookie.
+pub(crate) fn cookie_header(key: &str, value: &str, age: u64) -> HeaderValue {
     // Don't require HTTPS in debug
     #[cfg(debug_assertions)]
     let secure = "";
@@ -36,8 +40,5 @@ pub fn set_cookie(key: &str, value: &str, age: u64) {
     let secure = "Secure;";
 
     let cookie = format!("{key}={value}; Path=/; {secure} SameSite=Lax; HttpOnly; Max-Age={age}");
-    response.append_header(
-        SET_COOKIE,
-        HeaderValue::from_str(&cookie).expect("to create header value"),
-    );
+    HeaderValue::from_str(&cookie).expect("to create header value")
 }
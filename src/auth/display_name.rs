@@ -0,0 +1,193 @@
+//! Display names: what the site calls a user, in any script, alongside the ASCII username that identifies them in URLs
+//! and at sign-in. Display names don't have to be unique, but they mustn't pass for someone else's username, so each
+//! one is compared with usernames by its confusable skeleton (see Unicode TR39), and names that mix scripts the way
+//! spoofs do are refused.
+//!
+//! Every change goes through [`set`], which is where any further moderation, e.g. a word filter, belongs. Admins can
+//! reset a display name from `/admin/users`.
+
+use unicode_normalization::UnicodeNormalization;
+use unicode_security::MixedScript;
+
+use crate::prelude::*;
+
+use super::{RESERVED_NAMES, normalize_username};
+
+/// Most characters a display name may have
+pub const MAX_LENGTH: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum DisplayNameError {
+    #[error("Too long, must be at most {MAX_LENGTH} characters")]
+    TooLong,
+    #[error("Invisible and control characters aren't allowed")]
+    InvisibleCharacters,
+    #[error("Can't mix alphabets that look alike, such as Latin and Cyrillic")]
+    MixedScripts,
+    #[error("This name is not allowed")]
+    Reserved,
+    #[error("Looks too much like someone else's username")]
+    LooksLikeUsername,
+}
+
+/// The form a display name is stored in: NFC, trimmed, with each run of whitespace replaced by a single space. Empty
+/// means no display name.
+pub fn normalize(display_name: &str) -> String {
+    display_name.nfc().collect::<String>().split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Checks a [normalized](normalize) display name, except for whether it looks like someone's username.
+pub fn check_validity(display_name: &str) -> Result<(), DisplayNameError> {
+    if display_name.chars().count() > MAX_LENGTH {
+        return Err(DisplayNameError::TooLong);
+    }
+    if display_name.chars().any(is_invisible) {
+        return Err(DisplayNameError::InvisibleCharacters);
+    }
+    // Scripts that are written together, such as kanji and kana, count as one
+    if !display_name.is_single_script() {
+        return Err(DisplayNameError::MixedScripts);
+    }
+    if RESERVED_NAMES.iter().any(|name| lookalike_key(name) == lookalike_key(display_name)) {
+        return Err(DisplayNameError::Reserved);
+    }
+    Ok(())
+}
+
+/// The name to show for `user`.
+pub fn shown(user: &User) -> &str {
+    user.display_name.as_deref().unwrap_or(&user.username)
+}
+
+fn is_invisible(c: char) -> bool {
+    c.is_control() || matches!(c,
+        '\u{00AD}' | '\u{034F}' | '\u{061C}' | '\u{115F}' | '\u{1160}' | '\u{17B4}' | '\u{17B5}' | '\u{180E}' |
+        '\u{200B}'..='\u{200F}' | '\u{202A}'..='\u{202E}' | '\u{2060}'..='\u{206F}' | '\u{3164}' | '\u{FEFF}' |
+        '\u{FFA0}' | '\u{FFF0}'..='\u{FFFB}'
+    )
+}
+
+/// What a name looks like, ignoring case and spacing, for comparing with usernames. Of the characters allowed in
+/// usernames, the skeleton only changes `0`, `1`, `I` and `m`, which [`username_looks_like`] mirrors in SQL.
+fn lookalike_key(name: &str) -> String {
+    let skeleton = unicode_security::skeleton(&name.nfkc().collect::<String>())
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>();
+    normalize_username(&skeleton)
+        .chars()
+        .map(|c| match c {
+            '0' => 'o',
+            'i' => 'l',
+            c => c,
+        })
+        .collect::<String>()
+        .replace("rn", "m")
+}
+
+/// The [`lookalike_key`] of `users.username_normalized`, as SQL.
+#[cfg(feature = "ssr")]
+const USERNAME_LOOKALIKE_KEY: &str = "replace(translate(username_normalized, '0i', 'ol'), 'rn', 'm')";
+
+/// Whether the [`lookalike_key`] of `users.username_normalized` is `key`, as SQL.
+#[cfg(feature = "ssr")]
+fn username_looks_like(key: String) -> sea_orm::sea_query::SimpleExpr {
+    Expr::cust_with_values(format!("{USERNAME_LOOKALIKE_KEY} = $1"), [key])
+}
+
+#[cfg(feature = "ssr")]
+#[derive(Debug, Error)]
+pub enum SetDisplayNameError {
+    #[error("{0}")]
+    Invalid(#[from] DisplayNameError),
+    #[error("database error: {0}")]
+    Db(#[from] DbErr),
+}
+
+/// Sets `user`'s display name, or clears it if `display_name` is empty. `actor_id` is who made the change, for the audit
+/// log, with `reason` if it wasn't the user.
+#[cfg(feature = "ssr")]
+pub async fn set(
+    conn: &impl ConnectionTrait,
+    user: User,
+    display_name: &str,
+    actor_id: Uuid,
+    reason: Option<String>,
+) -> Result<User, SetDisplayNameError> {
+    use sea_orm::Set;
+    use crate::auth::audit::{AuditEvent, Entry};
+
+    let display_name = Some(normalize(display_name)).filter(|display_name| !display_name.is_empty());
+    if let Some(display_name) = &display_name {
+        check_validity(display_name)?;
+        let lookalike = Users::find()
+            .filter(entity::users::Column::Id.ne(user.id))
+            .filter(username_looks_like(lookalike_key(display_name)))
+            .one(conn)
+            .await?;
+        if lookalike.is_some() {
+            return Err(DisplayNameError::LooksLikeUsername.into());
+        }
+    }
+    if display_name == user.display_name {
+        return Ok(user);
+    }
+
+    let old_display_name = user.display_name.clone();
+    let mut record: entity::users::ActiveModel = user.into();
+    record.display_name = Set(display_name);
+    let user = record.update(conn).await?;
+
+    let mut details = serde_json::json!({ "from": old_display_name, "to": user.display_name });
+    if let Some(reason) = reason {
+        details["reason"] = reason.into();
+    }
+    Entry::new(AuditEvent::DisplayNameChanged, Some(actor_id))
+        .user(user.id)
+        .details(details)
+        .record(conn)
+        .await?;
+    Ok(user)
+}
+
+#[cfg(all(test, feature = "ssr"))]
+mod tests {
+    use sea_orm::{ConnectionTrait, Database, DbBackend, Statement};
+
+    use super::*;
+
+    /// Display names, the normalized usernames they look like, and the key both have.
+    const LOOKALIKES: [(&str, &str, &str); 6] = [
+        ("Iggy", "iggy", "lggy"),
+        ("B0B", "bob", "bob"),
+        ("rnoe", "moe", "moe"),
+        ("Moe", "rnoe", "moe"),
+        // The first letter is Cyrillic
+        ("\u{430}dmin", "admin", "admln"),
+        ("ｊｅｓｓ", "jess", "jess"),
+    ];
+
+    #[test]
+    fn lookalike_keys() {
+        for (display_name, _, key) in LOOKALIKES {
+            assert_eq!(lookalike_key(display_name), key, "key of {display_name:?}");
+        }
+    }
+
+    /// Checks [`USERNAME_LOOKALIKE_KEY`] against [`lookalike_key`].
+    #[tokio::test]
+    #[ignore = "needs a database, set DATABASE_URL and run with --ignored"]
+    async fn sql_lookalike_keys_match() {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL to be set");
+        let db = Database::connect(url).await.expect("to be able to connect to database");
+        for (display_name, username, _) in LOOKALIKES {
+            let sql = format!("SELECT {USERNAME_LOOKALIKE_KEY} AS key FROM (VALUES ($1)) AS users(username_normalized)");
+            let row = db
+                .query_one(Statement::from_sql_and_values(DbBackend::Postgres, sql, [normalize_username(username).into()]))
+                .await
+                .expect("to compute the key")
+                .expect("a row");
+            let key: String = row.try_get("", "key").expect("a string");
+            assert_eq!(key, lookalike_key(display_name), "key of {username:?} and {display_name:?}");
+        }
+    }
+}
@@ -0,0 +1,125 @@
+//! Letting admins see the site the way a user sees it, to help them with problems. An admin with
+//! [`Scope::ImpersonateUsers`](super::Scope::ImpersonateUsers) starts an impersonation from `/admin/users`, which swaps
+//! the access token of their own session for one with the user's ID and scopes, plus an [`Actor`] claim naming the admin.
+//! The session and its refresh token stay the admin's, so when the impersonation ends or expires the next access token
+//! is theirs again.
+//!
+//! Impersonations are read-only unless the admin asks to be able to edit the user's mods. [`guard`] enforces this by
+//! only letting through server functions that are known to be safe, so new ones are blocked until they are added here.
+//! Nothing that changes the user's account is ever allowed.
+
+use std::collections::HashSet;
+use std::sync::{Arc, LazyLock};
+
+use axum::extract::{Request, State};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use http::{StatusCode, header::COOKIE};
+use server_fn::error::FromServerFnError;
+use time::OffsetDateTime;
+
+use crate::prelude::*;
+use crate::auth::{
+    cookie::get_cookie_value,
+    keys::Keyring,
+    refresh::{ACCESS_COOKIE, ACCESS_TOKEN_LIFETIME_SECONDS},
+    roles,
+    token::{Actor, Claims, Service},
+};
+
+/// 30 minutes
+pub const IMPERSONATION_LENGTH: time::Duration = time::Duration::minutes(30);
+
+/// Server functions that only read, which work during every impersonation
+const READ_ONLY_SERVER_FNS: [&str; 22] = [
+    "get_session_user",
+    "is_logged_in",
+    "current_impersonation",
+    "stop_impersonation",
+    "session_mods",
+    "get_all_games",
+    "get_game",
+    "published_mods_by_recency",
+    "get_mod_by_slug",
+    "get_mod_media",
+    "get_mod_authors",
+    "get_user_profile",
+    "get_feed",
+    "get_subscription",
+    "list_passkeys",
+    "needs_trusted_passkey",
+    "recovery_code_count",
+    "get_username_settings",
+    "list_sessions",
+    "list_access_tokens",
+    "list_apps",
+    "list_activity",
+];
+
+/// Server functions that change the user's mods, which work if the admin asked to be able to make changes
+const MOD_EDITING_SERVER_FNS: [&str; 4] = ["new_mod", "edit_mod", "upload_image", "delete_media"];
+
+static SERVER_FN_PATHS: LazyLock<HashSet<&'static str>> =
+    LazyLock::new(|| server_fn::axum::server_fn_paths().map(|(path, _)| path).collect());
+
+/// The impersonation in progress in the admin's session `session_id`, if any.
+pub async fn active(conn: &impl ConnectionTrait, session_id: Uuid) -> Result<Option<entity::impersonations::Model>, DbErr> {
+    use entity::impersonations::Column;
+
+    Impersonations::find()
+        .filter(Column::SessionId.eq(session_id))
+        .filter(Column::EndedAt.is_null())
+        .filter(Column::ExpiresAt.gt(OffsetDateTime::now_utc()))
+        .order_by_desc(Column::StartedAt)
+        .one(conn)
+        .await
+}
+
+/// Claims for an access token that represents the impersonated user, which expires with the impersonation.
+pub async fn claims(conn: &impl ConnectionTrait, impersonation: &entity::impersonations::Model) -> Result<Claims, DbErr> {
+    let scopes = roles::scopes_for(conn, impersonation.user_id).await?;
+    let remaining = (impersonation.expires_at - OffsetDateTime::now_utc()).whole_seconds().max(0) as u64;
+    let mut claims = Claims::new(
+        impersonation.user_id,
+        scopes,
+        [Service::StarHavenPlatform],
+        remaining.min(ACCESS_TOKEN_LIFETIME_SECONDS),
+    );
+    claims.jti = impersonation.session_id;
+    claims.act = Some(Actor {
+        sub: impersonation.actor_id,
+        impersonation_id: impersonation.id,
+        read_only: impersonation.read_only,
+    });
+    Ok(claims)
+}
+
+/// Rejects server function calls that aren't allowed while impersonating, and makes the [`Actor`] of the ones that are
+/// available to [`crate::request::impersonator`].
+pub async fn guard(State(keyring): State<Arc<Keyring>>, mut request: Request, next: Next) -> Response {
+    let actor = request.headers()
+        .get(COOKIE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|cookies| get_cookie_value(cookies, ACCESS_COOKIE))
+        .and_then(|token| keyring.decode::<Claims>(&token, Claims::validation()).ok())
+        .and_then(|token_data| token_data.claims.act);
+    let Some(actor) = actor else {
+        return next.run(request).await;
+    };
+
+    let path = request.uri().path();
+    if SERVER_FN_PATHS.contains(path) && !is_allowed(path, actor.read_only) {
+        log::info!("blocked {path} for admin {} during impersonation {}", actor.sub, actor.impersonation_id);
+        let error: ServerFnError = ServerFnError::ServerError("You can't do that while viewing the site as someone else".to_string());
+        return (StatusCode::FORBIDDEN, error.ser()).into_response();
+    }
+
+    request.extensions_mut().insert(actor);
+    next.run(request).await
+}
+
+fn is_allowed(path: &str, read_only: bool) -> bool {
+    // Server function paths end with the function's name followed by a hash
+    let name = path.rsplit('/').next().unwrap_or_default().trim_end_matches(|c: char| c.is_ascii_digit());
+    READ_ONLY_SERVER_FNS.contains(&name) || (!read_only && MOD_EDITING_SERVER_FNS.contains(&name))
+}
@@ -0,0 +1,252 @@
+//! Keys used to sign and verify JSON Web Tokens.
+//!
+//! Every token carries a `kid` header naming the key that signed it. New tokens are signed with the active key, while
+//! tokens signed by any other key in the keyring remain valid until they expire. To rotate:
+//!
+//! 1. Add the new private key to `JWT_KEYS_DIR` and make it active with `JWT_ACTIVE_KEY`.
+//! 2. Once every token signed by the old key has expired, delete the old key (or keep only its public half to leave it
+//!    in the JWKS for a while longer).
+//!
+//! Other services verify tokens using the public keys published at `/.well-known/jwks.json`.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use jsonwebtoken::jwk::{
+    AlgorithmParameters, CommonParameters, EllipticCurve, EllipticCurveKeyParameters, EllipticCurveKeyType, Jwk,
+    JwkSet, KeyAlgorithm, OctetKeyPairParameters, OctetKeyPairType, PublicKeyUse,
+};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, TokenData, Validation};
+use ring::digest::{SHA256, digest};
+use ring::hmac;
+use ring::rand::SystemRandom;
+use ring::signature::{ECDSA_P256_SHA256_FIXED_SIGNING, EcdsaKeyPair, Ed25519KeyPair, KeyPair};
+use serde::{Serialize, de::DeserializeOwned};
+use thiserror::Error;
+
+use crate::config::Config;
+
+/// DER prefix of an Ed25519 SubjectPublicKeyInfo, followed by the 32-byte public key
+const ED25519_SPKI_PREFIX: &[u8] = &[0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00];
+
+/// DER prefix of a P-256 SubjectPublicKeyInfo, followed by the 65-byte uncompressed point
+const P256_SPKI_PREFIX: &[u8] = &[
+    0x30, 0x59, 0x30, 0x13, 0x06, 0x07, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01, 0x06, 0x08, 0x2a, 0x86, 0x48, 0xce,
+    0x3d, 0x03, 0x01, 0x07, 0x03, 0x42, 0x00,
+];
+
+#[derive(Debug, Error)]
+pub enum KeyError {
+    #[error("failed to read {path}: {error}")]
+    Io { path: String, error: std::io::Error },
+    #[error("{path} is not a PEM file: {error}")]
+    Pem { path: String, error: pem::PemError },
+    #[error("{path} must be a PKCS#8 Ed25519 or P-256 private key, or the public half of one")]
+    UnsupportedKey { path: String },
+    #[error("no JWT signing keys in {0}")]
+    NoKeys(String),
+    #[error("JWT_ACTIVE_KEY must be set to choose between the signing keys in {0}")]
+    AmbiguousActiveKey(String),
+    #[error("JWT_ACTIVE_KEY `{0}` does not name a private key in JWT_KEYS_DIR")]
+    UnknownActiveKey(String),
+    #[error("JWT_KEYS_DIR must be set in release builds")]
+    Missing,
+    #[error("failed to generate key")]
+    Generate,
+}
+
+struct Key {
+    algorithm: Algorithm,
+    /// Only present for private keys
+    encoding: Option<EncodingKey>,
+    /// Derived from the private key, for [`Keyring::derive`]
+    hmac: Option<hmac::Key>,
+    decoding: DecodingKey,
+    jwk: Jwk,
+}
+
+/// The set of keys this server signs and verifies tokens with.
+pub struct Keyring {
+    active: String,
+    keys: BTreeMap<String, Key>,
+}
+
+impl Keyring {
+    /// Loads every `<kid>.pem` file in `JWT_KEYS_DIR`. Without a keys directory, debug builds generate a temporary key
+    /// (so sessions end whenever the server restarts) and release builds refuse to start.
+    pub fn from_config(config: &Config) -> Result<Self, KeyError> {
+        let Some(dir) = &config.jwt_keys_dir else {
+            if cfg!(debug_assertions) {
+                log::warn!("JWT_KEYS_DIR is not set, using a temporary signing key");
+                return Self::ephemeral();
+            }
+            return Err(KeyError::Missing);
+        };
+
+        let dir_name = dir.display().to_string();
+        let entries = std::fs::read_dir(dir).map_err(|error| KeyError::Io { path: dir_name.clone(), error })?;
+        let mut keys = BTreeMap::new();
+        for entry in entries {
+            let path = entry.map_err(|error| KeyError::Io { path: dir_name.clone(), error })?.path();
+            if path.extension().is_some_and(|ext| ext == "pem") {
+                let kid = path.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+                keys.insert(kid.clone(), Key::load(&kid, &path)?);
+            }
+        }
+
+        let mut private = keys.iter().filter(|(_, key)| key.encoding.is_some()).map(|(kid, _)| kid);
+        let active = match &config.jwt_active_key {
+            Some(kid) if keys.get(kid).is_some_and(|key| key.encoding.is_some()) => kid.clone(),
+            Some(kid) => return Err(KeyError::UnknownActiveKey(kid.clone())),
+            None => match (private.next(), private.next()) {
+                (Some(kid), None) => kid.clone(),
+                (None, _) => return Err(KeyError::NoKeys(dir_name)),
+                (Some(_), Some(_)) => return Err(KeyError::AmbiguousActiveKey(dir_name)),
+            },
+        };
+
+        log::info!("loaded {} JWT keys, signing with `{active}`", keys.len());
+        Ok(Keyring { active, keys })
+    }
+
+    /// A keyring with a single, newly generated Ed25519 key.
+    pub fn ephemeral() -> Result<Self, KeyError> {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).map_err(|_| KeyError::Generate)?;
+        let kid = uuid::Uuid::new_v4().to_string();
+        let key = Key::from_private_der(&kid, pkcs8.as_ref()).ok_or(KeyError::Generate)?;
+        Ok(Keyring { active: kid.clone(), keys: BTreeMap::from([(kid, key)]) })
+    }
+
+    /// Signs claims with the active key.
+    pub fn encode<T: Serialize>(&self, claims: &T) -> Result<String, jsonwebtoken::errors::Error> {
+        let key = &self.keys[&self.active];
+        let mut header = Header::new(key.algorithm);
+        header.kid = Some(self.active.clone());
+        let encoding = key.encoding.as_ref().expect("active key to be a private key");
+        jsonwebtoken::encode(&header, claims, encoding)
+    }
+
+    /// Verifies a token against the key named in its header. `validation` is used for everything except the
+    /// algorithm, which is always the one belonging to the key.
+    pub fn decode<T: DeserializeOwned>(&self, token: &str, mut validation: Validation) -> Result<TokenData<T>, jsonwebtoken::errors::Error> {
+        use jsonwebtoken::errors::ErrorKind;
+
+        let header = jsonwebtoken::decode_header(token)?;
+        let key = header
+            .kid
+            .and_then(|kid| self.keys.get(&kid))
+            .ok_or(ErrorKind::InvalidKeyFormat)?;
+        validation.algorithms = vec![key.algorithm];
+        jsonwebtoken::decode(token, &key.decoding, &validation)
+    }
+
+    /// Derives a value from `input` that only this server can compute, and that stays the same until the active key
+    /// changes. `purpose` keeps values derived for different uses apart.
+    pub fn derive(&self, purpose: &str, input: &[u8]) -> Vec<u8> {
+        let key = self.keys[&self.active].hmac.as_ref().expect("active key to be a private key");
+        let mut context = hmac::Context::with_key(key);
+        context.update(purpose.as_bytes());
+        context.update(&[0]);
+        context.update(input);
+        context.sign().as_ref().to_vec()
+    }
+
+    /// Public keys for other services to verify our tokens with.
+    pub fn jwks(&self) -> JwkSet {
+        JwkSet { keys: self.keys.values().map(|key| key.jwk.clone()).collect() }
+    }
+}
+
+impl Key {
+    fn load(kid: &str, path: &Path) -> Result<Self, KeyError> {
+        let path_name = path.display().to_string();
+        let contents = std::fs::read(path).map_err(|error| KeyError::Io { path: path_name.clone(), error })?;
+        let pem = pem::parse(contents).map_err(|error| KeyError::Pem { path: path_name.clone(), error })?;
+        let key = match pem.tag() {
+            "PRIVATE KEY" => Key::from_private_der(kid, pem.contents()),
+            "PUBLIC KEY" => Key::from_public_der(kid, pem.contents()),
+            _ => None,
+        };
+        key.ok_or(KeyError::UnsupportedKey { path: path_name })
+    }
+
+    fn from_private_der(kid: &str, der: &[u8]) -> Option<Self> {
+        if let Ok(pair) = Ed25519KeyPair::from_pkcs8_maybe_unchecked(der) {
+            let mut key = Key::ed25519(kid, pair.public_key().as_ref())?;
+            key.encoding = Some(EncodingKey::from_ed_der(der));
+            key.hmac = Some(hmac::Key::new(hmac::HMAC_SHA256, digest(&SHA256, der).as_ref()));
+            return Some(key);
+        }
+        if let Ok(pair) = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, der, &SystemRandom::new()) {
+            let mut key = Key::p256(kid, pair.public_key().as_ref())?;
+            key.encoding = Some(EncodingKey::from_ec_der(der));
+            key.hmac = Some(hmac::Key::new(hmac::HMAC_SHA256, digest(&SHA256, der).as_ref()));
+            return Some(key);
+        }
+        None
+    }
+
+    fn from_public_der(kid: &str, der: &[u8]) -> Option<Self> {
+        if let Some(public) = der.strip_prefix(ED25519_SPKI_PREFIX) {
+            Key::ed25519(kid, public)
+        } else if let Some(public) = der.strip_prefix(P256_SPKI_PREFIX) {
+            Key::p256(kid, public)
+        } else {
+            None
+        }
+    }
+
+    fn ed25519(kid: &str, public: &[u8]) -> Option<Self> {
+        if public.len() != 32 {
+            return None;
+        }
+        let params = AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+            key_type: OctetKeyPairType::OctetKeyPair,
+            curve: EllipticCurve::Ed25519,
+            x: URL_SAFE_NO_PAD.encode(public),
+        });
+        Key::from_jwk(kid, Algorithm::EdDSA, KeyAlgorithm::EdDSA, params)
+    }
+
+    /// `public` is an uncompressed SEC1 point
+    fn p256(kid: &str, public: &[u8]) -> Option<Self> {
+        let [0x04, coordinates @ ..] = public else {
+            return None;
+        };
+        if coordinates.len() != 64 {
+            return None;
+        }
+        let (x, y) = coordinates.split_at(32);
+        let params = AlgorithmParameters::EllipticCurve(EllipticCurveKeyParameters {
+            key_type: EllipticCurveKeyType::EC,
+            curve: EllipticCurve::P256,
+            x: URL_SAFE_NO_PAD.encode(x),
+            y: URL_SAFE_NO_PAD.encode(y),
+        });
+        Key::from_jwk(kid, Algorithm::ES256, KeyAlgorithm::ES256, params)
+    }
+
+    fn from_jwk(kid: &str, algorithm: Algorithm, key_algorithm: KeyAlgorithm, params: AlgorithmParameters) -> Option<Self> {
+        let jwk = Jwk {
+            common: CommonParameters {
+                public_key_use: Some(PublicKeyUse::Signature),
+                key_algorithm: Some(key_algorithm),
+                key_id: Some(kid.to_owned()),
+                ..Default::default()
+            },
+            algorithm: params,
+        };
+        Some(Key {
+            algorithm,
+            encoding: None,
+            hmac: None,
+            decoding: DecodingKey::from_jwk(&jwk).ok()?,
+            jwk,
+        })
+    }
+}
+
+pub fn keyring() -> std::sync::Arc<Keyring> {
+    leptos::prelude::expect_context()
+}
@@ -0,0 +1,335 @@
+//! One-time recovery codes, which let users who lost every passkey register a new one.
+
+use crate::prelude::*;
+
+use webauthn_rs_proto::{CreationChallengeResponse, RegisterPublicKeyCredential};
+use phosphor_leptos::{Icon, IconWeight, WARNING, DOWNLOAD_SIMPLE};
+
+/// How many codes a user is given at a time.
+pub const RECOVERY_CODE_COUNT: usize = 10;
+
+#[cfg(feature = "ssr")]
+mod codes {
+    use ring::digest::{SHA256, digest};
+    use ring::rand::{SecureRandom, SystemRandom};
+    use sea_orm::{ConnectionTrait, Set};
+    use time::OffsetDateTime;
+
+    use crate::auth::audit::{AuditEvent, Entry};
+    use crate::prelude::*;
+
+    /// Crockford's base32, which avoids letters that are easily confused with digits
+    const ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+    /// Generates a code like `7G4Q-XM2C-9RTA`, with 60 bits of entropy.
+    fn generate() -> String {
+        let mut bytes = [0u8; 8];
+        SystemRandom::new().fill(&mut bytes).expect("system random number generator failed");
+        let mut bits = u64::from_le_bytes(bytes);
+
+        let mut code = String::with_capacity(14);
+        for i in 0..12 {
+            if i > 0 && i % 4 == 0 {
+                code.push('-');
+            }
+            code.push(ALPHABET[(bits & 31) as usize] as char);
+            bits >>= 5;
+        }
+        code
+    }
+
+    /// Hashes a code as typed by the user, ignoring case, separators and commonly confused characters.
+    pub fn hash(code: &str) -> Vec<u8> {
+        let normalized: String = code
+            .chars()
+            .filter(char::is_ascii_alphanumeric)
+            .map(|c| match c.to_ascii_uppercase() {
+                'O' => '0',
+                'I' | 'L' => '1',
+                c => c,
+            })
+            .collect();
+        digest(&SHA256, normalized.as_bytes()).as_ref().to_vec()
+    }
+
+    /// Replaces any codes the user has with a fresh set, returning the new codes. Only their hashes are stored, so this
+    /// is the only time they can be shown.
+    pub async fn replace(conn: &impl ConnectionTrait, user_id: Uuid) -> Result<Vec<String>, DbErr> {
+        use entity::recovery_codes::{ActiveModel, Column};
+
+        RecoveryCodes::delete_many()
+            .filter(Column::UserId.eq(user_id))
+            .exec(conn)
+            .await?;
+
+        let codes: Vec<String> = (0..super::RECOVERY_CODE_COUNT).map(|_| generate()).collect();
+        let now = OffsetDateTime::now_utc();
+        RecoveryCodes::insert_many(codes.iter().map(|code| ActiveModel {
+            id: Set(Uuid::new_v4()),
+            user_id: Set(user_id),
+            code_hash: Set(hash(code)),
+            created_at: Set(now),
+            used_at: Set(None),
+        }))
+        .exec(conn)
+        .await?;
+
+        Entry::new(AuditEvent::RecoveryCodesGenerated, Some(user_id)).user(user_id).record(conn).await?;
+        Ok(codes)
+    }
+}
+
+#[cfg(feature = "ssr")]
+pub use codes::replace as replace_recovery_codes;
+
+/// State held between start_recovery and finish_recovery calls.
+#[cfg(feature = "ssr")]
+#[derive(Serialize, Deserialize)]
+struct RecoveryState {
+    user_id: Uuid,
+    code_id: Uuid,
+    registration: crate::auth::attestation::Registration,
+}
+
+/// Checks a recovery code and starts registering a new passkey for its owner. The code isn't used up until the new
+/// passkey is registered, so abandoning the ceremony leaves it valid.
+#[server]
+pub async fn start_recovery(username: String, code: String) -> Result<(CreationChallengeResponse, Uuid), ServerFnError> {
+    use crate::auth::attestation;
+    use crate::auth::challenge::{Ceremony, challenges};
+    use crate::auth::passkey::challenge_client;
+
+    let subjects = super::throttle::check("start_recovery", Some(&username))?;
+    let result = async {
+        let invalid = || ServerFnError::new("Invalid username or recovery code.");
+
+        let user = Users::find()
+            .filter(entity::users::Column::UsernameNormalized.eq(super::normalize_username(&username)))
+            .one(&db())
+            .await?
+            .ok_or_else(invalid)?;
+        let recovery_code = RecoveryCodes::find()
+            .filter(entity::recovery_codes::Column::UserId.eq(user.id))
+            .filter(entity::recovery_codes::Column::CodeHash.eq(codes::hash(&code)))
+            .filter(entity::recovery_codes::Column::UsedAt.is_null())
+            .one(&db())
+            .await?
+            .ok_or_else(|| {
+                log::info!("rejected recovery code for user {}", user.id);
+                invalid()
+            })?;
+
+        let existing_credentials = Passkeys::find()
+            .filter(entity::passkeys::Column::UserId.eq(user.id))
+            .all(&db())
+            .await?
+            .into_iter()
+            .map(|passkey| passkey.id.into())
+            .collect();
+        // Privileged accounts still need a trusted authenticator, so that a stolen recovery code can't add any passkey
+        let (ccr, registration) = attestation::start_registration(&db(), user.id, &user.username, Some(existing_credentials)).await?;
+
+        let state = RecoveryState { user_id: user.id, code_id: recovery_code.id, registration };
+        let id = challenges().insert(Ceremony::Recover, &challenge_client(), &state).await?;
+        Ok((ccr, id))
+    }.await;
+    super::throttle::record(&subjects, result)
+}
+
+/// Registers the new passkey, uses up the recovery code, signs the user out everywhere else and signs them in here.
+#[server]
+pub async fn finish_recovery(id: Uuid, reg: RegisterPublicKeyCredential) -> Result<(), ServerFnError> {
+    use sea_orm::Set;
+    use time::OffsetDateTime;
+
+    use crate::auth::challenge::{Ceremony, challenges};
+    use crate::auth::audit::{AuditEvent, Entry, Target};
+    use crate::auth::attestation;
+    use crate::auth::session::revoke_sessions;
+
+    let subjects = super::throttle::check("finish_recovery", None)?;
+    let result = async {
+        let Some(RecoveryState { user_id, code_id, registration }) = challenges().take(Ceremony::Recover, id).await? else {
+            return Err(ServerFnError::new("No recovery challenge found."));
+        };
+
+        let passkey = attestation::finish_registration(&reg, &registration)?;
+        let passkey_id = passkey.id.clone();
+        if Passkeys::find_by_id(passkey_id.clone()).one(&db()).await?.is_some() {
+            return Err(ServerFnError::new("This passkey is already registered."));
+        }
+
+        let user = db().transaction::<_, User, anyhow::Error>(|txn| {
+            Box::pin(async move {
+                use entity::recovery_codes::Column;
+
+                // Another request may have used the code since start_recovery checked it
+                let used = RecoveryCodes::update_many()
+                    .col_expr(Column::UsedAt, Expr::value(OffsetDateTime::now_utc()))
+                    .filter(Column::Id.eq(code_id))
+                    .filter(Column::UsedAt.is_null())
+                    .exec(txn)
+                    .await?;
+                if used.rows_affected == 0 {
+                    anyhow::bail!("recovery code was already used");
+                }
+
+                entity::passkeys::ActiveModel {
+                    id: Set(passkey_id.clone()),
+                    user_id: Set(user_id),
+                    data: Set(passkey.data),
+                    name: Set(crate::request::user_agent().map(|user_agent| crate::request::describe_user_agent(&user_agent))),
+                    ..Default::default()
+                }.insert(txn).await?;
+
+                let remaining = RecoveryCodes::find()
+                    .filter(Column::UserId.eq(user_id))
+                    .filter(Column::UsedAt.is_null())
+                    .count(txn)
+                    .await?;
+                Entry::new(AuditEvent::RecoveryCodeUsed, Some(user_id))
+                    .user(user_id)
+                    .details(serde_json::json!({ "remaining": remaining }))
+                    .record(txn)
+                    .await?;
+                Entry::new(AuditEvent::PasskeyAdded, Some(user_id))
+                    .user(user_id)
+                    .target(Target::Passkey(passkey_id))
+                    .details(serde_json::json!({
+                        "via": "recovery_code",
+                        "authenticator": passkey.authenticator.map(|authenticator| authenticator.model),
+                    }))
+                    .record(txn)
+                    .await?;
+
+                Users::find_by_id(user_id).one(txn).await?.ok_or_else(|| anyhow::anyhow!("user {user_id} not found"))
+            })
+        }).await?;
+
+        // Whoever lost the passkey may have lost a signed-in device with it
+        revoke_sessions(entity::sessions::Column::UserId.eq(user.id)).await?;
+        session().await.login(&user).await?;
+        Ok(())
+    }.await;
+    super::throttle::record(&subjects, result)
+}
+
+#[server]
+pub async fn recovery_code_count() -> Result<u64, ServerFnError> {
+    let Some(user_id) = session().await.account_uuid() else {
+        return Err(ServerFnError::ServerError("Must be signed in".to_string()));
+    };
+
+    Ok(RecoveryCodes::find()
+        .filter(entity::recovery_codes::Column::UserId.eq(user_id))
+        .filter(entity::recovery_codes::Column::UsedAt.is_null())
+        .count(&db())
+        .await?)
+}
+
+/// Replaces the signed-in user's recovery codes, invalidating the old ones.
+#[server]
+pub async fn regenerate_recovery_codes() -> Result<Vec<String>, ServerFnError> {
+    let Some(user_id) = session().await.account_uuid() else {
+        return Err(ServerFnError::ServerError("Must be signed in".to_string()));
+    };
+
+    let codes = db().transaction::<_, Vec<String>, DbErr>(|txn| {
+        Box::pin(async move { codes::replace(txn, user_id).await })
+    }).await.map_err(|error| match error {
+        sea_orm::TransactionError::Connection(error) | sea_orm::TransactionError::Transaction(error) => error,
+    })?;
+    Ok(codes)
+}
+
+/// Shows freshly generated recovery codes, which can't be retrieved again later.
+#[component]
+pub fn RecoveryCodes(codes: Vec<String>, on_done: impl Fn() + 'static) -> impl IntoView {
+    let download = format!("data:text/plain;charset=utf-8,{}%0A", codes.join("%0A"));
+
+    view! {
+        <div>
+            <p class="text-sm mb-2">
+                "Save these recovery codes somewhere safe, like a password manager. If you lose your passkeys, you can use one of them to get back into your account. Each code works once."
+            </p>
+            <p class="text-sm font-semibold mb-2">"You won't be able to see them again."</p>
+            <ol class="grid grid-cols-2 gap-x-6 gap-y-1 font-mono my-4">
+                {codes.into_iter().map(|code| view! { <li>{code}</li> }).collect_view()}
+            </ol>
+            <div class="flex items-center gap-2">
+                <a
+                    href=download
+                    download="star-haven-recovery-codes.txt"
+                    class="border border-stone-400 font-semibold py-1.5 px-4 rounded inline-flex items-center justify-center gap-2"
+                >
+                    <Icon icon=DOWNLOAD_SIMPLE weight=IconWeight::Bold />
+                    "Download"
+                </a>
+                <button
+                    class="bg-yellow-500 hover:bg-yellow-600 text-white font-semibold py-1.5 px-4 rounded grow"
+                    on:click=move |_| on_done()
+                >
+                    "I've saved my codes"
+                </button>
+            </div>
+        </div>
+    }
+}
+
+/// Lets someone who has lost their passkeys register a new one using a recovery code.
+#[component]
+pub fn RecoveryForm() -> impl IntoView {
+    let username = RwSignal::new("".to_string());
+    let code = RwSignal::new("".to_string());
+
+    let recover: Action<_, Result<()>> = Action::new_local(|(username, code): &(String, String)| {
+        let (username, code) = (username.to_owned(), code.to_owned());
+        async move {
+            #[cfg(feature = "hydrate")]
+            {
+                let (ccr, id) = start_recovery(username, code).await.map_err(|error| match error {
+                    ServerFnError::ServerError(message) => anyhow::anyhow!(message),
+                    error => {
+                        log::error!("failed to start recovery: {error:?}");
+                        anyhow::anyhow!("Something went wrong, please try again")
+                    }
+                })?;
+                let credential = super::passkey::create_credential(ccr).await?;
+                finish_recovery(id, credential).await.map_err(|error| {
+                    log::error!("failed to finish recovery: {error:?}");
+                    anyhow::anyhow!("Failed to register passkey")
+                })?;
+                window().location().set_href("/account/security").expect("failed to redirect to account page");
+            }
+            #[cfg(not(feature = "hydrate"))]
+            {
+                let _ = (username, code);
+            }
+            Ok(())
+        }
+    });
+
+    view! {
+        <div class="flex flex-col gap-2">
+            <input type="text" autocomplete="username" maxlength=20 placeholder="Username" bind:value=username
+                class="border border-stone-200 py-1.5 px-4 rounded-md w-full bg-white text-stone-800 placeholder-stone-400" />
+            <input type="text" autocomplete="off" spellcheck="false" placeholder="Recovery code" bind:value=code
+                class="border border-stone-200 py-1.5 px-4 rounded-md w-full bg-white text-stone-800 placeholder-stone-400 font-mono" />
+        </div>
+
+        <button
+            class="bg-yellow-500 hover:bg-yellow-600 text-white font-semibold py-1.5 px-4 rounded w-full mt-3"
+            on:click={move |_| { recover.dispatch_local((username.get(), code.get())); }}
+            disabled={move || username.get().is_empty() || code.get().is_empty()}
+        >
+            "Recover account"
+        </button>
+
+        <div class="mt-1 text-xs text-red-500 min-h-5 flex items-center gap-1" aria-live="polite">
+            <Show when={move || recover.value().read().as_ref().is_some_and(|r| r.is_err())}>
+                <Icon icon=WARNING weight=IconWeight::Fill />
+            </Show>
+            {move || recover.value().read().as_ref().map(|r| r.as_ref().err().map(|e| e.to_string()))}
+        </div>
+    }
+}
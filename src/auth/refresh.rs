@@ -0,0 +1,237 @@
+//! Keeping sessions signed in without long-lived bearer tokens. The `session` cookie holds an access token that expires
+//! after a few minutes, and the `session_refresh` cookie holds a refresh token for getting a new one. Refresh tokens are
+//! stored hashed in `session_refresh_tokens` and rotate on every use. All of a session's refresh tokens form a family:
+//! if one that was already used comes back, someone else has a copy of it, so the whole session is revoked.
+//!
+//! Refreshing happens in [`middleware`] rather than in [`session`](super::session::session), so that the new cookies
+//! make it into the response even for pages that start streaming before they finish rendering. A client that kept a
+//! spent refresh token because it never saw the new one would otherwise look like a thief.
+
+use std::sync::Arc;
+
+use axum::extract::{Request, State};
+use axum::middleware::Next;
+use axum::response::Response;
+use http::{HeaderMap, HeaderValue, header::{COOKIE, SET_COOKIE}};
+use sea_orm::{DatabaseConnection, EntityTrait, Set};
+use time::OffsetDateTime;
+
+use crate::prelude::*;
+use crate::auth::{
+    audit::{Entry, AuditEvent, Target},
+    cookie::{cookie_header, get_cookie_value},
+    impersonation,
+    keys::Keyring,
+    roles, secret,
+    session::{SESSION_LENGTH_SECONDS, SessionError},
+    token::{Claims, Service},
+};
+
+/// 10 minutes
+pub const ACCESS_TOKEN_LIFETIME_SECONDS: u64 = 10 * 60;
+pub const ACCESS_COOKIE: &str = "session";
+pub const REFRESH_COOKIE: &str = "session_refresh";
+
+/// Access tokens are refreshed this close to expiring, so that they don't expire partway through a request
+const REFRESH_MARGIN_SECONDS: u64 = 60;
+/// A page that sends several requests at once sends the same refresh token with each. Reusing a token this soon after
+/// it was used gets a new access token but doesn't count as theft.
+const REUSE_GRACE: time::Duration = time::Duration::seconds(30);
+/// How long used refresh tokens are kept to recognise them if they come back
+const USED_TOKEN_RETENTION: time::Duration = time::Duration::days(7);
+const TOKEN_PREFIX: &str = "shsr_";
+
+/// Paths that never look at the session
+const SKIPPED_PREFIXES: [&str; 2] = ["/assets/", "/pkg/"];
+
+#[derive(Clone)]
+pub struct RefreshState {
+    pub db: DatabaseConnection,
+    pub keyring: Arc<Keyring>,
+}
+
+/// New cookies for the client, or `None` values to clear them.
+struct Refreshed {
+    access_token: Option<String>,
+    /// Left out when the client should keep the refresh token it has
+    refresh_token: Option<Option<String>>,
+}
+
+impl Refreshed {
+    fn signed_out() -> Self {
+        Refreshed { access_token: None, refresh_token: Some(None) }
+    }
+}
+
+/// Creates a refresh token for `session_id`, returning it to be sent to the client.
+pub async fn create_token(conn: &impl ConnectionTrait, session_id: Uuid) -> Result<String, DbErr> {
+    let (token, token_hash) = secret::generate(TOKEN_PREFIX);
+    entity::session_refresh_tokens::ActiveModel {
+        id: Set(Uuid::new_v4()),
+        session_id: Set(session_id),
+        token_hash: Set(token_hash),
+        created_at: Set(OffsetDateTime::now_utc()),
+        used_at: Set(None),
+    }.insert(conn).await?;
+    Ok(token)
+}
+
+/// Swaps the refresh token for a new access token when the current one is missing or about to expire, passing the new
+/// cookies on to the rest of the request as well as back to the client.
+pub async fn middleware(State(state): State<RefreshState>, mut request: Request, next: Next) -> Response {
+    if SKIPPED_PREFIXES.iter().any(|prefix| request.uri().path().starts_with(prefix)) {
+        return next.run(request).await;
+    }
+
+    let cookies = request.headers()
+        .get(COOKIE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    let claims = get_cookie_value(&cookies, ACCESS_COOKIE)
+        .and_then(|token| state.keyring.decode::<Claims>(&token, Claims::validation()).ok())
+        .map(|token_data| token_data.claims);
+    let refresh_token = get_cookie_value(&cookies, REFRESH_COOKIE);
+
+    let result = match (claims, refresh_token) {
+        (Some(claims), Some(_)) if claims.expires_in() > REFRESH_MARGIN_SECONDS => return next.run(request).await,
+        (_, Some(refresh_token)) => refresh(&state, &refresh_token).await,
+        _ => return next.run(request).await,
+    };
+    let refreshed = match result {
+        Ok(refreshed) => refreshed,
+        Err(error) => {
+            log::error!("failed to refresh session: {error}");
+            return next.run(request).await;
+        }
+    };
+
+    let mut updates = vec![(ACCESS_COOKIE, refreshed.access_token.clone())];
+    if let Some(refresh_token) = &refreshed.refresh_token {
+        updates.push((REFRESH_COOKIE, refresh_token.clone()));
+    }
+    let cookies = replace_cookies(&cookies, &updates);
+    match HeaderValue::from_str(&cookies) {
+        Ok(value) => { request.headers_mut().insert(COOKIE, value); }
+        Err(_) => { request.headers_mut().remove(COOKIE); }
+    }
+
+    let mut response = next.run(request).await;
+    for (name, value) in updates {
+        // Signing in or out sets these cookies itself, and that should win
+        if sets_cookie(response.headers(), name) {
+            continue;
+        }
+        let header = match (name, value) {
+            (ACCESS_COOKIE, Some(value)) => cookie_header(name, &value, ACCESS_TOKEN_LIFETIME_SECONDS),
+            (_, Some(value)) => cookie_header(name, &value, SESSION_LENGTH_SECONDS),
+            (_, None) => cookie_header(name, "", 0),
+        };
+        response.headers_mut().append(SET_COOKIE, header);
+    }
+    response
+}
+
+async fn refresh(state: &RefreshState, token: &str) -> Result<Refreshed, SessionError> {
+    use entity::session_refresh_tokens::Column;
+
+    let db = &state.db;
+    let now = OffsetDateTime::now_utc();
+    let Some(record) = SessionRefreshTokens::find()
+        .filter(Column::TokenHash.eq(secret::hash(token)))
+        .one(db)
+        .await?
+    else {
+        return Ok(Refreshed::signed_out());
+    };
+    let Some(session) = Sessions::find_by_id(record.session_id)
+        .one(db)
+        .await?
+        .filter(|session| session.revoked_at.is_none() && session.expires_at > now)
+    else {
+        return Ok(Refreshed::signed_out());
+    };
+
+    // Each refresh token works once. Claim it atomically so that two concurrent requests can't both rotate it.
+    let claimed = SessionRefreshTokens::update_many()
+        .col_expr(Column::UsedAt, Expr::value(now))
+        .filter(Column::Id.eq(record.id))
+        .filter(Column::UsedAt.is_null())
+        .exec(db)
+        .await?;
+    let refresh_token = if claimed.rows_affected == 1 {
+        SessionRefreshTokens::delete_many()
+            .filter(Column::SessionId.eq(session.id))
+            .filter(Column::UsedAt.lt(now - USED_TOKEN_RETENTION))
+            .exec(db)
+            .await?;
+        Some(Some(create_token(db, session.id).await?))
+    } else {
+        // If it wasn't used when we looked it up, another request claimed it just now
+        if record.used_at.is_some_and(|used_at| now - used_at > REUSE_GRACE) {
+            // Someone is replaying an old token, so it may have been stolen. Cut off the whole session.
+            Sessions::update_many()
+                .col_expr(entity::sessions::Column::RevokedAt, Expr::value(now))
+                .filter(entity::sessions::Column::Id.eq(session.id))
+                .exec(db)
+                .await?;
+            Entry::new(AuditEvent::SessionRefreshTokenReused, None)
+                .user(session.user_id)
+                .target(Target::Session(session.id))
+                .record(db)
+                .await?;
+            log::warn!("refresh token {} for session {} was reused; revoked the session", record.id, session.id);
+            return Ok(Refreshed::signed_out());
+        }
+        None
+    };
+
+    let access_token = issue_access_token(state, session).await?;
+    Ok(Refreshed { access_token: Some(access_token), refresh_token })
+}
+
+/// Claims for a new access token for `session`: the user's own with their current scopes, or those of whoever they are
+/// viewing the site as.
+pub async fn session_claims(conn: &impl ConnectionTrait, session: &entity::sessions::Model) -> Result<Claims, DbErr> {
+    if let Some(impersonation) = impersonation::active(conn, session.id).await? {
+        return impersonation::claims(conn, &impersonation).await;
+    }
+    let scopes = roles::scopes_for(conn, session.user_id).await?;
+    let mut claims = Claims::new(session.user_id, scopes, [Service::StarHavenPlatform], ACCESS_TOKEN_LIFETIME_SECONDS);
+    claims.jti = session.id;
+    Ok(claims)
+}
+
+/// Mints an access token for `session`, and pushes the session's expiry back.
+async fn issue_access_token(state: &RefreshState, session: entity::sessions::Model) -> Result<String, SessionError> {
+    let claims = session_claims(&state.db, &session).await?;
+    let token = state.keyring.encode(&claims)?;
+
+    let now = OffsetDateTime::now_utc();
+    let mut session: entity::sessions::ActiveModel = session.into();
+    session.expires_at = Set(now + time::Duration::seconds(SESSION_LENGTH_SECONDS as i64));
+    session.last_seen_at = Set(now);
+    session.refresh_scopes = Set(false);
+    session.update(&state.db).await?;
+    Ok(token)
+}
+
+/// `cookies` with each of `updates` replacing the cookie of the same name, or removing it if `None`.
+fn replace_cookies(cookies: &str, updates: &[(&str, Option<String>)]) -> String {
+    let kept = cookies.split(';')
+        .map(str::trim)
+        .filter(|cookie| !cookie.is_empty())
+        .filter(|cookie| {
+            let name = cookie.split_once('=').map_or(*cookie, |(name, _)| name.trim());
+            !updates.iter().any(|(update, _)| *update == name)
+        })
+        .map(ToOwned::to_owned);
+    let updated = updates.iter().filter_map(|(name, value)| value.as_ref().map(|value| format!("{name}={value}")));
+    kept.chain(updated).collect::<Vec<_>>().join("; ")
+}
+
+fn sets_cookie(headers: &HeaderMap, name: &str) -> bool {
+    headers.get_all(SET_COOKIE).iter().any(|value| {
+        value.to_str().is_ok_and(|value| value.split_once('=').is_some_and(|(cookie, _)| cookie.trim() == name))
+    })
+}
@@ -0,0 +1,90 @@
+//! Sending users back where they were after signing in. Pages link to `/auth?return=/some/page`, and the sign-in page
+//! goes there once it's done. The parameter only ever names a path on this site, so that a link to our sign-in page
+//! can't be used to send someone off to a lookalike one.
+
+use leptos_router::location::Url;
+
+use crate::prelude::*;
+
+/// The longest return path accepted, to keep sign-in links reasonable
+const MAX_LENGTH: usize = 2048;
+
+/// `raw` if it is a path on this site that is safe to redirect to after signing in, or the home page otherwise.
+pub fn return_path(raw: Option<&str>) -> String {
+    raw.filter(|path| is_safe(path)).unwrap_or("/").to_string()
+}
+
+fn is_safe(path: &str) -> bool {
+    let route = path.split(['?', '#']).next().unwrap_or_default();
+    path.len() <= MAX_LENGTH
+        // Relative to the root, and not `//host` or `/\host`, which browsers treat as another site
+        && path.starts_with('/')
+        && !path[1..].starts_with(['/', '\\'])
+        && !path.contains('\\')
+        // Browsers strip tabs and newlines from URLs, which could turn `/\t/host` into `//host`
+        && !path.chars().any(char::is_control)
+        // Signing in again would be pointless
+        && route != "/auth"
+}
+
+/// A link to the sign-in page that comes back to `path` afterwards.
+pub fn sign_in_href(path: &str) -> String {
+    if !is_safe(path) || path == "/" {
+        return "/auth".to_string();
+    }
+    format!("/auth?return={}", Url::escape(path))
+}
+
+/// A link to the sign-in page that comes back to the current page afterwards.
+pub fn use_sign_in_href() -> Signal<String> {
+    let location = leptos_router::hooks::use_location();
+    Signal::derive(move || {
+        let search = location.search.get();
+        let path = if search.is_empty() {
+            location.pathname.get()
+        } else {
+            format!("{}?{search}", location.pathname.get())
+        };
+        sign_in_href(&path)
+    })
+}
+
+/// Where the sign-in page should go once the user has signed in.
+pub fn use_return_path() -> Memo<String> {
+    let query = leptos_router::hooks::use_query_map();
+    Memo::new(move |_| return_path(query.read().get_str("return")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn other_sites_are_unsafe() {
+        for path in ["//evil.com", "/\\evil.com", "/\t/evil.com", "/\n/evil.com", "https://evil.com", "evil.com"] {
+            assert!(!is_safe(path), "{path:?} should be unsafe");
+            assert_eq!(return_path(Some(path)), "/");
+        }
+    }
+
+    #[test]
+    fn sign_in_page_is_unsafe() {
+        assert!(!is_safe("/auth"));
+        assert!(!is_safe("/auth?return=/x"));
+        assert!(!is_safe("/auth#top"));
+    }
+
+    #[test]
+    fn long_paths_are_unsafe() {
+        let path = format!("/mod/{}", "x".repeat(MAX_LENGTH));
+        assert!(!is_safe(&path));
+        assert_eq!(sign_in_href(&path), "/auth");
+    }
+
+    #[test]
+    fn paths_on_this_site_are_safe() {
+        assert!(is_safe("/mod/x?tab=y"));
+        assert_eq!(return_path(Some("/mod/x?tab=y")), "/mod/x?tab=y");
+        assert_eq!(return_path(None), "/");
+    }
+}
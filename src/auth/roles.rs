@@ -0,0 +1,181 @@
+//! Roles and per-user scope grants, which decide the scopes in a user's session token.
+//!
+//! A user's scopes are those of every default role, plus those of the roles assigned to them, plus any scopes granted
+//! to them directly, minus any scopes revoked from them directly. A revocation beats every grant, so publishing rights
+//! can be taken from one user without touching the roles everyone else shares.
+//!
+//! Scopes are read when a session token is minted. Changing a user's access flags their sessions, and each flagged
+//! session's token is reissued with the new scopes on its next request.
+
+use std::collections::BTreeSet;
+
+use sea_orm::sea_query::{OnConflict, Query};
+use sea_orm::{Condition, ConnectionTrait, Set};
+use time::OffsetDateTime;
+
+use crate::auth::Scope;
+use crate::auth::audit::{AuditEvent, Entry};
+use crate::prelude::*;
+
+/// The role that `BOOTSTRAP_ADMIN` is given.
+pub const ADMIN_ROLE: &str = "admin";
+
+/// The scopes `user_id` currently has.
+pub async fn scopes_for(conn: &impl ConnectionTrait, user_id: Uuid) -> Result<BTreeSet<Scope>, DbErr> {
+    use entity::{role_scopes, roles, user_roles, user_scopes};
+
+    let from_roles = RoleScopes::find()
+        .inner_join(Roles)
+        .filter(
+            Condition::any()
+                .add(roles::Column::IsDefault.eq(true))
+                .add(role_scopes::Column::RoleId.in_subquery(
+                    Query::select()
+                        .column(user_roles::Column::RoleId)
+                        .from(UserRoles)
+                        .and_where(user_roles::Column::UserId.eq(user_id))
+                        .to_owned(),
+                )),
+        )
+        .all(conn)
+        .await?
+        .into_iter()
+        .map(|role_scope| role_scope.scope);
+
+    let overrides = UserScopes::find()
+        .filter(user_scopes::Column::UserId.eq(user_id))
+        .all(conn)
+        .await?;
+
+    let mut scopes: BTreeSet<Scope> = from_roles
+        .chain(overrides.iter().filter(|o| o.granted).map(|o| o.scope.clone()))
+        .filter_map(|scope| parse_scope(&scope))
+        .collect();
+    for revoked in overrides.iter().filter(|o| !o.granted) {
+        if let Some(scope) = parse_scope(&revoked.scope) {
+            scopes.remove(&scope);
+        }
+    }
+    Ok(scopes)
+}
+
+fn parse_scope(scope: &str) -> Option<Scope> {
+    let parsed = scope.parse().ok();
+    if parsed.is_none() {
+        log::warn!("ignoring unknown scope `{scope}` in the database");
+    }
+    parsed
+}
+
+/// Assigns or unassigns a role, returning whether anything changed. `actor` is the user making the change, if any.
+pub async fn set_role(conn: &impl ConnectionTrait, user_id: Uuid, role_id: &str, assigned: bool, actor: Option<Uuid>) -> Result<bool, DbErr> {
+    use entity::user_roles::{ActiveModel, Column};
+
+    let changed = if assigned {
+        let result = UserRoles::insert(ActiveModel {
+            user_id: Set(user_id),
+            role_id: Set(role_id.to_string()),
+            created_at: Set(OffsetDateTime::now_utc()),
+        })
+        .on_conflict(OnConflict::columns([Column::UserId, Column::RoleId]).do_nothing().to_owned())
+        .exec_without_returning(conn)
+        .await?;
+        result > 0
+    } else {
+        let result = UserRoles::delete_many()
+            .filter(Column::UserId.eq(user_id))
+            .filter(Column::RoleId.eq(role_id))
+            .exec(conn)
+            .await?;
+        result.rows_affected > 0
+    };
+
+    if changed {
+        let event = if assigned { AuditEvent::RoleAssigned } else { AuditEvent::RoleRemoved };
+        Entry::new(event, actor).user(user_id).details(serde_json::json!({ "role": role_id })).record(conn).await?;
+        refresh_sessions(conn, user_id).await?;
+    }
+    Ok(changed)
+}
+
+/// Grants (`Some(true)`) or revokes (`Some(false)`) a scope directly, or removes a direct grant or revocation so that
+/// the user's roles decide (`None`).
+pub async fn set_scope(
+    conn: &impl ConnectionTrait,
+    user_id: Uuid,
+    scope: Scope,
+    granted: Option<bool>,
+    reason: Option<String>,
+    actor: Option<Uuid>,
+) -> Result<(), DbErr> {
+    use entity::user_scopes::{ActiveModel, Column};
+
+    let event = match granted {
+        Some(granted) => {
+            UserScopes::insert(ActiveModel {
+                user_id: Set(user_id),
+                scope: Set(scope.as_str().to_string()),
+                granted: Set(granted),
+                reason: Set(reason.clone()),
+                created_at: Set(OffsetDateTime::now_utc()),
+            })
+            .on_conflict(
+                OnConflict::columns([Column::UserId, Column::Scope])
+                    .update_columns([Column::Granted, Column::Reason, Column::CreatedAt])
+                    .to_owned(),
+            )
+            .exec_without_returning(conn)
+            .await?;
+            if granted { AuditEvent::ScopeGranted } else { AuditEvent::ScopeRevoked }
+        }
+        None => {
+            UserScopes::delete_many()
+                .filter(Column::UserId.eq(user_id))
+                .filter(Column::Scope.eq(scope.as_str()))
+                .exec(conn)
+                .await?;
+            AuditEvent::ScopeReset
+        }
+    };
+
+    Entry::new(event, actor).user(user_id).details(serde_json::json!({ "scope": scope, "reason": reason })).record(conn).await?;
+    refresh_sessions(conn, user_id).await
+}
+
+/// Makes the user's signed-in sessions pick up their new scopes on their next request.
+pub async fn refresh_sessions(conn: &impl ConnectionTrait, user_id: Uuid) -> Result<(), DbErr> {
+    use entity::sessions::Column;
+
+    Sessions::update_many()
+        .col_expr(Column::RefreshScopes, Expr::value(true))
+        .filter(Column::UserId.eq(user_id))
+        .filter(Column::RevokedAt.is_null())
+        .exec(conn)
+        .await?;
+    Ok(())
+}
+
+/// Gives `username` the admin role if nobody has it yet. Called at startup with `BOOTSTRAP_ADMIN`.
+pub async fn bootstrap_admin(conn: &impl ConnectionTrait, username: &str) -> Result<(), DbErr> {
+    let has_admin = UserRoles::find()
+        .filter(entity::user_roles::Column::RoleId.eq(ADMIN_ROLE))
+        .one(conn)
+        .await?
+        .is_some();
+    if has_admin {
+        return Ok(());
+    }
+
+    let Some(user) = Users::find()
+        .filter(entity::users::Column::UsernameNormalized.eq(super::normalize_username(username)))
+        .one(conn)
+        .await?
+    else {
+        log::warn!("BOOTSTRAP_ADMIN user `{username}` does not exist yet; sign up and restart the server");
+        return Ok(());
+    };
+
+    set_role(conn, user.id, ADMIN_ROLE, true, None).await?;
+    log::info!("gave the {ADMIN_ROLE} role to {}", user.username);
+    Ok(())
+}
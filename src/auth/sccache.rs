@@ -0,0 +1,36 @@
+//! Short-lived tokens for the shared build cache, which verifies them itself. See `docs/sccache-tokens.md`.
+
+use crate::auth::Scope;
+use crate::prelude::*;
+
+/// Scopes that tokens for the cache may carry. Platform scopes are never included.
+const SCCACHE_SCOPES: [Scope; 2] = [Scope::SccacheRead, Scope::SccacheWrite];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IssuedSccacheToken {
+    token: String,
+    /// Seconds until the token expires
+    expires_in: u64,
+}
+
+/// Exchanges a platform session or access token for a token that only the build cache accepts, carrying whichever
+/// cache scopes the caller has.
+#[server(endpoint = "sccache-token")]
+pub async fn sccache_token() -> Result<IssuedSccacheToken, ServerFnError> {
+    use crate::auth::token::{Claims, Service};
+
+    let session = session().await;
+    let Some(user_id) = session.uuid() else {
+        return Err(ServerFnError::ServerError("Must be signed in".to_string()));
+    };
+    let scopes: Vec<Scope> = SCCACHE_SCOPES.into_iter().filter(|scope| session.has_scope(*scope)).collect();
+    if scopes.is_empty() {
+        return Err(ServerFnError::ServerError("You don't have access to the build cache".to_string()));
+    }
+
+    let expires_in = config().sccache_token_ttl.as_secs();
+    let claims = Claims::new(user_id, scopes, [Service::StarHavenSccache], expires_in);
+    let token = claims.encode()?;
+    log::info!("issued build cache token {} to user {user_id}", claims.jti);
+    Ok(IssuedSccacheToken { token, expires_in })
+}
@@ -0,0 +1,87 @@
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+/// An action that a token allows one to take. Users get scopes from their roles and from grants made directly to them;
+/// see [`crate::auth::roles`].
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Scope {
+    /// Can create draft mods
+    CreateMod,
+    /// Can publish mods that they have created
+    PublishMod,
+    /// Is treated as an author of all mods (can view, edit, delete, etc.)
+    AdminAuthorAllMods,
+    /// Can grant and revoke other users' roles and scopes
+    ManageUsers,
+    /// Can search the audit log of every account and mod
+    ViewAuditLog,
+    /// Can view the site as another user, to help them with problems
+    ImpersonateUsers,
+    /// Can read from the shared build cache
+    SccacheRead,
+    /// Can write to the shared build cache
+    SccacheWrite,
+    #[serde(other)]
+    Unknown,
+}
+
+impl Scope {
+    /// Every scope that can be granted.
+    pub const ALL: [Scope; 8] = [
+        Scope::CreateMod,
+        Scope::PublishMod,
+        Scope::AdminAuthorAllMods,
+        Scope::ManageUsers,
+        Scope::ViewAuditLog,
+        Scope::ImpersonateUsers,
+        Scope::SccacheRead,
+        Scope::SccacheWrite,
+    ];
+
+    /// Scopes that apps and personal access tokens may be given. The rest only work in a session the user signed in to
+    /// themselves, so that a leaked token can't be used to administer the site.
+    pub const DELEGABLE: [Scope; 4] = [Scope::CreateMod, Scope::PublishMod, Scope::SccacheRead, Scope::SccacheWrite];
+
+    pub fn is_delegable(self) -> bool {
+        Scope::DELEGABLE.contains(&self)
+    }
+
+    /// The name used in tokens and the database.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Scope::CreateMod => "create_mod",
+            Scope::PublishMod => "publish_mod",
+            Scope::AdminAuthorAllMods => "admin_author_all_mods",
+            Scope::ManageUsers => "manage_users",
+            Scope::ViewAuditLog => "view_audit_log",
+            Scope::ImpersonateUsers => "impersonate_users",
+            Scope::SccacheRead => "sccache_read",
+            Scope::SccacheWrite => "sccache_write",
+            Scope::Unknown => "unknown",
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Scope::CreateMod => "Create draft mods",
+            Scope::PublishMod => "Publish their own mods",
+            Scope::AdminAuthorAllMods => "View, edit and delete every mod",
+            Scope::ManageUsers => "Grant and revoke other users' roles and scopes",
+            Scope::ViewAuditLog => "Search the audit log",
+            Scope::ImpersonateUsers => "View the site as another user",
+            Scope::SccacheRead => "Read from the shared build cache",
+            Scope::SccacheWrite => "Write to the shared build cache",
+            Scope::Unknown => "Unknown",
+        }
+    }
+}
+
+impl FromStr for Scope {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Scope::ALL.into_iter().find(|scope| scope.as_str() == s).ok_or(())
+    }
+}
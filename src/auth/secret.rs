@@ -0,0 +1,19 @@
+//! Random secrets that are handed out once and only stored as hashes, such as access tokens.
+
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use ring::digest::{SHA256, digest};
+use ring::rand::{SecureRandom, SystemRandom};
+
+/// Generates a new secret starting with `prefix`, returning it and its hash. The prefix makes leaked secrets
+/// recognisable, e.g. by secret scanners.
+pub fn generate(prefix: &str) -> (String, Vec<u8>) {
+    let mut bytes = [0u8; 32];
+    SystemRandom::new().fill(&mut bytes).expect("system random number generator failed");
+    let secret = format!("{prefix}{}", URL_SAFE_NO_PAD.encode(bytes));
+    let hash = hash(&secret);
+    (secret, hash)
+}
+
+pub fn hash(secret: &str) -> Vec<u8> {
+    digest(&SHA256, secret.as_bytes()).as_ref().to_vec()
+}
@@ -1,25 +1,45 @@
-use sea_orm::EntityTrait;
+use sea_orm::{EntityTrait, Set};
+use time::OffsetDateTime;
 use uuid::Uuid;
 
 use crate::prelude::*;
 use crate::auth::{
+    access_token,
+    audit::{AuditEvent, Entry, Target},
     cookie::{get_cookie, set_cookie},
-    token::{Claims, Scope, Service},
+    refresh::{ACCESS_COOKIE, ACCESS_TOKEN_LIFETIME_SECONDS, REFRESH_COOKIE},
+    roles,
+    scope::Scope,
+    token::{Actor, Claims, Service},
 };
 
-/// 30 days
+/// 30 days. Every refresh pushes a session's expiry this far out again, so only idle sessions expire.
 pub const SESSION_LENGTH_SECONDS: u64 = 30 * 24 * 60 * 60;
 
+/// How stale `sessions.last_seen_at` may get before a request updates it
+const LAST_SEEN_GRANULARITY: time::Duration = time::Duration::minutes(5);
+
+#[derive(Debug, Error)]
+pub enum SessionError {
+    #[error("failed to sign session token: {0}")]
+    Token(#[from] jsonwebtoken::errors::Error),
+    #[error("database error: {0}")]
+    Db(#[from] sea_orm::DbErr),
+}
+
 #[derive(Debug)]
 pub struct Session {
     claims: Option<Claims>,
+    /// Whether the request authenticated with a personal or OAuth access token rather than the session cookie
+    access_token: bool,
 }
 
 impl Session {
-    /// Does this session have permission to perform actions in the given scope?
+    /// Does this session have permission to perform actions in the given scope? Access tokens only ever have
+    /// [`Scope::DELEGABLE`] scopes, even if their owner has more.
     pub fn has_scope(&self, scope: Scope) -> bool {
         if let Some(claims) = &self.claims {
-            claims.scopes.contains(&scope)
+            claims.scopes.contains(&scope) && (!self.access_token || scope.is_delegable())
         } else {
             false
         }
@@ -37,32 +57,191 @@ impl Session {
         self.claims.as_ref().map(|claims| claims.sub)
     }
 
+    /// The signed-in user, but only if they signed in with a passkey rather than an access token. Account settings use
+    /// this so that a leaked token can't be used to take over the account.
+    pub fn account_uuid(&self) -> Option<Uuid> {
+        self.uuid().filter(|_| !self.access_token)
+    }
+
+    /// The ID of the row in the `sessions` table backing this session.
+    pub fn id(&self) -> Option<Uuid> {
+        self.claims.as_ref().filter(|_| !self.access_token).map(|claims| claims.jti)
+    }
+
+    /// The ID of the access token this request authenticated with, if any.
+    pub fn access_token_id(&self) -> Option<Uuid> {
+        self.claims.as_ref().filter(|_| self.access_token).map(|claims| claims.jti)
+    }
+
+    /// The admin viewing the site as the session user, if any.
+    pub fn impersonator(&self) -> Option<&Actor> {
+        self.claims.as_ref().and_then(|claims| claims.act.as_ref())
+    }
+
     pub fn is_logged_in(&self) -> bool {
         self.claims.is_some()
     }
 
-    pub fn login(&mut self, user: &User) -> Result<(), jsonwebtoken::errors::Error> {
-        let claims = Claims::new(user.id, [Scope::CreateMod, Scope::PublishMod], [Service::StarHavenPlatform], SESSION_LENGTH_SECONDS);
-        set_cookie("session", &claims.encode()?, SESSION_LENGTH_SECONDS);
+    pub async fn login(&mut self, user: &User) -> Result<(), SessionError> {
+        let scopes = roles::scopes_for(&db(), user.id).await?;
+        let claims = Claims::new(user.id, scopes, [Service::StarHavenPlatform], ACCESS_TOKEN_LIFETIME_SECONDS);
+
+        let now = OffsetDateTime::now_utc();
+        entity::sessions::ActiveModel {
+            id: Set(claims.jti),
+            user_id: Set(user.id),
+            user_agent: Set(crate::request::user_agent()),
+            ip: Set(crate::request::client_ip().map(|ip| ip.to_string())),
+            created_at: Set(now),
+            last_seen_at: Set(now),
+            expires_at: Set(now + time::Duration::seconds(SESSION_LENGTH_SECONDS as i64)),
+            revoked_at: Set(None),
+            refresh_scopes: Set(false),
+        }.insert(&db()).await?;
+
+        let refresh_token = super::refresh::create_token(&db(), claims.jti).await?;
+        set_cookie(REFRESH_COOKIE, &refresh_token, SESSION_LENGTH_SECONDS);
+        set_cookie(ACCESS_COOKIE, &claims.encode()?, ACCESS_TOKEN_LIFETIME_SECONDS);
+        Entry::new(AuditEvent::SignedIn, Some(user.id))
+            .user(user.id)
+            .target(Target::Session(claims.jti))
+            .record(&db())
+            .await?;
         self.claims = Some(claims);
         Ok(())
     }
 
-    pub fn logout(&mut self) {
-        set_cookie("session", "", SESSION_LENGTH_SECONDS);
+    pub async fn logout(&mut self) -> Result<(), sea_orm::DbErr> {
+        if let Some((id, user_id)) = self.id().zip(self.uuid()) {
+            revoke_sessions(entity::sessions::Column::Id.eq(id)).await?;
+            Entry::new(AuditEvent::SignedOut, Some(user_id)).user(user_id).target(Target::Session(id)).record(&db()).await?;
+        }
+        set_cookie(ACCESS_COOKIE, "", 0);
+        set_cookie(REFRESH_COOKIE, "", 0);
         self.claims = None;
+        Ok(())
+    }
+}
+
+/// Revokes every unrevoked session matching `condition`, returning how many were revoked.
+pub async fn revoke_sessions(condition: impl sea_orm::sea_query::IntoCondition) -> Result<u64, sea_orm::DbErr> {
+    let result = Sessions::update_many()
+        .col_expr(entity::sessions::Column::RevokedAt, Expr::value(OffsetDateTime::now_utc()))
+        .filter(condition)
+        .filter(entity::sessions::Column::RevokedAt.is_null())
+        .exec(&db())
+        .await?;
+    Ok(result.rows_affected)
+}
+
+/// Checks that the session a token belongs to still exists and hasn't been revoked, and notes that it was seen. If the
+/// user's access has changed since the token was minted, the token is reissued with their current scopes.
+async fn check_session(mut claims: Claims) -> Result<Option<Claims>, SessionError> {
+    let Some(record) = Sessions::find_by_id(claims.jti).one(&db()).await? else {
+        return Ok(None);
+    };
+    // While an admin is viewing the site as someone else, the session is still the admin's
+    let owner = claims.act.as_ref().map_or(claims.sub, |actor| actor.sub);
+    if record.user_id != owner || record.revoked_at.is_some() {
+        return Ok(None);
     }
+    if let Some(actor) = &claims.act {
+        let impersonation = super::impersonation::active(&db(), record.id).await?;
+        if impersonation.is_none_or(|impersonation| impersonation.id != actor.impersonation_id) {
+            return Ok(None);
+        }
+    }
+
+    let now = OffsetDateTime::now_utc();
+    if record.refresh_scopes {
+        claims.scopes = roles::scopes_for(&db(), claims.sub).await?.into_iter().collect();
+        set_cookie(ACCESS_COOKIE, &claims.encode()?, ACCESS_TOKEN_LIFETIME_SECONDS);
+    }
+    if record.refresh_scopes || now - record.last_seen_at > LAST_SEEN_GRANULARITY {
+        let mut record: entity::sessions::ActiveModel = record.into();
+        record.last_seen_at = Set(now);
+        record.refresh_scopes = Set(false);
+        record.update(&db()).await?;
+    }
+    Ok(Some(claims))
+}
+
+/// Checks that the app an OAuth access token was issued to still has access to the user's account.
+async fn check_oauth_token(claims: Claims) -> Result<Option<Claims>, SessionError> {
+    let Some(client_id) = claims.client_id else {
+        return Ok(None);
+    };
+    let authorized = crate::oauth::grant::is_authorized(&db(), claims.sub, client_id).await?;
+    Ok(authorized.then_some(claims))
 }
 
 #[cfg(feature = "ssr")]
-pub fn session() -> Session {
-    Session {
-        claims: get_cookie("session").and_then(|cookie| match Claims::validate(&cookie) {
-            Ok(claims) => Some(claims),
+pub async fn session() -> Session {
+    // A bearer token takes precedence over the cookie, and an invalid one doesn't fall back to it
+    if let Some(token) = crate::request::bearer_token() {
+        let claims = if token.starts_with(access_token::TOKEN_PREFIX) {
+            match access_token::authenticate(&token).await {
+                Ok(Some(claims)) => Some(claims),
+                Ok(None) => {
+                    log::info!("rejected unknown, expired or revoked access token");
+                    None
+                }
+                Err(error) => {
+                    log::error!("failed to check access token: {error}");
+                    None
+                }
+            }
+        } else {
+            // OAuth access tokens are only accepted while the user hasn't revoked the app. Session tokens are only
+            // accepted from the cookie, where they are checked against the `sessions` table.
+            match Claims::validate(&token) {
+                Ok(claims) if claims.client_id.is_some() => match check_oauth_token(claims).await {
+                    Ok(Some(claims)) => Some(claims),
+                    Ok(None) => {
+                        log::info!("rejected OAuth access token for a revoked app");
+                        None
+                    }
+                    Err(error) => {
+                        log::error!("failed to check OAuth access token: {error}");
+                        None
+                    }
+                },
+                Ok(claims) => {
+                    log::info!("rejected session token {} sent as a bearer token", claims.jti);
+                    None
+                }
+                Err(error) => {
+                    log::info!("rejected bearer token: {error}");
+                    None
+                }
+            }
+        };
+        return Session { claims, access_token: true };
+    }
+
+    // Expired access tokens were already swapped for new ones by the refresh middleware
+    let claims = get_cookie(ACCESS_COOKIE).and_then(|cookie| match Claims::validate(&cookie) {
+        Ok(claims) => Some(claims),
+        Err(error) => {
+            log::error!("token validation failed: {error}");
+            None
+        }
+    });
+
+    let claims = match claims {
+        Some(claims) => match check_session(claims.clone()).await {
+            Ok(Some(claims)) => Some(claims),
+            Ok(None) => {
+                log::info!("rejected token for revoked or unknown session {}", claims.jti);
+                None
+            }
             Err(error) => {
-                log::error!("token validation failed: {error}");
+                log::error!("failed to check session: {error}");
                 None
             }
-        }),
-    }
+        },
+        None => None,
+    };
+
+    Session { claims, access_token: false }
 }
@@ -0,0 +1,296 @@
+//! Slows down guessing and account enumeration on the passkey endpoints. Each client IP and each username gets a budget
+//! of attempts per minute, and repeated failures back off exponentially. Counts are kept in memory, so each instance
+//! throttles separately.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use http::{HeaderValue, StatusCode, header::RETRY_AFTER};
+
+use crate::prelude::*;
+
+use super::TOO_MANY_ATTEMPTS;
+
+const WINDOW: Duration = Duration::from_secs(60);
+const ATTEMPTS_PER_IP: u32 = 30;
+const ATTEMPTS_PER_USERNAME: u32 = 10;
+
+/// Failures allowed before backing off
+const FREE_FAILURES: u32 = 5;
+const BASE_BACKOFF: Duration = Duration::from_secs(2);
+const MAX_BACKOFF: Duration = Duration::from_secs(15 * 60);
+/// Failures are forgotten after this long without another one
+const FAILURE_MEMORY: Duration = Duration::from_secs(60 * 60);
+
+/// Who an attempt is counted against.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Subject {
+    Ip(IpAddr),
+    /// Normalised, so that variants of a name share a budget
+    Username(String),
+}
+
+impl fmt::Display for Subject {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Subject::Ip(ip) => write!(f, "ip {ip}"),
+            Subject::Username(username) => write!(f, "username {username}"),
+        }
+    }
+}
+
+struct Entry {
+    window_start: Instant,
+    attempts: u32,
+    failures: u32,
+    last_failure: Option<Instant>,
+}
+
+impl Entry {
+    fn new(now: Instant) -> Self {
+        Entry { window_start: now, attempts: 0, failures: 0, last_failure: None }
+    }
+
+    /// How long until this subject may try again, if it must wait.
+    fn wait(&self, subject: &Subject, now: Instant) -> Option<Duration> {
+        let limit = match subject {
+            Subject::Ip(_) => ATTEMPTS_PER_IP,
+            Subject::Username(_) => ATTEMPTS_PER_USERNAME,
+        };
+        let window_end = self.window_start + WINDOW;
+        let rate_wait = (self.attempts >= limit && window_end > now).then(|| window_end - now);
+
+        let backoff_wait = self.last_failure.zip(self.failures.checked_sub(FREE_FAILURES + 1)).and_then(|(last, excess)| {
+            let backoff = BASE_BACKOFF.saturating_mul(2u32.saturating_pow(excess)).min(MAX_BACKOFF);
+            (last + backoff > now).then(|| last + backoff - now)
+        });
+
+        rate_wait.max(backoff_wait)
+    }
+}
+
+pub struct Throttle {
+    entries: Mutex<HashMap<Subject, Entry>>,
+}
+
+impl Default for Throttle {
+    fn default() -> Self {
+        Throttle { entries: Mutex::new(HashMap::new()) }
+    }
+}
+
+impl Throttle {
+    /// Counts an attempt against each subject, unless one of them must wait, in which case this returns how long.
+    pub fn attempt(&self, subjects: &[Subject]) -> Result<(), Duration> {
+        self.attempt_at(subjects, Instant::now())
+    }
+
+    fn attempt_at(&self, subjects: &[Subject], now: Instant) -> Result<(), Duration> {
+        let mut entries = self.entries.lock().expect("throttle lock poisoned");
+
+        let wait = subjects.iter()
+            .filter_map(|subject| entries.get(subject).and_then(|entry| entry.wait(subject, now)))
+            .max();
+        if let Some(wait) = wait {
+            return Err(wait);
+        }
+
+        for subject in subjects {
+            let entry = entries.entry(subject.clone()).or_insert_with(|| Entry::new(now));
+            if entry.window_start + WINDOW <= now {
+                entry.window_start = now;
+                entry.attempts = 0;
+            }
+            entry.attempts += 1;
+        }
+        Ok(())
+    }
+
+    pub fn failure(&self, subjects: &[Subject]) {
+        self.failure_at(subjects, Instant::now());
+    }
+
+    fn failure_at(&self, subjects: &[Subject], now: Instant) {
+        let mut entries = self.entries.lock().expect("throttle lock poisoned");
+        for subject in subjects {
+            let entry = entries.entry(subject.clone()).or_insert_with(|| Entry::new(now));
+            if entry.last_failure.is_some_and(|last| last + FAILURE_MEMORY <= now) {
+                entry.failures = 0;
+            }
+            entry.failures += 1;
+            entry.last_failure = Some(now);
+        }
+    }
+
+    /// Forgets the failures of the usernames among `subjects`. IP addresses keep theirs, so that signing in to an
+    /// account of one's own doesn't reset the backoff for guessing at others.
+    pub fn success(&self, subjects: &[Subject]) {
+        let mut entries = self.entries.lock().expect("throttle lock poisoned");
+        for subject in subjects.iter().filter(|subject| matches!(subject, Subject::Username(_))) {
+            if let Some(entry) = entries.get_mut(subject) {
+                entry.failures = 0;
+                entry.last_failure = None;
+            }
+        }
+    }
+
+    /// Drops entries with nothing left to remember.
+    fn sweep(&self) -> usize {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().expect("throttle lock poisoned");
+        let before = entries.len();
+        entries.retain(|_, entry| {
+            entry.window_start + WINDOW > now || entry.last_failure.is_some_and(|last| last + FAILURE_MEMORY > now)
+        });
+        before - entries.len()
+    }
+
+    pub fn spawn_sweeper(self: Arc<Self>) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(WINDOW);
+            loop {
+                interval.tick().await;
+                let count = self.sweep();
+                if count > 0 {
+                    log::debug!("swept {count} throttle entries");
+                }
+            }
+        })
+    }
+}
+
+pub fn throttle() -> Arc<Throttle> {
+    expect_context()
+}
+
+/// The subjects for a request from the current client, about `username` if given.
+pub fn subjects(username: Option<&str>) -> Vec<Subject> {
+    let mut subjects: Vec<Subject> = crate::request::client_ip().map(Subject::Ip).into_iter().collect();
+    if let Some(username) = username {
+        subjects.push(Subject::Username(super::normalize_username(username)));
+    }
+    subjects
+}
+
+/// Counts an attempt at `endpoint`, or fails with a 429 if the client or username must wait. Returns the subjects so
+/// that the caller can record how the attempt went.
+pub fn check(endpoint: &str, username: Option<&str>) -> Result<Vec<Subject>, ServerFnError> {
+    let subjects = subjects(username);
+    if let Err(wait) = throttle().attempt(&subjects) {
+        let names = subjects.iter().map(ToString::to_string).collect::<Vec<_>>().join(", ");
+        log::warn!("throttled {endpoint} for {names}, retry in {}s", wait.as_secs() + 1);
+        if let Some(response) = use_context::<leptos_axum::ResponseOptions>() {
+            response.set_status(StatusCode::TOO_MANY_REQUESTS);
+            response.insert_header(RETRY_AFTER, HeaderValue::from(wait.as_secs() + 1));
+        }
+        return Err(ServerFnError::new(TOO_MANY_ATTEMPTS));
+    }
+    Ok(subjects)
+}
+
+/// Records whether an attempt that [`check`] let through succeeded, passing `result` through.
+pub fn record<T, E>(subjects: &[Subject], result: Result<T, E>) -> Result<T, E> {
+    match &result {
+        Ok(_) => throttle().success(subjects),
+        Err(_) => throttle().failure(subjects),
+    }
+    result
+}
+
+/// Like [`record`], but only counts failures. For the first step of a sign-in, which succeeds before the user has proven
+/// anything, so must not reset their backoff.
+pub fn record_failure<T, E>(subjects: &[Subject], result: Result<T, E>) -> Result<T, E> {
+    if result.is_err() {
+        throttle().failure(subjects);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip() -> Subject {
+        Subject::Ip(IpAddr::from([192, 0, 2, 1]))
+    }
+
+    fn username() -> Subject {
+        Subject::Username("iggy".to_string())
+    }
+
+    /// Fails `count` times, a second apart so that the attempts don't hit the rate limit first.
+    fn fail(throttle: &Throttle, subjects: &[Subject], start: Instant, count: u32) -> Instant {
+        let mut now = start;
+        for _ in 0..count {
+            now += Duration::from_secs(1);
+            throttle.failure_at(subjects, now);
+        }
+        now
+    }
+
+    #[test]
+    fn backoff_grows_with_failures() {
+        let throttle = Throttle::default();
+        let subjects = [username()];
+        let start = Instant::now();
+
+        let now = fail(&throttle, &subjects, start, FREE_FAILURES);
+        assert_eq!(throttle.attempt_at(&subjects, now), Ok(()), "the first few failures are free");
+
+        let now = fail(&throttle, &subjects, now, 1);
+        assert_eq!(throttle.attempt_at(&subjects, now), Err(BASE_BACKOFF));
+        let now = fail(&throttle, &subjects, now, 1);
+        assert_eq!(throttle.attempt_at(&subjects, now), Err(BASE_BACKOFF * 2));
+        let now = fail(&throttle, &subjects, now, 1);
+        assert_eq!(throttle.attempt_at(&subjects, now), Err(BASE_BACKOFF * 4));
+        assert_eq!(throttle.attempt_at(&subjects, now + BASE_BACKOFF * 4), Ok(()), "the backoff runs out");
+
+        let now = fail(&throttle, &subjects, now, 20);
+        assert_eq!(throttle.attempt_at(&subjects, now), Err(MAX_BACKOFF));
+    }
+
+    #[test]
+    fn success_resets_backoff() {
+        let throttle = Throttle::default();
+        let subjects = [username()];
+        let now = fail(&throttle, &subjects, Instant::now(), FREE_FAILURES + 3);
+        assert!(throttle.attempt_at(&subjects, now).is_err());
+
+        throttle.success(&subjects);
+        assert_eq!(throttle.attempt_at(&subjects, now), Ok(()));
+    }
+
+    #[test]
+    fn subjects_are_counted_separately() {
+        let throttle = Throttle::default();
+        let ip_failed = fail(&throttle, &[ip()], Instant::now(), FREE_FAILURES + 3);
+        let now = ip_failed;
+        assert!(throttle.attempt_at(&[ip()], now).is_err());
+        assert_eq!(throttle.attempt_at(&[username()], now), Ok(()), "another client's failures don't count against the username");
+        assert!(throttle.attempt_at(&[ip(), username()], now).is_err(), "an attempt waits for its slowest subject");
+
+        let now = fail(&throttle, &[username()], now, FREE_FAILURES + 3);
+        assert!(throttle.attempt_at(&[username()], now).is_err());
+        assert!(throttle.attempt_at(&[Subject::Username("bob".to_string())], now).is_ok());
+
+        // Signing in resets the username but not the client, which may be guessing at other accounts
+        throttle.success(&[ip(), username()]);
+        assert_eq!(throttle.attempt_at(&[username()], now), Ok(()));
+        assert!(throttle.attempt_at(&[ip()], ip_failed).is_err());
+    }
+
+    #[test]
+    fn attempts_are_limited_per_minute() {
+        let throttle = Throttle::default();
+        let now = Instant::now();
+        for _ in 0..ATTEMPTS_PER_USERNAME {
+            assert_eq!(throttle.attempt_at(&[username()], now), Ok(()));
+        }
+        assert_eq!(throttle.attempt_at(&[username()], now), Err(WINDOW));
+        assert_eq!(throttle.attempt_at(&[ip()], now), Ok(()), "the client has a larger budget of its own");
+        assert_eq!(throttle.attempt_at(&[username()], now + WINDOW), Ok(()));
+    }
+}
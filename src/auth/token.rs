@@ -1,16 +1,19 @@
 use std::collections::HashSet;
 
-use jsonwebtoken::{
-    DecodingKey, EncodingKey, Header, Validation, decode, encode, get_current_timestamp,
-};
+use jsonwebtoken::{Validation, get_current_timestamp};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+use crate::auth::keys::keyring;
+use crate::auth::scope::Scope;
+
 /// A deserialised JSON Web Token
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Claims {
     /// Subject: the user that this token represents
     pub sub: Uuid,
+    /// JWT ID: unique per token, used to look the token up server-side
+    pub jti: Uuid,
     /// Expiry: when the token becomes invalid
     exp: u64,
     /// Issued At
@@ -21,6 +24,23 @@ pub struct Claims {
     iss: Service,
     /// Actions that this token allows one to take
     pub scopes: HashSet<Scope>,
+    /// The OAuth client this token was issued to, if it is an OAuth access token
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<Uuid>,
+    /// Actor: the admin using this token to view the site as `sub`, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub act: Option<Actor>,
+}
+
+/// Who is really behind a token that represents someone else. See [`crate::auth::impersonation`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Actor {
+    /// The admin's user ID
+    pub sub: Uuid,
+    /// The row in the `impersonations` table
+    pub impersonation_id: Uuid,
+    /// Whether the admin can only look, not change anything
+    pub read_only: bool,
 }
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Serialize, Deserialize)]
@@ -32,49 +52,42 @@ pub enum Service {
     Unknown,
 }
 
-#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Serialize, Deserialize)]
-#[serde(rename_all = "snake_case")]
-pub enum Scope {
-    /// Can create draft mods
-    CreateMod,
-    /// Can publish mods that they have created
-    PublishMod,
-    /// Is treated as an author of all mods (can view, edit, delete, etc.)
-    AdminAuthorAllMods,
-    #[serde(other)]
-    Unknown,
-}
-
 impl Claims {
     pub fn new(user: Uuid, scopes: impl IntoIterator<Item = Scope>, audience: impl IntoIterator<Item = Service>, age: u64) -> Self {
         let now = get_current_timestamp();
         Claims {
             sub: user,
+            jti: Uuid::new_v4(),
             exp: now + age,
             iat: now,
             aud: HashSet::from_iter(audience),
             iss: Service::StarHavenPlatform,
             scopes: HashSet::from_iter(scopes),
+            client_id: None,
+            act: None,
         }
     }
 
+    /// Seconds until the token expires, or 0 if it already has.
+    pub fn expires_in(&self) -> u64 {
+        self.exp.saturating_sub(get_current_timestamp())
+    }
+
     pub fn encode(&self) -> Result<String, jsonwebtoken::errors::Error> {
-        encode(
-            &Header::default(),
-            &self,
-            &EncodingKey::from_secret("secret".as_ref()),
-        ) // TODO: key
+        keyring().encode(self)
     }
 
     pub fn validate(token: &str) -> Result<Self, jsonwebtoken::errors::Error> {
+        keyring()
+            .decode::<Claims>(token, Self::validation())
+            .map(|token_data| token_data.claims)
+    }
+
+    /// What [`Claims::validate`] checks, for validating with a keyring outside of a request context.
+    pub fn validation() -> Validation {
         let mut validation = Validation::default();
-        validation.set_required_spec_claims(&["sub", "exp"]);
+        validation.set_required_spec_claims(&["sub", "exp", "jti"]);
         validation.set_audience(&["star_haven_platform"]);
-        decode::<Claims>(
-            token,
-            &DecodingKey::from_secret("secret".as_ref()),
-            &validation,
-        ) // TODO: key
-        .map(|token_data| token_data.claims)
+        validation
     }
 }
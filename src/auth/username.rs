@@ -0,0 +1,132 @@
+//! Changing usernames. Each change is recorded in `username_history`, so that old names keep leading to the user and
+//! nobody else can take a name until its reservation runs out.
+
+use sea_orm::{ConnectionTrait, Set, SqlErr};
+use time::OffsetDateTime;
+
+use crate::auth::audit::{AuditEvent, Entry};
+use crate::prelude::*;
+
+use super::{UsernameValidationError, check_username_validity, normalize_username};
+
+/// How long a user must wait between username changes
+pub const CHANGE_COOLDOWN: time::Duration = time::Duration::days(30);
+
+/// How long an old username stays reserved for the user that gave it up
+pub const RESERVATION_PERIOD: time::Duration = time::Duration::days(90);
+
+#[derive(Debug, Error)]
+pub enum RenameError {
+    #[error("{0}")]
+    Invalid(#[from] UsernameValidationError),
+    #[error("That's already your username")]
+    Unchanged,
+    #[error("This username is taken")]
+    Taken,
+    #[error("You changed your username recently. You can change it again after {0}.")]
+    Cooldown(OffsetDateTime),
+    #[error("database error: {0}")]
+    Db(#[from] DbErr),
+}
+
+/// Whether `username` is free for `user_id` to use, or for a new user if `user_id` is `None`. Names that other users
+/// gave up recently are still reserved for them.
+pub async fn is_available(conn: &impl ConnectionTrait, username: &str, user_id: Option<Uuid>) -> Result<bool, DbErr> {
+    let normalized = normalize_username(username);
+
+    let mut current = Users::find().filter(entity::users::Column::UsernameNormalized.eq(&normalized));
+    let mut reserved = UsernameHistory::find()
+        .filter(entity::username_history::Column::UsernameNormalized.eq(&normalized))
+        .filter(entity::username_history::Column::ReservedUntil.gt(OffsetDateTime::now_utc()));
+    if let Some(user_id) = user_id {
+        current = current.filter(entity::users::Column::Id.ne(user_id));
+        reserved = reserved.filter(entity::username_history::Column::UserId.ne(user_id));
+    }
+    Ok(current.one(conn).await?.is_none() && reserved.one(conn).await?.is_none())
+}
+
+/// When `user_id` may next change their username, if they are still in the cooldown from their last change.
+pub async fn next_change_at(conn: &impl ConnectionTrait, user_id: Uuid) -> Result<Option<OffsetDateTime>, DbErr> {
+    let last_change = UsernameHistory::find()
+        .filter(entity::username_history::Column::UserId.eq(user_id))
+        .order_by_desc(entity::username_history::Column::ChangedAt)
+        .one(conn)
+        .await?;
+    Ok(last_change
+        .map(|change| change.changed_at + CHANGE_COOLDOWN)
+        .filter(|next| *next > OffsetDateTime::now_utc()))
+}
+
+/// Changes `user`'s username, recording the old one so that it redirects and stays reserved for them. Call it in a
+/// transaction, which holds a lock on the user until it ends.
+pub async fn rename(conn: &impl ConnectionTrait, user: User, new_username: &str) -> Result<User, RenameError> {
+    // Lock the user so that concurrent renames can't both pass the cooldown check
+    let user = Users::find_by_id(user.id)
+        .lock_exclusive()
+        .one(conn)
+        .await?
+        .ok_or_else(|| DbErr::RecordNotFound(format!("user {}", user.id)))?;
+
+    let new_username = new_username.trim();
+    check_username_validity(new_username)?;
+    if new_username == user.username {
+        return Err(RenameError::Unchanged);
+    }
+    if let Some(next) = next_change_at(conn, user.id).await? {
+        return Err(RenameError::Cooldown(next));
+    }
+    if !is_available(conn, new_username, Some(user.id)).await? {
+        return Err(RenameError::Taken);
+    }
+
+    let now = OffsetDateTime::now_utc();
+    entity::username_history::ActiveModel {
+        id: Set(Uuid::new_v4()),
+        user_id: Set(user.id),
+        username: Set(user.username.clone()),
+        username_normalized: Set(user.username_normalized.clone()),
+        changed_at: Set(now),
+        reserved_until: Set(now + RESERVATION_PERIOD),
+    }.insert(conn).await?;
+
+    let old_username = user.username.clone();
+    let mut record: entity::users::ActiveModel = user.into();
+    record.username = Set(new_username.to_string());
+    record.username_normalized = Set(normalize_username(new_username));
+    let user = record.update(conn).await.map_err(|error| match error.sql_err() {
+        // Someone else took the name since we checked
+        Some(SqlErr::UniqueConstraintViolation(_)) => RenameError::Taken,
+        _ => RenameError::Db(error),
+    })?;
+
+    Entry::new(AuditEvent::UsernameChanged, Some(user.id))
+        .user(user.id)
+        .details(serde_json::json!({
+            "from": old_username,
+            "to": user.username,
+        }))
+        .record(conn)
+        .await?;
+    Ok(user)
+}
+
+/// Finds the user that `username` refers to: whoever has it now, or else whoever had it most recently. Callers should
+/// redirect to the user's current name if it differs.
+pub async fn resolve(conn: &impl ConnectionTrait, username: &str) -> Result<Option<User>, DbErr> {
+    let normalized = normalize_username(username);
+    let current = Users::find()
+        .filter(entity::users::Column::UsernameNormalized.eq(&normalized))
+        .one(conn)
+        .await?;
+    if current.is_some() {
+        return Ok(current);
+    }
+
+    let previous = UsernameHistory::find()
+        .filter(entity::username_history::Column::UsernameNormalized.eq(&normalized))
+        .order_by_desc(entity::username_history::Column::ChangedAt)
+        .find_also_related(Users)
+        .one(conn)
+        .await?;
+    Ok(previous.and_then(|(_, user)| user))
+}
@@ -7,6 +7,8 @@ mod media;
 mod shop;
 
 pub use shop::ShopPage;
+#[cfg(feature = "ssr")]
+pub use media::{decode_image, paths_for_image};
 
 #[derive(Params, PartialEq)]
 struct ModPageParams {
@@ -39,6 +41,9 @@ pub fn ModPage() -> impl IntoView {
                 {move || match mod_data.get() {
                     Some(Ok((mod_data, is_author))) => {
                         let initial_data = mod_data.clone();
+                        let archived_at = mod_data.archived_at;
+                        let can_subscribe = mod_data.published_at.is_some() && archived_at.is_none();
+                        let mod_id = mod_data.id;
                         view! {
                             <Show when=move || is_author>
                                 <AuthorToolbar
@@ -47,6 +52,17 @@ pub fn ModPage() -> impl IntoView {
                                 />
                             </Show>
                             <div class="w-full max-w-screen-md mx-auto my-16">
+                                {archived_at.map(|date| view! {
+                                    <section role="status" class="bg-stone-600 border border-stone-500 text-stone-200 p-4 rounded-md mb-4">
+                                        "This mod was archived on " <LocaleDate date=Signal::derive(move || date) />
+                                        " when its last author deleted their account. Only admins can see it."
+                                    </section>
+                                })}
+                                {can_subscribe.then(|| view! {
+                                    <div class="flex justify-end mb-4">
+                                        <crate::feed::SubscribeButton mod_id=mod_id />
+                                    </div>
+                                })}
                                 <ModForm initial_data=initial_data is_editing=is_editing />
                             </div>
                         }.into_any()
@@ -70,11 +86,13 @@ async fn get_mod_by_slug(slug: String) -> Result<(Mod, bool), ServerFnError> {
         .add(entity::mods::Column::PublishedAt.is_not_null());
 
     // Users can view unpublished mods if they are authors of them
-    if let Some(user) = session().user().await? {
+    let session = session().await;
+    if let Some(user) = session.user().await? {
         condition = condition.add(entity::mod_authors::Column::UserId.eq(user.id));
     }
 
-    if session().has_scope(crate::auth::Scope::AdminAuthorAllMods) {
+    let is_admin = session.has_scope(crate::auth::Scope::AdminAuthorAllMods);
+    if is_admin {
         condition = condition.add(entity::mod_authors::Column::UserId.is_not_null()); // Always true
     }
 
@@ -84,6 +102,8 @@ async fn get_mod_by_slug(slug: String) -> Result<(Mod, bool), ServerFnError> {
         .filter(condition)
         .one(&db())
         .await?
+        // Archived mods have no authors left, so only admins can see them
+        .filter(|found| found.archived_at.is_none() || is_admin)
     else {
         let response = expect_context::<leptos_axum::ResponseOptions>();
         response.set_status(http::status::StatusCode::NOT_FOUND);
@@ -96,20 +116,44 @@ async fn get_mod_by_slug(slug: String) -> Result<(Mod, bool), ServerFnError> {
 
 #[cfg(feature = "ssr")]
 async fn is_session_mod_author(mod_id: Uuid) -> Result<bool, ServerFnError> {
-    let Some(user) = session().user().await? else { return Ok(false); };
+    let session = session().await;
+    let Some(user) = session.user().await? else { return Ok(false); };
 
-    if session().has_scope(crate::auth::Scope::AdminAuthorAllMods) {
+    if session.has_scope(crate::auth::Scope::AdminAuthorAllMods) {
         return Ok(true);
     }
 
+    Ok(is_mod_author(mod_id, user.id).await?)
+}
+
+#[cfg(feature = "ssr")]
+async fn is_mod_author(mod_id: Uuid, user_id: Uuid) -> Result<bool, sea_orm::DbErr> {
     let author = ModAuthors::find()
         .filter(entity::mod_authors::Column::ModId.eq(mod_id))
-        .filter(entity::mod_authors::Column::UserId.eq(user.id))
+        .filter(entity::mod_authors::Column::UserId.eq(user_id))
         .one(&db())
         .await?;
     Ok(author.is_some())
 }
 
+/// Records a change to a mod by the signed-in user, noting whether they made it through
+/// [`Scope::AdminAuthorAllMods`](crate::auth::Scope::AdminAuthorAllMods) rather than as one of its authors.
+#[cfg(feature = "ssr")]
+async fn record_mod_event(
+    event: crate::auth::audit::AuditEvent,
+    mod_id: Uuid,
+    target: crate::auth::audit::Target,
+    mut details: serde_json::Value,
+) -> Result<(), ServerFnError> {
+    let Some(user_id) = session().await.uuid() else {
+        return Ok(());
+    };
+    details["mod_id"] = mod_id.to_string().into();
+    details["as_admin"] = (!is_mod_author(mod_id, user_id).await?).into();
+    crate::auth::audit::Entry::new(event, Some(user_id)).target(target).details(details).record(&db()).await?;
+    Ok(())
+}
+
 #[cfg(feature = "ssr")]
 async fn require_session_mod_author(mod_id: Uuid) -> Result<(), ServerFnError> {
     if is_session_mod_author(mod_id).await? {
@@ -145,7 +189,9 @@ pub fn ModForm(initial_data: Mod, is_editing: Signal<bool>) -> impl IntoView {
     let name = RwSignal::new(initial_data.name);
     let description = RwSignal::new(initial_data.description);
 
+    let is_published = initial_data.published_at.is_some();
     let game = OnceResource::new_blocking(get_game(initial_data.game_id));
+    let authors = OnceResource::new_blocking(get_mod_authors(initial_data.id));
 
     view! {
         <ActionForm action=edit_mod>
@@ -174,6 +220,11 @@ pub fn ModForm(initial_data: Mod, is_editing: Signal<bool>) -> impl IntoView {
                             }}
                         </span>
                     </Suspense>
+                    <Suspense fallback=move || ()>
+                        {move || authors.get().and_then(Result::ok).map(|authors| view! {
+                            <span>"By " <crate::profile::UserLinks users=authors /></span>
+                        })}
+                    </Suspense>
                     <span>
                         "Release date: "
                         {match initial_data.published_at {
@@ -198,6 +249,16 @@ pub fn ModForm(initial_data: Mod, is_editing: Signal<bool>) -> impl IntoView {
                 </textarea>
             </Show>
 
+            <Show when=move || is_editing.get() && is_published>
+                <input
+                    type="text"
+                    name="update"
+                    maxlength=crate::feed::MAX_UPDATE_LENGTH
+                    placeholder="What changed? Leave empty unless followers should hear about it"
+                    class="block p-2 mb-4 border-2 border-stone-500 text-stone-200 bg-stone-700 w-full rounded-sm"
+                />
+            </Show>
+
             <Show when=is_editing>
                 <super::create::ActionFormSubmitButton
                     pending=edit_mod.pending()
@@ -222,18 +283,41 @@ async fn get_game(id: Uuid) -> Result<Game, ServerFnError> {
     }
 }
 
+/// The authors of a mod that the signed-in user can see.
 #[server]
-pub async fn edit_mod(id: Uuid, name: String, description: String) -> Result<(), ServerFnError> {
+async fn get_mod_authors(mod_id: Uuid) -> Result<Vec<crate::profile::UserLink>, ServerFnError> {
+    let Some(found) = Mods::find_by_id(mod_id).one(&db()).await? else {
+        return Err(ServerFnError::ServerError("Mod not found".to_string()));
+    };
+    if found.published_at.is_none() && !is_session_mod_author(mod_id).await? {
+        return Err(ServerFnError::ServerError("Mod not found".to_string()));
+    }
+    let mut authors = crate::profile::authors_of(&db(), &[mod_id]).await?;
+    Ok(authors.remove(&mod_id).unwrap_or_default())
+}
+
+/// `update` tells followers of a published mod what changed, if it isn't empty.
+#[server]
+pub async fn edit_mod(id: Uuid, name: String, description: String, update: Option<String>) -> Result<(), ServerFnError> {
     use sea_orm::Set;
+    use crate::auth::audit::{AuditEvent, Target};
 
     require_session_mod_author(id).await?;
+    if let Some(update) = &update {
+        crate::feed::check_update(update).map_err(|error| -> ServerFnError { ServerFnError::ServerError(error) })?;
+    }
 
-    entity::mods::ActiveModel {
+    let details = serde_json::json!({ "name": name });
+    let edited = entity::mods::ActiveModel {
         id: Set(id),
         name: Set(name),
         description: Set(description),
         ..Default::default()
-    }.save(&db()).await?;
+    }.update(&db()).await?;
+    if let Some(update) = update.filter(|_| edited.published_at.is_some()) {
+        crate::feed::record_update(&db(), id, &update).await?;
+    }
+    record_mod_event(AuditEvent::ModEdited, id, Target::Mod(id), details).await?;
 
     Ok(())
 }
@@ -17,11 +17,19 @@ pub fn paths_for_image(id: Uuid) -> (std::path::PathBuf, String) {
     (path, format!("/assets/mod_media/{filename}"))
 }
 
-#[server(input = MultipartFormData)]
-async fn upload_image(multipart: MultipartData) -> Result<ModMedia, ServerFnError> {
+/// Decodes an uploaded image of any format the `image` crate reads, ready to be saved as WebP.
+#[cfg(feature = "ssr")]
+pub fn decode_image(bytes: impl AsRef<[u8]>) -> Result<image::DynamicImage, ServerFnError> {
     use std::io::Cursor;
     use image::ImageReader;
 
+    Ok(ImageReader::new(Cursor::new(bytes)).with_guessed_format()?.decode()?)
+}
+
+#[server(input = MultipartFormData)]
+async fn upload_image(multipart: MultipartData) -> Result<ModMedia, ServerFnError> {
+    use crate::auth::audit::{AuditEvent, Target};
+
     // Parse the form data
     let mut multipart = multipart.into_inner().unwrap();
     let mut image = None;
@@ -54,10 +62,7 @@ async fn upload_image(multipart: MultipartData) -> Result<ModMedia, ServerFnErro
     let (mut path, url) = paths_for_image(id);
 
     // Save as WebP
-    let cursor = Cursor::new(image);
-    let mut image = ImageReader::new(cursor)
-        .with_guessed_format()?
-        .decode()?;
+    let mut image = decode_image(image)?;
     if image.width() > 1920 || image.height() > 1080 {
         image = image.resize_to_fill(1920, 1080, image::imageops::FilterType::Lanczos3);
     }
@@ -83,11 +88,14 @@ async fn upload_image(multipart: MultipartData) -> Result<ModMedia, ServerFnErro
         url: Set(url),
         position: Set(next_position),
     }.insert(&db()).await?;
+    super::record_mod_event(AuditEvent::ModMediaUploaded, mod_id, Target::ModMedia(id), serde_json::json!({})).await?;
     Ok(media)
 }
 
 #[server]
 async fn delete_media(id: Uuid) -> Result<(), ServerFnError> {
+    use crate::auth::audit::{AuditEvent, Target};
+
     let Some(media) = entity::mod_media::Entity::find_by_id(id).one(&db()).await? else {
         let response = expect_context::<leptos_axum::ResponseOptions>();
         response.set_status(http::status::StatusCode::NOT_FOUND);
@@ -101,7 +109,9 @@ async fn delete_media(id: Uuid) -> Result<(), ServerFnError> {
             log::error!("error deleting media image: {error:?}")
         }
     }
+    let mod_id = media.mod_id;
     media.delete(&db()).await?;
+    super::record_mod_event(AuditEvent::ModMediaDeleted, mod_id, Target::ModMedia(id), serde_json::json!({})).await?;
 
     Ok(())
 }
@@ -33,6 +33,7 @@ pub fn ShopPage() -> impl IntoView {
 async fn published_mods_by_recency() -> Result<Vec<Mod>, ServerFnError> {
     let mods = Mods::find()
         .filter(entity::mods::Column::PublishedAt.is_not_null())
+        .filter(entity::mods::Column::ArchivedAt.is_null())
         .order_by_desc(entity::mods::Column::PublishedAt)
         .all(&db())
         .await?;
@@ -0,0 +1,290 @@
+//! Server configuration, read from the environment at startup.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use serde::Deserialize;
+use thiserror::Error;
+use webauthn_rs::prelude::{AttestationCaList, AttestationCaListBuilder, Url, Uuid};
+
+use crate::auth::Scope;
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Where in-flight WebAuthn ceremony state is kept
+    pub challenge_store: ChallengeStoreKind,
+    /// How long a WebAuthn challenge may go unanswered before it is discarded
+    pub challenge_ttl: Duration,
+    /// Maximum number of outstanding challenges per client; the oldest is evicted beyond this
+    pub challenges_per_client: usize,
+    /// Trust the `X-Forwarded-For` header to contain the client IP (only set behind a proxy)
+    pub trust_forwarded_for: bool,
+    /// Relying party used for passkey ceremonies
+    pub webauthn: WebauthnConfig,
+    /// Where people reach the site, for links shown outside it, such as the device flow's verification URI
+    pub public_url: Url,
+    /// Directory of `<kid>.pem` keys used to sign and verify tokens
+    pub jwt_keys_dir: Option<PathBuf>,
+    /// Key ID of the key that signs new tokens
+    pub jwt_active_key: Option<String>,
+    /// Username to give the admin role at startup, if nobody has it yet
+    pub bootstrap_admin: Option<String>,
+    /// Lifetime of tokens issued for the shared build cache
+    pub sccache_token_ttl: Duration,
+    /// What to do when a passkey's signature counter doesn't go up, which suggests it was copied
+    pub passkey_counter_policy: CredentialPolicy,
+    /// What to do when a passkey's backup state changes between sign-ins
+    pub passkey_backup_state_policy: CredentialPolicy,
+    /// Which users must register passkeys from particular authenticators
+    pub passkey_attestation: AttestationPolicy,
+}
+
+/// The WebAuthn relying party, i.e. the site passkeys are bound to.
+#[derive(Debug, Clone)]
+pub struct WebauthnConfig {
+    /// Domain that passkeys are registered to. Changing this invalidates every existing passkey.
+    pub rp_id: String,
+    /// Name shown to the user by their authenticator
+    pub rp_name: String,
+    /// Origins that ceremonies may be performed from. Each must be `rp_id` or a subdomain of it.
+    pub origins: Vec<Url>,
+    /// Accept any subdomain of the allowed origins, e.g. for per-branch staging deployments
+    pub allow_subdomains: bool,
+    /// Ignore the port when matching origins
+    pub allow_any_port: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChallengeStoreKind {
+    /// Process-local, lost on restart. Fine for development and single-instance deployments.
+    Memory,
+    /// Shared between all instances using the database.
+    Postgres,
+}
+
+impl FromStr for ChallengeStoreKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "memory" => Ok(ChallengeStoreKind::Memory),
+            "postgres" => Ok(ChallengeStoreKind::Postgres),
+            _ => Err("expected `memory` or `postgres`".to_string()),
+        }
+    }
+}
+
+/// How to react when a passkey behaves as if it may have been cloned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CredentialPolicy {
+    /// Sign in as normal and only log it
+    Allow,
+    /// Sign in, but flag the passkey and tell its owner
+    Warn,
+    /// Refuse the sign-in, flag the passkey and tell its owner
+    Block,
+}
+
+impl FromStr for CredentialPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "allow" => Ok(CredentialPolicy::Allow),
+            "warn" => Ok(CredentialPolicy::Warn),
+            "block" => Ok(CredentialPolicy::Block),
+            _ => Err("expected `allow`, `warn` or `block`".to_string()),
+        }
+    }
+}
+
+/// Users with privileged scopes can be required to use passkeys whose attestation proves they're from a trusted
+/// authenticator model, such as a particular security key. See [`crate::auth::attestation`].
+#[derive(Debug, Clone, Default)]
+pub struct AttestationPolicy {
+    /// Users with any of these scopes need such a passkey. Empty turns the policy off.
+    pub scopes: Vec<Scope>,
+    /// The trusted authenticator models by AAGUID, with the root certificates their attestation must chain to
+    pub authenticators: AttestationCaList,
+}
+
+/// An entry in the `PASSKEY_AUTHENTICATORS` file.
+#[derive(Deserialize)]
+struct TrustedAuthenticator {
+    aaguid: Uuid,
+    /// Shown to users and recorded with their passkeys
+    model: String,
+    /// Root certificate of the model's attestation, as PEM or as base64 DER like the FIDO Metadata Service's
+    /// `attestationRootCertificates`
+    ca: String,
+}
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("{name} is invalid: {reason}")]
+    Invalid { name: &'static str, reason: String },
+}
+
+impl Config {
+    pub fn from_env() -> Result<Self, ConfigError> {
+        let webauthn = WebauthnConfig::from_env()?;
+        let public_url = match var("PUBLIC_URL") {
+            Some(url) => parse_origin("PUBLIC_URL", &url, &webauthn.rp_id)?,
+            None => default_public_url(&webauthn.origins[0])?,
+        };
+
+        Ok(Config {
+            challenge_store: parse_var("CHALLENGE_STORE")?.unwrap_or(ChallengeStoreKind::Memory),
+            challenge_ttl: Duration::from_secs(parse_var("CHALLENGE_TTL_SECONDS")?.unwrap_or(5 * 60)),
+            challenges_per_client: parse_var("CHALLENGES_PER_CLIENT")?.unwrap_or(10),
+            trust_forwarded_for: parse_var("TRUST_FORWARDED_FOR")?.unwrap_or(false),
+            webauthn,
+            public_url,
+            jwt_keys_dir: var("JWT_KEYS_DIR").map(PathBuf::from),
+            jwt_active_key: var("JWT_ACTIVE_KEY"),
+            bootstrap_admin: var("BOOTSTRAP_ADMIN"),
+            sccache_token_ttl: Duration::from_secs(parse_var("SCCACHE_TOKEN_TTL_SECONDS")?.unwrap_or(60 * 60)),
+            passkey_counter_policy: parse_var("PASSKEY_COUNTER_POLICY")?.unwrap_or(CredentialPolicy::Block),
+            passkey_backup_state_policy: parse_var("PASSKEY_BACKUP_STATE_POLICY")?.unwrap_or(CredentialPolicy::Warn),
+            passkey_attestation: AttestationPolicy::from_env()?,
+        })
+    }
+}
+
+impl AttestationPolicy {
+    fn from_env() -> Result<Self, ConfigError> {
+        let scopes = var("PASSKEY_ATTESTATION_SCOPES")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|scope| !scope.is_empty())
+            .map(|scope| scope.parse().map_err(|()| ConfigError::Invalid {
+                name: "PASSKEY_ATTESTATION_SCOPES",
+                reason: format!("`{scope}` is not a scope"),
+            }))
+            .collect::<Result<Vec<Scope>, _>>()?;
+
+        let authenticators = match var("PASSKEY_AUTHENTICATORS") {
+            Some(path) => read_authenticators(&path)?,
+            None => AttestationCaList::default(),
+        };
+        if !scopes.is_empty() && authenticators.is_empty() {
+            return Err(ConfigError::Invalid {
+                name: "PASSKEY_AUTHENTICATORS",
+                reason: "must list at least one authenticator when PASSKEY_ATTESTATION_SCOPES is set".to_string(),
+            });
+        }
+
+        Ok(AttestationPolicy { scopes, authenticators })
+    }
+}
+
+/// Reads a JSON list of [`TrustedAuthenticator`]s.
+fn read_authenticators(path: &str) -> Result<AttestationCaList, ConfigError> {
+    use base64::{Engine, engine::general_purpose::STANDARD};
+
+    let invalid = |reason: String| ConfigError::Invalid { name: "PASSKEY_AUTHENTICATORS", reason };
+
+    let json = std::fs::read_to_string(path).map_err(|error| invalid(format!("`{path}` can't be read: {error}")))?;
+    let authenticators: Vec<TrustedAuthenticator> = serde_json::from_str(&json)
+        .map_err(|error| invalid(format!("`{path}` is not a list of authenticators: {error}")))?;
+
+    let mut builder = AttestationCaListBuilder::new();
+    for TrustedAuthenticator { aaguid, model, ca } in authenticators {
+        let result = if ca.trim_start().starts_with("-----BEGIN") {
+            builder.insert_device_pem(ca.as_bytes(), aaguid, model, BTreeMap::new())
+        } else {
+            let der = STANDARD.decode(ca.trim()).map_err(|error| invalid(format!("the certificate for {aaguid} is not base64: {error}")))?;
+            builder.insert_device_der(&der, aaguid, model, BTreeMap::new())
+        };
+        result.map_err(|error| invalid(format!("the certificate for {aaguid} is invalid: {error}")))?;
+    }
+    Ok(builder.build())
+}
+
+impl WebauthnConfig {
+    fn from_env() -> Result<Self, ConfigError> {
+        let rp_id = var("WEBAUTHN_RP_ID").unwrap_or_else(|| "localhost".to_string());
+        if rp_id.contains(['/', ':']) || rp_id.starts_with('.') || rp_id.ends_with('.') {
+            return Err(ConfigError::Invalid {
+                name: "WEBAUTHN_RP_ID",
+                reason: format!("`{rp_id}` must be a bare domain name such as `starhaven.dev`"),
+            });
+        }
+
+        // Browsers only allow http for localhost
+        let default_scheme = if rp_id == "localhost" { "http" } else { "https" };
+        let origins = var("WEBAUTHN_ORIGINS")
+            .unwrap_or_else(|| format!("{default_scheme}://{rp_id}"))
+            .split(',')
+            .map(str::trim)
+            .filter(|origin| !origin.is_empty())
+            .map(|origin| parse_origin("WEBAUTHN_ORIGINS", origin, &rp_id))
+            .collect::<Result<Vec<_>, _>>()?;
+        if origins.is_empty() {
+            return Err(ConfigError::Invalid { name: "WEBAUTHN_ORIGINS", reason: "at least one origin is required".to_string() });
+        }
+
+        Ok(WebauthnConfig {
+            rp_name: var("WEBAUTHN_RP_NAME").unwrap_or_else(|| "Star Haven".to_string()),
+            origins,
+            allow_subdomains: parse_var("WEBAUTHN_ALLOW_SUBDOMAINS")?.unwrap_or(false),
+            allow_any_port: parse_var("WEBAUTHN_ALLOW_ANY_PORT")?.unwrap_or(rp_id == "localhost"),
+            rp_id,
+        })
+    }
+}
+
+/// The first allowed origin, which is where the site is reached unless `PUBLIC_URL` says otherwise. Origins on
+/// `localhost` usually leave out the port, since any is accepted, so it's taken from where the server listens.
+fn default_public_url(origin: &Url) -> Result<Url, ConfigError> {
+    let mut url = origin.clone();
+    if url.domain() == Some("localhost") && url.port().is_none() {
+        let site_addr = var("LEPTOS_SITE_ADDR").unwrap_or_else(|| "127.0.0.1:3000".to_string());
+        let port = site_addr.parse::<std::net::SocketAddr>().map(|addr| addr.port()).map_err(|_| ConfigError::Invalid {
+            name: "PUBLIC_URL",
+            reason: format!("must be set, as the port can't be taken from LEPTOS_SITE_ADDR `{site_addr}`"),
+        })?;
+        let _ = url.set_port(Some(port));
+    }
+    Ok(url)
+}
+
+/// Checks that an origin, read from `name`, is one that passkeys for `rp_id` can be used from.
+fn parse_origin(name: &'static str, origin: &str, rp_id: &str) -> Result<Url, ConfigError> {
+    let invalid = |reason: String| ConfigError::Invalid { name, reason: format!("`{origin}` {reason}") };
+
+    let url = Url::parse(origin).map_err(|error| invalid(format!("is not a URL: {error}")))?;
+    let Some(domain) = url.domain() else {
+        return Err(invalid("must have a domain name".to_string()));
+    };
+    match url.scheme() {
+        "https" => {}
+        "http" if domain == "localhost" => {}
+        _ => return Err(invalid("must use https (http is only allowed for localhost)".to_string())),
+    }
+    if url.path() != "/" || url.query().is_some() || url.fragment().is_some() {
+        return Err(invalid("must not have a path, query or fragment".to_string()));
+    }
+    if domain != rp_id && !domain.ends_with(&format!(".{rp_id}")) {
+        return Err(invalid(format!("is not `{rp_id}` or a subdomain of it (see WEBAUTHN_RP_ID)")));
+    }
+    Ok(url)
+}
+
+/// Reads an environment variable, treating empty values as unset.
+fn var(name: &'static str) -> Option<String> {
+    std::env::var(name).ok().filter(|value| !value.trim().is_empty())
+}
+
+fn parse_var<T>(name: &'static str) -> Result<Option<T>, ConfigError>
+where
+    T: FromStr,
+    T::Err: ToString,
+{
+    var(name)
+        .map(|value| value.trim().parse().map_err(|error: T::Err| ConfigError::Invalid { name, reason: error.to_string() }))
+        .transpose()
+}
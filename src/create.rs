@@ -35,7 +35,7 @@ pub fn DashboardPage() -> impl IntoView {
                             <h3>Your mods</h3>
                             <p>Error loading mods: {error.to_string()}</p>
                         }.into_any(),
-                        None | Some(Ok(None)) => view! {}.into_any(),
+                        None | Some(Ok(None)) => ().into_any(),
                     }}
                 </Suspense>
             </div>
@@ -45,7 +45,7 @@ pub fn DashboardPage() -> impl IntoView {
 
 #[server]
 async fn session_mods() -> Result<Option<Vec<Mod>>, ServerFnError> {
-    let Some(user) = session().user().await? else { return Ok(None); };
+    let Some(user) = session().await.user().await? else { return Ok(None); };
     let mods = Mods::find()
         .join(JoinType::InnerJoin, entity::mods::Relation::ModAuthors.def())
         .filter(entity::mod_authors::Column::UserId.eq(user.id))
@@ -56,7 +56,7 @@ async fn session_mods() -> Result<Option<Vec<Mod>>, ServerFnError> {
 }
 
 #[component]
-pub fn LinkButton(href: &'static str, children: Children) -> impl IntoView {
+pub fn LinkButton(#[prop(into)] href: Signal<String>, children: Children) -> impl IntoView {
     view! {
         <a href={href} class="bg-yellow-600 text-white font-semibold select-none shadow-sm py-2 px-3 rounded inline-flex items-center justify-center gap-2">
             {children()}
@@ -66,16 +66,16 @@ pub fn LinkButton(href: &'static str, children: Children) -> impl IntoView {
 
 /// A card that prompts the user to sign in. If they are already signed in, nothing is rendered.
 #[component]
-fn SessionRequiredBanner() -> impl IntoView {
+pub fn SessionRequiredBanner() -> impl IntoView {
     let is_logged_in = OnceResource::new_blocking(crate::auth::is_logged_in());
+    let sign_in_href = crate::auth::return_to::use_sign_in_href();
     view! {
         <Suspense fallback=|| {}>
             <Show when={move || matches!(is_logged_in.get(), Some(Ok(false)))}>
                 <section class="bg-stone-600 border border-stone-500 text-stone-200 p-4 rounded-md mb-4" role="alert">
                     <h4 class="font-bold">"Sign in required"</h4>
                     <p class="my-2">"You must be signed in to perform this action."</p>
-                    // TODO: ?return={this page}
-                    <LinkButton href="/auth">Sign in</LinkButton>
+                    <LinkButton href=sign_in_href>Sign in</LinkButton>
                 </section>
             </Show>
         </Suspense>
@@ -165,7 +165,7 @@ pub fn NewModPage() -> impl IntoView {
                         // TODO: markdown editor
                         <textarea name="description" class="block p-2 my-2 border-2 border-stone-500 text-stone-200 bg-stone-700 text-sm w-full rounded-sm" />
                     </label>
-                    <ActionFormSubmitButton pending=new_mod.pending() error=Signal::derive(move || new_mod.value().get().map(Result::err).flatten())>"Save & view page"</ActionFormSubmitButton>
+                    <ActionFormSubmitButton pending=new_mod.pending() error=Signal::derive(move || new_mod.value().get().and_then(Result::err))>"Save & view page"</ActionFormSubmitButton>
                 </ActionForm>
             </div>
         </Shell>
@@ -196,7 +196,7 @@ pub fn ActionFormSubmitButton(
                 {move || match error.get() {
                     Some(ServerFnError::ServerError(message)) => view! { {message} }.into_any(),
                     error => {
-                        log::error!("{:?}", error);
+                        log::error!("{error:?}");
                         view! { "Something went wrong, please try again" }.into_any()
                     }
                 }}
@@ -212,10 +212,11 @@ async fn new_mod(
     description: String,
     game: Uuid,
 ) -> Result<Mod, ServerFnError> {
-    let Some(user) = session().user().await? else {
+    let session = session().await;
+    let Some(user) = session.user().await? else {
         return Err(ServerFnError::ServerError("Must be signed in".to_string()))
     };
-    if !session().has_scope(crate::auth::Scope::CreateMod) {
+    if !session.has_scope(crate::auth::Scope::CreateMod) {
         return Err(ServerFnError::ServerError("You do not have permission to create mods".to_string()));
     }
 
@@ -224,6 +225,7 @@ async fn new_mod(
     let new_mod = db().transaction::<_, Mod, anyhow::Error>(|txn| {
         Box::pin(async move {
             use sea_orm::Set;
+            use crate::auth::audit::{AuditEvent, Entry, Target};
 
             let new_mod = entity::mods::ActiveModel {
                 id: Set(Uuid::new_v4()),
@@ -238,6 +240,11 @@ async fn new_mod(
                 user_id: Set(user.id),
                 mod_id: Set(new_mod.id),
             }.insert(txn).await?;
+            Entry::new(AuditEvent::ModCreated, Some(user.id))
+                .target(Target::Mod(new_mod.id))
+                .details(serde_json::json!({ "slug": new_mod.slug, "name": new_mod.name }))
+                .record(txn)
+                .await?;
 
             Ok(new_mod)
         })
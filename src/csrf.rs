@@ -0,0 +1,105 @@
+//! Cross-site request forgery protection for server functions. The session cookie is `SameSite=Lax`, which still lets
+//! other sites on the same registrable domain (and older browsers) send it with a POST, so every state-changing request
+//! must also show that it came from one of our own pages:
+//!
+//! 1. If it has an `Origin` header, that must be one of the configured origins.
+//! 2. Otherwise, if it has a `Sec-Fetch-Site` header, that must be `same-origin` or `none`.
+//! 3. Otherwise, it must send the value of the `csrf_token` cookie in an `X-CSRF-Token` header. Browsers always send
+//!    one of the headers above, so only scripts need to do this.
+//!
+//! Requests that authenticate with a bearer token don't use the cookie, so they are exempt, as are the OAuth endpoints
+//! that apps call directly.
+
+use std::sync::Arc;
+
+use axum::extract::{Request, State};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use http::{HeaderValue, Method, StatusCode, header::{COOKIE, ORIGIN, SET_COOKIE}};
+use webauthn_rs::prelude::Url;
+
+use crate::auth::cookie::get_cookie_value;
+use crate::config::Config;
+
+pub const COOKIE_NAME: &str = "csrf_token";
+pub const HEADER_NAME: &str = "x-csrf-token";
+
+/// Paths that apps call without a session, authenticating with client credentials in the body instead.
+const EXEMPT_PATHS: [&str; 2] = ["/oauth/token", "/oauth/device_authorization"];
+
+pub async fn verify(State(config): State<Arc<Config>>, request: Request, next: Next) -> Response {
+    let cookie = request.headers()
+        .get(COOKIE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|cookies| get_cookie_value(cookies, COOKIE_NAME));
+
+    if let Err(reason) = check(&config, &request, cookie.as_deref()) {
+        log::info!("rejected {} {}: {reason}", request.method(), request.uri().path());
+        return (StatusCode::FORBIDDEN, format!("Cross-site request rejected: {reason}")).into_response();
+    }
+
+    let mut response = next.run(request).await;
+    if cookie.is_none() {
+        // Don't require HTTPS in debug
+        #[cfg(debug_assertions)]
+        let secure = "";
+        #[cfg(not(debug_assertions))]
+        let secure = "Secure;";
+
+        let (token, _) = crate::auth::secret::generate("");
+        let cookie = format!("{COOKIE_NAME}={token}; Path=/; {secure} SameSite=Strict; HttpOnly");
+        response.headers_mut().append(SET_COOKIE, HeaderValue::from_str(&cookie).expect("to create header value"));
+    }
+    response
+}
+
+fn check(config: &Config, request: &Request, cookie: Option<&str>) -> Result<(), &'static str> {
+    if matches!(*request.method(), Method::GET | Method::HEAD | Method::OPTIONS)
+        || EXEMPT_PATHS.contains(&request.uri().path())
+        || crate::request::bearer_token_in(request.headers()).is_some()
+    {
+        return Ok(());
+    }
+
+    let headers = request.headers();
+    if let Some(origin) = headers.get(ORIGIN) {
+        let origin = origin.to_str().ok().and_then(|origin| Url::parse(origin).ok());
+        return match origin {
+            Some(origin) if origin_allowed(config, &origin) => Ok(()),
+            _ => Err("the Origin header doesn't match this site"),
+        };
+    }
+
+    if let Some(site) = headers.get("sec-fetch-site") {
+        return match site.as_bytes() {
+            b"same-origin" | b"none" => Ok(()),
+            _ => Err("the request came from another site (Sec-Fetch-Site)"),
+        };
+    }
+
+    let token = headers.get(HEADER_NAME).and_then(|value| value.to_str().ok());
+    match (cookie, token) {
+        (Some(cookie), Some(token)) if tokens_match(cookie, token) => Ok(()),
+        (None, _) => Err("no Origin or Sec-Fetch-Site header, and no csrf_token cookie"),
+        _ => Err("no Origin or Sec-Fetch-Site header, and the X-CSRF-Token header doesn't match the csrf_token cookie"),
+    }
+}
+
+/// Whether `origin` is one that passkeys may be used from, which is the same as being one of our pages.
+fn origin_allowed(config: &Config, origin: &Url) -> bool {
+    let Some(host) = origin.host_str() else {
+        return false;
+    };
+    config.webauthn.origins.iter().any(|allowed| {
+        let allowed_host = allowed.host_str().unwrap_or_default();
+        allowed.scheme() == origin.scheme()
+            && (host == allowed_host || (config.webauthn.allow_subdomains && host.ends_with(&format!(".{allowed_host}"))))
+            && (config.webauthn.allow_any_port || allowed.port_or_known_default() == origin.port_or_known_default())
+    })
+}
+
+/// Compares the hashes so that the time taken doesn't reveal how much of the token matched.
+fn tokens_match(cookie: &str, token: &str) -> bool {
+    use crate::auth::secret::hash;
+    hash(cookie) == hash(token)
+}
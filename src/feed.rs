@@ -0,0 +1,439 @@
+//! Keeping up with authors and mods. Users follow authors from their profiles and subscribe to mods from their pages,
+//! and `/feed` merges what happened to all of those mods: publications, releases, and edits that the authors chose to
+//! tell followers about. Each source is read a page at a time before `before`, and the pages merged, so paging stays
+//! cheap however much someone follows. Items newer than `users.feed_read_at` are unread.
+
+use time::OffsetDateTime;
+
+use crate::prelude::*;
+use crate::account::{ErrorMessage, Pagination};
+use crate::browse::LocaleDate;
+use crate::pagination::Cursor;
+use crate::profile::{UserLink, UserLinks};
+
+/// How many feed items are shown at once
+pub const PAGE_SIZE: u64 = 30;
+
+/// Most characters an update summary may have
+pub const MAX_UPDATE_LENGTH: usize = 280;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum FeedEvent {
+    Published,
+    Released { version: String },
+    Updated { summary: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FeedItem {
+    /// The mod, release or update the item is about
+    pub id: Uuid,
+    pub event: FeedEvent,
+    pub mod_slug: String,
+    pub mod_name: String,
+    pub authors: Vec<UserLink>,
+    pub created_at: OffsetDateTime,
+    pub unread: bool,
+}
+
+/// The published mods that `user_id` follows, directly or through one of their authors.
+#[cfg(feature = "ssr")]
+async fn followed_mods(conn: &impl ConnectionTrait, user_id: Uuid) -> Result<Vec<Mod>, DbErr> {
+    use sea_orm::QueryTrait;
+
+    let subscribed = ModSubscriptions::find()
+        .select_only()
+        .column(entity::mod_subscriptions::Column::ModId)
+        .filter(entity::mod_subscriptions::Column::UserId.eq(user_id))
+        .into_query();
+    let followed_authors = UserFollows::find()
+        .select_only()
+        .column(entity::user_follows::Column::FollowedId)
+        .filter(entity::user_follows::Column::FollowerId.eq(user_id))
+        .into_query();
+    let by_followed_authors = ModAuthors::find()
+        .select_only()
+        .column(entity::mod_authors::Column::ModId)
+        .filter(entity::mod_authors::Column::UserId.in_subquery(followed_authors))
+        .into_query();
+
+    Mods::find()
+        .filter(
+            sea_orm::Condition::any()
+                .add(entity::mods::Column::Id.in_subquery(subscribed))
+                .add(entity::mods::Column::Id.in_subquery(by_followed_authors)),
+        )
+        .filter(entity::mods::Column::PublishedAt.is_not_null())
+        .filter(entity::mods::Column::ArchivedAt.is_null())
+        .all(conn)
+        .await
+}
+
+/// A feed item before it's joined with its mod: its ID, its mod's ID, what happened, and when.
+#[cfg(feature = "ssr")]
+type Event = (Uuid, Uuid, FeedEvent, OffsetDateTime);
+
+/// The page of `events`, gathered from every source, that comes after `before`.
+#[cfg(feature = "ssr")]
+fn page(mut events: Vec<Event>, before: Cursor) -> Vec<Event> {
+    events.retain(|(id, _, _, created_at)| before.is_before(*created_at, *id));
+    events.sort_by(|a, b| (b.3, b.0).cmp(&(a.3, a.0)));
+    events.truncate(PAGE_SIZE as usize);
+    events
+}
+
+/// A page of `user_id`'s feed, newest first, starting after `before`.
+#[cfg(feature = "ssr")]
+async fn feed(conn: &impl ConnectionTrait, user_id: Uuid, before: Option<Cursor>) -> Result<Vec<FeedItem>, DbErr> {
+    use std::collections::HashMap;
+    use entity::{mod_releases, mod_updates};
+
+    let Some(user) = Users::find_by_id(user_id).one(conn).await? else {
+        return Ok(Vec::new());
+    };
+    let before = before.unwrap_or(Cursor { created_at: OffsetDateTime::now_utc(), id: Uuid::max() });
+    let mods: HashMap<Uuid, Mod> = followed_mods(conn, user_id).await?.into_iter().map(|found| (found.id, found)).collect();
+    let mod_ids: Vec<Uuid> = mods.keys().copied().collect();
+
+    let mut events: Vec<Event> = mods
+        .values()
+        .filter_map(|found| Some((found.id, found.id, FeedEvent::Published, found.published_at?)))
+        .collect();
+    let releases = ModReleases::find()
+        .filter(mod_releases::Column::ModId.is_in(mod_ids.clone()))
+        .filter(before.is_before_expr(mod_releases::Column::CreatedAt, mod_releases::Column::Id))
+        .order_by_desc(mod_releases::Column::CreatedAt)
+        .order_by_desc(mod_releases::Column::Id)
+        .limit(PAGE_SIZE)
+        .all(conn)
+        .await?;
+    events.extend(releases.into_iter().map(|release| {
+        (release.id, release.mod_id, FeedEvent::Released { version: release.version }, release.created_at)
+    }));
+    let updates = ModUpdates::find()
+        .filter(mod_updates::Column::ModId.is_in(mod_ids))
+        .filter(before.is_before_expr(mod_updates::Column::CreatedAt, mod_updates::Column::Id))
+        .order_by_desc(mod_updates::Column::CreatedAt)
+        .order_by_desc(mod_updates::Column::Id)
+        .limit(PAGE_SIZE)
+        .all(conn)
+        .await?;
+    events.extend(updates.into_iter().map(|update| {
+        (update.id, update.mod_id, FeedEvent::Updated { summary: update.summary }, update.created_at)
+    }));
+
+    let events = page(events, before);
+    let page_mod_ids: Vec<Uuid> = events.iter().map(|(_, mod_id, ..)| *mod_id).collect();
+    let authors = crate::profile::authors_of(conn, &page_mod_ids).await?;
+    Ok(events
+        .into_iter()
+        .filter_map(|(id, mod_id, event, created_at)| {
+            let found = mods.get(&mod_id)?;
+            Some(FeedItem {
+                id,
+                event,
+                mod_slug: found.slug.clone(),
+                mod_name: found.name.clone(),
+                authors: authors.get(&mod_id).cloned().unwrap_or_default(),
+                created_at,
+                unread: user.feed_read_at.is_none_or(|read_at| created_at > read_at),
+            })
+        })
+        .collect())
+}
+
+/// Checks an update summary before the edit it describes is saved.
+pub fn check_update(summary: &str) -> Result<(), String> {
+    if summary.trim().chars().count() > MAX_UPDATE_LENGTH {
+        return Err(format!("What changed must be at most {MAX_UPDATE_LENGTH} characters"));
+    }
+    Ok(())
+}
+
+/// Records an edit to a published mod that its authors want followers to know about, if `summary` isn't empty. The
+/// summary must have passed [`check_update`].
+#[cfg(feature = "ssr")]
+pub async fn record_update(conn: &impl ConnectionTrait, mod_id: Uuid, summary: &str) -> Result<(), DbErr> {
+    use sea_orm::Set;
+
+    let summary = summary.trim();
+    if summary.is_empty() {
+        return Ok(());
+    }
+    entity::mod_updates::ActiveModel {
+        id: Set(Uuid::new_v4()),
+        mod_id: Set(mod_id),
+        summary: Set(summary.to_string()),
+        created_at: Set(OffsetDateTime::now_utc()),
+    }.insert(conn).await?;
+    Ok(())
+}
+
+#[server]
+async fn get_feed(before: Option<Cursor>) -> Result<Vec<FeedItem>, ServerFnError> {
+    let Some(user_id) = session().await.uuid() else {
+        return Err(ServerFnError::ServerError("Must be signed in".to_string()));
+    };
+    Ok(feed(&db(), user_id, before).await?)
+}
+
+#[server]
+async fn mark_feed_read() -> Result<(), ServerFnError> {
+    let session = session().await;
+    let (Some(_), Some(user)) = (session.account_uuid(), session.user().await?) else {
+        return Err(ServerFnError::ServerError("Must be signed in".to_string()));
+    };
+
+    let mut record: entity::users::ActiveModel = user.into();
+    record.feed_read_at = sea_orm::Set(Some(OffsetDateTime::now_utc()));
+    record.update(&db()).await?;
+    Ok(())
+}
+
+#[server]
+async fn set_following(username: String, following: bool) -> Result<(), ServerFnError> {
+    use sea_orm::{Set, sea_query::OnConflict};
+    use entity::user_follows::Column;
+
+    let Some(user_id) = session().await.account_uuid() else {
+        return Err(ServerFnError::ServerError("Must be signed in".to_string()));
+    };
+    let Some(followed) = crate::auth::username::resolve(&db(), &username).await? else {
+        return Err(ServerFnError::ServerError("User not found".to_string()));
+    };
+    if followed.id == user_id {
+        return Err(ServerFnError::ServerError("You can't follow yourself".to_string()));
+    }
+
+    if following {
+        UserFollows::insert(entity::user_follows::ActiveModel {
+            follower_id: Set(user_id),
+            followed_id: Set(followed.id),
+            ..Default::default()
+        })
+        .on_conflict(OnConflict::columns([Column::FollowerId, Column::FollowedId]).do_nothing().to_owned())
+        .do_nothing()
+        .exec(&db())
+        .await?;
+    } else {
+        UserFollows::delete_many()
+            .filter(Column::FollowerId.eq(user_id))
+            .filter(Column::FollowedId.eq(followed.id))
+            .exec(&db())
+            .await?;
+    }
+    Ok(())
+}
+
+/// Whether the signed-in user is subscribed to a mod, or `None` if they aren't signed in.
+#[server]
+async fn get_subscription(mod_id: Uuid) -> Result<Option<bool>, ServerFnError> {
+    let Some(user_id) = session().await.uuid() else {
+        return Ok(None);
+    };
+    let subscription = ModSubscriptions::find_by_id((user_id, mod_id)).one(&db()).await?;
+    Ok(Some(subscription.is_some()))
+}
+
+#[server]
+async fn set_subscribed(mod_id: Uuid, subscribed: bool) -> Result<(), ServerFnError> {
+    use sea_orm::{Set, sea_query::OnConflict};
+    use entity::mod_subscriptions::Column;
+
+    let Some(user_id) = session().await.account_uuid() else {
+        return Err(ServerFnError::ServerError("Must be signed in".to_string()));
+    };
+
+    if subscribed {
+        let published = Mods::find_by_id(mod_id)
+            .filter(entity::mods::Column::PublishedAt.is_not_null())
+            .filter(entity::mods::Column::ArchivedAt.is_null())
+            .one(&db())
+            .await?;
+        if published.is_none() {
+            return Err(ServerFnError::ServerError("Mod not found".to_string()));
+        }
+        ModSubscriptions::insert(entity::mod_subscriptions::ActiveModel {
+            user_id: Set(user_id),
+            mod_id: Set(mod_id),
+            ..Default::default()
+        })
+        .on_conflict(OnConflict::columns([Column::UserId, Column::ModId]).do_nothing().to_owned())
+        .do_nothing()
+        .exec(&db())
+        .await?;
+    } else {
+        ModSubscriptions::delete_by_id((user_id, mod_id)).exec(&db()).await?;
+    }
+    Ok(())
+}
+
+/// Follows or unfollows `username`. `following` is `None` when there's nobody signed in, or it's their own profile.
+#[component]
+pub fn FollowButton(username: String, following: Option<bool>) -> impl IntoView {
+    let following = RwSignal::new(following);
+    let toggle = Action::new(move |follow: &bool| {
+        let (username, follow) = (username.clone(), *follow);
+        async move {
+            let result = set_following(username, follow).await;
+            if result.is_ok() {
+                following.set(Some(follow));
+            }
+            result
+        }
+    });
+
+    view! {
+        {move || following.get().map(|is_following| view! {
+            <button
+                class="bg-yellow-600 text-white font-semibold select-none shadow-sm py-2 px-3 rounded disabled:opacity-50"
+                class=("!bg-stone-600", is_following)
+                disabled=move || toggle.pending().get()
+                on:click=move |_| { toggle.dispatch(!is_following); }
+            >
+                {if is_following { "Following" } else { "Follow" }}
+            </button>
+        })}
+    }
+}
+
+/// Subscribes to or unsubscribes from a published mod. Hidden when nobody is signed in.
+#[component]
+pub fn SubscribeButton(mod_id: Uuid) -> impl IntoView {
+    let subscription = Resource::new(|| (), move |_| get_subscription(mod_id));
+    let toggle = Action::new(move |subscribe: &bool| {
+        let subscribe = *subscribe;
+        async move {
+            let result = set_subscribed(mod_id, subscribe).await;
+            subscription.refetch();
+            result
+        }
+    });
+
+    view! {
+        <Transition fallback=|| {}>
+            {move || subscription.get().and_then(Result::ok).flatten().map(|subscribed| view! {
+                <button
+                    type="button"
+                    class="bg-yellow-600 text-white font-semibold select-none shadow-sm py-1 px-3 rounded disabled:opacity-50"
+                    class=("!bg-stone-600", subscribed)
+                    disabled=move || toggle.pending().get()
+                    on:click=move |_| { toggle.dispatch(!subscribed); }
+                >
+                    {if subscribed { "Subscribed" } else { "Subscribe" }}
+                </button>
+            })}
+        </Transition>
+    }
+}
+
+#[component]
+pub fn FeedPage() -> impl IntoView {
+    let before = RwSignal::new(None::<Cursor>);
+    let items = Resource::new(move || before.get(), get_feed);
+    let mark_read = Action::new(move |_: &()| async move {
+        let result = mark_feed_read().await;
+        items.refetch();
+        result
+    });
+
+    view! {
+        <Shell>
+            <div class="w-full max-w-screen-md mx-auto my-8">
+                <crate::create::SessionRequiredBanner />
+                <div class="flex items-center mb-4">
+                    <h1 class="text-2xl font-bold">"Your feed"</h1>
+                    <button
+                        class="ml-auto text-stone-400 hover:text-stone-200 font-semibold"
+                        disabled=move || mark_read.pending().get()
+                        on:click=move |_| { mark_read.dispatch(()); }
+                    >
+                        "Mark all as read"
+                    </button>
+                </div>
+                <p class="text-stone-400 mb-4">
+                    "New releases and updates from the authors you follow and the mods you're subscribed to."
+                </p>
+                <ErrorMessage message=Signal::derive(move || mark_read.value().get().and_then(Result::err).map(|error| error.to_string())) />
+                <Transition fallback=|| {}>
+                    {move || items.get().map(|result| match result {
+                        Ok(items) => {
+                            let oldest = items
+                                .last()
+                                .map(|item| Cursor { created_at: item.created_at, id: item.id })
+                                .filter(|_| items.len() as u64 >= PAGE_SIZE);
+                            view! {
+                                <FeedItemList items />
+                                <Pagination oldest=oldest before=before />
+                            }.into_any()
+                        }
+                        Err(error) => view! { <ErrorMessage message=Some(error.to_string()) /> }.into_any(),
+                    })}
+                </Transition>
+            </div>
+        </Shell>
+    }
+}
+
+#[component]
+fn FeedItemList(items: Vec<FeedItem>) -> impl IntoView {
+    if items.is_empty() {
+        return view! {
+            <p class="text-stone-400">"Nothing here yet. Follow authors from their profiles, or subscribe to mods you like."</p>
+        }.into_any();
+    }
+
+    view! {
+        <ul class="flex flex-col gap-2 mb-4">
+            {items.into_iter().map(|item| {
+                let mod_link = view! {
+                    <a href=format!("/mod/{}", item.mod_slug) class="text-stone-200 font-semibold hover:underline">{item.mod_name}</a>
+                };
+                let (headline, summary) = match item.event {
+                    FeedEvent::Published => (view! { {mod_link} " was published" }.into_any(), None),
+                    FeedEvent::Released { version } => (view! { {mod_link} {format!(" {version} was released")} }.into_any(), None),
+                    FeedEvent::Updated { summary } => (view! { {mod_link} " was updated" }.into_any(), Some(summary)),
+                };
+                let created_at = item.created_at;
+                view! {
+                    <li class="bg-stone-800 p-4 rounded border-l-4 border-transparent" class=("!border-yellow-500", item.unread)>
+                        <p>
+                            {headline}
+                            {item.unread.then(|| view! { <span class="ml-2 text-xs text-yellow-400">"New"</span> })}
+                        </p>
+                        {summary.map(|summary| view! { <p class="whitespace-pre-wrap text-stone-200 my-1">{summary}</p> })}
+                        <p class="text-stone-400 text-xs">
+                            <LocaleDate date=Signal::derive(move || created_at) />
+                            {(!item.authors.is_empty()).then(|| view! { " · By " <UserLinks users=item.authors /> })}
+                        </p>
+                    </li>
+                }
+            }).collect_view()}
+        </ul>
+    }.into_any()
+}
+
+#[cfg(all(test, feature = "ssr"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pages_split_items_from_the_same_time() {
+        let created_at = OffsetDateTime::now_utc() - time::Duration::hours(1);
+        let events: Vec<Event> = (0..=PAGE_SIZE)
+            .map(|version| (Uuid::new_v4(), Uuid::nil(), FeedEvent::Released { version: version.to_string() }, created_at))
+            .collect();
+
+        let first = page(events.clone(), Cursor { created_at: OffsetDateTime::now_utc(), id: Uuid::max() });
+        assert_eq!(first.len() as u64, PAGE_SIZE);
+        let (id, _, _, created_at) = first.last().expect("a full page").clone();
+        let second = page(events.clone(), Cursor { created_at, id });
+        assert_eq!(second.len(), 1);
+
+        let mut seen: Vec<Uuid> = first.iter().chain(&second).map(|(id, ..)| *id).collect();
+        let mut all: Vec<Uuid> = events.iter().map(|(id, ..)| *id).collect();
+        seen.sort();
+        all.sort();
+        assert_eq!(seen, all);
+    }
+}
@@ -1,9 +1,24 @@
+pub mod account;
+pub mod admin;
 pub mod app;
 pub mod auth;
 pub mod prelude;
 pub mod shell;
 pub mod create;
 pub mod browse;
+pub mod feed;
+pub mod oauth;
+pub mod pagination;
+pub mod profile;
+
+#[cfg(feature = "ssr")]
+pub mod config;
+
+#[cfg(feature = "ssr")]
+pub mod request;
+
+#[cfg(feature = "ssr")]
+pub mod csrf;
 
 #[cfg(feature = "hydrate")]
 use wasm_bindgen::prelude::wasm_bindgen;
@@ -1,32 +1,100 @@
 #[cfg(feature = "ssr")]
 #[tokio::main]
 async fn main() {
-    use axum::Router;
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+
+    use axum::{Json, Router, routing::get};
     use leptos::prelude::*;
     use leptos_axum::{LeptosRoutes, generate_route_list};
     use sea_orm::Database;
     use star_haven_platform::app::*;
+    use star_haven_platform::auth::challenge::ChallengeStore;
+    use star_haven_platform::auth::keys::Keyring;
+    use star_haven_platform::auth::passkey::build_webauthn;
+    use star_haven_platform::auth::refresh::RefreshState;
+    use star_haven_platform::auth::throttle::Throttle;
+    use star_haven_platform::config::Config;
     use migration::{Migrator, MigratorTrait};
 
     femme::start();
 
+    let config = match Config::from_env() {
+        Ok(config) => Arc::new(config),
+        Err(error) => {
+            log::error!("invalid configuration: {error}");
+            std::process::exit(1);
+        }
+    };
+
     let db_url = std::env::var("DATABASE_URL").expect("DATABASE_URL to be set");
     let db = Database::connect(db_url).await.expect("to be able to connect to database");
     Migrator::up(&db, None).await.expect("to migrate the database");
     log::info!("database ok");
 
+    if let Some(username) = &config.bootstrap_admin {
+        if let Err(error) = star_haven_platform::auth::roles::bootstrap_admin(&db, username).await {
+            log::error!("failed to bootstrap admin: {error}");
+            std::process::exit(1);
+        }
+    }
+
+    let webauthn = match build_webauthn(&config.webauthn) {
+        Ok(webauthn) => Arc::new(webauthn),
+        Err(error) => {
+            log::error!("invalid webauthn configuration: {error}");
+            std::process::exit(1);
+        }
+    };
+    log::info!("webauthn relying party {} for {:?}", config.webauthn.rp_id, webauthn.get_allowed_origins());
+
+    let keyring = match Keyring::from_config(&config) {
+        Ok(keyring) => Arc::new(keyring),
+        Err(error) => {
+            log::error!("invalid JWT key configuration: {error}");
+            std::process::exit(1);
+        }
+    };
+
+    let challenges = Arc::new(ChallengeStore::from_config(&config, db.clone()));
+    challenges.clone().spawn_sweeper();
+
+    let throttle = Arc::new(Throttle::default());
+    throttle.clone().spawn_sweeper();
+
     let conf = get_configuration(None).unwrap();
     let addr = conf.leptos_options.site_addr;
     let leptos_options = conf.leptos_options;
     // Generate the list of routes in your Leptos App
     let routes = generate_route_list(App);
 
+    let csrf = axum::middleware::from_fn_with_state(config.clone(), star_haven_platform::csrf::verify);
+    let refresh = axum::middleware::from_fn_with_state(
+        RefreshState { db: db.clone(), keyring: keyring.clone() },
+        star_haven_platform::auth::refresh::middleware,
+    );
+
+    // Added before `refresh` so that it runs after it and sees the refreshed access token
+    let impersonation = axum::middleware::from_fn_with_state(
+        keyring.clone(),
+        star_haven_platform::auth::impersonation::guard,
+    );
+
     let app = Router::new()
+        .route("/.well-known/jwks.json", get({
+            let keyring = keyring.clone();
+            move || async move { ([(http::header::CACHE_CONTROL, "public, max-age=300")], Json(keyring.jwks())) }
+        }))
         .leptos_routes_with_context(
             &leptos_options,
             routes,
             move || {
                 provide_context(db.clone());
+                provide_context(config.clone());
+                provide_context(challenges.clone());
+                provide_context(webauthn.clone());
+                provide_context(keyring.clone());
+                provide_context(throttle.clone());
             },
             {
                 let leptos_options = leptos_options.clone();
@@ -35,13 +103,16 @@ async fn main() {
         )
         .nest_service("/assets", tower_http::services::ServeDir::new(star_haven_platform::static_assets_dir()))
         .fallback(leptos_axum::file_and_error_handler(shell))
+        .layer(impersonation)
+        .layer(refresh)
+        .layer(csrf)
         .with_state(leptos_options);
 
     // run our app with hyper
     // `axum::Server` is a re-export of `hyper::Server`
     log::info!("listening on http://{}", &addr);
     let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
-    axum::serve(listener, app.into_make_service())
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
         .await
         .unwrap();
 }
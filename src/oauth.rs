@@ -0,0 +1,501 @@
+//! An OAuth 2.0 authorization server, so that third-party Star Haven apps can act on behalf of users with the scopes
+//! they consent to. Web apps use the authorization code flow with PKCE, and desktop and CLI tools can use the device
+//! flow instead. See `docs/oauth.md`.
+
+use crate::prelude::*;
+
+use leptos::Params;
+use leptos_router::{hooks::use_query, params::Params};
+use phosphor_leptos::{Icon, IconWeight, CHECK, PLUGS_CONNECTED};
+
+use crate::account::ErrorMessage;
+use crate::auth::Scope;
+
+#[cfg(feature = "ssr")]
+pub mod grant;
+
+#[cfg(feature = "ssr")]
+pub mod endpoints;
+
+/// An error response from the token or device authorization endpoints, as described in RFC 6749 section 5.2.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OauthError {
+    error: String,
+    error_description: String,
+}
+
+impl OauthError {
+    pub fn new(error: &str, description: impl Into<String>) -> Self {
+        OauthError { error: error.to_string(), error_description: description.into() }
+    }
+}
+
+/// Tokens issued by the token endpoint.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IssuedTokens {
+    pub access_token: String,
+    pub token_type: String,
+    /// Seconds until the access token expires
+    pub expires_in: u64,
+    pub refresh_token: String,
+    /// The scopes actually granted, which may be fewer than were requested
+    pub scope: String,
+}
+
+/// The query parameters an app sends the user to `/oauth/authorize` with.
+#[derive(Params, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuthorizationParams {
+    response_type: Option<String>,
+    client_id: Option<String>,
+    redirect_uri: Option<String>,
+    scope: Option<String>,
+    state: Option<String>,
+    code_challenge: Option<String>,
+    code_challenge_method: Option<String>,
+}
+
+/// What the user is being asked to allow.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ConsentRequest {
+    client_name: String,
+    username: String,
+    scopes: Vec<Scope>,
+    /// Requested scopes that the user doesn't have, and so can't give the app
+    unavailable: Vec<Scope>,
+    /// Where the user will be sent afterwards, for the authorization code flow
+    redirect_host: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum AuthorizationPrompt {
+    Consent(ConsentRequest),
+    /// The request is invalid in a way the app should be told about, so the user can only be sent back to it.
+    Rejected { client_name: String, redirect: String },
+}
+
+/// An authorization request that passed validation, with the scopes the user is able to grant.
+#[cfg(feature = "ssr")]
+struct ValidAuthorization {
+    client: entity::oauth_clients::Model,
+    redirect_uri: webauthn_rs::prelude::Url,
+    state: Option<String>,
+    code_challenge: String,
+    scopes: std::collections::BTreeSet<Scope>,
+    unavailable: Vec<Scope>,
+}
+
+#[cfg(feature = "ssr")]
+enum AuthorizationRejection {
+    /// The client or redirect URI is wrong, so we can't safely send the user anywhere.
+    Invalid(ServerFnError),
+    /// Send the user back to the app with this URL, which carries an error.
+    Redirect { client_name: String, redirect: String },
+}
+
+/// Builds the URL that sends the user back to the app with the result of authorization.
+#[cfg(feature = "ssr")]
+fn redirect_with(redirect_uri: &webauthn_rs::prelude::Url, params: &[(&str, &str)], state: Option<&str>) -> String {
+    let mut url = redirect_uri.clone();
+    {
+        let mut query = url.query_pairs_mut();
+        for (key, value) in params {
+            query.append_pair(key, value);
+        }
+        if let Some(state) = state {
+            query.append_pair("state", state);
+        }
+    }
+    url.to_string()
+}
+
+#[cfg(feature = "ssr")]
+async fn validate_authorization(
+    params: &AuthorizationParams,
+    user_id: Uuid,
+) -> Result<ValidAuthorization, AuthorizationRejection> {
+    let invalid = |message: &str| AuthorizationRejection::Invalid(ServerFnError::ServerError(message.to_string()));
+
+    let client_id = params.client_id.as_deref().and_then(|id| id.parse::<Uuid>().ok());
+    let client = match client_id {
+        Some(client_id) => OauthClients::find_by_id(client_id)
+            .one(&db())
+            .await
+            .map_err(|error| AuthorizationRejection::Invalid(error.into()))?,
+        None => None,
+    };
+    let Some(client) = client else {
+        return Err(invalid("This app isn't registered with Star Haven."));
+    };
+    let Some(redirect_uri) = params
+        .redirect_uri
+        .as_deref()
+        .and_then(|uri| webauthn_rs::prelude::Url::parse(uri).ok())
+        .filter(|uri| grant::redirect_uri_allowed(&client, uri))
+    else {
+        return Err(invalid("This app asked to send you somewhere it hasn't registered, so it may be an impostor."));
+    };
+
+    let state = params.state.clone();
+    let reject = |error: &str, description: &str| AuthorizationRejection::Redirect {
+        client_name: client.name.clone(),
+        redirect: redirect_with(&redirect_uri, &[("error", error), ("error_description", description)], state.as_deref()),
+    };
+    if params.response_type.as_deref() != Some("code") {
+        return Err(reject("unsupported_response_type", "Only the `code` response type is supported"));
+    }
+    let Some(code_challenge) = params.code_challenge.clone().filter(|challenge| !challenge.is_empty()) else {
+        return Err(reject("invalid_request", "PKCE is required: send a `code_challenge`"));
+    };
+    if params.code_challenge_method.as_deref() != Some("S256") {
+        return Err(reject("invalid_request", "`code_challenge_method` must be `S256`"));
+    }
+    let requested = match grant::parse_scope_param(params.scope.as_deref()) {
+        Ok(requested) => requested,
+        Err(error) => return Err(reject("invalid_scope", &error.error_description)),
+    };
+
+    let user_scopes = crate::auth::roles::scopes_for(&db(), user_id)
+        .await
+        .map_err(|error| AuthorizationRejection::Invalid(error.into()))?;
+    let scopes = requested.intersection(&user_scopes).copied().collect();
+    let unavailable = requested.difference(&user_scopes).copied().collect();
+    Ok(ValidAuthorization { client, redirect_uri, state, code_challenge, scopes, unavailable })
+}
+
+/// Checks an authorization request and describes it for the consent screen.
+#[server]
+async fn authorization_request(params: AuthorizationParams) -> Result<AuthorizationPrompt, ServerFnError> {
+    let session = session().await;
+    let (Some(user_id), Some(user)) = (session.account_uuid(), session.user().await?) else {
+        return Err(ServerFnError::ServerError("Must be signed in".to_string()));
+    };
+
+    match validate_authorization(&params, user_id).await {
+        Ok(valid) => Ok(AuthorizationPrompt::Consent(ConsentRequest {
+            client_name: valid.client.name,
+            username: user.username,
+            scopes: valid.scopes.into_iter().collect(),
+            unavailable: valid.unavailable,
+            redirect_host: valid.redirect_uri.host_str().map(str::to_owned),
+        })),
+        Err(AuthorizationRejection::Invalid(error)) => Err(error),
+        Err(AuthorizationRejection::Redirect { client_name, redirect }) => {
+            Ok(AuthorizationPrompt::Rejected { client_name, redirect })
+        }
+    }
+}
+
+/// Records the user's decision, returning the URL to send them back to the app with.
+#[server]
+async fn authorize(params: AuthorizationParams, approve: bool) -> Result<String, ServerFnError> {
+    use crate::auth::audit::{AuditEvent, Entry};
+
+    let Some(user_id) = session().await.account_uuid() else {
+        return Err(ServerFnError::ServerError("Must be signed in".to_string()));
+    };
+
+    let valid = match validate_authorization(&params, user_id).await {
+        Ok(valid) => valid,
+        Err(AuthorizationRejection::Invalid(error)) => return Err(error),
+        Err(AuthorizationRejection::Redirect { redirect, .. }) => return Ok(redirect),
+    };
+    let state = valid.state.as_deref();
+    if !approve {
+        return Ok(redirect_with(&valid.redirect_uri, &[("error", "access_denied"), ("error_description", "The user denied access")], state));
+    }
+
+    let code = grant::create_authorization_code(
+        &db(),
+        valid.client.id,
+        user_id,
+        &valid.redirect_uri,
+        &valid.scopes,
+        valid.code_challenge,
+    ).await?;
+    Entry::new(AuditEvent::OauthAppAuthorized, Some(user_id))
+        .user(user_id)
+        .details(serde_json::json!({
+            "client_id": valid.client.id,
+            "name": valid.client.name,
+            "scopes": valid.scopes,
+        }))
+        .record(&db())
+        .await?;
+    Ok(redirect_with(&valid.redirect_uri, &[("code", &code)], state))
+}
+
+/// Finds a pending device authorization by the code the user typed in.
+#[cfg(feature = "ssr")]
+async fn find_device_code(user_code: &str) -> Result<(entity::oauth_device_codes::Model, entity::oauth_clients::Model), ServerFnError> {
+    use entity::oauth_device_codes::Column;
+
+    let not_found = || -> ServerFnError {
+        ServerFnError::ServerError("That code is wrong or has expired. Check your device for a new one.".to_string())
+    };
+    let (device_code, client) = OauthDeviceCodes::find()
+        .filter(Column::UserCode.eq(grant::normalize_user_code(user_code)))
+        .filter(Column::Status.eq(grant::device_status::PENDING))
+        .filter(Column::ExpiresAt.gt(time::OffsetDateTime::now_utc()))
+        .find_also_related(OauthClients)
+        .one(&db())
+        .await?
+        .ok_or_else(not_found)?;
+    Ok((device_code, client.ok_or_else(not_found)?))
+}
+
+#[server]
+async fn device_request(user_code: String) -> Result<ConsentRequest, ServerFnError> {
+    let session = session().await;
+    let (Some(user_id), Some(user)) = (session.account_uuid(), session.user().await?) else {
+        return Err(ServerFnError::ServerError("Must be signed in".to_string()));
+    };
+
+    let (device_code, client) = find_device_code(&user_code).await?;
+    let requested = crate::auth::access_token::parse_scopes(&device_code.scopes);
+    let user_scopes = crate::auth::roles::scopes_for(&db(), user_id).await?;
+    Ok(ConsentRequest {
+        client_name: client.name,
+        username: user.username,
+        scopes: requested.intersection(&user_scopes).copied().collect(),
+        unavailable: requested.difference(&user_scopes).copied().collect(),
+        redirect_host: None,
+    })
+}
+
+#[server]
+async fn decide_device_request(user_code: String, approve: bool) -> Result<(), ServerFnError> {
+    use sea_orm::Set;
+    use crate::auth::audit::{AuditEvent, Entry};
+
+    let Some(user_id) = session().await.account_uuid() else {
+        return Err(ServerFnError::ServerError("Must be signed in".to_string()));
+    };
+
+    let (device_code, client) = find_device_code(&user_code).await?;
+    let scopes = grant::current_scopes(&db(), user_id, &device_code.scopes).await?;
+    let status = if approve { grant::device_status::APPROVED } else { grant::device_status::DENIED };
+    let mut record: entity::oauth_device_codes::ActiveModel = device_code.into();
+    record.status = Set(status.to_string());
+    record.user_id = Set(Some(user_id));
+    record.scopes = Set(serde_json::to_value(&scopes)?);
+    record.update(&db()).await?;
+
+    if approve {
+        Entry::new(AuditEvent::OauthAppAuthorized, Some(user_id))
+            .user(user_id)
+            .details(serde_json::json!({
+                "client_id": client.id,
+                "name": client.name,
+                "scopes": scopes,
+            }))
+            .record(&db())
+            .await?;
+    }
+    Ok(())
+}
+
+fn error_message(error: ServerFnError) -> String {
+    match error {
+        ServerFnError::ServerError(message) => message,
+        _ => "Something went wrong, please try again".to_string(),
+    }
+}
+
+/// Asks the user whether to let an app act on their behalf.
+#[component]
+fn Consent(request: ConsentRequest, on_decide: impl Fn(bool) + Copy + 'static) -> impl IntoView {
+    let ConsentRequest { client_name, username, scopes, unavailable, redirect_host } = request;
+    view! {
+        <div class="bg-stone-800 p-6 rounded flex flex-col gap-4">
+            <h1 class="text-xl font-bold flex items-center gap-2">
+                <Icon icon=PLUGS_CONNECTED weight=IconWeight::Regular size="28px" />
+                {format!("Allow {client_name} to use your account?")}
+            </h1>
+            <p class="text-stone-400">"Signed in as " <span class="text-stone-200 font-semibold">{username}</span></p>
+            <div>
+                <p class="font-semibold mb-1">{format!("{client_name} will be able to:")}</p>
+                <ul class="flex flex-col gap-1">
+                    <li class="flex items-center gap-2">
+                        <Icon icon=CHECK weight=IconWeight::Bold />
+                        "See your username"
+                    </li>
+                    {scopes.into_iter().map(|scope| view! {
+                        <li class="flex items-center gap-2">
+                            <Icon icon=CHECK weight=IconWeight::Bold />
+                            {scope.description()}
+                            <span class="text-stone-400 text-xs font-mono">{scope.as_str()}</span>
+                        </li>
+                    }).collect_view()}
+                </ul>
+            </div>
+            {(!unavailable.is_empty()).then(|| view! {
+                <p class="text-stone-400 text-sm">
+                    "It also asked to "
+                    {unavailable.iter().map(|scope| scope.description().to_lowercase()).collect::<Vec<_>>().join(", ")}
+                    ", but you don't have permission to do that yourself."
+                </p>
+            })}
+            <p class="text-stone-400 text-sm">
+                "You can remove its access at any time from your account settings."
+                {redirect_host.map(|host| format!(" You'll be sent back to {host}."))}
+            </p>
+            <div class="flex gap-2">
+                <button
+                    class="bg-yellow-600 text-white font-semibold py-2 px-4 rounded grow"
+                    on:click=move |_| on_decide(true)
+                >
+                    "Allow"
+                </button>
+                <button
+                    class="border border-stone-400 font-semibold py-2 px-4 rounded grow"
+                    on:click=move |_| on_decide(false)
+                >
+                    "Deny"
+                </button>
+            </div>
+        </div>
+    }
+}
+
+/// The consent screen for the authorization code flow, at `/oauth/authorize`.
+#[component]
+pub fn AuthorizePage() -> impl IntoView {
+    let query = use_query::<AuthorizationParams>();
+    let prompt = Resource::new(
+        move || query.get().ok(),
+        |params| async move {
+            match params {
+                Some(params) => authorization_request(params).await,
+                None => Err(ServerFnError::ServerError("Invalid authorization request".to_string())),
+            }
+        },
+    );
+
+    let decide = Action::new(move |approve: &bool| {
+        let approve = *approve;
+        let params = query.get_untracked().ok();
+        async move {
+            let Some(params) = params else {
+                return Err(ServerFnError::ServerError("Invalid authorization request".to_string()));
+            };
+            let redirect = authorize(params, approve).await?;
+            #[cfg(feature = "hydrate")]
+            window().location().set_href(&redirect).expect("failed to redirect to app");
+            #[cfg(not(feature = "hydrate"))]
+            let _ = redirect;
+            Ok(())
+        }
+    });
+    let error = Signal::derive(move || decide.value().get().and_then(Result::err).map(error_message));
+
+    view! {
+        <Shell>
+            <div class="w-full max-w-md mx-auto my-8">
+                <crate::create::SessionRequiredBanner />
+                <Transition fallback=|| {}>
+                    {move || prompt.get().map(|result| match result {
+                        Ok(AuthorizationPrompt::Consent(request)) => view! {
+                            <Consent request on_decide=move |approve| { decide.dispatch(approve); } />
+                        }.into_any(),
+                        Ok(AuthorizationPrompt::Rejected { client_name, redirect }) => view! {
+                            <div class="bg-stone-800 p-6 rounded">
+                                <p class="mb-4">{format!("{client_name} sent an invalid sign-in request.")}</p>
+                                <a href=redirect class="font-semibold text-yellow-500">{format!("Return to {client_name}")}</a>
+                            </div>
+                        }.into_any(),
+                        Err(error) => view! {
+                            <div class="bg-stone-800 p-6 rounded">
+                                <p>{error_message(error)}</p>
+                            </div>
+                        }.into_any(),
+                    })}
+                </Transition>
+                <ErrorMessage message=error />
+            </div>
+        </Shell>
+    }
+}
+
+#[derive(Params, Debug, Clone, PartialEq)]
+struct DeviceParams {
+    user_code: Option<String>,
+}
+
+/// Where users enter the code shown by a desktop or CLI tool using the device flow, at `/oauth/device`.
+#[component]
+pub fn DevicePage() -> impl IntoView {
+    let query = use_query::<DeviceParams>();
+    let user_code = RwSignal::new(query.get_untracked().ok().and_then(|params| params.user_code).unwrap_or_default());
+    let done = RwSignal::new(None::<bool>);
+
+    let lookup = Action::new(move |code: &String| device_request(code.clone()));
+    let decide = Action::new(move |approve: &bool| {
+        let approve = *approve;
+        async move {
+            let result = decide_device_request(user_code.get_untracked(), approve).await;
+            if result.is_ok() {
+                done.set(Some(approve));
+            }
+            result
+        }
+    });
+
+    let error = Signal::derive(move || {
+        lookup.value().get().and_then(Result::err)
+            .or_else(|| decide.value().get().and_then(Result::err))
+            .map(error_message)
+    });
+
+    view! {
+        <Shell>
+            <div class="w-full max-w-md mx-auto my-8">
+                <crate::create::SessionRequiredBanner />
+                {move || match (done.get(), lookup.value().get()) {
+                    (Some(true), _) => view! {
+                        <div class="bg-stone-800 p-6 rounded">
+                            <p class="font-semibold">"All done! You can return to your device."</p>
+                        </div>
+                    }.into_any(),
+                    (Some(false), _) => view! {
+                        <div class="bg-stone-800 p-6 rounded">
+                            <p class="font-semibold">"Access denied. The app on your device won't be able to use your account."</p>
+                        </div>
+                    }.into_any(),
+                    (None, Some(Ok(request))) => view! {
+                        <Consent request on_decide=move |approve| { decide.dispatch(approve); } />
+                    }.into_any(),
+                    (None, _) => view! {
+                        <form
+                            class="bg-stone-800 p-6 rounded flex flex-col gap-4"
+                            on:submit=move |event| {
+                                event.prevent_default();
+                                lookup.dispatch(user_code.get_untracked());
+                            }
+                        >
+                            <h1 class="text-xl font-bold">"Connect a device"</h1>
+                            <label class="block">
+                                <span class="font-semibold">"Enter the code shown on your device"</span>
+                                <input
+                                    type="text"
+                                    placeholder="XXXX-XXXX"
+                                    autocomplete="off"
+                                    autocapitalize="characters"
+                                    bind:value=user_code
+                                    class="block w-full mt-1 p-2 border-2 border-stone-500 text-stone-200 bg-stone-700 rounded-sm font-mono text-xl tracking-widest uppercase"
+                                />
+                            </label>
+                            <button
+                                type="submit"
+                                class="bg-yellow-600 text-white font-semibold py-2 px-4 rounded disabled:opacity-50"
+                                disabled=move || user_code.read().trim().is_empty()
+                            >
+                                "Continue"
+                            </button>
+                        </form>
+                    }.into_any(),
+                }}
+                <ErrorMessage message=error />
+            </div>
+        </Shell>
+    }
+}
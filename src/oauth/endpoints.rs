@@ -0,0 +1,277 @@
+//! The endpoints that apps call directly rather than sending the user to. They take form-encoded requests and return
+//! JSON, as the OAuth specs require, so that off-the-shelf client libraries work.
+
+use http::{StatusCode, header::CACHE_CONTROL};
+use sea_orm::Set;
+use time::OffsetDateTime;
+
+use crate::auth::audit::{AuditEvent, Entry};
+use crate::prelude::*;
+
+use super::grant::{self, device_status};
+use super::{IssuedTokens, OauthError};
+
+const DEVICE_CODE_GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:device_code";
+
+/// Either a successful response or an error, serialised without a wrapper.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum OauthResponse<T> {
+    Ok(T),
+    Err(OauthError),
+}
+
+/// Sets the response status and headers that the specs require, and wraps the result.
+fn respond<T>(result: Result<T, OauthError>) -> OauthResponse<T> {
+    let response = expect_context::<leptos_axum::ResponseOptions>();
+    response.insert_header(CACHE_CONTROL, http::HeaderValue::from_static("no-store"));
+    match result {
+        Ok(value) => OauthResponse::Ok(value),
+        Err(error) => {
+            response.set_status(match error.error.as_str() {
+                "invalid_client" => StatusCode::UNAUTHORIZED,
+                "server_error" => StatusCode::INTERNAL_SERVER_ERROR,
+                _ => StatusCode::BAD_REQUEST,
+            });
+            OauthResponse::Err(error)
+        }
+    }
+}
+
+fn invalid_grant(description: &str) -> OauthError {
+    OauthError::new("invalid_grant", description)
+}
+
+/// The token endpoint (RFC 6749 section 3.2), which exchanges an authorization code, refresh token or approved device
+/// code for tokens.
+#[allow(clippy::too_many_arguments)]
+#[server(prefix = "/oauth", endpoint = "token")]
+pub async fn oauth_token(
+    grant_type: String,
+    client_id: Option<String>,
+    client_secret: Option<String>,
+    code: Option<String>,
+    redirect_uri: Option<String>,
+    code_verifier: Option<String>,
+    refresh_token: Option<String>,
+    device_code: Option<String>,
+) -> Result<OauthResponse<IssuedTokens>, ServerFnError> {
+    let result = async {
+        let client = grant::authenticate_client(client_id, client_secret).await?;
+        match grant_type.as_str() {
+            "authorization_code" => exchange_authorization_code(client, code, redirect_uri, code_verifier).await,
+            "refresh_token" => exchange_refresh_token(client, refresh_token).await,
+            DEVICE_CODE_GRANT_TYPE => exchange_device_code(client, device_code).await,
+            _ => Err(OauthError::new("unsupported_grant_type", format!("Unsupported grant type `{grant_type}`"))),
+        }
+    }.await;
+    Ok(respond(result))
+}
+
+async fn exchange_authorization_code(
+    client: entity::oauth_clients::Model,
+    code: Option<String>,
+    redirect_uri: Option<String>,
+    code_verifier: Option<String>,
+) -> Result<IssuedTokens, OauthError> {
+    use entity::oauth_authorization_codes::Column;
+
+    let (Some(code), Some(redirect_uri), Some(code_verifier)) = (code, redirect_uri, code_verifier) else {
+        return Err(OauthError::new("invalid_request", "`code`, `redirect_uri` and `code_verifier` are required"));
+    };
+    let Some(record) = OauthAuthorizationCodes::find()
+        .filter(Column::CodeHash.eq(crate::auth::secret::hash(&code)))
+        .one(&db())
+        .await?
+    else {
+        return Err(invalid_grant("Unknown or already used authorization code"));
+    };
+
+    // Codes are single use, whether or not the exchange succeeds
+    let deleted = OauthAuthorizationCodes::delete_by_id(record.id).exec(&db()).await?;
+    if deleted.rows_affected == 0 {
+        return Err(invalid_grant("Unknown or already used authorization code"));
+    }
+    if record.client_id != client.id {
+        return Err(invalid_grant("This authorization code was issued to another client"));
+    }
+    if record.expires_at <= OffsetDateTime::now_utc() {
+        return Err(invalid_grant("Authorization code expired"));
+    }
+    let redirect_uri = webauthn_rs::prelude::Url::parse(&redirect_uri).map(|url| url.to_string());
+    if redirect_uri.as_ref() != Ok(&record.redirect_uri) {
+        return Err(invalid_grant("`redirect_uri` doesn't match the one used to get this code"));
+    }
+    if !grant::verify_pkce(&record.code_challenge, &code_verifier) {
+        return Err(invalid_grant("`code_verifier` doesn't match the code challenge"));
+    }
+
+    let scopes = grant::current_scopes(&db(), record.user_id, &record.scopes).await?;
+    grant::issue_tokens(&db(), client.id, record.user_id, scopes).await
+}
+
+async fn exchange_refresh_token(
+    client: entity::oauth_clients::Model,
+    refresh_token: Option<String>,
+) -> Result<IssuedTokens, OauthError> {
+    use entity::oauth_refresh_tokens::Column;
+
+    let Some(refresh_token) = refresh_token else {
+        return Err(OauthError::new("invalid_request", "`refresh_token` is required"));
+    };
+    let Some(record) = OauthRefreshTokens::find()
+        .filter(Column::TokenHash.eq(crate::auth::secret::hash(&refresh_token)))
+        .one(&db())
+        .await?
+    else {
+        return Err(invalid_grant("Unknown refresh token"));
+    };
+    if record.client_id != client.id {
+        return Err(invalid_grant("This refresh token was issued to another client"));
+    }
+
+    // Each refresh token works once. Claim it atomically so that two concurrent requests can't both succeed.
+    let claimed = OauthRefreshTokens::update_many()
+        .col_expr(Column::UsedAt, Expr::value(OffsetDateTime::now_utc()))
+        .filter(Column::Id.eq(record.id))
+        .filter(Column::UsedAt.is_null())
+        .filter(Column::RevokedAt.is_null())
+        .exec(&db())
+        .await?;
+    if claimed.rows_affected == 0 {
+        let used = OauthRefreshTokens::find_by_id(record.id).one(&db()).await?.is_some_and(|record| record.used_at.is_some());
+        if !used {
+            return Err(invalid_grant("Refresh token revoked"));
+        }
+        // Someone is replaying a token that was already exchanged, so it may have been stolen. Cut off the whole grant.
+        grant::revoke_grant(&db(), record.user_id, client.id).await?;
+        Entry::new(AuditEvent::OauthRefreshTokenReused, None)
+            .user(record.user_id)
+            .details(serde_json::json!({ "client_id": client.id, "name": client.name }))
+            .record(&db())
+            .await?;
+        log::warn!("refresh token {} for client {} was reused; revoked the grant", record.id, client.id);
+        return Err(invalid_grant("Refresh token already used"));
+    }
+    if record.expires_at <= OffsetDateTime::now_utc() {
+        return Err(invalid_grant("Refresh token expired"));
+    }
+
+    let scopes = grant::current_scopes(&db(), record.user_id, &record.scopes).await?;
+    grant::issue_tokens(&db(), client.id, record.user_id, scopes).await
+}
+
+async fn exchange_device_code(
+    client: entity::oauth_clients::Model,
+    device_code: Option<String>,
+) -> Result<IssuedTokens, OauthError> {
+    use entity::oauth_device_codes::Column;
+
+    let Some(device_code) = device_code else {
+        return Err(OauthError::new("invalid_request", "`device_code` is required"));
+    };
+    let Some(record) = OauthDeviceCodes::find()
+        .filter(Column::DeviceCodeHash.eq(crate::auth::secret::hash(&device_code)))
+        .filter(Column::ClientId.eq(client.id))
+        .one(&db())
+        .await?
+    else {
+        return Err(invalid_grant("Unknown device code"));
+    };
+
+    let now = OffsetDateTime::now_utc();
+    if record.expires_at <= now {
+        OauthDeviceCodes::delete_by_id(record.id).exec(&db()).await?;
+        return Err(OauthError::new("expired_token", "The device code expired before the user approved it"));
+    }
+    let polled_too_soon = record.last_polled_at.is_some_and(|last_polled_at| {
+        now - last_polled_at < time::Duration::seconds(grant::DEVICE_POLL_INTERVAL_SECONDS as i64)
+    });
+
+    match record.status.as_str() {
+        device_status::APPROVED => {
+            let deleted = OauthDeviceCodes::delete_by_id(record.id).exec(&db()).await?;
+            let Some(user_id) = record.user_id.filter(|_| deleted.rows_affected == 1) else {
+                return Err(invalid_grant("Unknown device code"));
+            };
+            let scopes = grant::current_scopes(&db(), user_id, &record.scopes).await?;
+            grant::issue_tokens(&db(), client.id, user_id, scopes).await
+        }
+        device_status::DENIED => {
+            OauthDeviceCodes::delete_by_id(record.id).exec(&db()).await?;
+            Err(OauthError::new("access_denied", "The user denied access"))
+        }
+        _ => {
+            let mut record: entity::oauth_device_codes::ActiveModel = record.into();
+            record.last_polled_at = Set(Some(now));
+            record.update(&db()).await?;
+            if polled_too_soon {
+                Err(OauthError::new("slow_down", format!("Poll at most every {} seconds", grant::DEVICE_POLL_INTERVAL_SECONDS)))
+            } else {
+                Err(OauthError::new("authorization_pending", "The user hasn't approved the request yet"))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeviceAuthorization {
+    device_code: String,
+    user_code: String,
+    verification_uri: String,
+    verification_uri_complete: String,
+    /// Seconds until the codes expire
+    expires_in: u64,
+    /// Seconds to wait between polls of the token endpoint
+    interval: u64,
+}
+
+/// The device authorization endpoint (RFC 8628 section 3.1), where a device that can't show a browser gets a code for
+/// the user to enter at `/oauth/device`.
+#[server(prefix = "/oauth", endpoint = "device_authorization")]
+pub async fn oauth_device_authorization(
+    client_id: Option<String>,
+    client_secret: Option<String>,
+    scope: Option<String>,
+) -> Result<OauthResponse<DeviceAuthorization>, ServerFnError> {
+    let result = async {
+        let client = grant::authenticate_client(client_id, client_secret).await?;
+        let scopes = grant::parse_scope_param(scope.as_deref())?;
+        let (device_code, user_code) = grant::create_device_code(&db(), client.id, &scopes).await?;
+
+        let mut verification_uri = config().public_url.clone();
+        verification_uri.set_path("/oauth/device");
+        let mut verification_uri_complete = verification_uri.clone();
+        verification_uri_complete.query_pairs_mut().append_pair("user_code", &user_code);
+        Ok(DeviceAuthorization {
+            device_code,
+            user_code,
+            verification_uri: verification_uri.to_string(),
+            verification_uri_complete: verification_uri_complete.to_string(),
+            expires_in: grant::DEVICE_CODE_LIFETIME.whole_seconds() as u64,
+            interval: grant::DEVICE_POLL_INTERVAL_SECONDS,
+        })
+    }.await;
+    Ok(respond(result))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserInfo {
+    /// The user's ID, which never changes
+    sub: Uuid,
+    preferred_username: String,
+    /// The user's display name, if they have one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+}
+
+/// Tells an app who its access token belongs to, so that it can offer "Sign in with Star Haven".
+#[server(prefix = "/oauth", endpoint = "userinfo", input = server_fn::codec::GetUrl)]
+pub async fn oauth_userinfo() -> Result<UserInfo, ServerFnError> {
+    let Some(user) = session().await.user().await? else {
+        let response = expect_context::<leptos_axum::ResponseOptions>();
+        response.set_status(StatusCode::UNAUTHORIZED);
+        return Err(ServerFnError::ServerError("Must be signed in".to_string()));
+    };
+    Ok(UserInfo { sub: user.id, preferred_username: user.username, name: user.display_name })
+}
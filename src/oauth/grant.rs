@@ -0,0 +1,290 @@
+//! Issuing and checking the codes and tokens behind each OAuth flow.
+
+use std::collections::BTreeSet;
+
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use ring::digest::{SHA256, digest};
+use ring::rand::{SecureRandom, SystemRandom};
+use sea_orm::{ConnectionTrait, Set};
+use time::OffsetDateTime;
+use webauthn_rs::prelude::Url;
+
+use crate::auth::{Scope, access_token::parse_scopes, roles, secret, token::{Claims, Service}};
+use crate::prelude::*;
+
+use super::{IssuedTokens, OauthError};
+
+/// Access tokens are self-contained, so services that only check the signature accept them until they expire. They are
+/// kept short for that reason; apps use their refresh token for more.
+pub const ACCESS_TOKEN_LIFETIME_SECONDS: u64 = 15 * 60;
+/// Refresh tokens rotate on every use, and this is how long one lasts if it isn't used.
+const REFRESH_TOKEN_LIFETIME: time::Duration = time::Duration::days(30);
+const AUTHORIZATION_CODE_LIFETIME: time::Duration = time::Duration::minutes(5);
+pub const DEVICE_CODE_LIFETIME: time::Duration = time::Duration::minutes(10);
+/// How often device clients may poll the token endpoint, in seconds
+pub const DEVICE_POLL_INTERVAL_SECONDS: u64 = 5;
+
+pub const CLIENT_SECRET_PREFIX: &str = "shcs_";
+const AUTHORIZATION_CODE_PREFIX: &str = "shac_";
+const REFRESH_TOKEN_PREFIX: &str = "shrt_";
+const DEVICE_CODE_PREFIX: &str = "shdc_";
+
+/// Consonants only, so that user codes can't spell words and have no lookalike characters.
+const USER_CODE_ALPHABET: &[u8] = b"BCDFGHJKLMNPQRSTVWXZ";
+const USER_CODE_LENGTH: usize = 8;
+
+pub mod device_status {
+    pub const PENDING: &str = "pending";
+    pub const APPROVED: &str = "approved";
+    pub const DENIED: &str = "denied";
+}
+
+impl From<DbErr> for OauthError {
+    fn from(error: DbErr) -> Self {
+        log::error!("database error in OAuth flow: {error}");
+        OauthError::new("server_error", "Something went wrong, please try again")
+    }
+}
+
+impl From<jsonwebtoken::errors::Error> for OauthError {
+    fn from(error: jsonwebtoken::errors::Error) -> Self {
+        log::error!("failed to sign OAuth access token: {error}");
+        OauthError::new("server_error", "Something went wrong, please try again")
+    }
+}
+
+/// Parses a space-separated `scope` parameter. Unknown scopes are an error rather than being ignored, so that app
+/// developers notice typos. So are scopes that apps can't be given, see [`Scope::DELEGABLE`].
+pub fn parse_scope_param(scope: Option<&str>) -> Result<BTreeSet<Scope>, OauthError> {
+    scope
+        .unwrap_or_default()
+        .split_ascii_whitespace()
+        .map(|name| match name.parse::<Scope>() {
+            Ok(scope) if scope.is_delegable() => Ok(scope),
+            Ok(_) => Err(OauthError::new("invalid_scope", format!("Apps can't be given the `{name}` scope"))),
+            Err(()) => Err(OauthError::new("invalid_scope", format!("Unknown scope `{name}`"))),
+        })
+        .collect()
+}
+
+pub fn scope_param(scopes: &BTreeSet<Scope>) -> String {
+    scopes.iter().map(|scope| scope.as_str()).collect::<Vec<_>>().join(" ")
+}
+
+/// Checks that a redirect URI is one we're willing to send codes to: HTTPS, HTTP on the loopback interface for native
+/// apps, or a private-use scheme such as `dev.starhaven.app:/callback` (RFC 8252).
+pub fn validate_redirect_uri(uri: &str) -> Result<Url, String> {
+    let url = Url::parse(uri).map_err(|error| format!("`{uri}` is not a URL: {error}"))?;
+    if url.fragment().is_some() {
+        return Err(format!("`{uri}` must not have a fragment"));
+    }
+    match url.scheme() {
+        "https" => Ok(url),
+        "http" if is_loopback(&url) => Ok(url),
+        "http" => Err(format!("`{uri}` must use HTTPS unless it is on localhost")),
+        scheme if scheme.contains('.') => Ok(url),
+        _ => Err(format!("`{uri}` must use HTTPS, localhost or a reverse domain name scheme such as `com.example.app`")),
+    }
+}
+
+fn is_loopback(url: &Url) -> bool {
+    matches!(url.host_str(), Some("localhost" | "127.0.0.1" | "[::1]"))
+}
+
+/// Whether `requested` is one of the client's registered redirect URIs. Matches are exact, except that native apps
+/// may pick any port on the loopback interface.
+pub fn redirect_uri_allowed(client: &entity::oauth_clients::Model, requested: &Url) -> bool {
+    let registered: Vec<String> = serde_json::from_value(client.redirect_uris.clone()).unwrap_or_default();
+    registered.iter().filter_map(|uri| Url::parse(uri).ok()).any(|registered| {
+        if registered.scheme() == "http" && is_loopback(&registered) {
+            let mut requested = requested.clone();
+            let _ = requested.set_port(registered.port());
+            requested == registered
+        } else {
+            *requested == registered
+        }
+    })
+}
+
+/// Finds the client making a request to the token or device authorization endpoint. Confidential clients must send
+/// their secret, either with HTTP Basic authentication or in the request body.
+pub async fn authenticate_client(
+    client_id: Option<String>,
+    client_secret: Option<String>,
+) -> Result<entity::oauth_clients::Model, OauthError> {
+    let invalid = || OauthError::new("invalid_client", "Unknown client or wrong client secret");
+
+    let (client_id, client_secret) = match crate::request::basic_credentials() {
+        Some((id, secret)) => (Some(id), Some(secret)),
+        None => (client_id, client_secret),
+    };
+    let client_id = client_id.and_then(|id| id.parse::<Uuid>().ok()).ok_or_else(invalid)?;
+    let client = OauthClients::find_by_id(client_id).one(&db()).await?.ok_or_else(invalid)?;
+
+    match (&client.secret_hash, client_secret) {
+        (Some(hash), Some(secret)) if *hash == secret::hash(&secret) => Ok(client),
+        (None, None) => Ok(client),
+        _ => Err(invalid()),
+    }
+}
+
+/// Checks a PKCE code verifier against the S256 challenge sent when authorization started.
+pub fn verify_pkce(challenge: &str, verifier: &str) -> bool {
+    (43..=128).contains(&verifier.len())
+        && URL_SAFE_NO_PAD.encode(digest(&SHA256, verifier.as_bytes())) == challenge
+}
+
+/// The scopes from a grant that the user still has, in case their access was reduced since they consented.
+pub async fn current_scopes(conn: &impl ConnectionTrait, user_id: Uuid, granted: &Json) -> Result<BTreeSet<Scope>, DbErr> {
+    let user_scopes = roles::scopes_for(conn, user_id).await?;
+    Ok(parse_scopes(granted).intersection(&user_scopes).copied().filter(|scope| scope.is_delegable()).collect())
+}
+
+/// Creates a single-use authorization code for the client to exchange at the token endpoint.
+pub async fn create_authorization_code(
+    conn: &impl ConnectionTrait,
+    client_id: Uuid,
+    user_id: Uuid,
+    redirect_uri: &Url,
+    scopes: &BTreeSet<Scope>,
+    code_challenge: String,
+) -> Result<String, DbErr> {
+    let (code, code_hash) = secret::generate(AUTHORIZATION_CODE_PREFIX);
+    let now = OffsetDateTime::now_utc();
+    entity::oauth_authorization_codes::ActiveModel {
+        id: Set(Uuid::new_v4()),
+        code_hash: Set(code_hash),
+        client_id: Set(client_id),
+        user_id: Set(user_id),
+        redirect_uri: Set(redirect_uri.to_string()),
+        scopes: Set(serde_json::to_value(scopes).expect("scopes serialise")),
+        code_challenge: Set(code_challenge),
+        created_at: Set(now),
+        expires_at: Set(now + AUTHORIZATION_CODE_LIFETIME),
+    }.insert(conn).await?;
+    Ok(code)
+}
+
+/// Creates a device code for a client that can't open a browser itself, returning it and the code the user types in.
+pub async fn create_device_code(
+    conn: &impl ConnectionTrait,
+    client_id: Uuid,
+    scopes: &BTreeSet<Scope>,
+) -> Result<(String, String), DbErr> {
+    let (device_code, device_code_hash) = secret::generate(DEVICE_CODE_PREFIX);
+    let user_code = generate_user_code();
+    let now = OffsetDateTime::now_utc();
+    entity::oauth_device_codes::ActiveModel {
+        id: Set(Uuid::new_v4()),
+        device_code_hash: Set(device_code_hash),
+        user_code: Set(user_code.clone()),
+        client_id: Set(client_id),
+        user_id: Set(None),
+        scopes: Set(serde_json::to_value(scopes).expect("scopes serialise")),
+        status: Set(device_status::PENDING.to_string()),
+        created_at: Set(now),
+        expires_at: Set(now + DEVICE_CODE_LIFETIME),
+        last_polled_at: Set(None),
+    }.insert(conn).await?;
+    Ok((device_code, user_code))
+}
+
+fn generate_user_code() -> String {
+    // Bytes at or above this would make some characters more likely than others
+    let limit = (256 / USER_CODE_ALPHABET.len() * USER_CODE_ALPHABET.len()) as u8;
+    let rng = SystemRandom::new();
+    let mut chars = String::with_capacity(USER_CODE_LENGTH);
+    while chars.len() < USER_CODE_LENGTH {
+        let mut byte = [0u8; 1];
+        rng.fill(&mut byte).expect("system random number generator failed");
+        if byte[0] < limit {
+            chars.push(USER_CODE_ALPHABET[byte[0] as usize % USER_CODE_ALPHABET.len()] as char);
+        }
+    }
+    format!("{}-{}", &chars[..4], &chars[4..])
+}
+
+/// Puts a user code typed by a person into the stored form, e.g. "bcdf ghjk" becomes "BCDF-GHJK".
+pub fn normalize_user_code(code: &str) -> String {
+    let chars: String = code.chars().filter(char::is_ascii_alphabetic).map(|c| c.to_ascii_uppercase()).collect();
+    if chars.len() == USER_CODE_LENGTH {
+        format!("{}-{}", &chars[..4], &chars[4..])
+    } else {
+        chars
+    }
+}
+
+/// Issues an access token and a new refresh token to `client_id`, acting as `user_id` with `scopes`.
+pub async fn issue_tokens(
+    conn: &impl ConnectionTrait,
+    client_id: Uuid,
+    user_id: Uuid,
+    scopes: BTreeSet<Scope>,
+) -> Result<IssuedTokens, OauthError> {
+    let (refresh_token, token_hash) = secret::generate(REFRESH_TOKEN_PREFIX);
+    let now = OffsetDateTime::now_utc();
+    entity::oauth_refresh_tokens::ActiveModel {
+        id: Set(Uuid::new_v4()),
+        token_hash: Set(token_hash),
+        client_id: Set(client_id),
+        user_id: Set(user_id),
+        scopes: Set(serde_json::to_value(&scopes).expect("scopes serialise")),
+        created_at: Set(now),
+        expires_at: Set(now + REFRESH_TOKEN_LIFETIME),
+        revoked_at: Set(None),
+        used_at: Set(None),
+    }.insert(conn).await?;
+
+    let mut claims = Claims::new(user_id, scopes.iter().copied(), [Service::StarHavenPlatform], ACCESS_TOKEN_LIFETIME_SECONDS);
+    claims.client_id = Some(client_id);
+    Ok(IssuedTokens {
+        access_token: claims.encode()?,
+        token_type: "Bearer".to_string(),
+        expires_in: ACCESS_TOKEN_LIFETIME_SECONDS,
+        refresh_token,
+        scope: scope_param(&scopes),
+    })
+}
+
+/// Whether `user_id` still lets `client_id` act for them, i.e. the app has a refresh token that hasn't been revoked or
+/// expired. The platform checks this on every request with an OAuth access token, so revoking an app takes effect
+/// immediately rather than when its access tokens expire.
+pub async fn is_authorized(conn: &impl ConnectionTrait, user_id: Uuid, client_id: Uuid) -> Result<bool, DbErr> {
+    use entity::oauth_refresh_tokens::Column;
+    use sea_orm::PaginatorTrait;
+
+    let live = OauthRefreshTokens::find()
+        .filter(Column::UserId.eq(user_id))
+        .filter(Column::ClientId.eq(client_id))
+        .filter(Column::RevokedAt.is_null())
+        .filter(Column::ExpiresAt.gt(OffsetDateTime::now_utc()))
+        .count(conn)
+        .await?;
+    Ok(live > 0)
+}
+
+/// Revokes everything that lets `client_id` get tokens for `user_id`: its refresh tokens, and any authorization codes or
+/// approved device codes it hasn't exchanged yet. Returns how many were revoked.
+pub async fn revoke_grant(conn: &impl ConnectionTrait, user_id: Uuid, client_id: Uuid) -> Result<u64, DbErr> {
+    use entity::{oauth_authorization_codes, oauth_device_codes, oauth_refresh_tokens};
+
+    let refresh_tokens = OauthRefreshTokens::update_many()
+        .col_expr(oauth_refresh_tokens::Column::RevokedAt, Expr::value(OffsetDateTime::now_utc()))
+        .filter(oauth_refresh_tokens::Column::UserId.eq(user_id))
+        .filter(oauth_refresh_tokens::Column::ClientId.eq(client_id))
+        .filter(oauth_refresh_tokens::Column::RevokedAt.is_null())
+        .exec(conn)
+        .await?;
+    let authorization_codes = OauthAuthorizationCodes::delete_many()
+        .filter(oauth_authorization_codes::Column::UserId.eq(user_id))
+        .filter(oauth_authorization_codes::Column::ClientId.eq(client_id))
+        .exec(conn)
+        .await?;
+    let device_codes = OauthDeviceCodes::delete_many()
+        .filter(oauth_device_codes::Column::UserId.eq(user_id))
+        .filter(oauth_device_codes::Column::ClientId.eq(client_id))
+        .filter(oauth_device_codes::Column::Status.eq(device_status::APPROVED))
+        .exec(conn)
+        .await?;
+    Ok(refresh_tokens.rows_affected + authorization_codes.rows_affected + device_codes.rows_affected)
+}
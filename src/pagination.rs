@@ -0,0 +1,31 @@
+//! Paging through lists that are newest first. Items are ordered by when they happened, then by ID, so that a page
+//! that ends partway through items that happened at the same time doesn't skip the rest of them.
+
+use time::OffsetDateTime;
+
+use crate::prelude::*;
+
+/// Where a page ends: the last item on it. The next page has the items after it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Cursor {
+    pub created_at: OffsetDateTime,
+    pub id: Uuid,
+}
+
+impl Cursor {
+    /// Whether an item comes after the cursor, i.e. on a later page.
+    pub fn is_before(&self, created_at: OffsetDateTime, id: Uuid) -> bool {
+        (created_at, id) < (self.created_at, self.id)
+    }
+
+    /// [`Cursor::is_before`] for the rows of a table, as SQL.
+    #[cfg(feature = "ssr")]
+    pub fn is_before_expr(
+        &self,
+        created_at: impl sea_orm::sea_query::IntoColumnRef,
+        id: impl sea_orm::sea_query::IntoColumnRef,
+    ) -> sea_orm::sea_query::SimpleExpr {
+        Expr::tuple([Expr::col(created_at).into(), Expr::col(id).into()])
+            .lt(Expr::tuple([Expr::value(self.created_at), Expr::value(self.id)]))
+    }
+}
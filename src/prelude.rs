@@ -28,6 +28,10 @@ cfg_if! {
         pub fn db() -> sea_orm::DatabaseConnection {
             expect_context()
         }
+
+        pub fn config() -> std::sync::Arc<crate::config::Config> {
+            expect_context()
+        }
     }
 }
 
@@ -0,0 +1,307 @@
+//! Public user profiles at `/user/:username`, with what the user wrote about themselves and the mods they're an author
+//! of. Unpublished mods only appear to their own authors, the same as on `/mod/:slug`.
+
+use leptos::Params;
+use leptos_router::{NavigateOptions, hooks::{use_navigate, use_params}, params::Params};
+use time::OffsetDateTime;
+
+use crate::prelude::*;
+use crate::browse::LocaleDate;
+
+pub mod avatar;
+
+use avatar::{Avatar, AvatarRef};
+
+/// Most characters a bio may have
+pub const MAX_BIO_LENGTH: usize = 500;
+
+/// Most links a profile may have
+pub const MAX_LINKS: usize = 5;
+
+/// Most characters a link may have
+pub const MAX_LINK_LENGTH: usize = 200;
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ProfileError {
+    #[error("Bio is too long, must be at most {MAX_BIO_LENGTH} characters")]
+    BioTooLong,
+    #[error("Too many links, you can have at most {MAX_LINKS}")]
+    TooManyLinks,
+    #[error("Not a valid http:// or https:// link: {0}")]
+    InvalidLink(String),
+}
+
+/// The form a bio is stored in: trimmed, with Windows line endings replaced. Empty means no bio.
+pub fn normalize_bio(bio: &str) -> String {
+    bio.trim().replace("\r\n", "\n")
+}
+
+/// Splits links entered one per line, dropping blank lines.
+pub fn parse_links(links: &str) -> Vec<String> {
+    links.lines().map(str::trim).filter(|link| !link.is_empty()).map(str::to_string).collect()
+}
+
+/// Checks a [normalized](normalize_bio) bio and [parsed](parse_links) links.
+pub fn check_validity(bio: &str, links: &[String]) -> Result<(), ProfileError> {
+    if bio.chars().count() > MAX_BIO_LENGTH {
+        return Err(ProfileError::BioTooLong);
+    }
+    if links.len() > MAX_LINKS {
+        return Err(ProfileError::TooManyLinks);
+    }
+    for link in links {
+        let host = link.strip_prefix("https://").or_else(|| link.strip_prefix("http://")).unwrap_or_default();
+        if host.is_empty()
+            || host.starts_with('/')
+            || link.len() > MAX_LINK_LENGTH
+            || link.chars().any(|c| c.is_whitespace() || c.is_control())
+        {
+            return Err(ProfileError::InvalidLink(link.clone()));
+        }
+    }
+    Ok(())
+}
+
+/// Someone to link to, by the name the site shows for them.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UserLink {
+    pub username: String,
+    pub name: String,
+    pub avatar: AvatarRef,
+}
+
+impl From<&User> for UserLink {
+    fn from(user: &User) -> Self {
+        Self {
+            username: user.username.clone(),
+            name: crate::auth::display_name::shown(user).to_string(),
+            avatar: AvatarRef::from(user),
+        }
+    }
+}
+
+/// A mod on a profile.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProfileMod {
+    #[serde(rename = "mod")]
+    pub mod_data: Mod,
+    /// The mod's other authors
+    pub co_authors: Vec<UserLink>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UserProfile {
+    pub user: UserLink,
+    pub bio: Option<String>,
+    pub links: Vec<String>,
+    pub joined_at: OffsetDateTime,
+    pub mods: Vec<ProfileMod>,
+    pub followers: u64,
+    /// Whether the signed-in user follows them, or `None` if nobody is signed in or it's their own profile
+    pub following: Option<bool>,
+}
+
+/// The authors of each of `mod_ids`.
+#[cfg(feature = "ssr")]
+pub async fn authors_of(
+    conn: &impl ConnectionTrait,
+    mod_ids: &[Uuid],
+) -> Result<std::collections::HashMap<Uuid, Vec<UserLink>>, DbErr> {
+    let mut authors = std::collections::HashMap::<Uuid, Vec<UserLink>>::new();
+    let rows = ModAuthors::find()
+        .filter(entity::mod_authors::Column::ModId.is_in(mod_ids.iter().copied()))
+        .find_also_related(Users)
+        .order_by_asc(entity::users::Column::CreatedAt)
+        .all(conn)
+        .await?;
+    for (author, user) in rows {
+        if let Some(user) = user {
+            authors.entry(author.mod_id).or_default().push(UserLink::from(&user));
+        }
+    }
+    Ok(authors)
+}
+
+#[server]
+async fn get_user_profile(username: String) -> Result<UserProfile, ServerFnError> {
+    use sea_orm::{PaginatorTrait, QueryTrait};
+
+    let Some(user) = crate::auth::username::resolve(&db(), &username).await? else {
+        let response = expect_context::<leptos_axum::ResponseOptions>();
+        response.set_status(http::status::StatusCode::NOT_FOUND);
+        return Err(ServerFnError::ServerError("User not found".to_string()));
+    };
+
+    // Viewers can see unpublished mods if they are authors of them
+    let viewer_id = session().await.uuid();
+    let mut visible = sea_orm::Condition::any().add(entity::mods::Column::PublishedAt.is_not_null());
+    if let Some(viewer_id) = viewer_id {
+        let viewer_mods = ModAuthors::find()
+            .select_only()
+            .column(entity::mod_authors::Column::ModId)
+            .filter(entity::mod_authors::Column::UserId.eq(viewer_id))
+            .into_query();
+        visible = visible.add(entity::mods::Column::Id.in_subquery(viewer_mods));
+    }
+
+    let mods = Mods::find()
+        .join(JoinType::InnerJoin, entity::mods::Relation::ModAuthors.def())
+        .filter(entity::mod_authors::Column::UserId.eq(user.id))
+        .filter(entity::mods::Column::ArchivedAt.is_null())
+        .filter(visible)
+        .order_by_with_nulls(entity::mods::Column::PublishedAt, Order::Desc, NullOrdering::First)
+        .all(&db())
+        .await?;
+
+    let mod_ids = mods.iter().map(|mod_data| mod_data.id).collect::<Vec<_>>();
+    let mut authors = authors_of(&db(), &mod_ids).await?;
+    let mods = mods
+        .into_iter()
+        .map(|mod_data| {
+            let co_authors = authors
+                .remove(&mod_data.id)
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|author| author.username != user.username)
+                .collect();
+            ProfileMod { mod_data, co_authors }
+        })
+        .collect();
+
+    let followers = UserFollows::find()
+        .filter(entity::user_follows::Column::FollowedId.eq(user.id))
+        .count(&db())
+        .await?;
+    let following = match viewer_id {
+        Some(viewer_id) if viewer_id != user.id => {
+            Some(UserFollows::find_by_id((viewer_id, user.id)).one(&db()).await?.is_some())
+        }
+        _ => None,
+    };
+
+    Ok(UserProfile {
+        followers,
+        following,
+        user: UserLink::from(&user),
+        bio: user.bio,
+        links: serde_json::from_value(user.links).unwrap_or_default(),
+        joined_at: user.created_at.assume_utc(),
+        mods,
+    })
+}
+
+/// Links to users' profiles, with their avatars, separated by commas.
+#[component]
+pub fn UserLinks(users: Vec<UserLink>) -> impl IntoView {
+    let count = users.len();
+    users
+        .into_iter()
+        .enumerate()
+        .map(|(index, user)| view! {
+            <a href=format!("/user/{}", user.username) class="inline-flex items-center gap-1 align-middle text-yellow-400 hover:underline">
+                <Avatar avatar=user.avatar size=20 />
+                {user.name}
+            </a>
+            {(index + 1 < count).then_some(", ")}
+        })
+        .collect_view()
+}
+
+#[derive(Params, PartialEq)]
+struct UserProfileParams {
+    username: Option<String>,
+}
+
+#[component]
+pub fn UserProfilePage() -> impl IntoView {
+    let params = use_params::<UserProfileParams>();
+    let username = Signal::derive(move || {
+        params.read().as_ref().ok().and_then(|params| params.username.clone()).unwrap_or_default()
+    });
+
+    let profile = Resource::new_blocking(move || username.get(), get_user_profile);
+
+    // Old usernames lead to the user's current one
+    let navigate = use_navigate();
+    Effect::new(move || {
+        if let Some(Ok(profile)) = profile.get() {
+            if profile.user.username != username.get_untracked() {
+                navigate(&format!("/user/{}", profile.user.username), NavigateOptions { replace: true, ..Default::default() });
+            }
+        }
+    });
+
+    view! {
+        <Shell>
+            <Suspense fallback=|| {}>
+                {move || match profile.get() {
+                    Some(Ok(UserProfile { user, bio, links, joined_at, mods, followers, following })) => view! {
+                        <div class="w-full max-w-screen-lg mx-auto my-16">
+                            <section class="flex gap-6 items-start mb-8">
+                                <Avatar avatar=user.avatar size=96 />
+                                <div class="flex flex-col gap-2 min-w-0 grow">
+                                    <div class="flex items-center gap-4">
+                                        <h1 class="text-3xl text-white font-semibold">{user.name}</h1>
+                                        <span class="ml-auto">
+                                            <crate::feed::FollowButton username=user.username.clone() following />
+                                        </span>
+                                    </div>
+                                    <p class="text-stone-400">
+                                        {format!("@{}", user.username)} " · Joined " <LocaleDate date=Signal::derive(move || joined_at) />
+                                        {format!(" · {followers} {}", if followers == 1 { "follower" } else { "followers" })}
+                                    </p>
+                                    {bio.map(|bio| view! {
+                                        <p class="whitespace-pre-wrap text-stone-200 break-words">{bio}</p>
+                                    })}
+                                    {(!links.is_empty()).then(|| view! {
+                                        <ul class="flex flex-wrap gap-x-4 gap-y-1">
+                                            {links.into_iter().map(|link| {
+                                                let text = link.trim_start_matches("https://").trim_start_matches("http://").trim_end_matches('/').to_string();
+                                                view! {
+                                                    <li>
+                                                        <a href=link rel="nofollow ugc noopener noreferrer" target="_blank" class="text-yellow-400 hover:underline break-all">{text}</a>
+                                                    </li>
+                                                }
+                                            }).collect_view()}
+                                        </ul>
+                                    })}
+                                </div>
+                            </section>
+
+                            <h2 class="text-lg font-semibold mb-2">"Mods"</h2>
+                            {if mods.is_empty() {
+                                view! { <p class="text-stone-400">"No mods yet."</p> }.into_any()
+                            } else {
+                                view! {
+                                    <ul class="grid grid-cols-4 gap-4 my-4">
+                                        {mods.into_iter().map(|ProfileMod { mod_data, co_authors }| view! {
+                                            <li class="flex flex-col gap-1">
+                                                <a href=format!("/mod/{}", mod_data.slug) class="flex aspect-video bg-stone-800" aria-hidden="true" tabindex="-1" />
+                                                <a href=format!("/mod/{}", mod_data.slug) class="text-stone-200 font-semibold hover:underline">
+                                                    {mod_data.name}
+                                                </a>
+                                                {mod_data.published_at.is_none().then(|| view! {
+                                                    <span class="text-stone-400 text-xs">"Not published, only its authors can see it"</span>
+                                                })}
+                                                {(!co_authors.is_empty()).then(|| view! {
+                                                    <span class="text-stone-400 text-xs">"With " <UserLinks users=co_authors /></span>
+                                                })}
+                                            </li>
+                                        }).collect_view()}
+                                    </ul>
+                                }.into_any()
+                            }}
+                        </div>
+                    }.into_any(),
+                    Some(Err(ServerFnError::ServerError(s))) if s == "User not found" => view! {
+                        <div class="w-full max-w-screen-md mx-auto my-16 text-center">
+                            <h1 class="text-xl font-semibold mb-4">"We couldn't find this user"</h1>
+                            <a href="/browse" class="text-yellow-400 underline">Go back to browsing</a>
+                        </div>
+                    }.into_any(),
+                    _ => ().into_any(),
+                }}
+            </Suspense>
+        </Shell>
+    }
+}
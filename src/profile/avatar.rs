@@ -0,0 +1,151 @@
+//! Avatars. Uploads are decoded like mod media, cropped to a square from the centre, and saved as WebP in each of
+//! [`SIZES`] under a new `users.avatar_id`, so their URLs never need revalidating. Users without one are shown an
+//! identicon drawn from their ID.
+
+use crate::prelude::*;
+
+/// Widths, in pixels, that each avatar is saved in
+pub const SIZES: [u32; 3] = [64, 192, 512];
+
+/// Enough to show someone's avatar.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct AvatarRef {
+    pub user_id: Uuid,
+    pub avatar_id: Option<Uuid>,
+}
+
+impl From<&User> for AvatarRef {
+    fn from(user: &User) -> Self {
+        Self { user_id: user.id, avatar_id: user.avatar_id }
+    }
+}
+
+/// The URL of an uploaded avatar in one of [`SIZES`].
+pub fn url(avatar_id: Uuid, size: u32) -> String {
+    format!("/assets/avatars/{avatar_id}.{size}.webp")
+}
+
+/// Returns the file path and URL of an uploaded avatar in one of [`SIZES`].
+#[cfg(feature = "ssr")]
+pub fn paths(avatar_id: Uuid, size: u32) -> (std::path::PathBuf, String) {
+    let mut path = crate::static_assets_dir();
+    path.push("avatars");
+    let _ = std::fs::create_dir_all(&path);
+    path.push(format!("{avatar_id}.{size}.webp"));
+
+    (path, url(avatar_id, size))
+}
+
+/// Deletes an uploaded avatar's files.
+#[cfg(feature = "ssr")]
+pub fn remove_files(avatar_id: Uuid) {
+    for size in SIZES {
+        if let Err(error) = std::fs::remove_file(paths(avatar_id, size).0) {
+            log::error!("error deleting avatar {avatar_id}: {error:?}");
+        }
+    }
+}
+
+/// Sets `user`'s avatar to `image`, or back to their identicon if it's `None`. `actor_id` is who made the change, for
+/// the audit log, with `reason` if it wasn't the user.
+#[cfg(feature = "ssr")]
+pub async fn set(
+    conn: &impl ConnectionTrait,
+    user: User,
+    image: Option<&image::DynamicImage>,
+    actor_id: Uuid,
+    reason: Option<String>,
+) -> Result<User, ServerFnError> {
+    use sea_orm::Set;
+    use crate::auth::audit::{AuditEvent, Entry};
+
+    if image.is_none() && user.avatar_id.is_none() {
+        return Ok(user);
+    }
+
+    let avatar_id = match image {
+        Some(image) => {
+            let avatar_id = Uuid::new_v4();
+            for size in SIZES {
+                let saved = image.resize_to_fill(size, size, image::imageops::FilterType::Lanczos3).save(paths(avatar_id, size).0);
+                if let Err(error) = saved {
+                    remove_files(avatar_id);
+                    return Err(error.into());
+                }
+            }
+            Some(avatar_id)
+        }
+        None => None,
+    };
+
+    let old_avatar_id = user.avatar_id;
+    let mut record: entity::users::ActiveModel = user.into();
+    record.avatar_id = Set(avatar_id);
+    let user = match record.update(conn).await {
+        Ok(user) => user,
+        Err(error) => {
+            if let Some(avatar_id) = avatar_id {
+                remove_files(avatar_id);
+            }
+            return Err(error.into());
+        }
+    };
+    if let Some(old_avatar_id) = old_avatar_id {
+        remove_files(old_avatar_id);
+    }
+
+    let mut details = serde_json::json!({ "removed": avatar_id.is_none() });
+    if let Some(reason) = reason {
+        details["reason"] = reason.into();
+    }
+    Entry::new(AuditEvent::AvatarChanged, Some(actor_id))
+        .user(user.id)
+        .details(details)
+        .record(conn)
+        .await?;
+    Ok(user)
+}
+
+/// A user's avatar, `size` pixels across.
+#[component]
+pub fn Avatar(avatar: AvatarRef, size: u32) -> impl IntoView {
+    match avatar.avatar_id {
+        Some(avatar_id) => {
+            // Sharp on high density screens
+            let file_size = SIZES.into_iter().find(|file_size| *file_size >= size * 2).unwrap_or(SIZES[SIZES.len() - 1]);
+            view! {
+                <img src=url(avatar_id, file_size) alt="" width=size height=size class="rounded-full shrink-0 bg-stone-700" />
+            }.into_any()
+        }
+        None => view! { <Identicon user_id=avatar.user_id size=size /> }.into_any(),
+    }
+}
+
+/// A mirrored 5×5 pattern in a colour, both picked by the bits of `user_id`.
+#[component]
+fn Identicon(user_id: Uuid, size: u32) -> impl IntoView {
+    let bytes = user_id.as_bytes();
+    let hue = u16::from_be_bytes([bytes[0], bytes[1]]) % 360;
+    let cells = (0..3u8)
+        .flat_map(|column| (0..5u8).map(move |row| (column, row)))
+        .filter(|(column, row)| {
+            let bit = usize::from(column * 5 + row);
+            bytes[2 + bit / 8] & (1 << (bit % 8)) != 0
+        })
+        .flat_map(|(column, row)| if column == 2 { vec![(column, row)] } else { vec![(column, row), (4 - column, row)] })
+        .map(|(column, row)| view! { <rect x=column y=row width="1" height="1" /> })
+        .collect_view();
+
+    view! {
+        <svg
+            viewBox="-1 -1 7 7"
+            width=size
+            height=size
+            aria-hidden="true"
+            fill=format!("hsl({hue} 65% 55%)")
+            class="rounded-full shrink-0 bg-stone-200"
+        >
+            {cells}
+        </svg>
+    }
+}
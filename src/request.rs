@@ -0,0 +1,111 @@
+//! Information about the client making the current request.
+
+use std::net::{IpAddr, SocketAddr};
+
+use axum::extract::ConnectInfo;
+use axum::http::{header::{AUTHORIZATION, USER_AGENT}, request::Parts};
+
+use crate::prelude::*;
+
+/// The IP address of the client, if known.
+pub fn client_ip() -> Option<IpAddr> {
+    let request = use_context::<Parts>()?;
+
+    if config().trust_forwarded_for {
+        // The proxy appends the address it saw, so the last entry is the one we can trust
+        let forwarded = request
+            .headers
+            .get("x-forwarded-for")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.rsplit(',').next())
+            .and_then(|ip| ip.trim().parse().ok());
+        if forwarded.is_some() {
+            return forwarded;
+        }
+    }
+
+    request
+        .extensions
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip())
+}
+
+/// The `User-Agent` header sent by the client, if any.
+pub fn user_agent() -> Option<String> {
+    let request = use_context::<Parts>()?;
+    request
+        .headers
+        .get(USER_AGENT)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_owned)
+}
+
+/// The admin behind the request, if they are viewing the site as someone else.
+pub fn impersonator() -> Option<crate::auth::token::Actor> {
+    let request = use_context::<Parts>()?;
+    request.extensions.get().cloned()
+}
+
+/// The token in an `Authorization: Bearer <token>` header, if any.
+pub fn bearer_token() -> Option<String> {
+    let request = use_context::<Parts>()?;
+    bearer_token_in(&request.headers)
+}
+
+/// Like [`bearer_token`], for code that runs outside of Leptos, such as middleware.
+pub fn bearer_token_in(headers: &axum::http::HeaderMap) -> Option<String> {
+    headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(|token| token.trim().to_owned())
+}
+
+/// The credentials in an `Authorization: Basic <base64(username:password)>` header, if any.
+pub fn basic_credentials() -> Option<(String, String)> {
+    use base64::{Engine, engine::general_purpose::STANDARD};
+
+    let request = use_context::<Parts>()?;
+    let encoded = request
+        .headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Basic "))?;
+    let decoded = String::from_utf8(STANDARD.decode(encoded.trim()).ok()?).ok()?;
+    let (username, password) = decoded.split_once(':')?;
+    Some((username.to_owned(), password.to_owned()))
+}
+
+/// Summarises a user agent string as e.g. "Firefox on Linux".
+pub fn describe_user_agent(user_agent: &str) -> String {
+    // Order matters: Edge and Opera also claim to be Chrome, and Chrome also claims to be Safari
+    let browser = [
+        ("Edg/", "Edge"),
+        ("OPR/", "Opera"),
+        ("Firefox/", "Firefox"),
+        ("Chrome/", "Chrome"),
+        ("Safari/", "Safari"),
+    ]
+    .into_iter()
+    .find(|(token, _)| user_agent.contains(token))
+    .map(|(_, name)| name);
+
+    let os = [
+        ("Android", "Android"),
+        ("iPhone", "iOS"),
+        ("iPad", "iPadOS"),
+        ("Windows", "Windows"),
+        ("Mac OS X", "macOS"),
+        ("CrOS", "ChromeOS"),
+        ("Linux", "Linux"),
+    ]
+    .into_iter()
+    .find(|(token, _)| user_agent.contains(token))
+    .map(|(_, name)| name);
+
+    match (browser, os) {
+        (Some(browser), Some(os)) => format!("{browser} on {os}"),
+        (Some(name), None) | (None, Some(name)) => name.to_string(),
+        (None, None) => "Unknown device".to_string(),
+    }
+}
@@ -11,6 +11,8 @@ pub fn Shell(children: Children) -> impl IntoView {
             <nav::Nav />
             <div class="flex flex-col dark -ml-12">
                 <main>
+                    <crate::admin::ImpersonationBanner />
+                    <crate::account::TrustedPasskeyBanner />
                     {children()}
                 </main>
                 <div class="grow" />
@@ -2,6 +2,8 @@ use crate::prelude::*;
 
 use phosphor_leptos::{Icon, IconWeight, SIGN_IN, SIGN_OUT};
 
+use crate::profile::avatar::{Avatar, AvatarRef};
+
 #[component]
 pub fn Nav() -> impl IntoView {
     view! {
@@ -11,6 +13,7 @@ pub fn Nav() -> impl IntoView {
                 <NavItem href="/">"Star Haven"</NavItem>
                 <NavItem href="/browse">"browse"</NavItem>
                 <NavItem href="/create">"create"</NavItem>
+                <NavItem href="/feed">"feed"</NavItem>
                 <NavItem href="https://discord.com/invite/star-haven">"community"</NavItem>
                 <NavItem href="/about">"about"</NavItem>
                 <li class="mt-auto">
@@ -36,12 +39,13 @@ pub fn NavItem(href: &'static str, children: Children) -> impl IntoView {
 
 #[server]
 async fn get_session_user() -> Result<Option<User>, ServerFnError> {
-    Ok(session().user().await?)
+    Ok(session().await.user().await?)
 }
 
 #[component]
 fn SessionStatusBar() -> impl IntoView {
     let user = OnceResource::new_blocking(get_session_user());
+    let sign_in_href = crate::auth::return_to::use_sign_in_href();
     view! {
         <Suspense fallback=|| {}>
             {move || Suspend::new(async move {
@@ -49,8 +53,10 @@ fn SessionStatusBar() -> impl IntoView {
                     {move || match user.get().and_then(|r| r.ok()).flatten() {
                         Some(user) => view! {
                             <div class="border border-stone-200 rounded-full p-2 flex items-center bg-white">
-                                <div class="rounded-full bg-yellow-500 w-8 h-8 mr-2" />
-                                <p class="text-sm text-stone-800">{user.username}</p>
+                                <span class="mr-2 flex">
+                                    <Avatar avatar=AvatarRef::from(&user) size=32 />
+                                </span>
+                                <a href="/account/security" class="text-sm text-stone-800 hover:underline">{crate::auth::display_name::shown(&user).to_string()}</a>
                                 <button
                                     title="Log out"
                                     class="ml-auto mr-1 text-stone-500 hover:text-stone-700"
@@ -71,7 +77,7 @@ fn SessionStatusBar() -> impl IntoView {
                             </div>
                         }.into_any(),
                         None => view! {
-                            <a href="/auth" class="flex items-center gap-2 text-stone-500 hover:text-stone-700">
+                            <a href=sign_in_href class="flex items-center gap-2 text-stone-500 hover:text-stone-700">
                                 <Icon icon=SIGN_IN weight=IconWeight::Bold />
                                 "sign in"
                             </a>
@@ -0,0 +1,427 @@
+//! The passkey ceremonies in `auth::passkey`, driven end to end with a software authenticator against a real database.
+//! They are ignored by default; run them with `cargo test --features ssr --test passkeys -- --ignored` and
+//! `DATABASE_URL` set, e.g. to the development database.
+#![cfg(feature = "ssr")]
+
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::Arc;
+
+use http::header::{COOKIE, SET_COOKIE, USER_AGENT};
+use leptos::prelude::*;
+use leptos::reactive::computed::ScopedFuture;
+use leptos_axum::ResponseOptions;
+use migration::{Migrator, MigratorTrait};
+use sea_orm::{ColumnTrait, Database, DatabaseConnection, EntityTrait, PaginatorTrait, QueryFilter};
+use tokio::sync::OnceCell;
+use uuid::Uuid;
+use webauthn_authenticator_rs::{
+    WebauthnAuthenticator,
+    softpasskey::SoftPasskey,
+    softtoken::{AAGUID, SoftToken},
+};
+use webauthn_rs::prelude::{AttestationCaListBuilder, Url, Webauthn};
+use webauthn_rs_proto::{AllowCredentials, PublicKeyCredential, RequestChallengeResponse};
+
+use star_haven_platform::auth::{
+    Scope,
+    attestation,
+    challenge::ChallengeStore,
+    is_logged_in,
+    keys::Keyring,
+    passkey::{
+        ATTESTATION_REQUIRED, build_webauthn, finish_discoverable_login, finish_login, finish_register, start_discoverable_login, start_login,
+        start_register,
+    },
+    roles,
+    session::session,
+    throttle::Throttle,
+};
+use star_haven_platform::config::{AttestationPolicy, Config};
+
+static MIGRATED: OnceCell<()> = OnceCell::const_new();
+
+/// What `main` provides to every request.
+struct Server {
+    db: DatabaseConnection,
+    config: Arc<Config>,
+    webauthn: Arc<Webauthn>,
+    keyring: Arc<Keyring>,
+    challenges: Arc<ChallengeStore>,
+    throttle: Arc<Throttle>,
+    /// Users created by the test, deleted when it finishes
+    usernames: Vec<String>,
+}
+
+impl Server {
+    async fn start() -> Self {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL to be set");
+        let db = Database::connect(url).await.expect("to be able to connect to database");
+        MIGRATED.get_or_init(|| async { Migrator::up(&db, None).await.expect("to migrate the database") }).await;
+
+        let config = Config::from_env().expect("valid configuration");
+        Server {
+            webauthn: Arc::new(build_webauthn(&config.webauthn).expect("valid webauthn configuration")),
+            keyring: Arc::new(Keyring::ephemeral().expect("to generate a signing key")),
+            challenges: Arc::new(ChallengeStore::from_config(&config, db.clone())),
+            throttle: Arc::new(Throttle::default()),
+            config: Arc::new(config),
+            usernames: Vec::new(),
+            db,
+        }
+    }
+
+    /// Requires users with `scopes` to register passkeys from the authenticator model that `ca` attests.
+    fn require_attestation(&mut self, scopes: Vec<Scope>, ca_der: &[u8], model: &str) {
+        let mut builder = AttestationCaListBuilder::new();
+        builder.insert_device_der(ca_der, AAGUID, model.to_string(), BTreeMap::new()).expect("valid certificate");
+        let mut config = (*self.config).clone();
+        config.passkey_attestation = AttestationPolicy { scopes, authenticators: builder.build() };
+        self.config = Arc::new(config);
+    }
+
+    /// A username nobody has, which is cleaned up by [`Server::stop`].
+    fn username(&mut self) -> String {
+        let username = format!("pk{}", &Uuid::new_v4().simple().to_string()[..12]);
+        self.usernames.push(username.clone());
+        username
+    }
+
+    fn origin(&self) -> Url {
+        self.config.webauthn.origins[0].clone()
+    }
+
+    async fn passkey_count(&self, username: &str) -> u64 {
+        let user = self.user(username).await.expect("user to exist");
+        entity::passkeys::Entity::find()
+            .filter(entity::passkeys::Column::UserId.eq(user.id))
+            .count(&self.db)
+            .await
+            .expect("to count passkeys")
+    }
+
+    async fn user(&self, username: &str) -> Option<entity::users::Model> {
+        entity::users::Entity::find()
+            .filter(entity::users::Column::Username.eq(username))
+            .one(&self.db)
+            .await
+            .expect("to look up user")
+    }
+
+    async fn stop(self) {
+        entity::users::Entity::delete_many()
+            .filter(entity::users::Column::Username.is_in(self.usernames))
+            .exec(&self.db)
+            .await
+            .expect("to delete test users");
+    }
+}
+
+/// A browser's cookie jar, for calling server functions as it would.
+struct Browser<'a> {
+    server: &'a Server,
+    cookies: BTreeMap<String, String>,
+}
+
+impl<'a> Browser<'a> {
+    fn new(server: &'a Server) -> Self {
+        Browser { server, cookies: BTreeMap::new() }
+    }
+
+    /// Runs a server function in a request with this browser's cookies, keeping any cookies it sets.
+    async fn call<T>(&mut self, server_fn: impl Future<Output = T>) -> T {
+        let cookies = self.cookies.iter().map(|(name, value)| format!("{name}={value}")).collect::<Vec<_>>().join("; ");
+        let (parts, ()) = http::Request::builder()
+            .header(COOKIE, cookies)
+            .header(USER_AGENT, "Mozilla/5.0 (X11; Linux x86_64) Firefox/140.0")
+            .body(())
+            .expect("valid request")
+            .into_parts();
+        let response = ResponseOptions::default();
+
+        let owner = Owner::new();
+        let output = owner.with(|| {
+            provide_context(self.server.db.clone());
+            provide_context(self.server.config.clone());
+            provide_context(self.server.challenges.clone());
+            provide_context(self.server.webauthn.clone());
+            provide_context(self.server.keyring.clone());
+            provide_context(self.server.throttle.clone());
+            provide_context(parts);
+            provide_context(response.clone());
+            ScopedFuture::new(server_fn)
+        }).await;
+
+        for header in response.0.read().headers.get_all(SET_COOKIE) {
+            let header = header.to_str().expect("ASCII cookie");
+            let (name, rest) = header.split_once('=').expect("cookie to have a value");
+            let value = rest.split(';').next().unwrap_or_default();
+            if value.is_empty() || header.contains("Max-Age=0") {
+                self.cookies.remove(name);
+            } else {
+                self.cookies.insert(name.to_string(), value.to_string());
+            }
+        }
+        output
+    }
+
+    async fn signed_in_as(&mut self) -> Option<Uuid> {
+        self.call(async { session().await.uuid() }).await
+    }
+}
+
+/// A passkey held by a software authenticator.
+struct Passkey {
+    authenticator: WebauthnAuthenticator<SoftPasskey>,
+    credential_id: Vec<u8>,
+    /// The user ID the passkey was registered with, which a discoverable passkey reports when signing in
+    user_handle: Vec<u8>,
+}
+
+impl Passkey {
+    /// Registers a new passkey to `username`, creating the user if the browser isn't signed in.
+    async fn register(browser: &mut Browser<'_>, username: &str) -> Result<(Self, Option<Vec<String>>), ServerFnError> {
+        let (challenge, id) = browser.call(start_register(username.to_string())).await?;
+        let user_handle = challenge.public_key.user.id.to_vec();
+        // Passkeys need user verification, which the software authenticator can only pretend to do
+        let mut authenticator = WebauthnAuthenticator::new(SoftPasskey::new(true));
+        let credential = authenticator
+            .do_registration(browser.server.origin(), challenge)
+            .expect("authenticator to create a passkey");
+        let credential_id = credential.raw_id.to_vec();
+        let recovery_codes = browser.call(finish_register(id, credential)).await?;
+        Ok((Passkey { authenticator, credential_id, user_handle }, recovery_codes))
+    }
+
+    fn sign(&mut self, origin: Url, challenge: RequestChallengeResponse) -> PublicKeyCredential {
+        self.authenticator.do_authentication(origin, challenge).expect("authenticator to sign the challenge")
+    }
+
+    /// Answers a discoverable challenge the way an authenticator that stores the passkey would: by picking it itself and
+    /// reporting whose it is.
+    fn sign_discoverable(&mut self, origin: Url, mut challenge: RequestChallengeResponse) -> PublicKeyCredential {
+        assert!(challenge.public_key.allow_credentials.is_empty(), "discoverable challenges don't name passkeys");
+        challenge.public_key.allow_credentials = vec![self.allow()];
+        let mut credential = self.sign(origin, challenge);
+        credential.response.user_handle = Some(self.user_handle.clone().into());
+        credential
+    }
+
+    fn allow(&self) -> AllowCredentials {
+        AllowCredentials { type_: "public-key".to_string(), id: self.credential_id.clone().into(), transports: None }
+    }
+}
+
+#[tokio::test]
+#[ignore = "needs a database, set DATABASE_URL and run with --ignored"]
+async fn register_creates_user_and_signs_in() {
+    let mut server = Server::start().await;
+    let username = server.username();
+
+    let mut browser = Browser::new(&server);
+    let (_, recovery_codes) = Passkey::register(&mut browser, &username).await.expect("registration to succeed");
+
+    assert!(recovery_codes.is_some_and(|codes| !codes.is_empty()), "new users get recovery codes");
+    let user = server.user(&username).await.expect("user to be created");
+    assert_eq!(browser.signed_in_as().await, Some(user.id));
+    assert!(browser.call(is_logged_in()).await.expect("to check session"));
+    assert_eq!(server.passkey_count(&username).await, 1);
+
+    // Nobody else can take the username, though they only find out once they have made a passkey
+    let mut other = Browser::new(&server);
+    assert!(other.call(start_register(username.clone())).await.is_ok(), "starting doesn't reveal that the username is taken");
+    assert!(Passkey::register(&mut other, &username).await.is_err());
+    assert_eq!(server.passkey_count(&username).await, 1);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+#[ignore = "needs a database, set DATABASE_URL and run with --ignored"]
+async fn login_with_username() {
+    let mut server = Server::start().await;
+    let username = server.username();
+    let (mut passkey, _) = Passkey::register(&mut Browser::new(&server), &username).await.expect("registration to succeed");
+
+    let mut browser = Browser::new(&server);
+    let (challenge, id) = browser.call(start_login(username.clone())).await.expect("to get a challenge");
+    assert!(!challenge.public_key.allow_credentials.is_empty());
+    let credential = passkey.sign(server.origin(), challenge);
+    let flagged = browser.call(finish_login(id, credential)).await.expect("sign-in to succeed");
+
+    assert!(!flagged);
+    assert_eq!(browser.signed_in_as().await, server.user(&username).await.map(|user| user.id));
+
+    // Each challenge can only be answered once
+    let (challenge, id) = browser.call(start_login(username.clone())).await.expect("to get a challenge");
+    let credential = passkey.sign(server.origin(), challenge);
+    browser.call(finish_login(id, credential.clone())).await.expect("sign-in to succeed");
+    assert!(browser.call(finish_login(id, credential)).await.is_err());
+
+    server.stop().await;
+}
+
+#[tokio::test]
+#[ignore = "needs a database, set DATABASE_URL and run with --ignored"]
+async fn discoverable_login() {
+    let mut server = Server::start().await;
+    let username = server.username();
+    let (mut passkey, _) = Passkey::register(&mut Browser::new(&server), &username).await.expect("registration to succeed");
+
+    let mut browser = Browser::new(&server);
+    let (challenge, id) = browser.call(start_discoverable_login()).await.expect("to get a challenge");
+    let credential = passkey.sign_discoverable(server.origin(), challenge);
+    browser.call(finish_discoverable_login(id, credential)).await.expect("sign-in to succeed");
+
+    assert_eq!(browser.signed_in_as().await, server.user(&username).await.map(|user| user.id));
+
+    server.stop().await;
+}
+
+#[tokio::test]
+#[ignore = "needs a database, set DATABASE_URL and run with --ignored"]
+async fn add_second_passkey() {
+    let mut server = Server::start().await;
+    let username = server.username();
+    let mut browser = Browser::new(&server);
+    Passkey::register(&mut browser, &username).await.expect("registration to succeed");
+
+    let (mut second, recovery_codes) = Passkey::register(&mut browser, &username).await.expect("to add a passkey");
+    assert!(recovery_codes.is_none(), "existing users keep their recovery codes");
+    assert_eq!(server.passkey_count(&username).await, 2);
+
+    // The new passkey signs in too
+    let mut other = Browser::new(&server);
+    let (challenge, id) = other.call(start_login(username.clone())).await.expect("to get a challenge");
+    assert_eq!(challenge.public_key.allow_credentials.len(), 2);
+    let credential = second.sign(server.origin(), challenge);
+    other.call(finish_login(id, credential)).await.expect("sign-in to succeed");
+    assert_eq!(other.signed_in_as().await, browser.signed_in_as().await);
+
+    // Including without a username, which needs it to have been registered with the user's ID
+    let mut other = Browser::new(&server);
+    let (challenge, id) = other.call(start_discoverable_login()).await.expect("to get a challenge");
+    let credential = second.sign_discoverable(server.origin(), challenge);
+    other.call(finish_discoverable_login(id, credential)).await.expect("sign-in to succeed");
+    assert_eq!(other.signed_in_as().await, browser.signed_in_as().await);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+#[ignore = "needs a database, set DATABASE_URL and run with --ignored"]
+async fn wrong_user() {
+    let mut server = Server::start().await;
+    let (alice, bob, unknown) = (server.username(), server.username(), server.username());
+    let mut alice_browser = Browser::new(&server);
+    Passkey::register(&mut alice_browser, &alice).await.expect("registration to succeed");
+    let (mut bob_passkey, _) = Passkey::register(&mut Browser::new(&server), &bob).await.expect("registration to succeed");
+
+    // Alice can't add a passkey to Bob's account
+    assert!(alice_browser.call(start_register(bob.clone())).await.is_err());
+
+    // Bob's passkey doesn't sign in to Alice's account
+    let mut browser = Browser::new(&server);
+    let (mut challenge, id) = browser.call(start_login(alice.clone())).await.expect("to get a challenge");
+    challenge.public_key.allow_credentials = vec![bob_passkey.allow()];
+    let credential = bob_passkey.sign(server.origin(), challenge);
+    assert!(browser.call(finish_login(id, credential)).await.is_err());
+    assert_eq!(browser.signed_in_as().await, None);
+
+    // Nor by claiming to be Alice's in a discoverable sign-in
+    let (challenge, id) = browser.call(start_discoverable_login()).await.expect("to get a challenge");
+    let mut credential = bob_passkey.sign_discoverable(server.origin(), challenge);
+    let alice_id = server.user(&alice).await.expect("user to exist").id;
+    credential.response.user_handle = Some(alice_id.as_bytes().to_vec().into());
+    assert!(browser.call(finish_discoverable_login(id, credential)).await.is_err());
+    assert_eq!(browser.signed_in_as().await, None);
+
+    // Unknown usernames get a challenge that no passkey can answer
+    let (mut challenge, id) = browser.call(start_login(unknown)).await.expect("to get a decoy challenge");
+    assert!(!challenge.public_key.allow_credentials.is_empty());
+    challenge.public_key.allow_credentials = vec![bob_passkey.allow()];
+    let credential = bob_passkey.sign(server.origin(), challenge);
+    assert!(browser.call(finish_login(id, credential)).await.is_err());
+    assert_eq!(browser.signed_in_as().await, None);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+#[ignore = "needs a database, set DATABASE_URL and run with --ignored"]
+async fn attestation_required_for_admins() {
+    let mut server = Server::start().await;
+    let (token, ca) = SoftToken::new(true).expect("to create a security key");
+    server.require_attestation(vec![Scope::AdminAuthorAllMods], &ca.to_der().expect("DER certificate"), "Test Key");
+    let username = server.username();
+
+    // The policy doesn't apply to new users, who only get the default role
+    let mut browser = Browser::new(&server);
+    Passkey::register(&mut browser, &username).await.expect("registration to succeed");
+    let user_id = server.user(&username).await.expect("user to exist").id;
+    assert!(!browser.call(attestation::needs_passkey(&server.db, user_id)).await.expect("to check the policy"));
+
+    // Admins must add a passkey from a trusted authenticator
+    roles::set_role(&server.db, user_id, "admin", true, None).await.expect("to make the user an admin");
+    assert!(browser.call(attestation::needs_passkey(&server.db, user_id)).await.expect("to check the policy"));
+    let error = Passkey::register(&mut browser, &username).await.err().expect("untrusted passkey to be refused");
+    assert!(error.to_string().contains(ATTESTATION_REQUIRED), "unexpected error: {error}");
+
+    let (challenge, id) = browser.call(start_register(username.clone())).await.expect("to get a challenge");
+    let mut security_key = WebauthnAuthenticator::new(token);
+    let credential = security_key.do_registration(server.origin(), challenge).expect("security key to create a passkey");
+    let credential_id = credential.raw_id.to_vec();
+    browser.call(finish_register(id, credential)).await.expect("trusted passkey to be accepted");
+    assert!(!browser.call(attestation::needs_passkey(&server.db, user_id)).await.expect("to check the policy"));
+
+    // Its model is recorded, and kept when signing in updates the passkey
+    let mut other = Browser::new(&server);
+    let (challenge, id) = other.call(start_login(username.clone())).await.expect("to get a challenge");
+    let credential = security_key.do_authentication(server.origin(), challenge).expect("security key to sign the challenge");
+    other.call(finish_login(id, credential)).await.expect("sign-in to succeed");
+    let passkey = entity::passkeys::Entity::find_by_id(credential_id)
+        .one(&server.db)
+        .await
+        .expect("to look up passkey")
+        .expect("passkey to exist");
+    assert!(passkey.last_used_at.is_some());
+    let authenticator = attestation::recorded_authenticator(&passkey.data).expect("model to be recorded");
+    assert_eq!((authenticator.aaguid, authenticator.model.as_str()), (AAGUID, "Test Key"));
+
+    server.stop().await;
+}
+
+/// A challenge with the parts that differ every time, or between users, left out.
+fn shape(challenge: &RequestChallengeResponse) -> serde_json::Value {
+    let mut shape = serde_json::to_value(challenge).expect("challenge to serialize");
+    shape["publicKey"]["challenge"] = serde_json::Value::Null;
+    shape["publicKey"]["allowCredentials"] = serde_json::Value::Null;
+    shape
+}
+
+#[tokio::test]
+#[ignore = "needs a database, set DATABASE_URL and run with --ignored"]
+async fn decoy_challenge_looks_real() {
+    let mut server = Server::start().await;
+    let (known, unknown) = (server.username(), server.username());
+    let mut browser = Browser::new(&server);
+    Passkey::register(&mut browser, &known).await.expect("registration to succeed");
+
+    let (real, _) = browser.call(start_login(known)).await.expect("to get a challenge");
+    let (decoy, _) = browser.call(start_login(unknown.clone())).await.expect("to get a decoy challenge");
+    assert_eq!(shape(&decoy), shape(&real));
+    assert!((1..=3).contains(&decoy.public_key.allow_credentials.len()));
+    for credential in real.public_key.allow_credentials.iter().chain(&decoy.public_key.allow_credentials) {
+        assert_eq!((credential.type_.as_str(), &credential.transports), ("public-key", &None));
+        assert!((16..=1023).contains(&credential.id.len()), "unlikely credential ID length {}", credential.id.len());
+    }
+
+    // Asking again gives the same credential IDs
+    let (again, _) = browser.call(start_login(unknown)).await.expect("to get a decoy challenge");
+    let ids = |challenge: &RequestChallengeResponse| {
+        challenge.public_key.allow_credentials.iter().map(|credential| credential.id.to_vec()).collect::<Vec<_>>()
+    };
+    assert_eq!(ids(&again), ids(&decoy));
+
+    server.stop().await;
+}